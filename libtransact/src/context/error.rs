@@ -21,6 +21,7 @@ use crate::state::error::StateReadError;
 
 #[derive(Debug)]
 pub enum ContextManagerError {
+    AuthorizationError(String),
     MissingContextError(String),
     TransactionReceiptBuilderError(TransactionReceiptBuilderError),
     StateReadError(StateReadError),
@@ -29,6 +30,7 @@ pub enum ContextManagerError {
 impl Error for ContextManagerError {
     fn description(&self) -> &str {
         match *self {
+            ContextManagerError::AuthorizationError(ref msg) => msg,
             ContextManagerError::MissingContextError(ref msg) => msg,
             ContextManagerError::TransactionReceiptBuilderError(ref err) => err.description(),
             ContextManagerError::StateReadError(ref err) => err.description(),
@@ -37,6 +39,7 @@ impl Error for ContextManagerError {
 
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
+            ContextManagerError::AuthorizationError(_) => None,
             ContextManagerError::MissingContextError(_) => Some(self),
             ContextManagerError::TransactionReceiptBuilderError(ref err) => Some(err),
             ContextManagerError::StateReadError(ref err) => Some(err),
@@ -47,6 +50,9 @@ impl Error for ContextManagerError {
 impl std::fmt::Display for ContextManagerError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
+            ContextManagerError::AuthorizationError(ref s) => {
+                write!(f, "Context is not authorized to access address: {}", s)
+            }
             ContextManagerError::MissingContextError(ref s) => {
                 write!(f, "Unable to find specified Context: {:?}", s)
             }
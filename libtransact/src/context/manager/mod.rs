@@ -17,6 +17,7 @@
  */
 pub mod sync;
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::str;
@@ -39,6 +40,26 @@ impl ContextLifecycle for ContextManager {
         *new_context.id()
     }
 
+    /// Creates a Context limited to the declared inputs and outputs, and returns the resulting
+    /// ContextId.
+    fn create_context_with_permissions(
+        &mut self,
+        dependent_contexts: &[ContextId],
+        state_id: &str,
+        inputs: &[Vec<u8>],
+        outputs: &[Vec<u8>],
+    ) -> ContextId {
+        let new_context = Context::new_with_permissions(
+            state_id,
+            dependent_contexts.to_vec(),
+            inputs.to_vec(),
+            outputs.to_vec(),
+        );
+        let context_id = *new_context.id();
+        self.contexts.insert(context_id, new_context);
+        context_id
+    }
+
     fn drop_context(&mut self, _context_id: ContextId) {
         unimplemented!();
     }
@@ -93,14 +114,37 @@ impl ContextManager {
         })
     }
 
+    /// Returns an error if the specified Context may not read the key
+    fn check_read(&self, context_id: &ContextId, key: &str) -> Result<(), ContextManagerError> {
+        if self.get_context(context_id)?.may_read(key) {
+            Ok(())
+        } else {
+            Err(ContextManagerError::AuthorizationError(key.to_string()))
+        }
+    }
+
+    /// Returns an error if the specified Context may not set or delete the key
+    fn check_write(&self, context_id: &ContextId, key: &str) -> Result<(), ContextManagerError> {
+        if self.get_context(context_id)?.may_write(key) {
+            Ok(())
+        } else {
+            Err(ContextManagerError::AuthorizationError(key.to_string()))
+        }
+    }
+
     /// Get the values associated with list of keys, from a specific Context.
     /// If a key is not found in the context, State is then checked for these keys.
     /// Keys are returned with the associated value, if found in Context or State.
+    ///
+    /// Returns an AuthorizationError if the Context may not read one of the keys.
     pub fn get(
         &self,
         context_id: &ContextId,
         keys: &[String],
     ) -> Result<Vec<(String, Vec<u8>)>, ContextManagerError> {
+        for key in keys {
+            self.check_read(context_id, key)?;
+        }
         let mut key_values = Vec::new();
         for key in keys.iter().rev() {
             let mut context = self.get_context(context_id)?;
@@ -142,12 +186,15 @@ impl ContextManager {
     }
 
     /// Adds a StateChange::Set to the specified Context
+    ///
+    /// Returns an AuthorizationError if the Context may not set the key.
     pub fn set_state(
         &mut self,
         context_id: &ContextId,
         key: String,
         value: Vec<u8>,
     ) -> Result<(), ContextManagerError> {
+        self.check_write(context_id, &key)?;
         let context = self.get_context_mut(context_id)?;
         context.set_state(key, value);
         Ok(())
@@ -155,11 +202,14 @@ impl ContextManager {
 
     /// Adds a StateChange::Delete to the specified Context, returning the value, if found, that is
     /// associated with the specified key.
+    ///
+    /// Returns an AuthorizationError if the Context may not delete the key.
     pub fn delete_state(
         &mut self,
         context_id: &ContextId,
         key: &str,
     ) -> Result<Option<Vec<u8>>, ContextManagerError> {
+        self.check_write(context_id, key)?;
         // Adding a StateChange::Delete to the specified Context, which will occur no matter which
         // Context or State the key and associated value is found in.
         let context_value = self.get_context_mut(context_id)?.delete_state(key);
@@ -201,6 +251,66 @@ impl ContextManager {
         Ok(None)
     }
 
+    /// Adds a StateChange::Delete to the specified Context for every key that starts with the
+    /// prefix, whether it was set in the Context, in one of its dependent Contexts or in State.
+    /// The keys that were found are returned in key order.
+    ///
+    /// Returns an AuthorizationError, without deleting any key, if the Context may not delete one
+    /// of the keys found.
+    pub fn delete_state_by_prefix(
+        &mut self,
+        context_id: &ContextId,
+        prefix: &str,
+    ) -> Result<Vec<String>, ContextManagerError> {
+        // Whether each key under the prefix is set by its latest state change. Contexts are
+        // visited newest first, and their state changes from last to first, so the first change
+        // found for a key is its latest.
+        let mut keys = BTreeMap::new();
+
+        let current_context = self.get_context(context_id)?;
+        let mut contexts = VecDeque::new();
+        contexts.push_back(current_context);
+        while let Some(context) = contexts.pop_front() {
+            for state_change in context.state_changes().iter().rev() {
+                let (key, is_set) = match state_change {
+                    StateChange::Set { key, .. } => (key, true),
+                    StateChange::Delete { key } => (key, false),
+                };
+                if key.starts_with(prefix) {
+                    keys.entry(key.clone()).or_insert(is_set);
+                }
+            }
+            for context_id in context.base_contexts().iter() {
+                contexts.push_back(self.get_context(context_id)?);
+            }
+        }
+
+        for entry in self
+            .database
+            .range(current_context.state_id(), &prefix.to_string())?
+        {
+            let (key, _) = entry?;
+            keys.entry(key).or_insert(true);
+        }
+
+        // A key deleted by an earlier change has nothing left to delete
+        let keys = keys
+            .into_iter()
+            .filter_map(|(key, is_set)| if is_set { Some(key) } else { None })
+            .collect::<Vec<_>>();
+        for key in keys.iter() {
+            self.check_write(context_id, key)?;
+        }
+
+        let mut deleted = Vec::new();
+        for key in keys {
+            if self.delete_state(context_id, &key)?.is_some() {
+                deleted.push(key);
+            }
+        }
+        Ok(deleted)
+    }
+
     /// Adds an Event to the specified Context.
     pub fn add_event(
         &mut self,
@@ -421,6 +531,146 @@ mod tests {
             .is_none());
     }
 
+    #[test]
+    fn delete_state_by_prefix() {
+        let committed_key = format!("11{}", &KEY2[2..]);
+        let ancestor_key = format!("11{}", &KEY3[2..]);
+        let state_changes = vec![
+            state::StateChange::Set {
+                key: KEY1.to_string(),
+                value: BYTES1.to_vec(),
+            },
+            state::StateChange::Set {
+                key: committed_key.clone(),
+                value: BYTES1.to_vec(),
+            },
+            state::StateChange::Set {
+                key: KEY2.to_string(),
+                value: BYTES2.to_vec(),
+            },
+        ];
+        let (mut manager, state_id) = make_manager(Some(state_changes));
+        let ancestor_context = manager.create_context(&[], &state_id);
+        assert!(manager
+            .set_state(&ancestor_context, ancestor_key.clone(), BYTES3.to_vec())
+            .is_ok());
+
+        let current_context_id = manager.create_context(&[ancestor_context], &state_id);
+        assert!(manager
+            .set_state(&current_context_id, KEY3.to_string(), BYTES3.to_vec())
+            .is_ok());
+
+        // The prefix covers two keys committed to State and one set in the ancestor Context.
+        let deleted = manager
+            .delete_state_by_prefix(&current_context_id, "11")
+            .unwrap();
+        assert_eq!(vec![KEY1.to_string(), committed_key, ancestor_key], deleted);
+
+        let context = manager.get_context(&current_context_id).unwrap();
+        for key in &deleted {
+            assert!(context
+                .state_changes()
+                .contains(&StateChange::Delete { key: key.clone() }));
+        }
+        assert!(!context.state_changes().contains(&StateChange::Delete {
+            key: KEY2.to_string()
+        }));
+        assert!(context.contains(KEY3));
+    }
+
+    #[test]
+    // test that keys already deleted by the Context or its dependent Contexts are not reported
+    fn delete_state_by_prefix_skips_deleted() {
+        let committed_key = format!("11{}", &KEY2[2..]);
+        let state_changes = vec![
+            state::StateChange::Set {
+                key: KEY1.to_string(),
+                value: BYTES1.to_vec(),
+            },
+            state::StateChange::Set {
+                key: committed_key.clone(),
+                value: BYTES1.to_vec(),
+            },
+        ];
+        let (mut manager, state_id) = make_manager(Some(state_changes));
+        let ancestor_context = manager.create_context(&[], &state_id);
+        assert!(manager
+            .delete_state(&ancestor_context, KEY1)
+            .unwrap()
+            .is_some());
+
+        let current_context_id = manager.create_context(&[ancestor_context], &state_id);
+        let deleted = manager
+            .delete_state_by_prefix(&current_context_id, "11")
+            .unwrap();
+        assert_eq!(vec![committed_key], deleted);
+
+        let context = manager.get_context(&current_context_id).unwrap();
+        assert!(!context.state_changes().contains(&StateChange::Delete {
+            key: KEY1.to_string()
+        }));
+    }
+
+    #[test]
+    // test that a context created with permissions only reads its inputs and only writes and
+    // deletes its outputs
+    fn context_permissions() {
+        let state_changes = vec![
+            state::StateChange::Set {
+                key: KEY1.to_string(),
+                value: BYTES1.to_vec(),
+            },
+            state::StateChange::Set {
+                key: KEY2.to_string(),
+                value: BYTES2.to_vec(),
+            },
+        ];
+        let (mut manager, state_id) = make_manager(Some(state_changes));
+        let context_id = manager.create_context_with_permissions(
+            &[],
+            &state_id,
+            &[vec![0x11], vec![0x22]],
+            &[vec![0x22]],
+        );
+
+        assert_eq!(
+            manager.get(&context_id, &[KEY1.to_string()]).unwrap(),
+            vec![(KEY1.to_string(), BYTES1.to_vec())]
+        );
+        match manager.get(&context_id, &[KEY1.to_string(), KEY3.to_string()]) {
+            Err(ContextManagerError::AuthorizationError(key)) => assert_eq!(KEY3, key),
+            res => panic!("Expected AuthorizationError, got {:?}", res),
+        }
+
+        assert!(manager
+            .set_state(&context_id, KEY2.to_string(), BYTES3.to_vec())
+            .is_ok());
+        match manager.set_state(&context_id, KEY1.to_string(), BYTES3.to_vec()) {
+            Err(ContextManagerError::AuthorizationError(key)) => assert_eq!(KEY1, key),
+            res => panic!("Expected AuthorizationError, got {:?}", res),
+        }
+        match manager.delete_state(&context_id, KEY1) {
+            Err(ContextManagerError::AuthorizationError(key)) => assert_eq!(KEY1, key),
+            res => panic!("Expected AuthorizationError, got {:?}", res),
+        }
+        match manager.delete_state_by_prefix(&context_id, "") {
+            Err(ContextManagerError::AuthorizationError(key)) => assert_eq!(KEY1, key),
+            res => panic!("Expected AuthorizationError, got {:?}", res),
+        }
+        assert_eq!(
+            manager.delete_state_by_prefix(&context_id, "22").unwrap(),
+            vec![KEY2.to_string()]
+        );
+
+        let context = manager.get_context(&context_id).unwrap();
+        assert_eq!(
+            context.state_changes(),
+            &vec![StateChange::Delete {
+                key: KEY2.to_string()
+            }]
+        );
+    }
+
     #[test]
     fn get_values() {
         // Creating a ContextManager with a single Context, with a HashMapState backing it
@@ -90,6 +90,18 @@ impl ContextManager {
             .delete_state(context_id, key)
     }
 
+    /// Deletes every key that starts with the prefix, returning the keys that were found.
+    pub fn delete_state_by_prefix(
+        &self,
+        context_id: &ContextId,
+        prefix: &str,
+    ) -> Result<Vec<String>, ContextManagerError> {
+        self.internal_manager
+            .lock()
+            .expect("Lock in delete_state_by_prefix was poisoned")
+            .delete_state_by_prefix(context_id, prefix)
+    }
+
     pub fn add_event(
         &self,
         context_id: &ContextId,
@@ -122,6 +134,21 @@ impl ContextLifecycle for ContextManager {
             .create_context(dependent_contexts, state_id)
     }
 
+    /// Creates a Context limited to the declared inputs and outputs, and returns the resulting
+    /// ContextId.
+    fn create_context_with_permissions(
+        &mut self,
+        dependent_contexts: &[ContextId],
+        state_id: &str,
+        inputs: &[Vec<u8>],
+        outputs: &[Vec<u8>],
+    ) -> ContextId {
+        self.internal_manager
+            .lock()
+            .expect("Lock in create_context_with_permissions was poisoned")
+            .create_context_with_permissions(dependent_contexts, state_id, inputs, outputs)
+    }
+
     fn drop_context(&mut self, context_id: ContextId) {
         self.internal_manager
             .lock()
@@ -29,6 +29,7 @@ mod error;
 pub mod manager;
 
 use crate::context::manager::ContextManagerError;
+use crate::protocol::namespace::any_covers;
 use crate::protocol::receipt::{Event, StateChange, TransactionReceipt};
 use std::mem;
 use uuid::Uuid;
@@ -38,6 +39,22 @@ pub trait ContextLifecycle: Send {
     /// Create a new Context, returning a unique ContextId.
     fn create_context(&mut self, dependent_contexts: &[ContextId], state_id: &str) -> ContextId;
 
+    /// Create a new Context for a transaction with the given declared inputs and outputs,
+    /// returning a unique ContextId.
+    ///
+    /// Reads from the Context are limited to the addresses covered by the inputs, and writes and
+    /// deletes to those covered by the outputs (see `protocol::namespace`).  The default
+    /// implementation does not limit the Context.
+    fn create_context_with_permissions(
+        &mut self,
+        dependent_contexts: &[ContextId],
+        state_id: &str,
+        _inputs: &[Vec<u8>],
+        _outputs: &[Vec<u8>],
+    ) -> ContextId {
+        self.create_context(dependent_contexts, state_id)
+    }
+
     fn drop_context(&mut self, context_id: ContextId);
 
     fn get_transaction_receipt(
@@ -55,6 +72,14 @@ pub struct Context {
     data: Vec<Vec<u8>>,
     events: Vec<Event>,
     state_id: String,
+    permissions: Option<Permissions>,
+}
+
+/// The inputs and outputs declared by the transaction a Context was created for.
+#[derive(Debug, Clone, Default)]
+struct Permissions {
+    inputs: Vec<Vec<u8>>,
+    outputs: Vec<Vec<u8>>,
 }
 
 impl Context {
@@ -66,6 +91,21 @@ impl Context {
             id: *Uuid::new_v4().as_bytes(),
             data: Vec::new(),
             events: Vec::new(),
+            permissions: None,
+        }
+    }
+
+    /// Constructs a Context whose reads are limited to the addresses covered by `inputs`, and
+    /// whose writes to those covered by `outputs`.
+    pub fn new_with_permissions(
+        state_id: &str,
+        base_contexts: Vec<ContextId>,
+        inputs: Vec<Vec<u8>>,
+        outputs: Vec<Vec<u8>>,
+    ) -> Self {
+        Context {
+            permissions: Some(Permissions { inputs, outputs }),
+            ..Context::new(state_id, base_contexts)
         }
     }
 
@@ -92,6 +132,25 @@ impl Context {
         &self.state_id
     }
 
+    /// Returns true if the key may be read from this Context.
+    ///
+    /// Keys are hex-encoded addresses; a key that is not valid hex is covered by no declared
+    /// address.
+    pub fn may_read(&self, key: &str) -> bool {
+        match self.permissions {
+            Some(ref permissions) => is_covered(&permissions.inputs, key),
+            None => true,
+        }
+    }
+
+    /// Returns true if the key may be set or deleted in this Context.
+    pub fn may_write(&self, key: &str) -> bool {
+        match self.permissions {
+            Some(ref permissions) => is_covered(&permissions.outputs, key),
+            None => true,
+        }
+    }
+
     pub fn add_event(&mut self, event: Event) {
         if !self.events().contains(&event) {
             self.events.push(event);
@@ -168,6 +227,12 @@ impl Context {
     }
 }
 
+fn is_covered(declared: &[Vec<u8>], key: &str) -> bool {
+    hex::decode(key)
+        .map(|address| any_covers(declared, &address))
+        .unwrap_or(false)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -192,6 +257,26 @@ mod tests {
         assert_eq!(state_value, Some(first_value.as_slice()));
     }
 
+    #[test]
+    // test that a context's declared inputs and outputs, including namespaces, limit its keys
+    fn test_permissions() {
+        let context = Context::new(&KEY3, Vec::new());
+        assert!(context.may_read(&KEY1));
+        assert!(context.may_write("not hex"));
+
+        let context = Context::new_with_permissions(
+            &KEY3,
+            Vec::new(),
+            vec![vec![0x11]],
+            vec![hex::decode(KEY2).unwrap()],
+        );
+        assert!(context.may_read(&KEY1));
+        assert!(!context.may_read(&KEY2));
+        assert!(!context.may_read("not hex"));
+        assert!(context.may_write(&KEY2));
+        assert!(!context.may_write(&KEY1));
+    }
+
     #[test]
     fn test_compare_state_change() {
         let first_set: StateChange = StateChange::Set {
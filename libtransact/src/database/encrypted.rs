@@ -0,0 +1,564 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! A database wrapper encrypting its contents at rest.
+//!
+//! `EncryptedDatabase` wraps any `Database`, encrypting each value written to it with AES-256-GCM
+//! under a key supplied when it is constructed, and decrypting values as they are read.  Each
+//! value is encrypted with a random nonce, and is bound to the key and index it is stored under,
+//! so that a stored value cannot be moved to another key without detection.
+//!
+//! Keys may optionally be encrypted as well.  Keys must be found again by their plain text, so
+//! they are encrypted deterministically, with a nonce derived from the key itself; equal keys
+//! therefore have equal encrypted forms, which reveals only whether two keys are equal.  The
+//! encrypted keys are not in the order of the plain keys, so cursors over a database with
+//! encrypted keys iterate in an arbitrary order.  Keys should be left unencrypted for databases
+//! whose users rely on the order of keys, such as merkle state, whose write-ahead log and root
+//! history are ordered.
+//!
+//! Reads of a value that fails to decrypt, such as one written under another key, return an error
+//! where the reader's method can, and are otherwise logged and treated as missing.
+
+use std::sync::Arc;
+
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::error::DatabaseError;
+use super::{Database, DatabaseCursor, DatabaseReader, DatabaseReaderCursor, DatabaseWriter};
+
+/// The length of an encryption key, in bytes.
+pub const KEY_LEN: usize = 32;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// A database encrypting the values, and optionally the keys, stored in another.
+#[derive(Clone)]
+pub struct EncryptedDatabase {
+    inner: Box<dyn Database>,
+    crypter: Arc<Crypter>,
+}
+
+impl EncryptedDatabase {
+    /// Constructs a database encrypting the values stored in `inner` with the given key, which
+    /// must be `KEY_LEN` bytes long.
+    pub fn new(inner: Box<dyn Database>, key: &[u8]) -> Result<Self, DatabaseError> {
+        if key.len() != KEY_LEN {
+            return Err(DatabaseError::InitError(format!(
+                "Encryption key must be {} bytes, not {}",
+                KEY_LEN,
+                key.len()
+            )));
+        }
+        // Nonces of keys are derived with a key of their own, so that they reveal nothing of the
+        // encryption key
+        let mut preimage = Zeroizing::new(b"transact key nonce".to_vec());
+        preimage.extend_from_slice(key);
+        let mut digest = openssl::sha::sha256(&preimage);
+        let nonce_key = Zeroizing::new(digest.to_vec());
+        digest.zeroize();
+
+        Ok(EncryptedDatabase {
+            inner,
+            crypter: Arc::new(Crypter {
+                key: Zeroizing::new(key.to_vec()),
+                nonce_key,
+                encrypt_keys: false,
+            }),
+        })
+    }
+
+    /// Encrypts keys as well as values.
+    ///
+    /// Cursors over the database then iterate in an arbitrary order; see the module
+    /// documentation.
+    pub fn with_encrypted_keys(mut self) -> Self {
+        let mut crypter = (*self.crypter).clone();
+        crypter.encrypt_keys = true;
+        self.crypter = Arc::new(crypter);
+        self
+    }
+}
+
+impl Database for EncryptedDatabase {
+    fn get_reader<'a>(&'a self) -> Result<Box<dyn DatabaseReader + 'a>, DatabaseError> {
+        Ok(Box::new(EncryptedReader {
+            inner: self.inner.get_reader()?,
+            crypter: &*self.crypter,
+        }))
+    }
+
+    fn get_writer<'a>(&'a self) -> Result<Box<dyn DatabaseWriter + 'a>, DatabaseError> {
+        Ok(Box::new(EncryptedWriter {
+            inner: self.inner.get_writer()?,
+            crypter: &*self.crypter,
+        }))
+    }
+
+    fn clone_box(&self) -> Box<Database> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+/// Encrypts and decrypts the keys and values of a database.
+///
+/// The encryption key and nonce key are zeroed when the crypter is dropped.
+#[derive(Clone)]
+struct Crypter {
+    key: Zeroizing<Vec<u8>>,
+    nonce_key: Zeroizing<Vec<u8>>,
+    encrypt_keys: bool,
+}
+
+impl Crypter {
+    /// Returns the stored form of a key of the given index, or of the main database if none.
+    fn encrypt_key(&self, index: Option<&str>, key: &[u8]) -> Result<Vec<u8>, String> {
+        if !self.encrypt_keys {
+            return Ok(key.to_vec());
+        }
+        let aad = associated_data(index, &[]);
+        let nonce = self
+            .key_nonce(&aad, key)
+            .map_err(|err| format!("Unable to derive key nonce: {}", err))?;
+        self.seal(&nonce[..NONCE_LEN], &aad, key)
+    }
+
+    /// Returns a MAC of the key and its associated data, the start of which is the key's nonce.
+    fn key_nonce(&self, aad: &[u8], key: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+        let nonce_key = PKey::hmac(&self.nonce_key)?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &nonce_key)?;
+        signer.update(aad)?;
+        signer.update(key)?;
+        signer.sign_to_vec()
+    }
+
+    fn decrypt_key(&self, index: Option<&str>, stored: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+        if !self.encrypt_keys {
+            return Ok(stored.to_vec());
+        }
+        self.open(&associated_data(index, &[]), stored)
+    }
+
+    /// Returns the stored form of a value at the given plain key.
+    fn encrypt_value(
+        &self,
+        index: Option<&str>,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand_bytes(&mut nonce).map_err(|err| format!("Unable to generate nonce: {}", err))?;
+        self.seal(&nonce, &associated_data(index, key), value)
+    }
+
+    fn decrypt_value(
+        &self,
+        index: Option<&str>,
+        key: &[u8],
+        stored: &[u8],
+    ) -> Result<Vec<u8>, DatabaseError> {
+        self.open(&associated_data(index, key), stored)
+    }
+
+    /// Encrypts the given plain text, returning the nonce, cipher text and tag.
+    fn seal(&self, nonce: &[u8], aad: &[u8], plain: &[u8]) -> Result<Vec<u8>, String> {
+        let mut tag = [0u8; TAG_LEN];
+        let cipher_text = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(nonce),
+            aad,
+            plain,
+            &mut tag,
+        )
+        .map_err(|err| format!("Unable to encrypt entry: {}", err))?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + cipher_text.len() + TAG_LEN);
+        sealed.extend_from_slice(nonce);
+        sealed.extend_from_slice(&cipher_text);
+        sealed.extend_from_slice(&tag);
+        Ok(sealed)
+    }
+
+    /// Decrypts and authenticates the output of `seal`.
+    fn open(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err(DatabaseError::CorruptionError(
+                "Encrypted entry is truncated".into(),
+            ));
+        }
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (cipher_text, tag) = rest.split_at(rest.len() - TAG_LEN);
+        decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(nonce),
+            aad,
+            cipher_text,
+            tag,
+        )
+        .map_err(|err| DatabaseError::CorruptionError(format!("Unable to decrypt entry: {}", err)))
+    }
+}
+
+/// Returns the data authenticated with an entry: the name of its index, if any, and its plain
+/// key.
+fn associated_data(index: Option<&str>, key: &[u8]) -> Vec<u8> {
+    let mut aad = vec![];
+    if let Some(index) = index {
+        aad.push(1);
+        aad.extend_from_slice(&(index.len() as u32).to_be_bytes());
+        aad.extend_from_slice(index.as_bytes());
+    } else {
+        aad.push(0);
+    }
+    aad.extend_from_slice(key);
+    aad
+}
+
+/// Reads the entry at the given plain key through a reader of the underlying database.
+fn read(
+    reader: &dyn DatabaseReader,
+    crypter: &Crypter,
+    index: Option<&str>,
+    key: &[u8],
+) -> Result<Option<Vec<u8>>, DatabaseError> {
+    let stored_key = crypter
+        .encrypt_key(index, key)
+        .map_err(DatabaseError::ReaderError)?;
+    let stored = match index {
+        Some(index) => reader.index_get(index, &stored_key)?,
+        None => reader.get(&stored_key),
+    };
+    stored
+        .map(|stored| crypter.decrypt_value(index, key, &stored))
+        .transpose()
+}
+
+fn read_main(reader: &dyn DatabaseReader, crypter: &Crypter, key: &[u8]) -> Option<Vec<u8>> {
+    read(reader, crypter, None, key).unwrap_or_else(|err| {
+        error!("Unable to read from encrypted database: {}", err);
+        None
+    })
+}
+
+fn read_multiple(
+    reader: &dyn DatabaseReader,
+    crypter: &Crypter,
+    keys: &[&[u8]],
+) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+    let stored_keys = keys
+        .iter()
+        .map(|key| crypter.encrypt_key(None, key))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(DatabaseError::ReaderError)?;
+    let stored_key_refs: Vec<&[u8]> = stored_keys.iter().map(|key| key.as_slice()).collect();
+    reader
+        .get_multiple(&stored_key_refs)?
+        .into_iter()
+        .zip(keys)
+        .map(|(stored, key)| {
+            stored
+                .map(|stored| crypter.decrypt_value(None, key, &stored))
+                .transpose()
+        })
+        .collect()
+}
+
+fn decrypting_cursor<'a>(
+    inner: DatabaseCursor<'a>,
+    crypter: &'a Crypter,
+    index: Option<&str>,
+) -> DatabaseCursor<'a> {
+    Box::new(DecryptingCursor {
+        inner,
+        crypter,
+        index: index.map(String::from),
+    })
+}
+
+struct EncryptedReader<'a> {
+    inner: Box<dyn DatabaseReader + 'a>,
+    crypter: &'a Crypter,
+}
+
+impl<'a> DatabaseReader for EncryptedReader<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        read_main(&*self.inner, self.crypter, key)
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        read_multiple(&*self.inner, self.crypter, keys)
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        read(&*self.inner, self.crypter, Some(index), key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        Ok(decrypting_cursor(self.inner.cursor()?, self.crypter, None))
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        Ok(decrypting_cursor(
+            self.inner.index_cursor(index)?,
+            self.crypter,
+            Some(index),
+        ))
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.inner.count()
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        self.inner.index_count(index)
+    }
+}
+
+struct EncryptedWriter<'a> {
+    inner: Box<dyn DatabaseWriter + 'a>,
+    crypter: &'a Crypter,
+}
+
+impl<'a> EncryptedWriter<'a> {
+    fn encrypt(
+        &self,
+        index: Option<&str>,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>), DatabaseError> {
+        Ok((
+            self.crypter
+                .encrypt_key(index, key)
+                .map_err(DatabaseError::WriterError)?,
+            self.crypter
+                .encrypt_value(index, key, value)
+                .map_err(DatabaseError::WriterError)?,
+        ))
+    }
+
+    fn stored_key(&self, index: Option<&str>, key: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+        self.crypter
+            .encrypt_key(index, key)
+            .map_err(DatabaseError::WriterError)
+    }
+}
+
+impl<'a> DatabaseReader for EncryptedWriter<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        read_main(self.inner.as_reader(), self.crypter, key)
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        read_multiple(self.inner.as_reader(), self.crypter, keys)
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        read(self.inner.as_reader(), self.crypter, Some(index), key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        Ok(decrypting_cursor(self.inner.cursor()?, self.crypter, None))
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        Ok(decrypting_cursor(
+            self.inner.index_cursor(index)?,
+            self.crypter,
+            Some(index),
+        ))
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.inner.count()
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        self.inner.index_count(index)
+    }
+}
+
+impl<'a> DatabaseWriter for EncryptedWriter<'a> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        let (stored_key, stored_value) = self.encrypt(None, key, value)?;
+        self.inner.put(&stored_key, &stored_value)
+    }
+
+    fn overwrite(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        let (stored_key, stored_value) = self.encrypt(None, key, value)?;
+        self.inner.overwrite(&stored_key, &stored_value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), DatabaseError> {
+        let stored_key = self.stored_key(None, key)?;
+        self.inner.delete(&stored_key)
+    }
+
+    fn index_put(&mut self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        let (stored_key, stored_value) = self.encrypt(Some(index), key, value)?;
+        self.inner.index_put(index, &stored_key, &stored_value)
+    }
+
+    fn index_delete(&mut self, index: &str, key: &[u8]) -> Result<(), DatabaseError> {
+        let stored_key = self.stored_key(Some(index), key)?;
+        self.inner.index_delete(index, &stored_key)
+    }
+
+    fn commit(self: Box<Self>) -> Result<(), DatabaseError> {
+        self.inner.commit()
+    }
+
+    fn as_reader(&self) -> &dyn DatabaseReader {
+        self
+    }
+}
+
+/// A cursor decrypting the entries of a cursor over the underlying database.
+///
+/// Entries that fail to decrypt are logged and skipped.
+struct DecryptingCursor<'a> {
+    inner: DatabaseCursor<'a>,
+    crypter: &'a Crypter,
+    index: Option<String>,
+}
+
+impl<'a> DecryptingCursor<'a> {
+    fn decrypt(
+        &self,
+        (stored_key, stored_value): (Vec<u8>, Vec<u8>),
+    ) -> Option<(Vec<u8>, Vec<u8>)> {
+        let index = self.index.as_ref().map(String::as_str);
+        let decrypted = self
+            .crypter
+            .decrypt_key(index, &stored_key)
+            .and_then(|key| {
+                let value = self.crypter.decrypt_value(index, &key, &stored_value)?;
+                Ok((key, value))
+            });
+        match decrypted {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                error!("Skipping entry of encrypted database: {}", err);
+                None
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for DecryptingCursor<'a> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entry) = self.inner.next() {
+            if let Some(entry) = self.decrypt(entry) {
+                return Some(entry);
+            }
+        }
+        None
+    }
+}
+
+impl<'a> DatabaseReaderCursor for DecryptingCursor<'a> {
+    fn first(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let entry = DatabaseReaderCursor::first(&mut *self.inner)?;
+        self.decrypt(entry)
+    }
+
+    fn last(&mut self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let entry = DatabaseReaderCursor::last(&mut *self.inner)?;
+        self.decrypt(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+
+    const KEY: [u8; KEY_LEN] = [7; KEY_LEN];
+
+    /// Verifies that values, and keys if configured, are stored encrypted, and are read back in
+    /// plain text only under the same key.
+    #[test]
+    fn encrypted_database() {
+        for &encrypt_keys in &[false, true] {
+            let inner = BTreeDatabase::new(&["index"]);
+            let mut db = EncryptedDatabase::new(Box::new(inner.clone()), &KEY).unwrap();
+            if encrypt_keys {
+                db = db.with_encrypted_keys();
+            }
+
+            let mut writer = db.get_writer().unwrap();
+            writer.put(b"key", b"value").unwrap();
+            writer.index_put("index", b"key", b"indexed").unwrap();
+            assert_eq!(writer.as_reader().get(b"key"), Some(b"value".to_vec()));
+            writer.commit().unwrap();
+
+            match db.get_writer().unwrap().put(b"key", b"other") {
+                Err(DatabaseError::DuplicateEntry) => (),
+                res => panic!("Expected DuplicateEntry, got {:?}", res),
+            }
+
+            let reader = db.get_reader().unwrap();
+            assert_eq!(reader.get(b"key"), Some(b"value".to_vec()));
+            assert_eq!(
+                reader.index_get("index", b"key").unwrap(),
+                Some(b"indexed".to_vec())
+            );
+            assert_eq!(
+                reader
+                    .get_multiple(&[&b"key"[..], &b"missing"[..]])
+                    .unwrap(),
+                vec![Some(b"value".to_vec()), None]
+            );
+            assert_eq!(
+                reader.index_cursor("index").unwrap().collect::<Vec<_>>(),
+                vec![(b"key".to_vec(), b"indexed".to_vec())]
+            );
+
+            // Neither the value nor, if encrypted, the key is stored in plain text
+            let (stored_key, stored_value) = inner
+                .get_reader()
+                .unwrap()
+                .cursor()
+                .unwrap()
+                .next()
+                .unwrap();
+            assert_ne!(stored_value, b"value".to_vec());
+            assert_eq!(stored_key == b"key".to_vec(), !encrypt_keys);
+
+            // Under another key, the value cannot be read
+            let mut wrong_key =
+                EncryptedDatabase::new(Box::new(inner.clone()), &[8; KEY_LEN]).unwrap();
+            if encrypt_keys {
+                wrong_key = wrong_key.with_encrypted_keys();
+            }
+            assert_eq!(wrong_key.get_reader().unwrap().get(b"key"), None);
+            assert_eq!(wrong_key.get_reader().unwrap().cursor().unwrap().count(), 0);
+        }
+
+        assert!(EncryptedDatabase::new(Box::new(BTreeDatabase::new(&[])), &[0; 16]).is_err());
+    }
+}
@@ -27,6 +27,7 @@ pub enum DatabaseError {
     CorruptionError(String),
     NotFoundError(String),
     DuplicateEntry,
+    ReadOnlyError(String),
 }
 
 impl std::fmt::Display for DatabaseError {
@@ -38,6 +39,7 @@ impl std::fmt::Display for DatabaseError {
             DatabaseError::CorruptionError(ref msg) => write!(f, "CorruptionError: {}", msg),
             DatabaseError::NotFoundError(ref msg) => write!(f, "NotFoundError: {}", msg),
             DatabaseError::DuplicateEntry => write!(f, "DuplicateEntry"),
+            DatabaseError::ReadOnlyError(ref msg) => write!(f, "ReadOnlyError: {}", msg),
         }
     }
 }
@@ -51,6 +53,7 @@ impl std::error::Error for DatabaseError {
             DatabaseError::CorruptionError(ref msg) => msg,
             DatabaseError::NotFoundError(ref msg) => msg,
             DatabaseError::DuplicateEntry => "DuplicateEntry",
+            DatabaseError::ReadOnlyError(ref msg) => msg,
         }
     }
 
@@ -62,6 +65,7 @@ impl std::error::Error for DatabaseError {
             DatabaseError::CorruptionError(_) => None,
             DatabaseError::NotFoundError(_) => None,
             DatabaseError::DuplicateEntry => None,
+            DatabaseError::ReadOnlyError(_) => None,
         }
     }
 }
@@ -0,0 +1,238 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! A database wrapper reporting metrics of its use.
+//!
+//! `InstrumentedDatabase` wraps any `Database`, reporting to a `MetricsRecorder` the counters and
+//! durations named by this module's constants.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::metrics::MetricsRecorder;
+
+use super::error::DatabaseError;
+use super::{Database, DatabaseCursor, DatabaseReader, DatabaseWriter};
+
+/// The number of entries read, of the main database or an index.
+pub const READS: &str = "database.reads";
+/// The duration of reads, whether of one entry or several.
+pub const READ_DURATION: &str = "database.read_duration";
+/// The number of cursors opened.
+pub const CURSORS: &str = "database.cursors";
+/// The number of entries written or deleted, of the main database or an index.
+pub const WRITES: &str = "database.writes";
+/// The number of writers committed.
+pub const COMMITS: &str = "database.commits";
+/// The duration of writer commits.
+pub const COMMIT_DURATION: &str = "database.commit_duration";
+
+/// A database reporting metrics of its use to a `MetricsRecorder`.
+#[derive(Clone)]
+pub struct InstrumentedDatabase {
+    inner: Box<dyn Database>,
+    metrics: Arc<dyn MetricsRecorder>,
+}
+
+impl InstrumentedDatabase {
+    pub fn new(inner: Box<dyn Database>, metrics: Arc<dyn MetricsRecorder>) -> Self {
+        InstrumentedDatabase { inner, metrics }
+    }
+}
+
+impl Database for InstrumentedDatabase {
+    fn get_reader<'a>(&'a self) -> Result<Box<dyn DatabaseReader + 'a>, DatabaseError> {
+        Ok(Box::new(InstrumentedReader {
+            inner: self.inner.get_reader()?,
+            metrics: &*self.metrics,
+        }))
+    }
+
+    fn get_writer<'a>(&'a self) -> Result<Box<dyn DatabaseWriter + 'a>, DatabaseError> {
+        Ok(Box::new(InstrumentedWriter {
+            inner: self.inner.get_writer()?,
+            metrics: &*self.metrics,
+        }))
+    }
+
+    fn clone_box(&self) -> Box<Database> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+struct InstrumentedReader<'a> {
+    inner: Box<dyn DatabaseReader + 'a>,
+    metrics: &'a dyn MetricsRecorder,
+}
+
+impl<'a> DatabaseReader for InstrumentedReader<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        timed_read(self.metrics, || self.inner.get(key))
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        timed_reads(self.metrics, keys.len(), || self.inner.get_multiple(keys))
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        timed_read(self.metrics, || self.inner.index_get(index, key))
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        self.metrics.increment_counter(CURSORS, 1);
+        self.inner.cursor()
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        self.metrics.increment_counter(CURSORS, 1);
+        self.inner.index_cursor(index)
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.inner.count()
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        self.inner.index_count(index)
+    }
+}
+
+struct InstrumentedWriter<'a> {
+    inner: Box<dyn DatabaseWriter + 'a>,
+    metrics: &'a dyn MetricsRecorder,
+}
+
+impl<'a> DatabaseReader for InstrumentedWriter<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        timed_read(self.metrics, || self.inner.get(key))
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        timed_reads(self.metrics, keys.len(), || self.inner.get_multiple(keys))
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        timed_read(self.metrics, || self.inner.index_get(index, key))
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        self.metrics.increment_counter(CURSORS, 1);
+        self.inner.cursor()
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        self.metrics.increment_counter(CURSORS, 1);
+        self.inner.index_cursor(index)
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.inner.count()
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        self.inner.index_count(index)
+    }
+}
+
+impl<'a> DatabaseWriter for InstrumentedWriter<'a> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.metrics.increment_counter(WRITES, 1);
+        self.inner.put(key, value)
+    }
+
+    fn overwrite(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.metrics.increment_counter(WRITES, 1);
+        self.inner.overwrite(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), DatabaseError> {
+        self.metrics.increment_counter(WRITES, 1);
+        self.inner.delete(key)
+    }
+
+    fn index_put(&mut self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.metrics.increment_counter(WRITES, 1);
+        self.inner.index_put(index, key, value)
+    }
+
+    fn index_delete(&mut self, index: &str, key: &[u8]) -> Result<(), DatabaseError> {
+        self.metrics.increment_counter(WRITES, 1);
+        self.inner.index_delete(index, key)
+    }
+
+    fn commit(self: Box<Self>) -> Result<(), DatabaseError> {
+        let InstrumentedWriter { inner, metrics } = *self;
+        let start = Instant::now();
+        let result = inner.commit();
+        metrics.record_duration(COMMIT_DURATION, start.elapsed());
+        metrics.increment_counter(COMMITS, 1);
+        result
+    }
+
+    fn as_reader(&self) -> &dyn DatabaseReader {
+        self
+    }
+}
+
+fn timed_read<T, F: FnOnce() -> T>(metrics: &dyn MetricsRecorder, read: F) -> T {
+    timed_reads(metrics, 1, read)
+}
+
+fn timed_reads<T, F: FnOnce() -> T>(metrics: &dyn MetricsRecorder, count: usize, read: F) -> T {
+    let start = Instant::now();
+    let result = read();
+    metrics.record_duration(READ_DURATION, start.elapsed());
+    metrics.increment_counter(READS, count as u64);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::metrics::InMemoryMetrics;
+
+    #[test]
+    // test that reads, writes and commits through the wrapper are counted
+    fn instrumented_database() {
+        let metrics = Arc::new(InMemoryMetrics::new());
+        let db =
+            InstrumentedDatabase::new(Box::new(BTreeDatabase::new(&["index"])), metrics.clone());
+
+        let mut writer = db.get_writer().unwrap();
+        writer.put(b"key", b"value").unwrap();
+        writer.index_put("index", b"key", b"value").unwrap();
+        assert_eq!(writer.as_reader().get(b"key"), Some(b"value".to_vec()));
+        writer.commit().unwrap();
+
+        let reader = db.get_reader().unwrap();
+        assert_eq!(reader.get(b"key"), Some(b"value".to_vec()));
+        assert_eq!(
+            reader.index_get("index", b"key").unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(reader.cursor().unwrap().count(), 1);
+
+        assert_eq!(metrics.counter(WRITES), 2);
+        assert_eq!(metrics.counter(COMMITS), 1);
+        assert_eq!(metrics.counter(READS), 3);
+        assert_eq!(metrics.counter(CURSORS), 1);
+        assert_eq!(metrics.histogram(READ_DURATION).unwrap().count, 3);
+        assert_eq!(metrics.histogram(COMMIT_DURATION).unwrap().count, 1);
+    }
+}
@@ -22,7 +22,9 @@ use crate::database::{
 };
 use std::collections::HashMap;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use lmdb_zero as lmdb;
 
@@ -30,9 +32,67 @@ use crate::database::error::DatabaseError;
 
 const DEFAULT_SIZE: usize = 1 << 40; // 1024 ** 4
 
+/// How long a writer waits for the other transactions of this process to finish before giving
+/// up on growing the map.
+const RESIZE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How an `LmdbContext` grows its memory map when a write fails with `MDB_MAP_FULL`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MapGrowthPolicy {
+    /// Never grow the map; a full map is reported to the writer as a `WriterError`.
+    Disabled,
+    /// Multiply the map size by the given factor.
+    Multiply(usize),
+    /// Add the given number of bytes to the map size.
+    Increment(usize),
+}
+
+impl MapGrowthPolicy {
+    /// Returns the size the map should grow to from `current`, or `None` if it may not grow.
+    fn next_size(self, current: usize, max_size: Option<usize>) -> Option<usize> {
+        let next = match self {
+            MapGrowthPolicy::Disabled => None,
+            MapGrowthPolicy::Multiply(factor) => current.checked_mul(factor),
+            MapGrowthPolicy::Increment(bytes) => current.checked_add(bytes),
+        }?;
+        let next = max_size.map(|max| next.min(max)).unwrap_or(next);
+        if next > current {
+            Some(next)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for MapGrowthPolicy {
+    fn default() -> Self {
+        MapGrowthPolicy::Multiply(2)
+    }
+}
+
 #[derive(Clone)]
 pub struct LmdbContext {
     pub env: Arc<lmdb::Environment>,
+    growth_policy: MapGrowthPolicy,
+    max_size: Option<usize>,
+    // Held for writing while the map is resized, and for reading whenever the map is accessed:
+    // for the lifetime of a writer or a cursor, and for the duration of a reader's transaction
+    // begin and of each of its lookups.
+    //
+    // `mdb_env_set_mapsize` is documented as requiring that no transactions are active in the
+    // process. Open read transactions are deliberately not waited for, since any long-lived
+    // reader would otherwise keep the map from growing. This relies on how LMDB 0.9 implements
+    // the resize rather than on its documented contract: it only refuses to resize while a write
+    // transaction is active, and between calls a read transaction refers to its snapshot by page
+    // number and by its slot in the lock file, never by a pointer into the map. Its next lookup
+    // therefore goes through the new mapping, and the pages of its snapshot are not reused while
+    // it holds its slot. Everything that does point into the map (a writer, a cursor, or a value
+    // being copied out by a lookup) holds this lock.
+    //
+    // All writes take the lock with `try_write`, so that no thread ever waits for writing while
+    // holding it for reading, which would let a recursive read deadlock.
+    resize_lock: Arc<RwLock<()>>,
+    read_only: bool,
 }
 
 impl LmdbContext {
@@ -45,7 +105,31 @@ impl LmdbContext {
             | lmdb::open::WRITEMAP
             | lmdb::open::NORDAHEAD
             | lmdb::open::NOSUBDIR;
+        Self::open(filepath, indexes, size, flags, false)
+    }
 
+    /// Opens an existing environment for reading only, such as by analytics or backup tools
+    /// attached to the database of a running node.
+    ///
+    /// Readers register with the environment's lock file like any other, so the node's writer
+    /// neither blocks on them nor reuses the pages they read.  Creating a writer fails with a
+    /// `ReadOnlyError`.  If the node grows the map, readers adopt the new size.
+    pub fn new_read_only(
+        filepath: &Path,
+        indexes: usize,
+        size: Option<usize>,
+    ) -> Result<Self, DatabaseError> {
+        let flags = lmdb::open::RDONLY | lmdb::open::NORDAHEAD | lmdb::open::NOSUBDIR;
+        Self::open(filepath, indexes, size, flags, true)
+    }
+
+    fn open(
+        filepath: &Path,
+        indexes: usize,
+        size: Option<usize>,
+        flags: lmdb::open::Flags,
+        read_only: bool,
+    ) -> Result<Self, DatabaseError> {
         let filepath_str = filepath
             .to_str()
             .ok_or_else(|| DatabaseError::InitError(format!("Invalid filepath: {:?}", filepath)))?;
@@ -65,7 +149,121 @@ impl LmdbContext {
                 .open(filepath_str, flags, 0o600)
                 .map_err(|err| DatabaseError::InitError(format!("Database not found: {}", err)))
         }?;
-        Ok(LmdbContext { env: Arc::new(env) })
+        Ok(LmdbContext {
+            env: Arc::new(env),
+            growth_policy: MapGrowthPolicy::default(),
+            max_size: None,
+            resize_lock: Arc::new(RwLock::new(())),
+            read_only,
+        })
+    }
+
+    /// Returns whether this environment was opened for reading only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Sets how the map grows once it is full. The map doubles in size by default.
+    pub fn with_growth_policy(mut self, growth_policy: MapGrowthPolicy) -> Self {
+        self.growth_policy = growth_policy;
+        self
+    }
+
+    /// Sets the size, in bytes, beyond which the map will not grow.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Returns the current size of the map, in bytes.
+    pub fn map_size(&self) -> Result<usize, DatabaseError> {
+        self.env
+            .info()
+            .map(|info| info.mapsize)
+            .map_err(|err| DatabaseError::ReaderError(format!("Failed to get map size: {}", err)))
+    }
+
+    fn lock_for_transaction(&self) -> RwLockReadGuard<()> {
+        self.resize_lock
+            .read()
+            .expect("Couldn't lock LMDB resize lock!")
+    }
+
+    /// Locks the map for a resize, waiting up to `RESIZE_TIMEOUT` for the open writer, the open
+    /// cursors and any lookups in progress within this process to finish. Returns `None` on
+    /// timeout.
+    fn lock_for_resize(&self) -> Option<RwLockWriteGuard<()>> {
+        let deadline = Instant::now() + RESIZE_TIMEOUT;
+        loop {
+            match self.resize_lock.try_write() {
+                Ok(guard) => return Some(guard),
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(1))
+                }
+                Err(TryLockError::WouldBlock) => return None,
+                Err(TryLockError::Poisoned(_)) => panic!("Couldn't lock LMDB resize lock!"),
+            }
+        }
+    }
+
+    /// Adopts the map size set by another process. The caller must not hold a transaction.
+    fn adopt_map_size(&self) -> Result<(), DatabaseError> {
+        let _guard = self.lock_for_resize().ok_or_else(|| {
+            DatabaseError::ReaderError(
+                "Map was resized and could not be adopted while other cursors are open".into(),
+            )
+        })?;
+        // A size of zero adopts the size recorded in the environment
+        unsafe { self.env.set_mapsize(0) }
+            .map_err(|err| DatabaseError::ReaderError(format!("Failed to adopt map size: {}", err)))
+    }
+
+    /// Grows the map according to the growth policy. The caller must not hold a transaction.
+    ///
+    /// The resize waits for the open writer and cursors of this process and for lookups in
+    /// progress, but not for open readers, which keep their snapshots and may go on reading once
+    /// the map has grown.  See `resize_lock` for why this departs from the LMDB contract.
+    fn grow_map(&self) -> Result<(), DatabaseError> {
+        let observed_size = self.map_size()?;
+
+        let _guard = self.lock_for_resize().ok_or_else(|| {
+            DatabaseError::WriterError(
+                "Map is full and could not be resized while other cursors are open".into(),
+            )
+        })?;
+
+        // Another writer may have grown the map while this one waited for the lock.
+        let current_size = self.map_size()?;
+        if current_size > observed_size {
+            return Ok(());
+        }
+
+        let new_size = self
+            .growth_policy
+            .next_size(current_size, self.max_size)
+            .ok_or_else(|| {
+                DatabaseError::WriterError(format!(
+                    "Map is full at {} bytes and may not grow further",
+                    current_size
+                ))
+            })?;
+        unsafe { self.env.set_mapsize(new_size) }.map_err(|err| {
+            DatabaseError::WriterError(format!("Failed to grow map to {} bytes: {}", new_size, err))
+        })
+    }
+}
+
+fn is_map_full(err: &lmdb::error::Error) -> bool {
+    match err {
+        lmdb::error::Error::Code(code) => *code == lmdb::error::MAP_FULL,
+        _ => false,
+    }
+}
+
+fn is_map_resized(err: &lmdb::error::Error) -> bool {
+    match err {
+        lmdb::error::Error::Code(code) => *code == lmdb::error::MAP_RESIZED,
+        _ => false,
     }
 }
 
@@ -77,11 +275,18 @@ pub struct LmdbDatabase {
 }
 
 impl LmdbDatabase {
+    /// Opens the main database and the given indexes of the environment, creating any that do
+    /// not exist unless the environment is read-only.
     pub fn new<S: AsRef<str>>(ctx: LmdbContext, indexes: &[S]) -> Result<Self, DatabaseError> {
+        let db_flags = if ctx.read_only {
+            lmdb::db::Flags::empty()
+        } else {
+            lmdb::db::CREATE
+        };
         let main = lmdb::Database::open(
             ctx.env.clone(),
             Some("main"),
-            &lmdb::DatabaseOptions::new(lmdb::db::CREATE),
+            &lmdb::DatabaseOptions::new(db_flags),
         )
         .map_err(|err| DatabaseError::InitError(format!("Failed to open database: {:?}", err)))?;
 
@@ -90,7 +295,7 @@ impl LmdbDatabase {
             let db = lmdb::Database::open(
                 ctx.env.clone(),
                 Some(name.as_ref()),
-                &lmdb::DatabaseOptions::new(lmdb::db::CREATE),
+                &lmdb::DatabaseOptions::new(db_flags),
             )
             .map_err(|err| {
                 DatabaseError::InitError(format!("Failed to open database: {:?}", err))
@@ -104,34 +309,53 @@ impl LmdbDatabase {
         })
     }
 
+    /// Opens a reader on the current state of the database.
+    ///
+    /// The reader does not hold off a resize of the map while it is open, only while it begins
+    /// its transaction and while it looks up a value; its cursors hold off a resize until they
+    /// are dropped.
     pub fn reader(&self) -> Result<LmdbDatabaseReader, DatabaseError> {
-        let txn = lmdb::ReadTransaction::new(self.ctx.env.clone()).map_err(|err| {
-            DatabaseError::ReaderError(format!("Failed to create reader: {}", err))
-        })?;
+        let guard = self.ctx.lock_for_transaction();
+        let txn = match lmdb::ReadTransaction::new(self.ctx.env.clone()) {
+            // Another process has grown the map
+            Err(ref err) if is_map_resized(err) => {
+                drop(guard);
+                self.ctx.adopt_map_size()?;
+                let _guard = self.ctx.lock_for_transaction();
+                lmdb::ReadTransaction::new(self.ctx.env.clone())
+            }
+            result => result,
+        }
+        .map_err(|err| DatabaseError::ReaderError(format!("Failed to create reader: {}", err)))?;
         Ok(LmdbDatabaseReader { db: &self, txn })
     }
 
     pub fn writer(&self) -> Result<LmdbDatabaseWriter, DatabaseError> {
+        if self.ctx.read_only {
+            return Err(DatabaseError::ReadOnlyError(
+                "LMDB environment was opened read-only".into(),
+            ));
+        }
+        let guard = self.ctx.lock_for_transaction();
         let txn = lmdb::WriteTransaction::new(self.ctx.env.clone()).map_err(|err| {
             DatabaseError::WriterError(format!("Failed to create writer: {}", err))
         })?;
-        Ok(LmdbDatabaseWriter { db: &self, txn })
+        Ok(LmdbDatabaseWriter {
+            db: &self,
+            txn: Some(txn),
+            guard: Some(guard),
+            operations: vec![],
+        })
     }
 }
 
 impl Database for LmdbDatabase {
     fn get_reader<'a>(&'a self) -> Result<Box<dyn DatabaseReader + 'a>, DatabaseError> {
-        let txn = lmdb::ReadTransaction::new(self.ctx.env.clone()).map_err(|err| {
-            DatabaseError::ReaderError(format!("Failed to create reader: {}", err))
-        })?;
-        Ok(Box::new(LmdbDatabaseReader { db: &self, txn }))
+        Ok(Box::new(self.reader()?))
     }
 
     fn get_writer<'a>(&'a self) -> Result<Box<dyn DatabaseWriter + 'a>, DatabaseError> {
-        let txn = lmdb::WriteTransaction::new(self.ctx.env.clone()).map_err(|err| {
-            DatabaseError::WriterError(format!("Failed to create writer: {}", err))
-        })?;
-        Ok(Box::new(LmdbDatabaseWriter { db: &self, txn }))
+        Ok(Box::new(self.writer()?))
     }
 
     fn clone_box(&self) -> Box<Database> {
@@ -144,31 +368,58 @@ pub struct LmdbDatabaseReader<'a> {
     txn: lmdb::ReadTransaction<'a>,
 }
 
+impl<'a> LmdbDatabaseReader<'a> {
+    /// Holds off a resize of the map for as long as the returned guard lives.
+    fn lock(&self) -> RwLockReadGuard<'a, ()> {
+        let db = self.db;
+        db.ctx.lock_for_transaction()
+    }
+}
+
 impl<'a> DatabaseReader for LmdbDatabaseReader<'a> {
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let _guard = self.lock();
         let access = self.txn.access();
         let val: Result<&[u8], _> = access.get(&self.db.main, key);
         val.ok().map(Vec::from)
     }
 
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        let _guard = self.lock();
+        let access = self.txn.access();
+        Ok(keys
+            .iter()
+            .map(|key| {
+                let val: Result<&[u8], _> = access.get(&self.db.main, *key);
+                val.ok().map(Vec::from)
+            })
+            .collect())
+    }
+
     fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
         let index = self
             .db
             .indexes
             .get(index)
             .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
+        let _guard = self.lock();
         let access = self.txn.access();
         let val: Result<&[u8], _> = access.get(index, key);
         Ok(val.ok().map(Vec::from))
     }
 
     fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        let guard = self.lock();
         let cursor = self
             .txn
             .cursor(self.db.main.clone())
             .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))?;
         let access = self.txn.access();
-        Ok(Box::new(LmdbDatabaseReaderCursor { access, cursor }))
+        Ok(Box::new(LmdbDatabaseReaderCursor {
+            access,
+            cursor,
+            _guard: Some(guard),
+        }))
     }
 
     fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
@@ -177,15 +428,21 @@ impl<'a> DatabaseReader for LmdbDatabaseReader<'a> {
             .indexes
             .get(index)
             .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
+        let guard = self.lock();
         let cursor = self
             .txn
             .cursor(index)
             .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))?;
         let access = self.txn.access();
-        Ok(Box::new(LmdbDatabaseReaderCursor { access, cursor }))
+        Ok(Box::new(LmdbDatabaseReaderCursor {
+            access,
+            cursor,
+            _guard: Some(guard),
+        }))
     }
 
     fn count(&self) -> Result<usize, DatabaseError> {
+        let _guard = self.lock();
         self.txn
             .db_stat(&self.db.main)
             .map_err(|err| {
@@ -200,6 +457,7 @@ impl<'a> DatabaseReader for LmdbDatabaseReader<'a> {
             .indexes
             .get(index)
             .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
+        let _guard = self.lock();
         self.txn
             .db_stat(index)
             .map_err(|err| {
@@ -212,6 +470,10 @@ impl<'a> DatabaseReader for LmdbDatabaseReader<'a> {
 pub struct LmdbDatabaseReaderCursor<'a> {
     access: lmdb::ConstAccessor<'a>,
     cursor: lmdb::Cursor<'a, 'a>,
+    // Holds off a resize while the cursor points into the map. Declared after the cursor, so
+    // that the cursor is closed before the guard is released. A writer's cursors have none,
+    // since the writer holds the lock itself.
+    _guard: Option<RwLockReadGuard<'a, ()>>,
 }
 
 impl<'a> DatabaseReaderCursor for LmdbDatabaseReaderCursor<'a> {
@@ -243,64 +505,197 @@ impl<'a> Iterator for LmdbDatabaseReaderCursor<'a> {
 
 pub struct LmdbDatabaseWriter<'a> {
     db: &'a LmdbDatabase,
-    txn: lmdb::WriteTransaction<'a>,
+    // `None` only while the map is being resized. Declared before the guard, so that the
+    // transaction is closed before the guard is released.
+    txn: Option<lmdb::WriteTransaction<'a>>,
+    guard: Option<RwLockReadGuard<'a, ()>>,
+    // Copies of the operations applied so far, which are replayed onto a new transaction once
+    // the map has grown. Nothing is copied or recorded if the map may not grow.
+    operations: Vec<WriteOperation<'a, Vec<u8>>>,
+}
+
+/// A write, over borrowed bytes while it is first applied and over owned copies once recorded.
+enum WriteOperation<'a, B> {
+    Put {
+        db: &'a lmdb::Database<'static>,
+        key: B,
+        value: B,
+        flags: lmdb::put::Flags,
+    },
+    Delete {
+        db: &'a lmdb::Database<'static>,
+        key: B,
+    },
+}
+
+impl<'a, B: AsRef<[u8]>> WriteOperation<'a, B> {
+    fn apply(&self, txn: &lmdb::WriteTransaction) -> Result<(), lmdb::error::Error> {
+        match self {
+            WriteOperation::Put {
+                db,
+                key,
+                value,
+                flags,
+            } => {
+                let (key, value): (&[u8], &[u8]) = (key.as_ref(), value.as_ref());
+                txn.access().put(db, key, value, *flags)
+            }
+            WriteOperation::Delete { db, key } => {
+                let key: &[u8] = key.as_ref();
+                txn.access().del_key(db, key)
+            }
+        }
+    }
+
+    fn copied(&self) -> WriteOperation<'a, Vec<u8>> {
+        match self {
+            WriteOperation::Put {
+                db,
+                key,
+                value,
+                flags,
+            } => WriteOperation::Put {
+                db: *db,
+                key: key.as_ref().to_vec(),
+                value: value.as_ref().to_vec(),
+                flags: *flags,
+            },
+            WriteOperation::Delete { db, key } => WriteOperation::Delete {
+                db: *db,
+                key: key.as_ref().to_vec(),
+            },
+        }
+    }
+}
+
+fn to_writer_error(err: lmdb::error::Error) -> DatabaseError {
+    match err {
+        lmdb::error::Error::Code(lmdb::error::KEYEXIST) => DatabaseError::DuplicateEntry,
+        _ => DatabaseError::WriterError(format!("{}", err)),
+    }
+}
+
+impl<'a> LmdbDatabaseWriter<'a> {
+    fn txn(&self) -> Result<&lmdb::WriteTransaction<'a>, DatabaseError> {
+        self.txn.as_ref().ok_or_else(|| {
+            DatabaseError::WriterError("Transaction was aborted while growing the map".into())
+        })
+    }
+
+    fn index(&self, index: &str) -> Result<&'a lmdb::Database<'static>, DatabaseError> {
+        let db = self.db;
+        db.indexes
+            .get(index)
+            .ok_or_else(|| DatabaseError::WriterError(format!("Not an index: {}", index)))
+    }
+
+    fn can_grow(&self) -> bool {
+        self.db.ctx.growth_policy != MapGrowthPolicy::Disabled
+    }
+
+    fn execute(&mut self, operation: WriteOperation<'a, &[u8]>) -> Result<(), DatabaseError> {
+        match operation.apply(self.txn()?) {
+            Ok(()) => {
+                if self.can_grow() {
+                    self.operations.push(operation.copied());
+                }
+                Ok(())
+            }
+            Err(ref err) if is_map_full(err) && self.can_grow() => {
+                self.operations.push(operation.copied());
+                self.grow_and_replay()
+            }
+            Err(err) => Err(to_writer_error(err)),
+        }
+    }
+
+    /// Aborts the current transaction, grows the map and replays the recorded operations onto
+    /// a new transaction, repeating until they fit or the map may not grow any further.
+    fn grow_and_replay(&mut self) -> Result<(), DatabaseError> {
+        let db = self.db;
+        loop {
+            self.txn = None;
+            self.guard = None;
+            db.ctx.grow_map()?;
+
+            self.guard = Some(db.ctx.lock_for_transaction());
+            self.txn = Some(
+                lmdb::WriteTransaction::new(db.ctx.env.clone()).map_err(|err| {
+                    DatabaseError::WriterError(format!("Failed to create writer: {}", err))
+                })?,
+            );
+
+            let txn = self.txn()?;
+            match self
+                .operations
+                .iter()
+                .try_for_each(|operation| operation.apply(txn))
+            {
+                Ok(()) => return Ok(()),
+                Err(ref err) if is_map_full(err) => continue,
+                Err(err) => return Err(to_writer_error(err)),
+            }
+        }
+    }
 }
 
 impl<'a> DatabaseWriter for LmdbDatabaseWriter<'a> {
     /// Writes the given key/value pair. If the key/value pair already exists,
     /// it will return a DatabaseError::DuplicateEntry.
     fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
-        self.txn
-            .access()
-            .put(&self.db.main, key, value, lmdb::put::NOOVERWRITE)
-            .map_err(|err| match err {
-                lmdb::error::Error::Code(lmdb::error::KEYEXIST) => DatabaseError::DuplicateEntry,
-                _ => DatabaseError::WriterError(format!("{}", err)),
-            })
+        let db = self.db;
+        self.execute(WriteOperation::Put {
+            db: &db.main,
+            key,
+            value,
+            flags: lmdb::put::NOOVERWRITE,
+        })
     }
 
     fn overwrite(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
-        self.txn
-            .access()
-            .put(&self.db.main, key, value, lmdb::put::Flags::empty())
-            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))
+        let db = self.db;
+        self.execute(WriteOperation::Put {
+            db: &db.main,
+            key,
+            value,
+            flags: lmdb::put::Flags::empty(),
+        })
     }
 
     fn delete(&mut self, key: &[u8]) -> Result<(), DatabaseError> {
-        self.txn
-            .access()
-            .del_key(&self.db.main, key)
-            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))
+        let db = self.db;
+        self.execute(WriteOperation::Delete { db: &db.main, key })
     }
 
     fn index_put(&mut self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
-        let index = self
-            .db
-            .indexes
-            .get(index)
-            .ok_or_else(|| DatabaseError::WriterError(format!("Not an index: {}", index)))?;
-        self.txn
-            .access()
-            .put(index, key, value, lmdb::put::Flags::empty())
-            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))
+        let index = self.index(index)?;
+        self.execute(WriteOperation::Put {
+            db: index,
+            key,
+            value,
+            flags: lmdb::put::Flags::empty(),
+        })
     }
 
     fn index_delete(&mut self, index: &str, key: &[u8]) -> Result<(), DatabaseError> {
-        let index = self
-            .db
-            .indexes
-            .get(index)
-            .ok_or_else(|| DatabaseError::WriterError(format!("Not an index: {}", index)))?;
-        self.txn
-            .access()
-            .del_key(index, key)
-            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))
+        let index = self.index(index)?;
+        self.execute(WriteOperation::Delete { db: index, key })
     }
 
     fn commit(self: Box<Self>) -> Result<(), DatabaseError> {
-        self.txn
-            .commit()
-            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))
+        let mut writer = *self;
+        loop {
+            let txn = writer.txn.take().ok_or_else(|| {
+                DatabaseError::WriterError("Transaction was aborted while growing the map".into())
+            })?;
+            match txn.commit() {
+                Ok(()) => return Ok(()),
+                Err(ref err) if is_map_full(err) && writer.can_grow() => {
+                    writer.grow_and_replay()?
+                }
+                Err(err) => return Err(DatabaseError::WriterError(format!("{}", err))),
+            }
+        }
     }
 
     fn as_reader(&self) -> &dyn DatabaseReader {
@@ -310,29 +705,44 @@ impl<'a> DatabaseWriter for LmdbDatabaseWriter<'a> {
 
 impl<'a> DatabaseReader for LmdbDatabaseWriter<'a> {
     fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        let access = self.txn.access();
+        let access = self.txn().ok()?.access();
         let val: Result<&[u8], _> = access.get(&self.db.main, key);
         val.ok().map(Vec::from)
     }
 
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        let access = self.txn()?.access();
+        Ok(keys
+            .iter()
+            .map(|key| {
+                let val: Result<&[u8], _> = access.get(&self.db.main, *key);
+                val.ok().map(Vec::from)
+            })
+            .collect())
+    }
+
     fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
         let index = self
             .db
             .indexes
             .get(index)
             .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
-        let access = self.txn.access();
+        let access = self.txn()?.access();
         let val: Result<&[u8], _> = access.get(index, key);
         Ok(val.ok().map(Vec::from))
     }
 
     fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
-        let cursor = self
-            .txn
+        let txn = self.txn()?;
+        let cursor = txn
             .cursor(self.db.main.clone())
             .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))?;
-        let access = (*self.txn).access();
-        Ok(Box::new(LmdbDatabaseReaderCursor { access, cursor }))
+        let access = (**txn).access();
+        Ok(Box::new(LmdbDatabaseReaderCursor {
+            access,
+            cursor,
+            _guard: None,
+        }))
     }
 
     fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
@@ -341,16 +751,20 @@ impl<'a> DatabaseReader for LmdbDatabaseWriter<'a> {
             .indexes
             .get(index)
             .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
-        let cursor = self
-            .txn
+        let txn = self.txn()?;
+        let cursor = txn
             .cursor(index)
             .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))?;
-        let access = (*self.txn).access();
-        Ok(Box::new(LmdbDatabaseReaderCursor { access, cursor }))
+        let access = (**txn).access();
+        Ok(Box::new(LmdbDatabaseReaderCursor {
+            access,
+            cursor,
+            _guard: None,
+        }))
     }
 
     fn count(&self) -> Result<usize, DatabaseError> {
-        self.txn
+        self.txn()?
             .db_stat(&self.db.main)
             .map_err(|err| {
                 DatabaseError::CorruptionError(format!("Failed to get database stats: {}", err))
@@ -364,7 +778,7 @@ impl<'a> DatabaseReader for LmdbDatabaseWriter<'a> {
             .indexes
             .get(index)
             .ok_or_else(|| DatabaseError::ReaderError(format!("Not an index: {}", index)))?;
-        self.txn
+        self.txn()?
             .db_stat(index)
             .map_err(|err| {
                 DatabaseError::CorruptionError(format!("Failed to get database stats: {}", err))
@@ -463,6 +877,16 @@ mod tests {
             assert_key_value(5, 6, &database);
             assert_key_value(3, 4, &database);
 
+            {
+                let reader = database.reader().unwrap();
+                assert_eq!(
+                    reader
+                        .get_multiple(&[&[5][..], &[4][..], &[3][..]])
+                        .unwrap(),
+                    vec![Some(vec![6]), None, Some(vec![4])]
+                );
+            }
+
             // Delete {3: 4}
             let mut writer = database.get_writer().unwrap();
             writer.delete(&[3]).unwrap();
@@ -520,6 +944,108 @@ mod tests {
         })
     }
 
+    /// Writes more than fits into a small map, and asserts that the map grows to hold it.
+    #[test]
+    fn test_lmdb_map_growth() {
+        run_test(|blockstore_path| {
+            let ctx = LmdbContext::new(Path::new(blockstore_path), 1, Some(1024 * 1024)).unwrap();
+            let database = LmdbDatabase::new(ctx.clone(), &["a"]).unwrap();
+
+            let mut writer = database.get_writer().unwrap();
+            for i in 0..4000u32 {
+                writer.put(&i.to_be_bytes(), &[0; 1024]).unwrap();
+            }
+            writer.index_put("a", &[1], &[2]).unwrap();
+            writer.commit().unwrap();
+
+            assert_database_count(4000, &database);
+            assert_index_key_value("a", 1, 2, &database);
+            assert!(ctx.map_size().unwrap() > 1024 * 1024);
+        })
+    }
+
+    /// Asserts that a reader left open does not keep the map from growing, and that it goes on
+    /// reading its snapshot afterwards.
+    #[test]
+    fn test_lmdb_map_growth_with_open_reader() {
+        run_test(|blockstore_path| {
+            let ctx = LmdbContext::new(Path::new(blockstore_path), 1, Some(1024 * 1024)).unwrap();
+            let database = LmdbDatabase::new(ctx.clone(), &["a"]).unwrap();
+
+            let mut writer = database.get_writer().unwrap();
+            writer.put(&[1], &[2]).unwrap();
+            writer.commit().unwrap();
+
+            let reader = database.reader().unwrap();
+
+            let mut writer = database.get_writer().unwrap();
+            for i in 0..4000u32 {
+                writer.put(&i.to_be_bytes(), &[0; 1024]).unwrap();
+            }
+            writer.commit().unwrap();
+
+            assert!(ctx.map_size().unwrap() > 1024 * 1024);
+            assert_eq!(reader.get(&[1]).unwrap(), [2]);
+            assert_eq!(reader.count().unwrap(), 1);
+
+            // A thread may only have one read transaction open at a time
+            drop(reader);
+            assert_database_count(4001, &database);
+        })
+    }
+
+    /// Asserts that the map does not grow beyond its maximum size, and that the write which
+    /// would need it to is reported as an error.
+    #[test]
+    fn test_lmdb_map_growth_limit() {
+        run_test(|blockstore_path| {
+            let ctx = LmdbContext::new(Path::new(blockstore_path), 1, Some(1024 * 1024))
+                .unwrap()
+                .with_growth_policy(MapGrowthPolicy::Increment(512 * 1024))
+                .with_max_size(2 * 1024 * 1024);
+            let database = LmdbDatabase::new(ctx.clone(), &["a"]).unwrap();
+
+            let mut writer = database.get_writer().unwrap();
+            match (0..4000u32).try_for_each(|i| writer.put(&i.to_be_bytes(), &[0; 1024])) {
+                Err(DatabaseError::WriterError(_)) => (),
+                res => panic!("Expected WriterError, got {:?}", res),
+            }
+
+            assert_eq!(ctx.map_size().unwrap(), 2 * 1024 * 1024);
+        })
+    }
+
+    /// Opens a written environment read-only, and asserts that it may be read but not written.
+    #[test]
+    fn test_lmdb_read_only() {
+        run_test(|blockstore_path| {
+            {
+                let ctx =
+                    LmdbContext::new(Path::new(blockstore_path), 1, Some(1024 * 1024)).unwrap();
+                let database = LmdbDatabase::new(ctx, &["a"]).unwrap();
+                let mut writer = database.get_writer().unwrap();
+                writer.put(&[1], &[2]).unwrap();
+                writer.index_put("a", &[3], &[4]).unwrap();
+                writer.commit().unwrap();
+            }
+
+            let ctx = LmdbContext::new_read_only(Path::new(blockstore_path), 2, Some(1024 * 1024))
+                .unwrap();
+            assert!(ctx.is_read_only());
+            let database = LmdbDatabase::new(ctx, &["a"]).unwrap();
+            assert_key_value(1, 2, &database);
+            assert_index_key_value("a", 3, 4, &database);
+
+            match database.get_writer() {
+                Err(DatabaseError::ReadOnlyError(_)) => (),
+                Err(err) => panic!("Expected ReadOnlyError, got {:?}", err),
+                Ok(_) => panic!("Expected ReadOnlyError, got a writer"),
+            }
+            // Indexes are not created in a read-only environment
+            assert!(LmdbDatabase::new(database.ctx.clone(), &["a", "b"]).is_err());
+        })
+    }
+
     fn run_test<T>(test: T) -> ()
     where
         T: FnOnce(&str) -> () + panic::UnwindSafe,
@@ -30,8 +30,21 @@
 //! Changes to the underlying database are rendered via the DatabaseWriter's commit method.
 
 pub mod btree;
+pub mod encrypted;
 pub mod error;
+pub mod instrumented;
 pub mod lmdb;
+#[cfg(any(feature = "redis", feature = "rocksdb"))]
+mod pending;
+#[cfg(feature = "postgresql")]
+pub mod postgresql;
+pub mod read_only;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "rocksdb")]
+pub mod rocksdb;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
 use crate::database::error::DatabaseError;
 
@@ -54,6 +67,14 @@ pub trait DatabaseReader {
     /// Returns the bytes stored at the given key, if found.
     fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
 
+    /// Returns the bytes stored at each of the given keys, in the order of the keys.
+    ///
+    /// Implementations that can look up several keys at once override this, to avoid a round
+    /// trip to the underlying store per key.
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        Ok(keys.iter().map(|key| self.get(key)).collect())
+    }
+
     /// Returns the bytes stored at the given key on a specified index, if found.
     fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError>;
 
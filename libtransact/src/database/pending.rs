@@ -0,0 +1,191 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Uncommitted changes, for writers whose backing store cannot read its own pending writes.
+//!
+//! Changes are grouped by table: the main database, under the empty name, and each index, under
+//! its name.  Reads through a writer consult its pending changes before the store itself.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::database::btree::BTreeDatabaseCursor;
+use crate::database::error::DatabaseError;
+use crate::database::DatabaseCursor;
+
+/// The table name under which changes to the main database are kept.
+pub const MAIN_TABLE: &str = "";
+
+/// The pending changes to a single table, where `None` marks a deleted key.
+pub type TableChanges = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+#[derive(Default)]
+pub struct PendingWrites {
+    tables: HashMap<String, TableChanges>,
+}
+
+impl PendingWrites {
+    pub fn new() -> Self {
+        PendingWrites::default()
+    }
+
+    /// Returns the pending change to the given key, if any: `Some(None)` if the key is to be
+    /// deleted, and `None` if the key is unchanged.
+    pub fn get(&self, table: &str, key: &[u8]) -> Option<Option<&Vec<u8>>> {
+        self.tables
+            .get(table)
+            .and_then(|changes| changes.get(key))
+            .map(Option::as_ref)
+    }
+
+    pub fn put(&mut self, table: &str, key: &[u8], value: &[u8]) {
+        self.table_mut(table)
+            .insert(key.to_vec(), Some(value.to_vec()));
+    }
+
+    pub fn delete(&mut self, table: &str, key: &[u8]) {
+        self.table_mut(table).insert(key.to_vec(), None);
+    }
+
+    /// Returns the number of entries in the table once the changes are applied, given its
+    /// committed count and a test for whether a key is committed.
+    pub fn count<F>(&self, table: &str, committed: usize, mut is_committed: F) -> usize
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let changes = match self.tables.get(table) {
+            Some(changes) => changes,
+            None => return committed,
+        };
+
+        changes.iter().fold(committed, |count, (key, value)| {
+            match (is_committed(key), value.is_some()) {
+                (false, true) => count + 1,
+                (true, false) => count - 1,
+                _ => count,
+            }
+        })
+    }
+
+    /// Returns the values of the given keys once the changes are applied, reading the keys without
+    /// pending changes with a single call to `get_committed`.
+    pub fn get_multiple<F>(
+        &self,
+        table: &str,
+        keys: &[&[u8]],
+        get_committed: F,
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError>
+    where
+        F: FnOnce(&[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError>,
+    {
+        let changes: Vec<_> = keys.iter().map(|key| self.get(table, key)).collect();
+        let unchanged: Vec<&[u8]> = keys
+            .iter()
+            .zip(&changes)
+            .filter(|(_, change)| change.is_none())
+            .map(|(key, _)| *key)
+            .collect();
+
+        let mut committed = if unchanged.is_empty() {
+            vec![]
+        } else {
+            get_committed(&unchanged)?
+        }
+        .into_iter();
+
+        Ok(changes
+            .into_iter()
+            .map(|change| match change {
+                Some(value) => value.cloned(),
+                None => committed.next().unwrap_or(None),
+            })
+            .collect())
+    }
+
+    /// Returns a cursor over the table's committed entries with the changes applied.
+    ///
+    /// As with the BTree writer's cursors, the entries are collected when the cursor is created.
+    pub fn cursor<'a, I>(&self, table: &str, committed: I) -> DatabaseCursor<'a>
+    where
+        I: Iterator<Item = (Vec<u8>, Vec<u8>)>,
+    {
+        let mut entries: BTreeMap<Vec<u8>, Vec<u8>> = committed.collect();
+        if let Some(changes) = self.tables.get(table) {
+            for (key, value) in changes {
+                match value {
+                    Some(value) => entries.insert(key.clone(), value.clone()),
+                    None => entries.remove(key),
+                };
+            }
+        }
+        Box::new(BTreeDatabaseCursor::new(entries))
+    }
+
+    /// Consumes the pending writes, returning the changes to each table.
+    pub fn into_tables(self) -> HashMap<String, TableChanges> {
+        self.tables
+    }
+
+    fn table_mut(&mut self, table: &str) -> &mut TableChanges {
+        self.tables
+            .entry(table.to_string())
+            .or_insert_with(BTreeMap::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_writes() {
+        let mut pending = PendingWrites::new();
+        pending.put(MAIN_TABLE, &[1], &[10]);
+        pending.put(MAIN_TABLE, &[2], &[20]);
+        pending.delete(MAIN_TABLE, &[3]);
+        pending.put("index", &[1], &[11]);
+
+        assert_eq!(pending.get(MAIN_TABLE, &[1]), Some(Some(&vec![10])));
+        assert_eq!(pending.get(MAIN_TABLE, &[3]), Some(None));
+        assert_eq!(pending.get(MAIN_TABLE, &[4]), None);
+        assert_eq!(pending.get("index", &[2]), None);
+
+        // Committed keys are 2, 3 and 4
+        let committed = vec![(vec![2], vec![2]), (vec![3], vec![3]), (vec![4], vec![4])];
+        assert_eq!(
+            pending.count(MAIN_TABLE, committed.len(), |key| key[0] > 1),
+            3
+        );
+        assert_eq!(pending.count("other", 7, |_| true), 7);
+
+        // Only the keys without pending changes are read from the committed entries
+        let keys = [&[1][..], &[3][..], &[4][..], &[5][..]];
+        let values = pending.get_multiple(MAIN_TABLE, &keys, |unchanged| {
+            assert_eq!(unchanged, &keys[2..]);
+            Ok(vec![Some(vec![4]), None])
+        });
+        assert_eq!(
+            values.unwrap(),
+            vec![Some(vec![10]), None, Some(vec![4]), None]
+        );
+
+        let entries: Vec<_> = pending.cursor(MAIN_TABLE, committed.into_iter()).collect();
+        assert_eq!(
+            entries,
+            vec![(vec![1], vec![10]), (vec![2], vec![20]), (vec![4], vec![4])]
+        );
+    }
+}
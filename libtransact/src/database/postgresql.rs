@@ -0,0 +1,729 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! A PostgreSQL implementation of the database traits.
+//!
+//! Entries are stored in a single table, in which every entry is keyed by the name of its index
+//! (empty for the main database) and its key.  PostgreSQL compares `bytea` values bytewise, so
+//! cursors iterate in the same natural key order as the other implementations.
+//!
+//! Connections are drawn from a pool.  Each reader holds a read-only, repeatable-read transaction,
+//! and therefore sees a consistent snapshot for its lifetime; the statements it runs are prepared
+//! once per reader.  Writers are serialized by an advisory lock, taken for the duration of the
+//! writer's transaction; the transaction is applied by `commit` and rolled back if the writer is
+//! dropped.
+//!
+//! Connections are encrypted according to the `TlsMode` given when the database is opened; managed
+//! PostgreSQL services generally require `TlsMode::Require`, with an OpenSSL `SslConnector`
+//! configured with the service's certificate authority.
+//!
+//! The schema is created and upgraded when the database is opened.  Each migration is applied at
+//! most once, and the applied versions are recorded in the `transact_schema_migrations` table.
+//!
+//! This module is only available with the `postgresql` feature.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use openssl::ssl::SslConnector;
+use postgres::config::SslMode;
+use postgres::types::ToSql;
+use postgres::{Client, Config, NoTls, Row, Statement};
+use postgres_openssl::MakeTlsConnector;
+use r2d2::{Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
+
+use crate::database::error::DatabaseError;
+use crate::database::{
+    Database, DatabaseCursor, DatabaseReader, DatabaseReaderCursor, DatabaseWriter,
+};
+
+/// The schema migrations, in order.  Migration `n` (counting from 1) upgrades the schema from
+/// version `n - 1` to version `n`.  Existing migrations must never be changed.
+const MIGRATIONS: &[&str] = &["CREATE TABLE transact_entries (
+        idx TEXT NOT NULL,
+        key BYTEA NOT NULL,
+        value BYTEA NOT NULL,
+        PRIMARY KEY (idx, key)
+    )"];
+
+/// The advisory lock key held while migrating the schema.
+const MIGRATION_LOCK: i64 = 0x7472_616e_7361_6374;
+
+/// The advisory lock key held by a writer for the duration of its transaction.
+const WRITER_LOCK: i64 = 0x7472_616e_7361_6375;
+
+/// The index name under which entries of the main database are stored.
+const MAIN_INDEX: &str = "";
+
+/// The number of entries a cursor loads from the database at a time.
+const CURSOR_PAGE_SIZE: i64 = 256;
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+type Params<'p> = &'p [&'p (dyn ToSql + Sync)];
+
+/// Whether connections to the database are encrypted.
+#[derive(Clone)]
+pub enum TlsMode {
+    /// Connections are not encrypted.
+    None,
+    /// Connections must be encrypted, and are established with the given connector.
+    Require(SslConnector),
+}
+
+#[derive(Clone)]
+enum ConnectionPool {
+    Plain(Pool<PostgresConnectionManager<NoTls>>),
+    Tls(Pool<PostgresConnectionManager<MakeTlsConnector>>),
+}
+
+impl ConnectionPool {
+    fn new(config: Config, tls_mode: TlsMode, max_size: u32) -> Result<Self, r2d2::Error> {
+        match tls_mode {
+            TlsMode::None => Pool::builder()
+                .max_size(max_size)
+                .build(PostgresConnectionManager::new(config, NoTls))
+                .map(ConnectionPool::Plain),
+            TlsMode::Require(connector) => {
+                let mut config = config;
+                config.ssl_mode(SslMode::Require);
+                Pool::builder()
+                    .max_size(max_size)
+                    .build(PostgresConnectionManager::new(
+                        config,
+                        MakeTlsConnector::new(connector),
+                    ))
+                    .map(ConnectionPool::Tls)
+            }
+        }
+    }
+
+    fn get(&self) -> Result<PooledClient, r2d2::Error> {
+        match self {
+            ConnectionPool::Plain(pool) => pool.get().map(PooledClient::Plain),
+            ConnectionPool::Tls(pool) => pool.get().map(PooledClient::Tls),
+        }
+    }
+}
+
+enum PooledClient {
+    Plain(PooledConnection<PostgresConnectionManager<NoTls>>),
+    Tls(PooledConnection<PostgresConnectionManager<MakeTlsConnector>>),
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        match self {
+            PooledClient::Plain(client) => client,
+            PooledClient::Tls(client) => client,
+        }
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        match self {
+            PooledClient::Plain(client) => client,
+            PooledClient::Tls(client) => client,
+        }
+    }
+}
+
+/// A pooled connection with an open transaction, and the statements prepared on it.
+struct Session {
+    client: PooledClient,
+    statements: HashMap<&'static str, Statement>,
+}
+
+impl Session {
+    fn statement(&mut self, sql: &'static str) -> Result<Statement, postgres::Error> {
+        if let Some(statement) = self.statements.get(sql) {
+            return Ok(statement.clone());
+        }
+        let statement = self.client.prepare(sql)?;
+        self.statements.insert(sql, statement.clone());
+        Ok(statement)
+    }
+
+    fn query(&mut self, sql: &'static str, params: Params) -> Result<Vec<Row>, postgres::Error> {
+        let statement = self.statement(sql)?;
+        self.client.query(&statement, params)
+    }
+
+    fn execute(&mut self, sql: &'static str, params: Params) -> Result<u64, postgres::Error> {
+        let statement = self.statement(sql)?;
+        self.client.execute(&statement, params)
+    }
+}
+
+#[derive(Clone)]
+pub struct PostgresDatabase {
+    pool: ConnectionPool,
+    indexes: Arc<HashSet<String>>,
+}
+
+impl PostgresDatabase {
+    /// Connects to the database at the given URL, with a pool of at most `max_connections`
+    /// connections using the given TLS mode, and migrates its schema to the current version.
+    pub fn new(
+        url: &str,
+        tls_mode: TlsMode,
+        indexes: &[&str],
+        max_connections: u32,
+    ) -> Result<Self, DatabaseError> {
+        let mut index_names = HashSet::with_capacity(indexes.len());
+        for name in indexes {
+            if *name == MAIN_INDEX {
+                return Err(DatabaseError::InitError(
+                    "Index names must not be empty".into(),
+                ));
+            }
+            index_names.insert(name.to_string());
+        }
+
+        let config = url
+            .parse::<Config>()
+            .map_err(|err| DatabaseError::InitError(format!("Invalid database URL: {}", err)))?;
+        let pool = ConnectionPool::new(config, tls_mode, max_connections).map_err(|err| {
+            DatabaseError::InitError(format!("Unable to connect to database: {}", err))
+        })?;
+
+        let mut client = pool.get().map_err(|err| {
+            DatabaseError::InitError(format!("Unable to connect to database: {}", err))
+        })?;
+        migrate(&mut client)?;
+
+        Ok(PostgresDatabase {
+            pool,
+            indexes: Arc::new(index_names),
+        })
+    }
+
+    pub fn reader(&self) -> Result<PostgresDatabaseReader, DatabaseError> {
+        let to_error = |err: &dyn std::fmt::Display| DatabaseError::ReaderError(format!("{}", err));
+
+        let mut client = self.pool.get().map_err(|err| to_error(&err))?;
+        client
+            .batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY")
+            .map_err(|err| to_error(&err))?;
+        Ok(PostgresDatabaseReader::new(self, client))
+    }
+
+    pub fn writer(&self) -> Result<PostgresDatabaseWriter, DatabaseError> {
+        let to_error = |err: &dyn std::fmt::Display| DatabaseError::WriterError(format!("{}", err));
+
+        let mut client = self.pool.get().map_err(|err| to_error(&err))?;
+        client
+            .batch_execute(&format!(
+                "BEGIN ISOLATION LEVEL READ COMMITTED; SELECT pg_advisory_xact_lock({})",
+                WRITER_LOCK
+            ))
+            .map_err(|err| to_error(&err))?;
+        Ok(PostgresDatabaseWriter {
+            reader: PostgresDatabaseReader::new(self, client),
+        })
+    }
+
+    fn index_name<'i>(
+        &self,
+        index: &'i str,
+        to_error: fn(String) -> DatabaseError,
+    ) -> Result<&'i str, DatabaseError> {
+        if self.indexes.contains(index) {
+            Ok(index)
+        } else {
+            Err(to_error(format!("Not an index: {}", index)))
+        }
+    }
+}
+
+impl Database for PostgresDatabase {
+    fn get_reader<'a>(&'a self) -> Result<Box<dyn DatabaseReader + 'a>, DatabaseError> {
+        Ok(Box::new(self.reader()?))
+    }
+
+    fn get_writer<'a>(&'a self) -> Result<Box<dyn DatabaseWriter + 'a>, DatabaseError> {
+        Ok(Box::new(self.writer()?))
+    }
+
+    fn clone_box(&self) -> Box<dyn Database> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+/// Applies any migrations that have not yet been applied to the database.
+fn migrate(client: &mut Client) -> Result<(), DatabaseError> {
+    let to_error =
+        |err: postgres::Error| DatabaseError::InitError(format!("Unable to migrate: {}", err));
+
+    let mut txn = client.transaction().map_err(to_error)?;
+    txn.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+        .map_err(to_error)?;
+    txn.batch_execute(
+        "CREATE TABLE IF NOT EXISTS transact_schema_migrations (version INTEGER PRIMARY KEY)",
+    )
+    .map_err(to_error)?;
+
+    let version: i32 = txn
+        .query_one(
+            "SELECT COALESCE(MAX(version), 0) FROM transact_schema_migrations",
+            &[],
+        )
+        .map_err(to_error)?
+        .get(0);
+    if version as usize > MIGRATIONS.len() {
+        return Err(DatabaseError::InitError(format!(
+            "Database schema version {} is newer than the supported version {}",
+            version,
+            MIGRATIONS.len()
+        )));
+    }
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        debug!("Applying database schema migration {}", i + 1);
+        txn.batch_execute(migration).map_err(to_error)?;
+        txn.execute(
+            "INSERT INTO transact_schema_migrations (version) VALUES ($1)",
+            &[&(i as i32 + 1)],
+        )
+        .map_err(to_error)?;
+    }
+
+    txn.commit().map_err(to_error)
+}
+
+pub struct PostgresDatabaseReader<'a> {
+    database: &'a PostgresDatabase,
+    session: RefCell<Option<Session>>,
+}
+
+impl<'a> PostgresDatabaseReader<'a> {
+    fn new(database: &'a PostgresDatabase, client: PooledClient) -> Self {
+        PostgresDatabaseReader {
+            database,
+            session: RefCell::new(Some(Session {
+                client,
+                statements: HashMap::new(),
+            })),
+        }
+    }
+
+    fn query(&self, sql: &'static str, params: Params) -> Result<Vec<Row>, DatabaseError> {
+        with_session(&self.session, |session| session.query(sql, params))
+            .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))
+    }
+
+    fn get_entry(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let rows = self.query(
+            "SELECT value FROM transact_entries WHERE idx = $1 AND key = $2",
+            &[&index, &key],
+        )?;
+        Ok(rows.first().map(|row| row.get(0)))
+    }
+
+    fn get_entries(
+        &self,
+        index: &str,
+        keys: &[&[u8]],
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        let rows = self.query(
+            "SELECT key, value FROM transact_entries WHERE idx = $1 AND key = ANY($2)",
+            &[&index, &keys],
+        )?;
+        let found: HashMap<Vec<u8>, Vec<u8>> =
+            rows.iter().map(|row| (row.get(0), row.get(1))).collect();
+        Ok(keys.iter().map(|key| found.get(*key).cloned()).collect())
+    }
+
+    fn count_entries(&self, index: &str) -> Result<usize, DatabaseError> {
+        let rows = self.query(
+            "SELECT COUNT(*) FROM transact_entries WHERE idx = $1",
+            &[&index],
+        )?;
+        Ok(rows
+            .first()
+            .map(|row| row.get::<_, i64>(0) as usize)
+            .unwrap_or(0))
+    }
+
+    fn entry_cursor(&self, index: &str) -> DatabaseCursor {
+        Box::new(PostgresDatabaseCursor {
+            session: &self.session,
+            index: index.to_string(),
+            page: VecDeque::new(),
+            last_key: None,
+            exhausted: false,
+        })
+    }
+}
+
+/// Runs the given function against the session, which is held until its transaction ends.
+fn with_session<T, F>(session: &RefCell<Option<Session>>, f: F) -> Result<T, postgres::Error>
+where
+    F: FnOnce(&mut Session) -> Result<T, postgres::Error>,
+{
+    f(session
+        .borrow_mut()
+        .as_mut()
+        .expect("The connection is held until the transaction ends"))
+}
+
+impl<'a> DatabaseReader for PostgresDatabaseReader<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.get_entry(MAIN_INDEX, key) {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Unable to read from PostgreSQL database: {}", err);
+                None
+            }
+        }
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        self.get_entries(MAIN_INDEX, keys)
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let index = self
+            .database
+            .index_name(index, DatabaseError::ReaderError)?;
+        self.get_entry(index, key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        Ok(self.entry_cursor(MAIN_INDEX))
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        let index = self
+            .database
+            .index_name(index, DatabaseError::ReaderError)?;
+        Ok(self.entry_cursor(index))
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.count_entries(MAIN_INDEX)
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        let index = self
+            .database
+            .index_name(index, DatabaseError::ReaderError)?;
+        self.count_entries(index)
+    }
+}
+
+impl<'a> Drop for PostgresDatabaseReader<'a> {
+    fn drop(&mut self) {
+        if let Some(mut session) = self.session.get_mut().take() {
+            if let Err(err) = session.client.batch_execute("ROLLBACK") {
+                error!("Unable to end PostgreSQL transaction: {}", err);
+            }
+        }
+    }
+}
+
+/// A cursor over one index of the database, loading its entries a page at a time.
+pub struct PostgresDatabaseCursor<'a> {
+    session: &'a RefCell<Option<Session>>,
+    index: String,
+    page: VecDeque<Entry>,
+    last_key: Option<Vec<u8>>,
+    exhausted: bool,
+}
+
+impl<'a> PostgresDatabaseCursor<'a> {
+    fn load_page(&mut self) -> Result<(), postgres::Error> {
+        let entries = match self.last_key {
+            Some(ref last_key) => query_entries(
+                self.session,
+                "SELECT key, value FROM transact_entries \
+                 WHERE idx = $1 AND key > $2 ORDER BY key LIMIT $3",
+                &[&self.index, last_key, &CURSOR_PAGE_SIZE],
+            )?,
+            None => query_entries(
+                self.session,
+                "SELECT key, value FROM transact_entries WHERE idx = $1 ORDER BY key LIMIT $2",
+                &[&self.index, &CURSOR_PAGE_SIZE],
+            )?,
+        };
+
+        if (entries.len() as i64) < CURSOR_PAGE_SIZE {
+            self.exhausted = true;
+        }
+        if let Some((key, _)) = entries.last() {
+            self.last_key = Some(key.clone());
+        }
+        self.page.extend(entries);
+
+        Ok(())
+    }
+
+    fn end_entry(&self, sql: &'static str) -> Option<Entry> {
+        match query_entries(self.session, sql, &[&self.index]) {
+            Ok(mut entries) => entries.pop(),
+            Err(err) => {
+                error!("Unable to read from PostgreSQL database: {}", err);
+                None
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for PostgresDatabaseCursor<'a> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.exhausted {
+            if let Err(err) = self.load_page() {
+                error!("Unable to read from PostgreSQL database: {}", err);
+                self.exhausted = true;
+            }
+        }
+        self.page.pop_front()
+    }
+}
+
+impl<'a> DatabaseReaderCursor for PostgresDatabaseCursor<'a> {
+    fn first(&mut self) -> Option<Self::Item> {
+        self.end_entry(
+            "SELECT key, value FROM transact_entries WHERE idx = $1 ORDER BY key ASC LIMIT 1",
+        )
+    }
+
+    fn last(&mut self) -> Option<Self::Item> {
+        self.end_entry(
+            "SELECT key, value FROM transact_entries WHERE idx = $1 ORDER BY key DESC LIMIT 1",
+        )
+    }
+}
+
+fn query_entries(
+    session: &RefCell<Option<Session>>,
+    sql: &'static str,
+    params: Params,
+) -> Result<Vec<Entry>, postgres::Error> {
+    let rows = with_session(session, |session| session.query(sql, params))?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+pub struct PostgresDatabaseWriter<'a> {
+    reader: PostgresDatabaseReader<'a>,
+}
+
+impl<'a> PostgresDatabaseWriter<'a> {
+    /// Executes the given statement, returning the number of rows it modified.
+    fn modify(&self, sql: &'static str, params: Params) -> Result<u64, DatabaseError> {
+        with_session(&self.reader.session, |session| session.execute(sql, params))
+            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))
+    }
+
+    fn remove(&self, index: &str, key: &[u8]) -> Result<(), DatabaseError> {
+        let removed = self.modify(
+            "DELETE FROM transact_entries WHERE idx = $1 AND key = $2",
+            &[&index, &key],
+        )?;
+
+        if removed == 0 {
+            Err(DatabaseError::NotFoundError(format!(
+                "Key not found: {}",
+                ::hex::encode(key)
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn replace(&self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.modify(
+            "INSERT INTO transact_entries (idx, key, value) VALUES ($1, $2, $3) \
+             ON CONFLICT (idx, key) DO UPDATE SET value = EXCLUDED.value",
+            &[&index, &key, &value],
+        )
+        .map(|_| ())
+    }
+}
+
+impl<'a> DatabaseWriter for PostgresDatabaseWriter<'a> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        let inserted = self.modify(
+            "INSERT INTO transact_entries (idx, key, value) VALUES ($1, $2, $3) \
+             ON CONFLICT (idx, key) DO NOTHING",
+            &[&MAIN_INDEX, &key, &value],
+        )?;
+
+        if inserted == 0 {
+            Err(DatabaseError::DuplicateEntry)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn overwrite(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.replace(MAIN_INDEX, key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), DatabaseError> {
+        self.remove(MAIN_INDEX, key)
+    }
+
+    fn index_put(&mut self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        let index = self
+            .reader
+            .database
+            .index_name(index, DatabaseError::WriterError)?;
+        self.replace(index, key, value)
+    }
+
+    fn index_delete(&mut self, index: &str, key: &[u8]) -> Result<(), DatabaseError> {
+        let index = self
+            .reader
+            .database
+            .index_name(index, DatabaseError::WriterError)?;
+        self.remove(index, key)
+    }
+
+    fn commit(mut self: Box<Self>) -> Result<(), DatabaseError> {
+        let mut session = self
+            .reader
+            .session
+            .get_mut()
+            .take()
+            .ok_or_else(|| DatabaseError::WriterError("Transaction already ended".into()))?;
+        session
+            .client
+            .batch_execute("COMMIT")
+            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))
+    }
+
+    fn as_reader(&self) -> &dyn DatabaseReader {
+        &self.reader
+    }
+}
+
+impl<'a> DatabaseReader for PostgresDatabaseWriter<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.reader.get(key)
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        self.reader.get_multiple(keys)
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.reader.index_get(index, key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        self.reader.cursor()
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        self.reader.index_cursor(index)
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.reader.count()
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        self.reader.index_count(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    /// The environment variable naming the database used by these tests, which clear the
+    /// database's entries.  The tests require a database, so they are ignored unless run with
+    /// `--ignored`.
+    const TEST_URL_VAR: &str = "TRANSACT_POSTGRES_TEST_URL";
+
+    fn test_url() -> String {
+        env::var(TEST_URL_VAR)
+            .unwrap_or_else(|_| panic!("{} must be set to run the PostgreSQL tests", TEST_URL_VAR))
+    }
+
+    /// Exercises the basic operations of a PostgresDatabase, making assertions about the database
+    /// contents at each step.
+    #[test]
+    #[ignore]
+    fn test_postgres() {
+        let url = test_url();
+        let database = PostgresDatabase::new(&url, TlsMode::None, &["a", "b"], 4).unwrap();
+        database
+            .pool
+            .get()
+            .unwrap()
+            .batch_execute("DELETE FROM transact_entries")
+            .unwrap();
+
+        assert_eq!(database.reader().unwrap().count().unwrap(), 0);
+
+        // Writes are only visible to other readers once committed
+        let mut writer = database.writer().unwrap();
+        writer.put(&[3], &[4]).unwrap();
+        assert_eq!(writer.get(&[3]), Some(vec![4]));
+        assert!(database.reader().unwrap().get(&[3]).is_none());
+        Box::new(writer).commit().unwrap();
+        assert_eq!(database.reader().unwrap().get(&[3]), Some(vec![4]));
+
+        // Put does not replace an existing entry, but overwrite does
+        let mut writer = database.writer().unwrap();
+        match writer.put(&[3], &[5]) {
+            Err(DatabaseError::DuplicateEntry) => (),
+            res => panic!("Expected DuplicateEntry, got {:?}", res),
+        }
+        writer.overwrite(&[3], &[5]).unwrap();
+        Box::new(writer).commit().unwrap();
+
+        // A reader keeps its snapshot while later writes are committed
+        let reader = database.reader().unwrap();
+        assert_eq!(reader.get(&[3]), Some(vec![5]));
+        let mut writer = database.writer().unwrap();
+        writer.delete(&[3]).unwrap();
+        match writer.delete(&[3]) {
+            Err(DatabaseError::NotFoundError(_)) => (),
+            res => panic!("Expected NotFoundError, got {:?}", res),
+        }
+        writer.index_put("a", &[55], &[5]).unwrap();
+        assert!(writer.index_put("c", &[55], &[5]).is_err());
+        Box::new(writer).commit().unwrap();
+        assert_eq!(reader.get(&[3]), Some(vec![5]));
+        assert_eq!(reader.index_count("a").unwrap(), 0);
+        drop(reader);
+
+        let reader = database.reader().unwrap();
+        assert!(reader.get(&[3]).is_none());
+        assert_eq!(reader.index_get("a", &[55]).unwrap(), Some(vec![5]));
+        assert!(reader.index_get("b", &[55]).unwrap().is_none());
+        assert!(reader.get(&[55]).is_none());
+        assert_eq!(reader.index_count("a").unwrap(), 1);
+        assert_eq!(
+            reader.index_cursor("a").unwrap().collect::<Vec<_>>(),
+            vec![(vec![55], vec![5])]
+        );
+    }
+}
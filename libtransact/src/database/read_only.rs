@@ -0,0 +1,82 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! A database wrapper permitting only reads.
+//!
+//! `ReadOnlyDatabase` wraps any `Database`, rejecting the creation of writers with a
+//! `ReadOnlyError`, so that a tool given a database cannot write to it by mistake.  It does not
+//! change how the underlying database is opened; LMDB and SQLite may also be opened read-only
+//! themselves, with `LmdbContext::new_read_only` and `SqliteDatabase::open_read_only`, which
+//! additionally keeps the tool from taking the locks of writers.
+
+use super::error::DatabaseError;
+use super::{Database, DatabaseReader, DatabaseWriter};
+
+/// A database whose writers may not be created.
+#[derive(Clone)]
+pub struct ReadOnlyDatabase {
+    inner: Box<dyn Database>,
+}
+
+impl ReadOnlyDatabase {
+    pub fn new(inner: Box<dyn Database>) -> Self {
+        ReadOnlyDatabase { inner }
+    }
+}
+
+impl Database for ReadOnlyDatabase {
+    fn get_reader<'a>(&'a self) -> Result<Box<dyn DatabaseReader + 'a>, DatabaseError> {
+        self.inner.get_reader()
+    }
+
+    fn get_writer<'a>(&'a self) -> Result<Box<dyn DatabaseWriter + 'a>, DatabaseError> {
+        Err(DatabaseError::ReadOnlyError(
+            "Database was opened read-only".into(),
+        ))
+    }
+
+    fn clone_box(&self) -> Box<Database> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+
+    #[test]
+    // test that reads pass through the wrapper, and that writers are rejected
+    fn read_only_database() {
+        let inner = BTreeDatabase::new(&[]);
+        let mut writer = inner.get_writer().unwrap();
+        writer.put(b"key", b"value").unwrap();
+        writer.commit().unwrap();
+
+        let db = ReadOnlyDatabase::new(Box::new(inner));
+        assert_eq!(
+            db.get_reader().unwrap().get(b"key"),
+            Some(b"value".to_vec())
+        );
+        match db.get_writer() {
+            Err(DatabaseError::ReadOnlyError(_)) => (),
+            Err(err) => panic!("Expected ReadOnlyError, got {:?}", err),
+            Ok(_) => panic!("Expected ReadOnlyError, got a writer"),
+        };
+    }
+}
@@ -0,0 +1,787 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! A Redis implementation of the database traits.
+//!
+//! Each table (the main database, and each index) is stored in two Redis keys under a common
+//! prefix: a hash from each key to its value, and a sorted set of the keys, all with the same
+//! score so that they are ordered bytewise.  Several databases may share a Redis server by using
+//! different prefixes.
+//!
+//! Writers are serialized across every client sharing the prefix by a lock key, taken with
+//! `SET NX` when the writer is created and released by its commit.  A writer buffers its changes
+//! until `commit`, which sends them in a single `MULTI`/`EXEC` transaction; the lock key is
+//! watched, so if the lock expires and is taken by another writer before the commit, the commit
+//! fails without making any changes.  Cursors load their entries a page at a time, fetching
+//! each page's values in a single command.
+//!
+//! Redis does not provide snapshots, so unlike the other implementations, a reader observes
+//! changes committed during its lifetime; each individual read, and each commit, is atomic.  This
+//! implementation is therefore best suited to test clusters and ephemeral environments.
+//!
+//! This module is only available with the `redis` feature.
+
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use redis::{Client, Connection, RedisResult};
+
+use crate::database::error::DatabaseError;
+use crate::database::pending::{PendingWrites, MAIN_TABLE};
+use crate::database::{
+    Database, DatabaseCursor, DatabaseReader, DatabaseReaderCursor, DatabaseWriter,
+};
+
+/// The number of entries a cursor loads from the database at a time.
+const CURSOR_PAGE_SIZE: usize = 256;
+
+/// How long a writer's lock expires after, should its client fail to release it.
+const WRITER_LOCK_TTL: Duration = Duration::from_secs(30);
+
+/// How long creating a writer waits for another client's writer to be released.
+const WRITER_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+const WRITER_LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Deletes the lock key only if it still holds the given writer's token.
+const RELEASE_LOCK_SCRIPT: &str = "if redis.call('GET', KEYS[1]) == ARGV[1] then \
+                                   return redis.call('DEL', KEYS[1]) else return 0 end";
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+#[derive(Clone)]
+pub struct RedisDatabase {
+    client: Client,
+    prefix: String,
+    indexes: Arc<HashSet<String>>,
+    connections: Arc<Mutex<Vec<Connection>>>,
+    writer_lock: Arc<Mutex<()>>,
+}
+
+impl RedisDatabase {
+    /// Connects to the Redis server at the given URL, storing the database's entries under keys
+    /// beginning with the given prefix.
+    pub fn new(url: &str, prefix: &str, indexes: &[&str]) -> Result<Self, DatabaseError> {
+        let client = Client::open(url)
+            .map_err(|err| DatabaseError::InitError(format!("Invalid Redis URL: {}", err)))?;
+
+        let database = RedisDatabase {
+            client,
+            prefix: prefix.to_string(),
+            indexes: Arc::new(indexes.iter().map(|name| name.to_string()).collect()),
+            connections: Arc::new(Mutex::new(Vec::new())),
+            writer_lock: Arc::new(Mutex::new(())),
+        };
+
+        // Connect once, so that an unreachable server is reported here.
+        let conn = database.acquire(DatabaseError::InitError)?;
+        database.release(conn);
+
+        Ok(database)
+    }
+
+    pub fn reader(&self) -> Result<RedisDatabaseReader, DatabaseError> {
+        Ok(RedisDatabaseReader {
+            database: self,
+            conn: Some(RefCell::new(self.acquire(DatabaseError::ReaderError)?)),
+        })
+    }
+
+    pub fn writer(&self) -> Result<RedisDatabaseWriter, DatabaseError> {
+        let lock = self
+            .writer_lock
+            .lock()
+            .map_err(|_| DatabaseError::WriterError("Writer lock was poisoned".into()))?;
+        let mut conn = self.acquire(DatabaseError::WriterError)?;
+        let token = uuid::Uuid::new_v4().to_string();
+        if let Err(err) = self.lock(&mut conn, &token) {
+            self.release(conn);
+            return Err(err);
+        }
+
+        Ok(RedisDatabaseWriter {
+            reader: RedisDatabaseReader {
+                database: self,
+                conn: Some(RefCell::new(conn)),
+            },
+            pending: PendingWrites::new(),
+            token,
+            committed: false,
+            _lock: lock,
+        })
+    }
+
+    /// Takes the lock shared by all writers using this prefix, waiting for any other writer to
+    /// release it, and watches it for the writer's commit.
+    fn lock(&self, conn: &mut Connection, token: &str) -> Result<(), DatabaseError> {
+        let lock_key = self.lock_key();
+        let deadline = Instant::now() + WRITER_LOCK_TIMEOUT;
+        loop {
+            let acquired: Option<String> = redis::cmd("SET")
+                .arg(&lock_key)
+                .arg(token)
+                .arg("NX")
+                .arg("PX")
+                .arg(WRITER_LOCK_TTL.as_millis() as u64)
+                .query(conn)
+                .map_err(|err| DatabaseError::WriterError(format!("{}", err)))?;
+
+            if acquired.is_some() {
+                return redis::cmd("WATCH")
+                    .arg(&lock_key)
+                    .query(conn)
+                    .map_err(|err| DatabaseError::WriterError(format!("{}", err)));
+            }
+            if Instant::now() >= deadline {
+                return Err(DatabaseError::WriterError(
+                    "Timed out waiting for another client's writer".into(),
+                ));
+            }
+            thread::sleep(WRITER_LOCK_RETRY_INTERVAL);
+        }
+    }
+
+    fn lock_key(&self) -> String {
+        format!("{}:writer-lock", self.prefix)
+    }
+
+    fn acquire(&self, to_error: fn(String) -> DatabaseError) -> Result<Connection, DatabaseError> {
+        let pooled = self
+            .connections
+            .lock()
+            .map_err(|_| to_error("Connection pool lock was poisoned".into()))?
+            .pop();
+
+        match pooled {
+            Some(conn) => Ok(conn),
+            None => self
+                .client
+                .get_connection()
+                .map_err(|err| to_error(format!("Unable to connect to Redis: {}", err))),
+        }
+    }
+
+    fn release(&self, conn: Connection) {
+        if let Ok(mut connections) = self.connections.lock() {
+            connections.push(conn);
+        }
+    }
+
+    /// Returns the Redis keys of the given table's values hash and keys sorted set.
+    fn table_keys(&self, table: &str) -> TableKeys {
+        let name = if table == MAIN_TABLE {
+            "main".to_string()
+        } else {
+            format!("index:{}", table)
+        };
+        TableKeys {
+            values: format!("{}:{}:values", self.prefix, name),
+            keys: format!("{}:{}:keys", self.prefix, name),
+        }
+    }
+
+    fn check_index(
+        &self,
+        index: &str,
+        to_error: fn(String) -> DatabaseError,
+    ) -> Result<(), DatabaseError> {
+        if self.indexes.contains(index) {
+            Ok(())
+        } else {
+            Err(to_error(format!("Not an index: {}", index)))
+        }
+    }
+}
+
+impl Database for RedisDatabase {
+    fn get_reader<'a>(&'a self) -> Result<Box<dyn DatabaseReader + 'a>, DatabaseError> {
+        Ok(Box::new(self.reader()?))
+    }
+
+    fn get_writer<'a>(&'a self) -> Result<Box<dyn DatabaseWriter + 'a>, DatabaseError> {
+        Ok(Box::new(self.writer()?))
+    }
+
+    fn clone_box(&self) -> Box<dyn Database> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+struct TableKeys {
+    values: String,
+    keys: String,
+}
+
+pub struct RedisDatabaseReader<'a> {
+    database: &'a RedisDatabase,
+    conn: Option<RefCell<Connection>>,
+}
+
+impl<'a> RedisDatabaseReader<'a> {
+    fn conn(&self) -> &RefCell<Connection> {
+        self.conn
+            .as_ref()
+            .expect("The connection is held until the reader is dropped")
+    }
+
+    fn get_entry(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        redis::cmd("HGET")
+            .arg(self.database.table_keys(table).values)
+            .arg(key)
+            .query(&mut *self.conn().borrow_mut())
+            .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))
+    }
+
+    /// Fetches the values of the given keys in a single `HMGET` command.
+    fn get_entries(
+        &self,
+        table: &str,
+        keys: &[&[u8]],
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        redis::cmd("HMGET")
+            .arg(self.database.table_keys(table).values)
+            .arg(keys)
+            .query(&mut *self.conn().borrow_mut())
+            .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))
+    }
+
+    fn contains_entry(&self, table: &str, key: &[u8]) -> Result<bool, DatabaseError> {
+        redis::cmd("HEXISTS")
+            .arg(self.database.table_keys(table).values)
+            .arg(key)
+            .query(&mut *self.conn().borrow_mut())
+            .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))
+    }
+
+    fn count_entries(&self, table: &str) -> Result<usize, DatabaseError> {
+        redis::cmd("ZCARD")
+            .arg(self.database.table_keys(table).keys)
+            .query(&mut *self.conn().borrow_mut())
+            .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))
+    }
+
+    fn entry_cursor(&self, table: &str) -> RedisDatabaseCursor {
+        RedisDatabaseCursor {
+            conn: self.conn(),
+            table_keys: self.database.table_keys(table),
+            page: VecDeque::new(),
+            last_key: None,
+            exhausted: false,
+        }
+    }
+}
+
+impl<'a> DatabaseReader for RedisDatabaseReader<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.get_entry(MAIN_TABLE, key) {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Unable to read from Redis: {}", err);
+                None
+            }
+        }
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        self.get_entries(MAIN_TABLE, keys)
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.database
+            .check_index(index, DatabaseError::ReaderError)?;
+        self.get_entry(index, key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        Ok(Box::new(self.entry_cursor(MAIN_TABLE)))
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        self.database
+            .check_index(index, DatabaseError::ReaderError)?;
+        Ok(Box::new(self.entry_cursor(index)))
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.count_entries(MAIN_TABLE)
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        self.database
+            .check_index(index, DatabaseError::ReaderError)?;
+        self.count_entries(index)
+    }
+}
+
+impl<'a> Drop for RedisDatabaseReader<'a> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.database.release(conn.into_inner());
+        }
+    }
+}
+
+/// A cursor over one table of the database, loading its entries a page at a time.
+pub struct RedisDatabaseCursor<'a> {
+    conn: &'a RefCell<Connection>,
+    table_keys: TableKeys,
+    page: VecDeque<Entry>,
+    last_key: Option<Vec<u8>>,
+    exhausted: bool,
+}
+
+impl<'a> RedisDatabaseCursor<'a> {
+    fn load_page(&mut self) -> RedisResult<()> {
+        // Lexicographical range bounds are the key, prefixed by "(" for an exclusive bound.
+        let start = match self.last_key {
+            Some(ref last_key) => {
+                let mut start = b"(".to_vec();
+                start.extend_from_slice(last_key);
+                start
+            }
+            None => b"-".to_vec(),
+        };
+
+        let mut conn = self.conn.borrow_mut();
+        let keys: Vec<Vec<u8>> = redis::cmd("ZRANGEBYLEX")
+            .arg(&self.table_keys.keys)
+            .arg(start)
+            .arg("+")
+            .arg("LIMIT")
+            .arg(0)
+            .arg(CURSOR_PAGE_SIZE)
+            .query(&mut *conn)?;
+
+        if keys.len() < CURSOR_PAGE_SIZE {
+            self.exhausted = true;
+        }
+        if let Some(key) = keys.last() {
+            self.last_key = Some(key.clone());
+        }
+
+        let entries = load_values(&mut conn, &self.table_keys, keys)?;
+        self.page.extend(entries);
+
+        Ok(())
+    }
+
+    fn end_entry(&self, rank: isize) -> Option<Entry> {
+        let mut conn = self.conn.borrow_mut();
+        let result = redis::cmd("ZRANGE")
+            .arg(&self.table_keys.keys)
+            .arg(rank)
+            .arg(rank)
+            .query(&mut *conn)
+            .and_then(|keys| load_values(&mut conn, &self.table_keys, keys));
+
+        match result {
+            Ok(mut entries) => entries.pop(),
+            Err(err) => {
+                error!("Unable to read from Redis: {}", err);
+                None
+            }
+        }
+    }
+}
+
+/// Fetches the values of the given keys in a single command, omitting any keys whose values have
+/// since been deleted.
+fn load_values(
+    conn: &mut Connection,
+    table_keys: &TableKeys,
+    keys: Vec<Vec<u8>>,
+) -> RedisResult<Vec<Entry>> {
+    if keys.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let values: Vec<Option<Vec<u8>>> = redis::cmd("HMGET")
+        .arg(&table_keys.values)
+        .arg(keys.as_slice())
+        .query(conn)?;
+
+    Ok(keys
+        .into_iter()
+        .zip(values)
+        .filter_map(|(key, value)| value.map(|value| (key, value)))
+        .collect())
+}
+
+impl<'a> Iterator for RedisDatabaseCursor<'a> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.page.is_empty() && !self.exhausted {
+            if let Err(err) = self.load_page() {
+                error!("Unable to read from Redis: {}", err);
+                self.exhausted = true;
+            }
+        }
+        self.page.pop_front()
+    }
+}
+
+impl<'a> DatabaseReaderCursor for RedisDatabaseCursor<'a> {
+    fn first(&mut self) -> Option<Self::Item> {
+        self.end_entry(0)
+    }
+
+    fn last(&mut self) -> Option<Self::Item> {
+        self.end_entry(-1)
+    }
+}
+
+pub struct RedisDatabaseWriter<'a> {
+    reader: RedisDatabaseReader<'a>,
+    pending: PendingWrites,
+    token: String,
+    committed: bool,
+    _lock: MutexGuard<'a, ()>,
+}
+
+impl<'a> RedisDatabaseWriter<'a> {
+    fn table_get(&self, table: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        match self.pending.get(table, key) {
+            Some(value) => Ok(value.cloned()),
+            None => self.reader.get_entry(table, key),
+        }
+    }
+
+    fn table_count(&self, table: &str) -> Result<usize, DatabaseError> {
+        let committed = self.reader.count_entries(table)?;
+        Ok(self.pending.count(table, committed, |key| {
+            // A key that cannot be read is treated as absent, as it is by `get`.
+            self.reader.contains_entry(table, key).unwrap_or(false)
+        }))
+    }
+
+    fn table_delete(&mut self, table: &str, key: &[u8]) -> Result<(), DatabaseError> {
+        if self.table_get(table, key)?.is_none() {
+            return Err(DatabaseError::NotFoundError(format!(
+                "Key not found: {}",
+                ::hex::encode(key)
+            )));
+        }
+        self.pending.delete(table, key);
+        Ok(())
+    }
+}
+
+impl<'a> DatabaseWriter for RedisDatabaseWriter<'a> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        if self.table_get(MAIN_TABLE, key)?.is_some() {
+            return Err(DatabaseError::DuplicateEntry);
+        }
+        self.pending.put(MAIN_TABLE, key, value);
+        Ok(())
+    }
+
+    fn overwrite(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.pending.put(MAIN_TABLE, key, value);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), DatabaseError> {
+        self.table_delete(MAIN_TABLE, key)
+    }
+
+    fn index_put(&mut self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.reader
+            .database
+            .check_index(index, DatabaseError::WriterError)?;
+        self.pending.put(index, key, value);
+        Ok(())
+    }
+
+    fn index_delete(&mut self, index: &str, key: &[u8]) -> Result<(), DatabaseError> {
+        self.reader
+            .database
+            .check_index(index, DatabaseError::WriterError)?;
+        self.table_delete(index, key)
+    }
+
+    fn commit(mut self: Box<Self>) -> Result<(), DatabaseError> {
+        let pending = std::mem::replace(&mut self.pending, PendingWrites::new());
+        let database = self.reader.database;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (table, changes) in pending.into_tables() {
+            let table_keys = database.table_keys(&table);
+            for (key, value) in changes {
+                match value {
+                    Some(value) => {
+                        pipe.cmd("HSET")
+                            .arg(&table_keys.values)
+                            .arg(&key[..])
+                            .arg(value)
+                            .ignore();
+                        pipe.cmd("ZADD")
+                            .arg(&table_keys.keys)
+                            .arg(0)
+                            .arg(key)
+                            .ignore();
+                    }
+                    None => {
+                        pipe.cmd("HDEL")
+                            .arg(&table_keys.values)
+                            .arg(&key[..])
+                            .ignore();
+                        pipe.cmd("ZREM").arg(&table_keys.keys).arg(key).ignore();
+                    }
+                }
+            }
+        }
+
+        pipe.cmd("DEL").arg(database.lock_key()).ignore();
+
+        // The transaction is aborted, returning nil, if the watched lock key has changed
+        let result = pipe.query::<Option<()>>(&mut *self.reader.conn().borrow_mut());
+        match result {
+            Ok(Some(())) => {
+                self.committed = true;
+                Ok(())
+            }
+            Ok(None) => Err(DatabaseError::WriterError(
+                "The writer's lock expired before it was committed".into(),
+            )),
+            Err(err) => Err(DatabaseError::WriterError(format!("{}", err))),
+        }
+    }
+
+    fn as_reader(&self) -> &dyn DatabaseReader {
+        self
+    }
+}
+
+impl<'a> Drop for RedisDatabaseWriter<'a> {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+
+        // Release the lock, and stop watching it before the connection is returned to the pool
+        let mut conn = self.reader.conn().borrow_mut();
+        let result = redis::cmd("UNWATCH").query::<()>(&mut *conn).and_then(|_| {
+            redis::cmd("EVAL")
+                .arg(RELEASE_LOCK_SCRIPT)
+                .arg(1)
+                .arg(self.reader.database.lock_key())
+                .arg(&self.token)
+                .query::<i64>(&mut *conn)
+        });
+        if let Err(err) = result {
+            warn!("Unable to release Redis writer lock: {}", err);
+        }
+    }
+}
+
+impl<'a> DatabaseReader for RedisDatabaseWriter<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.table_get(MAIN_TABLE, key) {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Unable to read from Redis: {}", err);
+                None
+            }
+        }
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        self.pending.get_multiple(MAIN_TABLE, keys, |keys| {
+            self.reader.get_entries(MAIN_TABLE, keys)
+        })
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.reader
+            .database
+            .check_index(index, DatabaseError::ReaderError)?;
+        self.table_get(index, key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        Ok(self
+            .pending
+            .cursor(MAIN_TABLE, self.reader.entry_cursor(MAIN_TABLE)))
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        self.reader
+            .database
+            .check_index(index, DatabaseError::ReaderError)?;
+        Ok(self.pending.cursor(index, self.reader.entry_cursor(index)))
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.table_count(MAIN_TABLE)
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        self.reader
+            .database
+            .check_index(index, DatabaseError::ReaderError)?;
+        self.table_count(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    /// The environment variable naming the Redis server used by these tests.  The tests require
+    /// a server, so they are ignored unless run with `--ignored`.
+    const TEST_URL_VAR: &str = "TRANSACT_REDIS_TEST_URL";
+
+    fn test_url() -> String {
+        env::var(TEST_URL_VAR)
+            .unwrap_or_else(|_| panic!("{} must be set to run the Redis tests", TEST_URL_VAR))
+    }
+
+    /// Exercises the basic operations of a RedisDatabase, making assertions about the database
+    /// contents at each step.
+    #[test]
+    #[ignore]
+    fn test_redis() {
+        let url = test_url();
+        let prefix = format!("transact-test-{}", uuid::Uuid::new_v4());
+        let database = RedisDatabase::new(&url, &prefix, &["a", "b"]).unwrap();
+
+        assert_eq!(database.reader().unwrap().count().unwrap(), 0);
+
+        // Writes are only visible to readers once committed
+        let mut writer = database.writer().unwrap();
+        for i in 0..(CURSOR_PAGE_SIZE as u16 + 1) {
+            writer.put(&i.to_be_bytes(), &[1]).unwrap();
+        }
+        assert_eq!(writer.get(&[0, 3]), Some(vec![1]));
+        assert!(database.reader().unwrap().get(&[0, 3]).is_none());
+        Box::new(writer).commit().unwrap();
+
+        let reader = database.reader().unwrap();
+        assert_eq!(reader.count().unwrap(), CURSOR_PAGE_SIZE + 1);
+        let keys: Vec<Vec<u8>> = reader.cursor().unwrap().map(|(key, _)| key).collect();
+        let expected: Vec<Vec<u8>> = (0..(CURSOR_PAGE_SIZE as u16 + 1))
+            .map(|i| i.to_be_bytes().to_vec())
+            .collect();
+        assert_eq!(keys, expected);
+        drop(reader);
+
+        // Put does not replace an existing entry, but overwrite does
+        let mut writer = database.writer().unwrap();
+        match writer.put(&[0, 3], &[5]) {
+            Err(DatabaseError::DuplicateEntry) => (),
+            res => panic!("Expected DuplicateEntry, got {:?}", res),
+        }
+        writer.overwrite(&[0, 3], &[5]).unwrap();
+        writer.delete(&[0, 4]).unwrap();
+        assert_eq!(
+            writer
+                .get_multiple(&[&[0, 3][..], &[0, 4][..], &[0, 5][..]])
+                .unwrap(),
+            vec![Some(vec![5]), None, Some(vec![1])]
+        );
+        match writer.delete(&[0, 4]) {
+            Err(DatabaseError::NotFoundError(_)) => (),
+            res => panic!("Expected NotFoundError, got {:?}", res),
+        }
+        writer.index_put("a", &[55], &[5]).unwrap();
+        assert!(writer.index_put("c", &[55], &[5]).is_err());
+        assert_eq!(writer.count().unwrap(), CURSOR_PAGE_SIZE);
+        Box::new(writer).commit().unwrap();
+
+        let reader = database.reader().unwrap();
+        assert_eq!(reader.get(&[0, 3]), Some(vec![5]));
+        assert!(reader.get(&[0, 4]).is_none());
+        assert_eq!(
+            reader
+                .get_multiple(&[&[0, 3][..], &[0, 4][..], &[0, 5][..]])
+                .unwrap(),
+            vec![Some(vec![5]), None, Some(vec![1])]
+        );
+        assert_eq!(reader.index_get("a", &[55]).unwrap(), Some(vec![5]));
+        assert!(reader.index_get("b", &[55]).unwrap().is_none());
+        assert_eq!(
+            reader.index_cursor("a").unwrap().collect::<Vec<_>>(),
+            vec![(vec![55], vec![5])]
+        );
+        drop(reader);
+
+        // Remove the test's entries
+        let mut writer = database.writer().unwrap();
+        for i in 0..(CURSOR_PAGE_SIZE as u16 + 1) {
+            let _ = writer.delete(&i.to_be_bytes());
+        }
+        writer.index_delete("a", &[55]).unwrap();
+        Box::new(writer).commit().unwrap();
+        assert_eq!(database.reader().unwrap().count().unwrap(), 0);
+    }
+
+    /// Increments a counter from two databases over the same Redis prefix, in separate threads
+    /// with no lock shared in the process, and checks that no increment is lost.
+    #[test]
+    #[ignore]
+    fn test_redis_concurrent_writers() {
+        const INCREMENTS: u64 = 50;
+
+        let url = test_url();
+        let prefix = format!("transact-test-{}", uuid::Uuid::new_v4());
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let database = RedisDatabase::new(&url, &prefix, &[]).unwrap();
+                thread::spawn(move || {
+                    for _ in 0..INCREMENTS {
+                        let mut writer = database.writer().unwrap();
+                        let count = writer.get(b"counter").map_or(0, |value| {
+                            let mut bytes = [0u8; 8];
+                            bytes.copy_from_slice(&value);
+                            u64::from_be_bytes(bytes)
+                        });
+                        writer
+                            .overwrite(b"counter", &(count + 1).to_be_bytes())
+                            .unwrap();
+                        Box::new(writer).commit().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let database = RedisDatabase::new(&url, &prefix, &[]).unwrap();
+        assert_eq!(
+            database.reader().unwrap().get(b"counter"),
+            Some((2 * INCREMENTS).to_be_bytes().to_vec())
+        );
+
+        // A writer dropped without committing releases the lock for the next writer
+        drop(database.writer().unwrap());
+        let mut writer = database.writer().unwrap();
+        writer.delete(b"counter").unwrap();
+        Box::new(writer).commit().unwrap();
+    }
+}
@@ -0,0 +1,684 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! A RocksDB implementation of the database traits.
+//!
+//! The main database is stored in RocksDB's default column family, and each index in a column
+//! family of the same name.  Readers operate on a snapshot, taken when the reader is created.
+//! Writers are serialized, and buffer their changes until `commit`, which applies them in a
+//! single atomic write batch.
+//!
+//! RocksDB does not track the number of entries in a column family, so counts are computed by
+//! iterating over the entries.
+//!
+//! This module is only available with the `rocksdb` feature.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rocksdb::{
+    ColumnFamily, ColumnFamilyDescriptor, DBCompactionStyle, DBIterator, IteratorMode, Options,
+    Snapshot, WriteBatch, DB,
+};
+
+use crate::database::error::DatabaseError;
+use crate::database::pending::{PendingWrites, MAIN_TABLE};
+use crate::database::{
+    Database, DatabaseCursor, DatabaseReader, DatabaseReaderCursor, DatabaseWriter,
+};
+
+/// The name of the column family that RocksDB creates for every database.
+const DEFAULT_COLUMN_FAMILY: &str = "default";
+
+/// The compaction strategies that RocksDB supports.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactionStyle {
+    /// Sorted runs are organized into levels of increasing size; the lowest write amplification
+    /// for reads, and RocksDB's default.
+    Level,
+    /// Sorted runs of similar size are merged together; lower write amplification, at the cost
+    /// of space and read amplification.
+    Universal,
+    /// The oldest files are deleted once the size limit is reached; only suited to caches.
+    Fifo,
+}
+
+/// Tuning options for a RocksDB database, applied to every column family.
+///
+/// Unset options keep RocksDB's defaults.
+#[derive(Clone, Debug, Default)]
+pub struct RocksDbOptions {
+    compaction_style: Option<CompactionStyle>,
+    write_buffer_size: Option<usize>,
+    max_write_buffer_number: Option<i32>,
+    target_file_size_base: Option<u64>,
+    level_zero_file_num_compaction_trigger: Option<i32>,
+    parallelism: Option<i32>,
+}
+
+impl RocksDbOptions {
+    pub fn with_compaction_style(mut self, compaction_style: CompactionStyle) -> Self {
+        self.compaction_style = Some(compaction_style);
+        self
+    }
+
+    /// Sets the size, in bytes, of a memtable before it is flushed to disk.
+    pub fn with_write_buffer_size(mut self, write_buffer_size: usize) -> Self {
+        self.write_buffer_size = Some(write_buffer_size);
+        self
+    }
+
+    /// Sets the number of memtables that may be held in memory at once.
+    pub fn with_max_write_buffer_number(mut self, max_write_buffer_number: i32) -> Self {
+        self.max_write_buffer_number = Some(max_write_buffer_number);
+        self
+    }
+
+    /// Sets the target size, in bytes, of files at the first level of level compaction.
+    pub fn with_target_file_size_base(mut self, target_file_size_base: u64) -> Self {
+        self.target_file_size_base = Some(target_file_size_base);
+        self
+    }
+
+    /// Sets the number of files at level zero that triggers a compaction.
+    pub fn with_level_zero_file_num_compaction_trigger(mut self, trigger: i32) -> Self {
+        self.level_zero_file_num_compaction_trigger = Some(trigger);
+        self
+    }
+
+    /// Sets the number of background threads used for flushes and compactions.
+    pub fn with_parallelism(mut self, parallelism: i32) -> Self {
+        self.parallelism = Some(parallelism);
+        self
+    }
+
+    fn to_options(&self) -> Options {
+        let mut options = Options::default();
+        if let Some(compaction_style) = self.compaction_style {
+            options.set_compaction_style(match compaction_style {
+                CompactionStyle::Level => DBCompactionStyle::Level,
+                CompactionStyle::Universal => DBCompactionStyle::Universal,
+                CompactionStyle::Fifo => DBCompactionStyle::Fifo,
+            });
+        }
+        if let Some(size) = self.write_buffer_size {
+            options.set_write_buffer_size(size);
+        }
+        if let Some(number) = self.max_write_buffer_number {
+            options.set_max_write_buffer_number(number);
+        }
+        if let Some(size) = self.target_file_size_base {
+            options.set_target_file_size_base(size);
+        }
+        if let Some(trigger) = self.level_zero_file_num_compaction_trigger {
+            options.set_level_zero_file_num_compaction_trigger(trigger);
+        }
+        if let Some(parallelism) = self.parallelism {
+            options.increase_parallelism(parallelism);
+        }
+        options
+    }
+}
+
+#[derive(Clone)]
+pub struct RocksDbDatabase {
+    db: Arc<DB>,
+    indexes: Arc<HashSet<String>>,
+    writer_lock: Arc<Mutex<()>>,
+}
+
+impl RocksDbDatabase {
+    /// Opens the database at the given path, creating it and any missing index column families
+    /// if necessary.
+    pub fn new(
+        path: &Path,
+        indexes: &[&str],
+        options: &RocksDbOptions,
+    ) -> Result<Self, DatabaseError> {
+        let mut index_names = HashSet::with_capacity(indexes.len());
+        for name in indexes {
+            if *name == MAIN_TABLE || *name == DEFAULT_COLUMN_FAMILY {
+                return Err(DatabaseError::InitError(format!(
+                    "Invalid index name: {:?}",
+                    name
+                )));
+            }
+            index_names.insert(name.to_string());
+        }
+
+        let mut db_options = options.to_options();
+        db_options.create_if_missing(true);
+        db_options.create_missing_column_families(true);
+
+        let column_families: Vec<_> = index_names
+            .iter()
+            .map(|name| ColumnFamilyDescriptor::new(name.as_str(), options.to_options()))
+            .collect();
+
+        let db = DB::open_cf_descriptors(&db_options, path, column_families)
+            .map_err(|err| DatabaseError::InitError(format!("{}", err)))?;
+
+        Ok(RocksDbDatabase {
+            db: Arc::new(db),
+            indexes: Arc::new(index_names),
+            writer_lock: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub fn reader(&self) -> RocksDbReader {
+        RocksDbReader {
+            database: self,
+            snapshot: self.db.snapshot(),
+        }
+    }
+
+    pub fn writer(&self) -> Result<RocksDbWriter, DatabaseError> {
+        let lock = self
+            .writer_lock
+            .lock()
+            .map_err(|_| DatabaseError::WriterError("Writer lock was poisoned".into()))?;
+        Ok(RocksDbWriter {
+            reader: self.reader(),
+            pending: PendingWrites::new(),
+            _lock: lock,
+        })
+    }
+
+    fn column_family(
+        &self,
+        index: &str,
+        to_error: fn(String) -> DatabaseError,
+    ) -> Result<&ColumnFamily, DatabaseError> {
+        if self.indexes.contains(index) {
+            self.db
+                .cf_handle(index)
+                .ok_or_else(|| to_error(format!("Missing column family: {}", index)))
+        } else {
+            Err(to_error(format!("Not an index: {}", index)))
+        }
+    }
+}
+
+impl Database for RocksDbDatabase {
+    fn get_reader<'a>(&'a self) -> Result<Box<dyn DatabaseReader + 'a>, DatabaseError> {
+        Ok(Box::new(self.reader()))
+    }
+
+    fn get_writer<'a>(&'a self) -> Result<Box<dyn DatabaseWriter + 'a>, DatabaseError> {
+        Ok(Box::new(self.writer()?))
+    }
+
+    fn clone_box(&self) -> Box<dyn Database> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+pub struct RocksDbReader<'a> {
+    database: &'a RocksDbDatabase,
+    snapshot: Snapshot<'a>,
+}
+
+impl<'a> RocksDbReader<'a> {
+    fn iterator(&self, column_family: Option<&ColumnFamily>, mode: IteratorMode) -> DBIterator {
+        match column_family {
+            Some(column_family) => self.snapshot.iterator_cf(column_family, mode),
+            None => self.snapshot.iterator(mode),
+        }
+    }
+
+    fn entries(
+        &self,
+        column_family: Option<&ColumnFamily>,
+    ) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        self.iterator(column_family, IteratorMode::Start)
+            .map(|(key, value)| (key.into_vec(), value.into_vec()))
+    }
+
+    fn get_entry(
+        &self,
+        column_family: Option<&ColumnFamily>,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let result = match column_family {
+            Some(column_family) => self.snapshot.get_cf(column_family, key),
+            None => self.snapshot.get(key),
+        };
+        result
+            .map(|value| value.map(|value| value.to_vec()))
+            .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))
+    }
+
+    fn get_entries(
+        &self,
+        column_family: Option<&ColumnFamily>,
+        keys: &[&[u8]],
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        // Snapshots do not provide a batched lookup, so each key is read from the snapshot in turn
+        keys.iter()
+            .map(|key| self.get_entry(column_family, key))
+            .collect()
+    }
+}
+
+impl<'a> DatabaseReader for RocksDbReader<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.get_entry(None, key) {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Unable to read from RocksDB database: {}", err);
+                None
+            }
+        }
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        self.get_entries(None, keys)
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let column_family = self
+            .database
+            .column_family(index, DatabaseError::ReaderError)?;
+        self.get_entry(Some(column_family), key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        Ok(Box::new(RocksDbCursor {
+            reader: self,
+            column_family: None,
+            iter: self.iterator(None, IteratorMode::Start),
+        }))
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        let column_family = self
+            .database
+            .column_family(index, DatabaseError::ReaderError)?;
+        Ok(Box::new(RocksDbCursor {
+            reader: self,
+            column_family: Some(column_family),
+            iter: self.iterator(Some(column_family), IteratorMode::Start),
+        }))
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        Ok(self.iterator(None, IteratorMode::Start).count())
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        let column_family = self
+            .database
+            .column_family(index, DatabaseError::ReaderError)?;
+        Ok(self
+            .iterator(Some(column_family), IteratorMode::Start)
+            .count())
+    }
+}
+
+pub struct RocksDbCursor<'a> {
+    reader: &'a RocksDbReader<'a>,
+    column_family: Option<&'a ColumnFamily>,
+    iter: DBIterator<'a>,
+}
+
+impl<'a> RocksDbCursor<'a> {
+    fn end_entry(&self, mode: IteratorMode) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.reader
+            .iterator(self.column_family, mode)
+            .next()
+            .map(|(key, value)| (key.into_vec(), value.into_vec()))
+    }
+}
+
+impl<'a> Iterator for RocksDbCursor<'a> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter
+            .next()
+            .map(|(key, value)| (key.into_vec(), value.into_vec()))
+    }
+}
+
+impl<'a> DatabaseReaderCursor for RocksDbCursor<'a> {
+    fn first(&mut self) -> Option<Self::Item> {
+        self.end_entry(IteratorMode::Start)
+    }
+
+    fn last(&mut self) -> Option<Self::Item> {
+        self.end_entry(IteratorMode::End)
+    }
+}
+
+pub struct RocksDbWriter<'a> {
+    reader: RocksDbReader<'a>,
+    pending: PendingWrites,
+    _lock: MutexGuard<'a, ()>,
+}
+
+impl<'a> RocksDbWriter<'a> {
+    fn table_get(
+        &self,
+        table: &str,
+        column_family: Option<&ColumnFamily>,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        match self.pending.get(table, key) {
+            Some(value) => Ok(value.cloned()),
+            None => self.reader.get_entry(column_family, key),
+        }
+    }
+
+    fn table_count(
+        &self,
+        table: &str,
+        column_family: Option<&ColumnFamily>,
+    ) -> Result<usize, DatabaseError> {
+        let committed = self
+            .reader
+            .iterator(column_family, IteratorMode::Start)
+            .count();
+        Ok(self.pending.count(table, committed, |key| {
+            // A key that cannot be read is treated as absent, as it is by `get`.
+            self.reader
+                .get_entry(column_family, key)
+                .map(|value| value.is_some())
+                .unwrap_or(false)
+        }))
+    }
+
+    fn table_delete(
+        &mut self,
+        table: &str,
+        column_family: Option<&ColumnFamily>,
+        key: &[u8],
+    ) -> Result<(), DatabaseError> {
+        if self.table_get(table, column_family, key)?.is_none() {
+            return Err(DatabaseError::NotFoundError(format!(
+                "Key not found: {}",
+                ::hex::encode(key)
+            )));
+        }
+        self.pending.delete(table, key);
+        Ok(())
+    }
+}
+
+impl<'a> DatabaseWriter for RocksDbWriter<'a> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        if self.table_get(MAIN_TABLE, None, key)?.is_some() {
+            return Err(DatabaseError::DuplicateEntry);
+        }
+        self.pending.put(MAIN_TABLE, key, value);
+        Ok(())
+    }
+
+    fn overwrite(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.pending.put(MAIN_TABLE, key, value);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), DatabaseError> {
+        self.table_delete(MAIN_TABLE, None, key)
+    }
+
+    fn index_put(&mut self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.reader
+            .database
+            .column_family(index, DatabaseError::WriterError)?;
+        self.pending.put(index, key, value);
+        Ok(())
+    }
+
+    fn index_delete(&mut self, index: &str, key: &[u8]) -> Result<(), DatabaseError> {
+        let column_family = self
+            .reader
+            .database
+            .column_family(index, DatabaseError::WriterError)?;
+        self.table_delete(index, Some(column_family), key)
+    }
+
+    fn commit(self: Box<Self>) -> Result<(), DatabaseError> {
+        let RocksDbWriter {
+            reader,
+            pending,
+            _lock,
+        } = *self;
+        let database = reader.database;
+        let to_error = |err: rocksdb::Error| DatabaseError::WriterError(format!("{}", err));
+
+        let mut batch = WriteBatch::default();
+        for (table, changes) in pending.into_tables() {
+            let column_family = if table == MAIN_TABLE {
+                None
+            } else {
+                Some(database.column_family(&table, DatabaseError::WriterError)?)
+            };
+            for (key, value) in changes {
+                match (column_family, value) {
+                    (Some(cf), Some(value)) => batch.put_cf(cf, key, value),
+                    (Some(cf), None) => batch.delete_cf(cf, key),
+                    (None, Some(value)) => batch.put(key, value),
+                    (None, None) => batch.delete(key),
+                }
+            }
+        }
+
+        // Release the snapshot before writing; the writer lock is held until the batch is written.
+        drop(reader);
+        database.db.write(batch).map_err(to_error)
+    }
+
+    fn as_reader(&self) -> &dyn DatabaseReader {
+        self
+    }
+}
+
+impl<'a> DatabaseReader for RocksDbWriter<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.table_get(MAIN_TABLE, None, key) {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Unable to read from RocksDB database: {}", err);
+                None
+            }
+        }
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        self.pending
+            .get_multiple(MAIN_TABLE, keys, |keys| self.reader.get_entries(None, keys))
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let column_family = self
+            .reader
+            .database
+            .column_family(index, DatabaseError::ReaderError)?;
+        self.table_get(index, Some(column_family), key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        Ok(self.pending.cursor(MAIN_TABLE, self.reader.entries(None)))
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        let column_family = self
+            .reader
+            .database
+            .column_family(index, DatabaseError::ReaderError)?;
+        Ok(self
+            .pending
+            .cursor(index, self.reader.entries(Some(column_family))))
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.table_count(MAIN_TABLE, None)
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        let column_family = self
+            .reader
+            .database
+            .column_family(index, DatabaseError::ReaderError)?;
+        self.table_count(index, Some(column_family))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs::remove_dir_all;
+    use std::panic;
+    use std::path::PathBuf;
+    use std::thread;
+
+    use crate::state::merkle::{MerkleRadixTree, INDEXES};
+    use crate::state::StateChange;
+
+    /// Exercises the basic operations of a RocksDbDatabase, making assertions about the database
+    /// contents at each step.
+    #[test]
+    fn test_rocksdb() {
+        run_test(|db_path| {
+            let options = RocksDbOptions::default()
+                .with_compaction_style(CompactionStyle::Universal)
+                .with_write_buffer_size(4 * 1024 * 1024);
+            let database = RocksDbDatabase::new(db_path, &["a", "b"], &options).unwrap();
+
+            assert_eq!(database.reader().count().unwrap(), 0);
+
+            // Writes are only visible to other readers once committed
+            let mut writer = database.writer().unwrap();
+            writer.put(&[3], &[4]).unwrap();
+            writer.put(&[5], &[6]).unwrap();
+            assert_eq!(writer.get(&[3]), Some(vec![4]));
+            assert_eq!(writer.count().unwrap(), 2);
+            assert!(database.reader().get(&[3]).is_none());
+            Box::new(writer).commit().unwrap();
+
+            let reader = database.reader();
+            assert_eq!(reader.get(&[3]), Some(vec![4]));
+            assert_eq!(reader.count().unwrap(), 2);
+            let mut cursor = reader.cursor().unwrap();
+            assert_eq!(
+                DatabaseReaderCursor::last(&mut *cursor),
+                Some((vec![5], vec![6]))
+            );
+            assert_eq!(cursor.next(), Some((vec![3], vec![4])));
+            drop(cursor);
+
+            // Put does not replace an existing entry, but overwrite does
+            let mut writer = database.writer().unwrap();
+            match writer.put(&[3], &[5]) {
+                Err(DatabaseError::DuplicateEntry) => (),
+                res => panic!("Expected DuplicateEntry, got {:?}", res),
+            }
+            writer.overwrite(&[3], &[5]).unwrap();
+            writer.delete(&[5]).unwrap();
+            writer.put(&[7], &[8]).unwrap();
+            writer.delete(&[7]).unwrap();
+            assert_eq!(
+                writer
+                    .get_multiple(&[&[3][..], &[5][..], &[7][..]])
+                    .unwrap(),
+                vec![Some(vec![5]), None, None]
+            );
+            match writer.delete(&[5]) {
+                Err(DatabaseError::NotFoundError(_)) => (),
+                res => panic!("Expected NotFoundError, got {:?}", res),
+            }
+            assert_eq!(
+                writer.cursor().unwrap().collect::<Vec<_>>(),
+                vec![(vec![3], vec![5])]
+            );
+            writer.index_put("a", &[55], &[5]).unwrap();
+            assert!(writer.index_put("c", &[55], &[5]).is_err());
+            Box::new(writer).commit().unwrap();
+
+            // The earlier reader keeps its snapshot
+            assert_eq!(reader.get(&[3]), Some(vec![4]));
+            assert_eq!(reader.index_count("a").unwrap(), 0);
+            drop(reader);
+
+            let reader = database.reader();
+            assert_eq!(reader.get(&[3]), Some(vec![5]));
+            assert!(reader.get(&[5]).is_none());
+            assert_eq!(
+                reader
+                    .get_multiple(&[&[3][..], &[4][..], &[5][..]])
+                    .unwrap(),
+                vec![Some(vec![5]), None, None]
+            );
+            assert_eq!(reader.index_get("a", &[55]).unwrap(), Some(vec![5]));
+            assert!(reader.index_get("b", &[55]).unwrap().is_none());
+            assert!(reader.get(&[55]).is_none());
+            assert_eq!(reader.index_count("a").unwrap(), 1);
+        })
+    }
+
+    /// Verifies that a state tree backed by RocksDB can be updated and read.
+    #[test]
+    fn test_rocksdb_merkle() {
+        run_test(|db_path| {
+            let database =
+                RocksDbDatabase::new(db_path, &INDEXES, &RocksDbOptions::default()).unwrap();
+            let mut merkle_db = MerkleRadixTree::new(Box::new(database), None).unwrap();
+
+            let state_root = merkle_db
+                .update(
+                    &[StateChange::Set {
+                        key: "ab0000".to_string(),
+                        value: b"0001".to_vec(),
+                    }],
+                    false,
+                )
+                .unwrap();
+            merkle_db.set_merkle_root(state_root).unwrap();
+
+            assert_eq!(
+                merkle_db.get_value("ab0000").unwrap(),
+                Some(b"0001".to_vec())
+            );
+        })
+    }
+
+    fn run_test<T>(test: T) -> ()
+    where
+        T: FnOnce(&Path) -> () + panic::UnwindSafe,
+    {
+        let db_path = temp_db_path();
+
+        let test_path = db_path.clone();
+        let result = panic::catch_unwind(move || test(&test_path));
+
+        remove_dir_all(&db_path).unwrap();
+
+        assert!(result.is_ok())
+    }
+
+    fn temp_db_path() -> PathBuf {
+        let mut temp_dir = env::temp_dir();
+
+        let thread_id = thread::current().id();
+        temp_dir.push(format!("rocksdb-{:?}", thread_id));
+        temp_dir
+    }
+}
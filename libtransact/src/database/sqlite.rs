@@ -0,0 +1,747 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! A SQLite implementation of the database traits.
+//!
+//! The database is stored in a single SQLite file, holding a single table in which every entry is
+//! keyed by the name of its index (empty for the main database) and its key.  SQLite compares
+//! blobs bytewise, so cursors iterate in the same natural key order as the other implementations.
+//!
+//! The file is opened in write-ahead-logging mode.  Each reader holds a read transaction, and
+//! therefore sees a consistent snapshot for its lifetime, while a writer holds the database's
+//! single write transaction, which is applied by `commit` and rolled back if the writer is dropped.
+//! Connections are pooled and reused across readers and writers.
+//!
+//! A database opened with `SqliteDatabase::open_read_only` uses read-only connections, so that
+//! tools such as backups may read the database of a running node without interfering with its
+//! writes; creating a writer fails with a `ReadOnlyError`.
+//!
+//! This module is only available with the `sqlite` feature.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection, ErrorCode, OpenFlags, OptionalExtension, NO_PARAMS};
+
+use crate::database::error::DatabaseError;
+use crate::database::{
+    Database, DatabaseCursor, DatabaseReader, DatabaseReaderCursor, DatabaseWriter,
+};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS transact_entries (
+    idx TEXT NOT NULL,
+    key BLOB NOT NULL,
+    value BLOB NOT NULL,
+    PRIMARY KEY (idx, key)
+) WITHOUT ROWID;";
+
+/// The index name under which entries of the main database are stored.
+const MAIN_INDEX: &str = "";
+
+/// The number of entries a cursor loads from the database at a time.
+const CURSOR_PAGE_SIZE: i64 = 256;
+
+/// The number of keys looked up per query by `get_multiple`, which keeps each query well below
+/// SQLite's default limit of 999 parameters.
+const MULTI_GET_CHUNK_SIZE: usize = 500;
+
+/// How long a connection waits on a locked database before failing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    path: PathBuf,
+    indexes: Arc<HashSet<String>>,
+    connections: Arc<Mutex<Vec<Connection>>>,
+    read_only: bool,
+}
+
+impl SqliteDatabase {
+    /// Opens the database file at the given path, creating it if it does not exist.
+    pub fn new(path: &Path, indexes: &[&str]) -> Result<Self, DatabaseError> {
+        let database = Self::open(path, indexes, false)?;
+
+        let conn = database.open_connection()?;
+        conn.execute_batch(SCHEMA)
+            .map_err(|err| DatabaseError::InitError(format!("Unable to create schema: {}", err)))?;
+        database.release(conn);
+
+        Ok(database)
+    }
+
+    /// Opens the existing database file at the given path for reading only.
+    pub fn open_read_only(path: &Path, indexes: &[&str]) -> Result<Self, DatabaseError> {
+        let database = Self::open(path, indexes, true)?;
+
+        // Open a connection now, so that a missing or unreadable file is reported here
+        let conn = database.open_connection()?;
+        database.release(conn);
+
+        Ok(database)
+    }
+
+    fn open(path: &Path, indexes: &[&str], read_only: bool) -> Result<Self, DatabaseError> {
+        let mut index_names = HashSet::with_capacity(indexes.len());
+        for name in indexes {
+            if *name == MAIN_INDEX {
+                return Err(DatabaseError::InitError(
+                    "Index names must not be empty".into(),
+                ));
+            }
+            index_names.insert(name.to_string());
+        }
+
+        Ok(SqliteDatabase {
+            path: path.to_path_buf(),
+            indexes: Arc::new(index_names),
+            connections: Arc::new(Mutex::new(Vec::new())),
+            read_only,
+        })
+    }
+
+    pub fn reader(&self) -> Result<SqliteDatabaseReader, DatabaseError> {
+        let conn = self.acquire()?;
+        conn.execute_batch("BEGIN DEFERRED")
+            .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))?;
+        Ok(SqliteDatabaseReader {
+            database: self,
+            conn: Some(conn),
+        })
+    }
+
+    pub fn writer(&self) -> Result<SqliteDatabaseWriter, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnlyError(
+                "SQLite database was opened read-only".into(),
+            ));
+        }
+        let conn = self.acquire()?;
+        conn.execute_batch("BEGIN IMMEDIATE")
+            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))?;
+        Ok(SqliteDatabaseWriter {
+            reader: SqliteDatabaseReader {
+                database: self,
+                conn: Some(conn),
+            },
+        })
+    }
+
+    fn open_connection(&self) -> Result<Connection, DatabaseError> {
+        let conn = if self.read_only {
+            Connection::open_with_flags(
+                &self.path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )
+        } else {
+            Connection::open(&self.path)
+        }
+        .map_err(|err| DatabaseError::InitError(format!("Unable to open database: {}", err)))?;
+        conn.busy_timeout(BUSY_TIMEOUT)
+            .map_err(|err| DatabaseError::InitError(format!("{}", err)))?;
+        if self.read_only {
+            // The journal mode is a property of the file, set by the writer
+            return Ok(conn);
+        }
+        // Setting the journal mode returns the resulting mode as a row, so it must be queried.
+        conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |row| {
+            row.get::<_, String>(0)
+        })
+        .map_err(|err| {
+            DatabaseError::InitError(format!("Unable to enable write-ahead logging: {}", err))
+        })?;
+        Ok(conn)
+    }
+
+    fn acquire(&self) -> Result<Connection, DatabaseError> {
+        let pooled = self
+            .connections
+            .lock()
+            .map_err(|_| DatabaseError::InitError("Connection pool lock was poisoned".into()))?
+            .pop();
+
+        match pooled {
+            Some(conn) => Ok(conn),
+            None => self.open_connection(),
+        }
+    }
+
+    fn release(&self, conn: Connection) {
+        if let Ok(mut connections) = self.connections.lock() {
+            connections.push(conn);
+        }
+    }
+
+    fn index_name<'i>(
+        &self,
+        index: &'i str,
+        to_error: fn(String) -> DatabaseError,
+    ) -> Result<&'i str, DatabaseError> {
+        if self.indexes.contains(index) {
+            Ok(index)
+        } else {
+            Err(to_error(format!("Not an index: {}", index)))
+        }
+    }
+}
+
+impl Database for SqliteDatabase {
+    fn get_reader<'a>(&'a self) -> Result<Box<dyn DatabaseReader + 'a>, DatabaseError> {
+        Ok(Box::new(self.reader()?))
+    }
+
+    fn get_writer<'a>(&'a self) -> Result<Box<dyn DatabaseWriter + 'a>, DatabaseError> {
+        Ok(Box::new(self.writer()?))
+    }
+
+    fn clone_box(&self) -> Box<dyn Database> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+pub struct SqliteDatabaseReader<'a> {
+    database: &'a SqliteDatabase,
+    conn: Option<Connection>,
+}
+
+impl<'a> SqliteDatabaseReader<'a> {
+    fn conn(&self) -> &Connection {
+        self.conn
+            .as_ref()
+            .expect("The connection is held until the transaction ends")
+    }
+
+    fn get_entry(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.conn()
+            .prepare_cached("SELECT value FROM transact_entries WHERE idx = ?1 AND key = ?2")
+            .and_then(|mut stmt| {
+                stmt.query_row(params![index, key], |row| row.get(0))
+                    .optional()
+            })
+            .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))
+    }
+
+    fn get_entries(
+        &self,
+        index: &str,
+        keys: &[&[u8]],
+    ) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        let mut found = HashMap::with_capacity(keys.len());
+        for chunk in keys.chunks(MULTI_GET_CHUNK_SIZE) {
+            let placeholders = (0..chunk.len())
+                .map(|i| format!("?{}", i + 2))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let sql = format!(
+                "SELECT key, value FROM transact_entries WHERE idx = ?1 AND key IN ({})",
+                placeholders
+            );
+            let mut params: Vec<&dyn ToSql> = Vec::with_capacity(chunk.len() + 1);
+            params.push(&index);
+            params.extend(chunk.iter().map(|key| key as &dyn ToSql));
+            found.extend(
+                query_entries(self.conn(), &sql, &params)
+                    .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))?,
+            );
+        }
+        Ok(keys.iter().map(|key| found.get(*key).cloned()).collect())
+    }
+
+    fn count_entries(&self, index: &str) -> Result<usize, DatabaseError> {
+        self.conn()
+            .prepare_cached("SELECT COUNT(*) FROM transact_entries WHERE idx = ?1")
+            .and_then(|mut stmt| stmt.query_row(params![index], |row| row.get::<_, i64>(0)))
+            .map(|count| count as usize)
+            .map_err(|err| DatabaseError::ReaderError(format!("{}", err)))
+    }
+
+    fn entry_cursor(&self, index: &str) -> DatabaseCursor {
+        Box::new(SqliteDatabaseCursor {
+            conn: self.conn(),
+            index: index.to_string(),
+            page: VecDeque::new(),
+            last_key: None,
+            exhausted: false,
+        })
+    }
+}
+
+impl<'a> DatabaseReader for SqliteDatabaseReader<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.get_entry(MAIN_INDEX, key) {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Unable to read from SQLite database: {}", err);
+                None
+            }
+        }
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        self.get_entries(MAIN_INDEX, keys)
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let index = self
+            .database
+            .index_name(index, DatabaseError::ReaderError)?;
+        self.get_entry(index, key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        Ok(self.entry_cursor(MAIN_INDEX))
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        let index = self
+            .database
+            .index_name(index, DatabaseError::ReaderError)?;
+        Ok(self.entry_cursor(index))
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.count_entries(MAIN_INDEX)
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        let index = self
+            .database
+            .index_name(index, DatabaseError::ReaderError)?;
+        self.count_entries(index)
+    }
+}
+
+impl<'a> Drop for SqliteDatabaseReader<'a> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            // A connection that cannot end its transaction is discarded rather than reused.
+            match conn.execute_batch("ROLLBACK") {
+                Ok(()) => self.database.release(conn),
+                Err(err) => error!("Unable to end SQLite transaction: {}", err),
+            }
+        }
+    }
+}
+
+/// A cursor over one index of the database, loading its entries a page at a time.
+pub struct SqliteDatabaseCursor<'a> {
+    conn: &'a Connection,
+    index: String,
+    page: VecDeque<Entry>,
+    last_key: Option<Vec<u8>>,
+    exhausted: bool,
+}
+
+impl<'a> SqliteDatabaseCursor<'a> {
+    fn load_page(&mut self) -> Result<(), rusqlite::Error> {
+        let entries = match self.last_key {
+            Some(ref last_key) => query_entries(
+                self.conn,
+                "SELECT key, value FROM transact_entries \
+                 WHERE idx = ?1 AND key > ?2 ORDER BY key LIMIT ?3",
+                params![self.index, last_key, CURSOR_PAGE_SIZE],
+            )?,
+            None => query_entries(
+                self.conn,
+                "SELECT key, value FROM transact_entries WHERE idx = ?1 ORDER BY key LIMIT ?2",
+                params![self.index, CURSOR_PAGE_SIZE],
+            )?,
+        };
+
+        if (entries.len() as i64) < CURSOR_PAGE_SIZE {
+            self.exhausted = true;
+        }
+        if let Some((key, _)) = entries.last() {
+            self.last_key = Some(key.clone());
+        }
+        self.page.extend(entries);
+
+        Ok(())
+    }
+
+    fn end_entry(&self, sql: &str) -> Option<Entry> {
+        match query_entries(self.conn, sql, params![self.index]) {
+            Ok(mut entries) => entries.pop(),
+            Err(err) => {
+                error!("Unable to read from SQLite database: {}", err);
+                None
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for SqliteDatabaseCursor<'a> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.exhausted {
+            if let Err(err) = self.load_page() {
+                error!("Unable to read from SQLite database: {}", err);
+                self.exhausted = true;
+            }
+        }
+        self.page.pop_front()
+    }
+}
+
+impl<'a> DatabaseReaderCursor for SqliteDatabaseCursor<'a> {
+    fn first(&mut self) -> Option<Self::Item> {
+        self.end_entry(
+            "SELECT key, value FROM transact_entries WHERE idx = ?1 ORDER BY key ASC LIMIT 1",
+        )
+    }
+
+    fn last(&mut self) -> Option<Self::Item> {
+        self.end_entry(
+            "SELECT key, value FROM transact_entries WHERE idx = ?1 ORDER BY key DESC LIMIT 1",
+        )
+    }
+}
+
+fn query_entries(
+    conn: &Connection,
+    sql: &str,
+    params: &[&dyn ToSql],
+) -> Result<Vec<Entry>, rusqlite::Error> {
+    let mut stmt = conn.prepare_cached(sql)?;
+    let rows = stmt.query_map(params, |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+pub struct SqliteDatabaseWriter<'a> {
+    reader: SqliteDatabaseReader<'a>,
+}
+
+impl<'a> SqliteDatabaseWriter<'a> {
+    fn insert(&self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.reader
+            .conn()
+            .prepare_cached("INSERT INTO transact_entries (idx, key, value) VALUES (?1, ?2, ?3)")
+            .and_then(|mut stmt| stmt.execute(params![index, key, value]))
+            .map(|_| ())
+            .map_err(|err| match err {
+                rusqlite::Error::SqliteFailure(ref failure, _)
+                    if failure.code == ErrorCode::ConstraintViolation =>
+                {
+                    DatabaseError::DuplicateEntry
+                }
+                _ => DatabaseError::WriterError(format!("{}", err)),
+            })
+    }
+
+    fn replace(&self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.reader
+            .conn()
+            .prepare_cached(
+                "INSERT OR REPLACE INTO transact_entries (idx, key, value) VALUES (?1, ?2, ?3)",
+            )
+            .and_then(|mut stmt| stmt.execute(params![index, key, value]))
+            .map(|_| ())
+            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))
+    }
+
+    fn remove(&self, index: &str, key: &[u8]) -> Result<(), DatabaseError> {
+        let removed = self
+            .reader
+            .conn()
+            .prepare_cached("DELETE FROM transact_entries WHERE idx = ?1 AND key = ?2")
+            .and_then(|mut stmt| stmt.execute(params![index, key]))
+            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))?;
+
+        if removed == 0 {
+            Err(DatabaseError::NotFoundError(format!(
+                "Key not found: {}",
+                ::hex::encode(key)
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> DatabaseWriter for SqliteDatabaseWriter<'a> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.insert(MAIN_INDEX, key, value)
+    }
+
+    fn overwrite(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        self.replace(MAIN_INDEX, key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), DatabaseError> {
+        self.remove(MAIN_INDEX, key)
+    }
+
+    fn index_put(&mut self, index: &str, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+        let index = self
+            .reader
+            .database
+            .index_name(index, DatabaseError::WriterError)?;
+        self.replace(index, key, value)
+    }
+
+    fn index_delete(&mut self, index: &str, key: &[u8]) -> Result<(), DatabaseError> {
+        let index = self
+            .reader
+            .database
+            .index_name(index, DatabaseError::WriterError)?;
+        self.remove(index, key)
+    }
+
+    fn commit(mut self: Box<Self>) -> Result<(), DatabaseError> {
+        let conn = self
+            .reader
+            .conn
+            .take()
+            .ok_or_else(|| DatabaseError::WriterError("Transaction already ended".into()))?;
+        conn.execute_batch("COMMIT")
+            .map_err(|err| DatabaseError::WriterError(format!("{}", err)))?;
+        self.reader.database.release(conn);
+        Ok(())
+    }
+
+    fn as_reader(&self) -> &dyn DatabaseReader {
+        &self.reader
+    }
+}
+
+impl<'a> DatabaseReader for SqliteDatabaseWriter<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.reader.get(key)
+    }
+
+    fn get_multiple(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+        self.reader.get_multiple(keys)
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.reader.index_get(index, key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        self.reader.cursor()
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        self.reader.index_cursor(index)
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        self.reader.count()
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        self.reader.index_count(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs::remove_file;
+    use std::panic;
+    use std::thread;
+
+    use crate::state::merkle::{MerkleRadixTree, INDEXES};
+    use crate::state::StateChange;
+
+    /// Exercises the basic operations of a SqliteDatabase, making assertions about the database
+    /// contents at each step.
+    #[test]
+    fn test_sqlite() {
+        run_test(|db_path| {
+            let database = SqliteDatabase::new(db_path, &["a", "b"]).unwrap();
+
+            assert_eq!(database.reader().unwrap().count().unwrap(), 0);
+
+            // Writes are only visible to other readers once committed
+            let mut writer = database.writer().unwrap();
+            writer.put(&[3], &[4]).unwrap();
+            assert_eq!(writer.get(&[3]), Some(vec![4]));
+            assert!(database.reader().unwrap().get(&[3]).is_none());
+            Box::new(writer).commit().unwrap();
+
+            let reader = database.reader().unwrap();
+            assert_eq!(reader.get(&[3]), Some(vec![4]));
+            assert_eq!(reader.count().unwrap(), 1);
+            drop(reader);
+
+            // Put does not replace an existing entry, but overwrite does
+            let mut writer = database.writer().unwrap();
+            match writer.put(&[3], &[5]) {
+                Err(DatabaseError::DuplicateEntry) => (),
+                res => panic!("Expected DuplicateEntry, got {:?}", res),
+            }
+            writer.overwrite(&[3], &[5]).unwrap();
+            Box::new(writer).commit().unwrap();
+            assert_eq!(database.reader().unwrap().get(&[3]), Some(vec![5]));
+
+            // A writer that is dropped rolls back its changes
+            let mut writer = database.writer().unwrap();
+            writer.delete(&[3]).unwrap();
+            drop(writer);
+            assert_eq!(database.reader().unwrap().get(&[3]), Some(vec![5]));
+
+            let mut writer = database.writer().unwrap();
+            writer.delete(&[3]).unwrap();
+            match writer.delete(&[3]) {
+                Err(DatabaseError::NotFoundError(_)) => (),
+                res => panic!("Expected NotFoundError, got {:?}", res),
+            }
+            Box::new(writer).commit().unwrap();
+            assert!(database.reader().unwrap().get(&[3]).is_none());
+
+            // Indexes are kept apart from the main database and from each other
+            let mut writer = database.writer().unwrap();
+            writer.index_put("a", &[55], &[5]).unwrap();
+            assert!(writer.index_put("c", &[55], &[5]).is_err());
+            Box::new(writer).commit().unwrap();
+
+            let reader = database.reader().unwrap();
+            assert_eq!(reader.index_get("a", &[55]).unwrap(), Some(vec![5]));
+            assert!(reader.index_get("b", &[55]).unwrap().is_none());
+            assert!(reader.get(&[55]).is_none());
+            assert_eq!(reader.index_count("a").unwrap(), 1);
+            assert_eq!(reader.index_count("b").unwrap(), 0);
+            assert!(reader.index_get("c", &[55]).is_err());
+        })
+    }
+
+    /// Verifies that cursors iterate in key order across page boundaries, and that a reader's
+    /// view is unaffected by writes committed during its lifetime.
+    #[test]
+    fn test_sqlite_cursor() {
+        run_test(|db_path| {
+            let database = SqliteDatabase::new(db_path, &[]).unwrap();
+
+            let entry_count = CURSOR_PAGE_SIZE as u16 * 2 + 1;
+            let mut writer = database.writer().unwrap();
+            for i in (0..entry_count).rev() {
+                writer.put(&i.to_be_bytes(), &[1]).unwrap();
+            }
+            Box::new(writer).commit().unwrap();
+
+            let reader = database.reader().unwrap();
+            let mut cursor = reader.cursor().unwrap();
+            assert_eq!(cursor.first().unwrap().0, 0u16.to_be_bytes().to_vec());
+            assert_eq!(
+                DatabaseReaderCursor::last(&mut *cursor).unwrap().0,
+                (entry_count - 1).to_be_bytes().to_vec()
+            );
+
+            let mut writer = database.writer().unwrap();
+            writer.put(&entry_count.to_be_bytes(), &[1]).unwrap();
+            Box::new(writer).commit().unwrap();
+
+            let keys: Vec<Vec<u8>> = cursor.map(|(key, _)| key).collect();
+            let expected: Vec<Vec<u8>> =
+                (0..entry_count).map(|i| i.to_be_bytes().to_vec()).collect();
+            assert_eq!(keys, expected);
+        })
+    }
+
+    /// Verifies that a state tree backed by SQLite can be updated and read.
+    #[test]
+    fn test_sqlite_merkle() {
+        run_test(|db_path| {
+            let database = SqliteDatabase::new(db_path, &INDEXES).unwrap();
+            let mut merkle_db = MerkleRadixTree::new(Box::new(database), None).unwrap();
+
+            let state_root = merkle_db
+                .update(
+                    &[StateChange::Set {
+                        key: "ab0000".to_string(),
+                        value: b"0001".to_vec(),
+                    }],
+                    false,
+                )
+                .unwrap();
+            merkle_db.set_merkle_root(state_root).unwrap();
+
+            assert_eq!(
+                merkle_db.get_value("ab0000").unwrap(),
+                Some(b"0001".to_vec())
+            );
+        })
+    }
+
+    /// Verifies that a database opened read-only may be read, but not written, while it is open
+    /// for writing elsewhere.
+    #[test]
+    fn test_sqlite_read_only() {
+        run_test(|db_path| {
+            let database = SqliteDatabase::new(db_path, &["a"]).unwrap();
+            let mut writer = database.writer().unwrap();
+            writer.put(b"key", b"value").unwrap();
+            Box::new(writer).commit().unwrap();
+
+            let read_only = SqliteDatabase::open_read_only(db_path, &["a"]).unwrap();
+            assert_eq!(
+                read_only.reader().unwrap().get(b"key"),
+                Some(b"value".to_vec())
+            );
+            match read_only.get_writer() {
+                Err(DatabaseError::ReadOnlyError(_)) => (),
+                Err(err) => panic!("Expected ReadOnlyError, got {:?}", err),
+                Ok(_) => panic!("Expected ReadOnlyError, got a writer"),
+            }
+
+            // Writes through the writable database are seen by new readers
+            let mut writer = database.writer().unwrap();
+            writer.put(b"other", b"value").unwrap();
+            Box::new(writer).commit().unwrap();
+            assert_eq!(read_only.reader().unwrap().count().unwrap(), 2);
+
+            let mut missing = db_path.to_path_buf();
+            missing.set_extension("missing");
+            assert!(SqliteDatabase::open_read_only(&missing, &[]).is_err());
+        })
+    }
+
+    fn run_test<T>(test: T) -> ()
+    where
+        T: FnOnce(&Path) -> () + panic::UnwindSafe,
+    {
+        let db_path = temp_db_path();
+
+        let test_path = db_path.clone();
+        let result = panic::catch_unwind(move || test(&test_path));
+
+        remove_file(&db_path).unwrap();
+        for suffix in &["-wal", "-shm"] {
+            let mut path = db_path.clone().into_os_string();
+            path.push(suffix);
+            let _ = remove_file(path);
+        }
+
+        assert!(result.is_ok())
+    }
+
+    fn temp_db_path() -> PathBuf {
+        let mut temp_dir = env::temp_dir();
+
+        let thread_id = thread::current().id();
+        temp_dir.push(format!("sqlite-{:?}.db", thread_id));
+        temp_dir
+    }
+}
@@ -258,6 +258,12 @@ impl<'a, 'b> TransactionContext for StaticContext<'a, 'b> {
         Ok(results)
     }
 
+    fn delete_state_entries_by_prefix(&self, prefix: &str) -> Result<Vec<String>, ContextError> {
+        self.context_manager
+            .delete_state_by_prefix(self.context_id, prefix)
+            .map_err(ContextError::from)
+    }
+
     fn add_receipt_data(&self, data: Vec<u8>) -> Result<(), ContextError> {
         self.context_manager
             .add_data(self.context_id, data)
@@ -285,8 +291,13 @@ impl<'a, 'b> TransactionContext for StaticContext<'a, 'b> {
 
 impl From<ContextManagerError> for ContextError {
     fn from(err: ContextManagerError) -> Self {
-        // Error's should be addressed in the handler::error module.
-        ContextError::SendError(Box::new(err))
+        match err {
+            ContextManagerError::AuthorizationError(address) => {
+                ContextError::AuthorizationError(address)
+            }
+            // Error's should be addressed in the handler::error module.
+            err => ContextError::SendError(Box::new(err)),
+        }
     }
 }
 
@@ -357,6 +368,67 @@ mod test {
         assert!(Box::new(static_adapter).stop().is_ok());
     }
 
+    /// Apply the static adapter with a transaction that emits an event and receipt data, and
+    /// check that both appear in the receipt produced by the context manager.
+    #[test]
+    fn apply_static_adapter_event_and_data() {
+        let registry = MockRegistry::default();
+
+        let state = HashMapState::new();
+        let state_id = HashMapState::state_id(&HashMap::new());
+
+        let mut context_manager: ContextManager = ContextManager::new(Box::new(state));
+
+        let handler = CommandTransactionHandler::new();
+
+        let mut static_adapter =
+            StaticExecutionAdapter::new_adapter(vec![Box::new(handler)], context_manager.clone())
+                .expect("Could not create adapter");
+
+        assert!(static_adapter.start(Box::new(registry.clone())).is_ok());
+
+        let txn_pair = make_command_transaction(&[
+            Command::AddEvent {
+                event_type: "test/event".into(),
+                attributes: vec![("key".into(), "value".into())],
+                data: b"event data".to_vec(),
+            },
+            Command::AddReceiptData {
+                data: b"receipt data".to_vec(),
+            },
+        ]);
+        let txn_id: String = txn_pair.transaction().header_signature().into();
+        let context_id = context_manager.create_context(&[], &state_id);
+
+        let (send, recv) = std::sync::mpsc::channel();
+        assert!(static_adapter
+            .execute(
+                txn_pair,
+                context_id.clone(),
+                Box::new(move |res| {
+                    send.send(res).expect("Unable to send result");
+                }),
+            )
+            .is_ok());
+        let result = recv.recv().unwrap();
+
+        assert_eq!(
+            ExecutionTaskCompletionNotification::Valid(context_id.clone(), txn_id.clone()),
+            result.unwrap()
+        );
+
+        let receipt = context_manager
+            .get_transaction_receipt(&context_id, &txn_id)
+            .expect("Unable to get receipt");
+        assert_eq!(1, receipt.events.len());
+        assert_eq!("test/event", receipt.events[0].event_type);
+        assert_eq!(Some("value"), receipt.events[0].attribute("key"));
+        assert_eq!(b"event data".to_vec(), receipt.events[0].data);
+        assert_eq!(vec![b"receipt data".to_vec()], receipt.data);
+
+        assert!(Box::new(static_adapter).stop().is_ok());
+    }
+
     /// Apply the static adapter with a failing transaction
     #[test]
     fn apply_static_adapter_invalid_txn() {
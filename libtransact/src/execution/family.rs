@@ -0,0 +1,262 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Descriptions of the transaction families known to a node.
+//!
+//! A `FamilyDescriptor` names a transaction family, the versions of it that are served, and the
+//! namespaces (hex address prefixes) its transactions may read and write.  A `FamilyRegistry`
+//! indexes descriptors by family name and version, so that executors and routing layers can
+//! answer which family serves a given transaction.
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+
+use crate::protocol::transaction::TransactionPair;
+
+use super::{ExecutionRegistry, TransactionFamily};
+
+/// The name, versions and namespaces of a transaction family.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FamilyDescriptor {
+    name: String,
+    versions: Vec<String>,
+    namespaces: Vec<String>,
+}
+
+impl FamilyDescriptor {
+    /// Constructs a descriptor.  An empty list of namespaces places no restriction on the
+    /// addresses the family's transactions may use.
+    pub fn new(name: String, versions: Vec<String>, namespaces: Vec<String>) -> Self {
+        FamilyDescriptor {
+            name,
+            versions,
+            namespaces,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn versions(&self) -> &[String] {
+        &self.versions
+    }
+
+    pub fn namespaces(&self) -> &[String] {
+        &self.namespaces
+    }
+
+    /// The transaction families, one per version, described by this descriptor.
+    pub fn transaction_families(&self) -> Vec<TransactionFamily> {
+        self.versions
+            .iter()
+            .map(|version| TransactionFamily::new(self.name.clone(), version.clone()))
+            .collect()
+    }
+
+    /// Returns true if the hex-encoded address lies within one of the family's namespaces.
+    pub fn covers_address(&self, address: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|ns| address.starts_with(ns))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FamilyRegistryError {
+    /// A descriptor is already registered for the given family name and version.
+    AlreadyRegistered(String),
+    /// No descriptor is registered for the transaction's family name and version.
+    UnknownFamily(String),
+    /// The transaction declares an input or output outside its family's namespaces.
+    AddressOutsideNamespace(String),
+}
+
+impl StdError for FamilyRegistryError {
+    fn description(&self) -> &str {
+        match *self {
+            FamilyRegistryError::AlreadyRegistered(ref msg) => msg,
+            FamilyRegistryError::UnknownFamily(ref msg) => msg,
+            FamilyRegistryError::AddressOutsideNamespace(ref msg) => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for FamilyRegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            FamilyRegistryError::AlreadyRegistered(ref s) => write!(f, "AlreadyRegistered: {}", s),
+            FamilyRegistryError::UnknownFamily(ref s) => write!(f, "UnknownFamily: {}", s),
+            FamilyRegistryError::AddressOutsideNamespace(ref s) => {
+                write!(f, "AddressOutsideNamespace: {}", s)
+            }
+        }
+    }
+}
+
+/// A registry of family descriptors, keyed by family name and version.
+///
+/// The registry also implements `ExecutionRegistry`, so it may be passed to an execution adapter
+/// to record the families the adapter's handlers serve.  Families registered that way have no
+/// namespace restrictions.
+#[derive(Debug, Default)]
+pub struct FamilyRegistry {
+    descriptors: HashMap<TransactionFamily, FamilyDescriptor>,
+}
+
+impl FamilyRegistry {
+    pub fn new() -> Self {
+        FamilyRegistry::default()
+    }
+
+    /// Registers the descriptor under each of its versions.
+    ///
+    /// Fails, without registering any version, if any of the versions is already registered.
+    pub fn register(&mut self, descriptor: FamilyDescriptor) -> Result<(), FamilyRegistryError> {
+        let families = descriptor.transaction_families();
+        if let Some(family) = families.iter().find(|f| self.descriptors.contains_key(f)) {
+            return Err(FamilyRegistryError::AlreadyRegistered(format!(
+                "{} {}",
+                family.family_name(),
+                family.family_version()
+            )));
+        }
+
+        for family in families {
+            self.descriptors.insert(family, descriptor.clone());
+        }
+        Ok(())
+    }
+
+    /// Removes the descriptor registered for the given family version, if any.
+    pub fn unregister(&mut self, family: &TransactionFamily) -> Option<FamilyDescriptor> {
+        self.descriptors.remove(family)
+    }
+
+    pub fn get(&self, family: &TransactionFamily) -> Option<&FamilyDescriptor> {
+        self.descriptors.get(family)
+    }
+
+    /// Returns the descriptor of the family that serves the transaction.
+    pub fn descriptor_for(&self, pair: &TransactionPair) -> Option<&FamilyDescriptor> {
+        self.get(&TransactionFamily::from_pair(pair))
+    }
+
+    /// Checks that the transaction's family is registered and that its declared inputs and
+    /// outputs lie within the family's namespaces.
+    pub fn check_transaction(
+        &self,
+        pair: &TransactionPair,
+    ) -> Result<&FamilyDescriptor, FamilyRegistryError> {
+        let descriptor = self.descriptor_for(pair).ok_or_else(|| {
+            FamilyRegistryError::UnknownFamily(format!(
+                "{} {}",
+                pair.header().family_name(),
+                pair.header().family_version()
+            ))
+        })?;
+
+        for address in pair.header().inputs().iter().chain(pair.header().outputs()) {
+            let address = hex::encode(address);
+            if !descriptor.covers_address(&address) {
+                return Err(FamilyRegistryError::AddressOutsideNamespace(format!(
+                    "{} is not in a namespace of {}",
+                    address,
+                    descriptor.name()
+                )));
+            }
+        }
+
+        Ok(descriptor)
+    }
+
+    /// The registered family versions.
+    pub fn families(&self) -> impl Iterator<Item = &TransactionFamily> {
+        self.descriptors.keys()
+    }
+}
+
+impl ExecutionRegistry for FamilyRegistry {
+    fn register_transaction_family(&mut self, family: TransactionFamily) {
+        let descriptor = FamilyDescriptor::new(
+            family.family_name().to_string(),
+            vec![family.family_version().to_string()],
+            vec![],
+        );
+        self.descriptors.entry(family).or_insert(descriptor);
+    }
+
+    fn unregister_transaction_family(&mut self, family: &TransactionFamily) {
+        self.descriptors.remove(family);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
+
+    fn make_pair(version: &str, address: Vec<u8>) -> TransactionPair {
+        TransactionBuilder::new()
+            .with_family_name("intkey".to_string())
+            .with_family_version(version.to_string())
+            .with_inputs(vec![address.clone()])
+            .with_outputs(vec![address])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(b"payload".to_vec())
+            .build_pair(&HashSigner::new())
+            .unwrap()
+    }
+
+    #[test]
+    // test that descriptors are found by family and version, and that namespaces are enforced
+    fn family_registry_lookup() {
+        let mut registry = FamilyRegistry::new();
+        registry
+            .register(FamilyDescriptor::new(
+                "intkey".to_string(),
+                vec!["1.0".to_string(), "1.1".to_string()],
+                vec!["1cf126".to_string()],
+            ))
+            .unwrap();
+
+        let pair = make_pair("1.1", vec![0x1c, 0xf1, 0x26, 0x01]);
+        assert_eq!("intkey", registry.check_transaction(&pair).unwrap().name());
+
+        let pair = make_pair("1.0", vec![0x00, 0x01]);
+        assert!(registry.descriptor_for(&pair).is_some());
+        match registry.check_transaction(&pair) {
+            Err(FamilyRegistryError::AddressOutsideNamespace(_)) => (),
+            res => panic!("expected AddressOutsideNamespace, got {:?}", res),
+        }
+
+        let pair = make_pair("2.0", vec![0x1c, 0xf1, 0x26, 0x01]);
+        assert!(registry.descriptor_for(&pair).is_none());
+
+        assert!(registry
+            .register(FamilyDescriptor::new(
+                "intkey".to_string(),
+                vec!["1.1".to_string()],
+                vec![],
+            ))
+            .is_err());
+
+        let family = TransactionFamily::new("intkey".to_string(), "1.0".to_string());
+        assert!(registry.unregister(&family).is_some());
+        assert_eq!(1, registry.families().count());
+    }
+}
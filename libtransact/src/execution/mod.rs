@@ -20,6 +20,7 @@
 
 pub mod adapter;
 pub mod executor;
+pub mod family;
 
 use crate::protocol::transaction::TransactionPair;
 
@@ -54,6 +54,8 @@ pub enum ContextError {
     SendError(Box<dyn Error>),
     /// Returned when an error is returned when sending a message
     ReceiveError(Box<dyn Error>),
+    /// Returned when the context does not support the requested operation
+    UnsupportedError(String),
 }
 
 impl Error for ContextError {
@@ -65,6 +67,7 @@ impl Error for ContextError {
             ContextError::SerializationError(err) => Some(&**err),
             ContextError::SendError(err) => Some(&**err),
             ContextError::ReceiveError(err) => Some(&**err),
+            ContextError::UnsupportedError(_) => None,
         }
     }
 }
@@ -84,6 +87,7 @@ impl std::fmt::Display for ContextError {
             }
             ContextError::SendError(ref err) => write!(f, "SendError: {}", err.description()),
             ContextError::ReceiveError(ref err) => write!(f, "ReceiveError: {}", err.description()),
+            ContextError::UnsupportedError(ref s) => write!(f, "UnsupportedError: {}", s),
         }
     }
 }
@@ -91,7 +95,7 @@ impl std::fmt::Display for ContextError {
 impl From<ContextError> for ApplyError {
     fn from(context_error: ContextError) -> Self {
         match context_error {
-            ContextError::TransactionReceiptError(..) => {
+            ContextError::TransactionReceiptError(..) | ContextError::UnsupportedError(..) => {
                 ApplyError::InternalError(format!("{}", context_error))
             }
             _ => ApplyError::InvalidTransaction(format!("{}", context_error)),
@@ -97,6 +97,24 @@ pub trait TransactionContext {
     /// * `addresses` - the addresses to delete
     fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError>;
 
+    /// delete_state_entries_by_prefix requests that every address starting with
+    /// the provided prefix be unset in validator state, including addresses
+    /// that have already been committed. A list of successfully deleted
+    /// addresses is returned, in address order.
+    ///
+    /// Contexts which cannot enumerate the addresses under a prefix return an
+    /// `UnsupportedError`.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - the prefix of the addresses to delete
+    fn delete_state_entries_by_prefix(&self, prefix: &str) -> Result<Vec<String>, ContextError> {
+        Err(ContextError::UnsupportedError(format!(
+            "Deleting the state entries under prefix {} is not supported by this context",
+            prefix
+        )))
+    }
+
     /// add_receipt_data adds a blob to the execution result for this transaction
     ///
     /// # Arguments
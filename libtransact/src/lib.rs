@@ -111,7 +111,48 @@
 //! ## Sawtooth Compatibility Layer
 //!
 //! Hyperledger Transact provides optional support for smart contract engines implemented for
-//! Hyperledger Sawtooth through the `sawtooth-compat` feature.
+//! Hyperledger Sawtooth through the `sawtooth-compat` feature.  The same feature provides
+//! conversions between Transact's transactions, batches and receipts and the Sawtooth SDK's
+//! messages.
+//!
+//! ## JSON Serialization
+//!
+//! The `serde` feature provides `Serialize` and `Deserialize` implementations for the protocol
+//! types, using a stable JSON representation with hex-encoded bytes.  The `receipt-cbor` feature
+//! adds a canonical CBOR encoding of transaction receipts.
+//!
+//! ## Asynchronous Signing
+//!
+//! The `async` feature provides the `AsyncSigner` trait, for signers such as remote signing
+//! services that answer asynchronously, and `build_async` methods on the transaction and batch
+//! builders that await their signatures.
+//!
+//! ## Database Backends
+//!
+//! State is stored through the `database` traits, which are implemented over LMDB and an
+//! in-memory BTree by default.  The `sqlite` feature adds a SQLite implementation, for embedded
+//! deployments that cannot use LMDB's memory-mapped files, the `postgresql` feature adds a
+//! PostgreSQL implementation, for hosting state in a managed database, and the `rocksdb` feature
+//! adds a RocksDB implementation, for large state workloads.  The `redis` feature adds a Redis
+//! implementation, which lets test clusters and ephemeral environments share state without local
+//! files.
+//!
+//! ## Merkle Hashes
+//!
+//! The merkle trie hashes its nodes with the first half of SHA-512 by default, or with SHA-256,
+//! as chosen per database with `MerkleRadixTree::new_with_hash_provider`.  The `blake2` feature
+//! adds BLAKE2b-256.
+//!
+//! ## Value Compression
+//!
+//! The `state-compression` feature allows the values of merkle leaves to be compressed on disk
+//! with LZ4 or zstd, as chosen per database with `state::compression::record_value_compression`.
+//! State roots do not depend on compression.
+//!
+//! ## Metrics
+//!
+//! The `metrics` module defines a `MetricsRecorder` trait, to which an `InstrumentedDatabase`
+//! and a `MerkleState` report counters and latencies of reads and commits.
 
 #![cfg_attr(feature = "nightly", feature(test))]
 
@@ -119,6 +160,7 @@ pub mod context;
 pub mod database;
 pub mod execution;
 pub mod handler;
+pub mod metrics;
 pub mod protocol;
 #[allow(renamed_and_removed_lints)]
 pub mod protos;
@@ -127,6 +169,7 @@ pub mod sawtooth;
 pub mod scheduler;
 pub mod signing;
 pub mod state;
+pub mod validation;
 #[cfg(test)]
 pub mod workload;
 
@@ -0,0 +1,180 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Lightweight metrics of the state layer.
+//!
+//! Instrumented components report counters and durations to a `MetricsRecorder`, which may
+//! forward them to whatever monitoring system an application uses.  `InMemoryMetrics` is a
+//! simple recorder that keeps totals and latency histograms in memory, to be read directly.
+//!
+//! The `InstrumentedDatabase` wrapper and a `MerkleState` given a recorder with
+//! `MerkleState::with_metrics` report the metrics named by their modules' constants.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Receives the metrics reported by instrumented components.
+pub trait MetricsRecorder: Send + Sync {
+    /// Adds the given value to the named counter.
+    fn increment_counter(&self, name: &'static str, value: u64);
+
+    /// Records a duration in the named histogram.
+    fn record_duration(&self, name: &'static str, duration: Duration);
+}
+
+/// The upper bounds of the buckets of `InMemoryMetrics` histograms, in microseconds.  Larger
+/// durations are counted in a final, unbounded bucket.
+pub const HISTOGRAM_BUCKETS: [u64; 12] = [
+    10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000, 5_000_000,
+];
+
+/// The durations recorded in a histogram of `InMemoryMetrics`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// The number of durations in each bucket of `HISTOGRAM_BUCKETS`, followed by the number of
+    /// durations beyond the last bucket.
+    pub buckets: Vec<u64>,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            count: 0,
+            sum: Duration::from_secs(0),
+            min: Duration::from_secs(0),
+            max: Duration::from_secs(0),
+            buckets: vec![0; HISTOGRAM_BUCKETS.len() + 1],
+        }
+    }
+
+    fn record(&mut self, duration: Duration) {
+        if self.count == 0 || duration < self.min {
+            self.min = duration;
+        }
+        if duration > self.max {
+            self.max = duration;
+        }
+        self.count += 1;
+        self.sum += duration;
+
+        let micros = duration.as_micros();
+        let bucket = HISTOGRAM_BUCKETS
+            .iter()
+            .position(|bound| micros <= u128::from(*bound))
+            .unwrap_or_else(|| HISTOGRAM_BUCKETS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    /// Returns the mean of the recorded durations, or zero if there are none.
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::from_secs(0)
+        } else {
+            self.sum / self.count as u32
+        }
+    }
+}
+
+/// A `MetricsRecorder` keeping its metrics in memory.
+#[derive(Default)]
+pub struct InMemoryMetrics {
+    counters: Mutex<HashMap<&'static str, u64>>,
+    histograms: Mutex<HashMap<&'static str, Histogram>>,
+}
+
+impl InMemoryMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the total of the named counter, which is zero if it has not been incremented.
+    pub fn counter(&self, name: &str) -> u64 {
+        self.counters
+            .lock()
+            .expect("Couldn't lock counters mutex!")
+            .get(name)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Returns the named histogram, if any durations have been recorded in it.
+    pub fn histogram(&self, name: &str) -> Option<Histogram> {
+        self.histograms
+            .lock()
+            .expect("Couldn't lock histograms mutex!")
+            .get(name)
+            .cloned()
+    }
+}
+
+impl MetricsRecorder for InMemoryMetrics {
+    fn increment_counter(&self, name: &'static str, value: u64) {
+        *self
+            .counters
+            .lock()
+            .expect("Couldn't lock counters mutex!")
+            .entry(name)
+            .or_insert(0) += value;
+    }
+
+    fn record_duration(&self, name: &'static str, duration: Duration) {
+        self.histograms
+            .lock()
+            .expect("Couldn't lock histograms mutex!")
+            .entry(name)
+            .or_insert_with(Histogram::new)
+            .record(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // test that counters are totalled, and durations are counted in their buckets
+    fn in_memory_metrics() {
+        let metrics = InMemoryMetrics::new();
+        assert_eq!(metrics.counter("gets"), 0);
+        assert_eq!(metrics.histogram("latency"), None);
+
+        metrics.increment_counter("gets", 2);
+        metrics.increment_counter("gets", 3);
+        assert_eq!(metrics.counter("gets"), 5);
+
+        metrics.record_duration("latency", Duration::from_micros(5));
+        metrics.record_duration("latency", Duration::from_micros(700));
+        metrics.record_duration("latency", Duration::from_secs(10));
+
+        let histogram = metrics.histogram("latency").unwrap();
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.min, Duration::from_micros(5));
+        assert_eq!(histogram.max, Duration::from_secs(10));
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[4], 1);
+        assert_eq!(histogram.buckets[HISTOGRAM_BUCKETS.len()], 1);
+        assert_eq!(
+            histogram.mean(),
+            (Duration::from_secs(10) + Duration::from_micros(705)) / 3
+        );
+    }
+}
@@ -24,6 +24,7 @@
 use hex;
 use protobuf::Message;
 use std;
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error as StdError;
 
 use crate::protos;
@@ -31,13 +32,25 @@ use crate::protos::{
     FromBytes, FromNative, FromProto, IntoBytes, IntoNative, IntoProto, ProtoConversionError,
 };
 use crate::signing;
-
-use super::transaction::Transaction;
+#[cfg(feature = "async")]
+use crate::signing::async_signer::AsyncSigner;
+use crate::signing::SignatureAlgorithm;
+
+use super::id;
+use super::structure::{structure_violations, StructureLimits, StructureViolation};
+use super::transaction::{
+    protocol_version_from_proto, protocol_version_to_proto, signature_algorithm_from_proto,
+    signature_algorithm_to_proto, Transaction,
+};
+use super::version::{ProtocolVersion, CURRENT_PROTOCOL_VERSION};
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct BatchHeader {
     signer_public_key: Vec<u8>,
     transaction_ids: Vec<Vec<u8>>,
+    signature_algorithm: SignatureAlgorithm,
+    protocol_version: ProtocolVersion,
+    metadata: BTreeMap<String, String>,
 }
 
 impl BatchHeader {
@@ -48,6 +61,19 @@ impl BatchHeader {
     pub fn transaction_ids(&self) -> &[Vec<u8>] {
         &self.transaction_ids
     }
+
+    pub fn signature_algorithm(&self) -> SignatureAlgorithm {
+        self.signature_algorithm
+    }
+
+    pub fn protocol_version(&self) -> &ProtocolVersion {
+        &self.protocol_version
+    }
+
+    /// The operator-defined tags of the batch; empty if none were set.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
 }
 
 impl FromProto<protos::batch::BatchHeader> for BatchHeader {
@@ -60,6 +86,13 @@ impl FromProto<protos::batch::BatchHeader> for BatchHeader {
                 .into_iter()
                 .map(|t| hex::decode(t).map_err(ProtoConversionError::from))
                 .collect::<Result<_, _>>()?,
+            signature_algorithm: signature_algorithm_from_proto(header.get_signature_algorithm())?,
+            protocol_version: protocol_version_from_proto(header.get_protocol_version())?,
+            metadata: header
+                .get_metadata()
+                .iter()
+                .map(|entry| (entry.get_key().to_string(), entry.get_value().to_string()))
+                .collect(),
         })
     }
 }
@@ -75,6 +108,21 @@ impl FromNative<BatchHeader> for protos::batch::BatchHeader {
                 .map(hex::encode)
                 .collect::<protobuf::RepeatedField<String>>(),
         );
+        proto_header
+            .set_signature_algorithm(signature_algorithm_to_proto(header.signature_algorithm));
+        proto_header.set_protocol_version(protocol_version_to_proto(&header.protocol_version));
+        proto_header.set_metadata(
+            header
+                .metadata
+                .into_iter()
+                .map(|(key, value)| {
+                    let mut entry = protos::batch::BatchMetadataEntry::new();
+                    entry.set_key(key);
+                    entry.set_value(value);
+                    entry
+                })
+                .collect(),
+        );
         Ok(proto_header)
     }
 }
@@ -106,15 +154,62 @@ impl IntoBytes for BatchHeader {
 impl IntoProto<protos::batch::BatchHeader> for BatchHeader {}
 impl IntoNative<BatchHeader> for protos::batch::BatchHeader {}
 
+/// A signature of a batch header made by a co-signer of the batch.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct BatchSignature {
+    signer_public_key: Vec<u8>,
+    signature: String,
+}
+
+impl BatchSignature {
+    pub fn new(signer_public_key: Vec<u8>, signature: String) -> Self {
+        BatchSignature {
+            signer_public_key,
+            signature,
+        }
+    }
+
+    pub fn signer_public_key(&self) -> &[u8] {
+        &self.signer_public_key
+    }
+
+    pub fn signature(&self) -> &str {
+        &self.signature
+    }
+}
+
+impl FromProto<protos::batch::BatchSignature> for BatchSignature {
+    fn from_proto(signature: protos::batch::BatchSignature) -> Result<Self, ProtoConversionError> {
+        Ok(BatchSignature {
+            signer_public_key: hex::decode(signature.get_signer_public_key())?,
+            signature: signature.get_signature().to_string(),
+        })
+    }
+}
+
+impl FromNative<BatchSignature> for protos::batch::BatchSignature {
+    fn from_native(signature: BatchSignature) -> Result<Self, ProtoConversionError> {
+        let mut proto_signature = protos::batch::BatchSignature::new();
+        proto_signature.set_signer_public_key(hex::encode(signature.signer_public_key));
+        proto_signature.set_signature(signature.signature);
+        Ok(proto_signature)
+    }
+}
+
+impl IntoProto<protos::batch::BatchSignature> for BatchSignature {}
+impl IntoNative<BatchSignature> for protos::batch::BatchSignature {}
+
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Batch {
     header: Vec<u8>,
     header_signature: String,
     transactions: Vec<Transaction>,
     trace: bool,
+    cosignatures: Vec<BatchSignature>,
 }
 
 impl Batch {
+    /// The serialized header, exactly as it was signed.
     pub fn header(&self) -> &[u8] {
         &self.header
     }
@@ -131,8 +226,114 @@ impl Batch {
         self.trace
     }
 
+    /// The signatures of the header made by co-signers, in addition to the header signature.
+    pub fn cosignatures(&self) -> &[BatchSignature] {
+        &self.cosignatures
+    }
+
+    /// Adds a co-signature of the batch header made with the given signer.
+    ///
+    /// This allows each member of a consortium to sign the batch independently, after it has
+    /// been built by the primary signer.
+    pub fn cosign(&mut self, signer: &signing::Signer) -> Result<(), BatchBuildError> {
+        let signature = hex::encode(
+            signer
+                .sign(&self.header)
+                .map_err(|e| BatchBuildError::SigningError(format!("{}", e)))?,
+        );
+        self.cosignatures.push(BatchSignature {
+            signer_public_key: signer.public_key().to_vec(),
+            signature,
+        });
+        Ok(())
+    }
+
+    /// Verifies that at least `threshold` of the `allowed_signers` have validly signed the batch
+    /// header.
+    ///
+    /// Both the header signature and the co-signatures are considered; each signer is counted
+    /// at most once. Signatures by keys outside of `allowed_signers` are ignored, but any invalid
+    /// signature results in an error.
+    pub fn verify_signatures(
+        &self,
+        verifier: &signing::VerifierSelector,
+        allowed_signers: &[Vec<u8>],
+        threshold: usize,
+    ) -> Result<(), BatchVerificationError> {
+        let header = BatchHeader::from_bytes(&self.header)
+            .map_err(|e| BatchVerificationError::DeserializationError(format!("{}", e)))?;
+        let algorithm = header.signature_algorithm();
+        let verifier = verifier
+            .select(algorithm)
+            .ok_or_else(|| BatchVerificationError::UnsupportedAlgorithm(algorithm.name().into()))?;
+
+        let signatures = std::iter::once((header.signer_public_key(), self.header_signature()))
+            .chain(
+                self.cosignatures
+                    .iter()
+                    .map(|sig| (sig.signer_public_key(), sig.signature())),
+            );
+
+        let mut valid_signers = HashSet::new();
+        for (public_key, signature) in signatures {
+            let signature_bytes = hex::decode(signature).map_err(|e| {
+                BatchVerificationError::InvalidSignature(format!(
+                    "Signature by {} is not valid hex: {}",
+                    hex::encode(public_key),
+                    e
+                ))
+            })?;
+            let is_valid = verifier
+                .verify(&self.header, &signature_bytes, public_key)
+                .map_err(|e| BatchVerificationError::VerifierError(format!("{}", e)))?;
+            if !is_valid {
+                return Err(BatchVerificationError::InvalidSignature(format!(
+                    "Signature by {} does not match the batch header",
+                    hex::encode(public_key)
+                )));
+            }
+            if allowed_signers
+                .iter()
+                .any(|key| key.as_slice() == public_key)
+            {
+                valid_signers.insert(public_key.to_vec());
+            }
+        }
+
+        if valid_signers.len() < threshold {
+            return Err(BatchVerificationError::ThresholdNotMet(format!(
+                "{} of {} required signatures present",
+                valid_signers.len(),
+                threshold
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the structure of the batch without verifying signatures, using the default
+    /// `StructureLimits`.
+    ///
+    /// Returns every violation found; a well-formed batch returns an empty list.
+    pub fn validate_structure(&self) -> Vec<StructureViolation> {
+        self.validate_structure_with_limits(&StructureLimits::default())
+    }
+
+    pub fn validate_structure_with_limits(
+        &self,
+        limits: &StructureLimits,
+    ) -> Vec<StructureViolation> {
+        structure_violations(self, limits)
+    }
+
+    /// Parses the serialized header into its expanded form.
+    pub fn parse_header(&self) -> Result<BatchHeader, ProtoConversionError> {
+        BatchHeader::from_bytes(&self.header)
+    }
+
+    /// Pairs the batch with its parsed header.
     pub fn into_pair(self) -> Result<BatchPair, BatchBuildError> {
-        let header = BatchHeader::from_bytes(&self.header)?;
+        let header = self.parse_header()?;
 
         Ok(BatchPair {
             batch: self,
@@ -156,29 +357,161 @@ impl BatchPair {
         &self.header
     }
 
+    /// The operator-defined tags of the batch.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.header.metadata
+    }
+
+    /// The serialized header of the batch, exactly as it was signed.
+    pub fn header_bytes(&self) -> &[u8] {
+        self.batch.header()
+    }
+
     pub fn take(self) -> (Batch, BatchHeader) {
         (self.batch, self.header)
     }
 }
 
+impl FromProto<protos::batch::Batch> for Batch {
+    fn from_proto(mut batch: protos::batch::Batch) -> Result<Self, ProtoConversionError> {
+        let cosignatures = batch
+            .take_cosignatures()
+            .into_iter()
+            .map(BatchSignature::from_proto)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(batch_from_proto(batch, cosignatures))
+    }
+}
+
+/// Converts a protobuf batch, dropping any co-signature that cannot be decoded.
+///
+/// Prefer `Batch::from_proto`, which rejects such a batch instead of losing its co-signatures.
 impl From<protos::batch::Batch> for Batch {
-    fn from(batch: protos::batch::Batch) -> Self {
-        Batch {
-            header: batch.get_header().to_vec(),
-            header_signature: batch.get_header_signature().to_string(),
-            transactions: batch
-                .get_transactions()
-                .to_vec()
+    fn from(mut batch: protos::batch::Batch) -> Self {
+        let cosignatures = batch
+            .take_cosignatures()
+            .into_iter()
+            .filter_map(|sig| BatchSignature::from_proto(sig).ok())
+            .collect();
+        batch_from_proto(batch, cosignatures)
+    }
+}
+
+fn batch_from_proto(batch: protos::batch::Batch, cosignatures: Vec<BatchSignature>) -> Batch {
+    Batch {
+        header: batch.get_header().to_vec(),
+        header_signature: batch.get_header_signature().to_string(),
+        transactions: batch
+            .get_transactions()
+            .to_vec()
+            .into_iter()
+            .map(Transaction::from)
+            .collect(),
+        trace: batch.get_trace(),
+        cosignatures,
+    }
+}
+
+impl FromNative<Batch> for protos::batch::Batch {
+    fn from_native(batch: Batch) -> Result<Self, ProtoConversionError> {
+        let mut proto_batch = protos::batch::Batch::new();
+        proto_batch.set_header(batch.header);
+        proto_batch.set_header_signature(batch.header_signature);
+        proto_batch.set_transactions(
+            batch
+                .transactions
                 .into_iter()
-                .map(Transaction::from)
-                .collect(),
-            trace: batch.get_trace(),
+                .map(protos::transaction::Transaction::from_native)
+                .collect::<Result<protobuf::RepeatedField<_>, _>>()?,
+        );
+        proto_batch.set_trace(batch.trace);
+        proto_batch.set_cosignatures(
+            batch
+                .cosignatures
+                .into_iter()
+                .map(protos::batch::BatchSignature::from_native)
+                .collect::<Result<protobuf::RepeatedField<_>, _>>()?,
+        );
+        Ok(proto_batch)
+    }
+}
+
+impl IntoProto<protos::batch::Batch> for Batch {}
+impl IntoNative<Batch> for protos::batch::Batch {}
+
+/// A batch whose header has been built but not yet signed.
+///
+/// The `header_bytes` are signed outside the process and the detached signature passed to
+/// `assemble`, which checks it before producing the batch.
+#[derive(Debug, Clone)]
+pub struct UnsignedBatch {
+    header: BatchHeader,
+    header_bytes: Vec<u8>,
+    transactions: Vec<Transaction>,
+    trace: bool,
+}
+
+impl UnsignedBatch {
+    pub fn header(&self) -> &BatchHeader {
+        &self.header
+    }
+
+    /// The serialized header; these are the exact bytes that must be signed.
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.header_bytes
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Assembles the batch from a detached signature of the header bytes.
+    ///
+    /// The signature is checked against the header's signer public key with the verifier for
+    /// the header's signature algorithm.  Co-signatures may be added to the assembled batch with
+    /// `Batch::cosign`.
+    pub fn assemble(
+        self,
+        signature: &[u8],
+        verifier: &signing::VerifierSelector,
+    ) -> Result<BatchPair, BatchBuildError> {
+        let algorithm = self.header.signature_algorithm;
+        let verifier = verifier.select(algorithm).ok_or_else(|| {
+            BatchBuildError::SigningError(format!("no verifier available for {}", algorithm.name()))
+        })?;
+        let valid = verifier
+            .verify(
+                &self.header_bytes,
+                signature,
+                &self.header.signer_public_key,
+            )
+            .map_err(|e| BatchBuildError::SigningError(format!("{}", e)))?;
+        if !valid {
+            return Err(BatchBuildError::InvalidSignature(
+                "signature does not match the batch header".to_string(),
+            ));
+        }
+
+        Ok(self.into_pair(id::id_from_signature(signature)))
+    }
+
+    fn into_pair(self, header_signature: String) -> BatchPair {
+        BatchPair {
+            batch: Batch {
+                header: self.header_bytes,
+                header_signature,
+                transactions: self.transactions,
+                trace: self.trace,
+                cosignatures: vec![],
+            },
+            header: self.header,
         }
     }
 }
 
 #[derive(Debug)]
 pub enum BatchBuildError {
+    InvalidSignature(String),
     MissingField(String),
     SerializationError(String),
     DeserializationError(String),
@@ -188,6 +521,7 @@ pub enum BatchBuildError {
 impl StdError for BatchBuildError {
     fn description(&self) -> &str {
         match *self {
+            BatchBuildError::InvalidSignature(ref msg) => msg,
             BatchBuildError::MissingField(ref msg) => msg,
             BatchBuildError::SerializationError(ref msg) => msg,
             BatchBuildError::DeserializationError(ref msg) => msg,
@@ -199,6 +533,7 @@ impl StdError for BatchBuildError {
 impl std::fmt::Display for BatchBuildError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
+            BatchBuildError::InvalidSignature(ref s) => write!(f, "InvalidSignature: {}", s),
             BatchBuildError::MissingField(ref s) => write!(f, "MissingField: {}", s),
             BatchBuildError::SerializationError(ref s) => write!(f, "SerializationError: {}", s),
             BatchBuildError::DeserializationError(ref s) => {
@@ -215,10 +550,48 @@ impl From<ProtoConversionError> for BatchBuildError {
     }
 }
 
+#[derive(Debug)]
+pub enum BatchVerificationError {
+    DeserializationError(String),
+    InvalidSignature(String),
+    ThresholdNotMet(String),
+    UnsupportedAlgorithm(String),
+    VerifierError(String),
+}
+
+impl StdError for BatchVerificationError {
+    fn description(&self) -> &str {
+        match *self {
+            BatchVerificationError::DeserializationError(ref msg) => msg,
+            BatchVerificationError::InvalidSignature(ref msg) => msg,
+            BatchVerificationError::ThresholdNotMet(ref msg) => msg,
+            BatchVerificationError::UnsupportedAlgorithm(ref msg) => msg,
+            BatchVerificationError::VerifierError(ref msg) => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for BatchVerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            BatchVerificationError::DeserializationError(ref s) => {
+                write!(f, "DeserializationError: {}", s)
+            }
+            BatchVerificationError::InvalidSignature(ref s) => write!(f, "InvalidSignature: {}", s),
+            BatchVerificationError::ThresholdNotMet(ref s) => write!(f, "ThresholdNotMet: {}", s),
+            BatchVerificationError::UnsupportedAlgorithm(ref s) => {
+                write!(f, "UnsupportedAlgorithm: {}", s)
+            }
+            BatchVerificationError::VerifierError(ref s) => write!(f, "VerifierError: {}", s),
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct BatchBuilder {
     transactions: Option<Vec<Transaction>>,
     trace: Option<bool>,
+    metadata: BTreeMap<String, String>,
 }
 
 impl BatchBuilder {
@@ -236,7 +609,52 @@ impl BatchBuilder {
         self
     }
 
+    /// Sets the operator-defined tags of the batch, replacing any set previously.
+    ///
+    /// The tags are carried in the signed header, so they cannot be altered once the batch is
+    /// signed.
+    pub fn with_metadata(mut self, metadata: BTreeMap<String, String>) -> BatchBuilder {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Adds a single tag to the batch's metadata.
+    pub fn with_metadata_entry(mut self, key: String, value: String) -> BatchBuilder {
+        self.metadata.insert(key, value);
+        self
+    }
+
     pub fn build_pair(self, signer: &signing::Signer) -> Result<BatchPair, BatchBuildError> {
+        self.build_pair_with_cosigners(signer, &[])
+    }
+
+    /// Builds the batch, signing the header with `signer` and adding a co-signature from each of
+    /// the `cosigners`.
+    pub fn build_pair_with_cosigners(
+        self,
+        signer: &signing::Signer,
+        cosigners: &[&signing::Signer],
+    ) -> Result<BatchPair, BatchBuildError> {
+        let unsigned = self.build_unsigned(signer.public_key().to_vec(), signer.algorithm())?;
+        let header_signature = id::sign_header(unsigned.header_bytes(), signer)
+            .map_err(|e| BatchBuildError::SigningError(format!("{}", e)))?;
+
+        let mut pair = unsigned.into_pair(header_signature);
+        for cosigner in cosigners {
+            pair.batch.cosign(*cosigner)?;
+        }
+
+        Ok(pair)
+    }
+
+    /// Builds the batch header for the given signer, without signing it.
+    ///
+    /// The header is signed elsewhere and the batch completed with `UnsignedBatch::assemble`.
+    pub fn build_unsigned(
+        self,
+        signer_public_key: Vec<u8>,
+        signature_algorithm: SignatureAlgorithm,
+    ) -> Result<UnsignedBatch, BatchBuildError> {
         let transactions = self.transactions.ok_or_else(|| {
             BatchBuildError::MissingField("'transactions' field is required".to_string())
         })?;
@@ -244,16 +662,17 @@ impl BatchBuilder {
         let transaction_ids = transactions
             .iter()
             .flat_map(|t| {
-                vec![hex::decode(t.header_signature())
+                vec![id::id_to_bytes(t.header_signature())
                     .map_err(|e| BatchBuildError::SerializationError(format!("{}", e)))]
             })
             .collect::<Result<_, _>>()?;
 
-        let signer_public_key = signer.public_key().to_vec();
-
         let header = BatchHeader {
             signer_public_key,
             transaction_ids,
+            signature_algorithm,
+            protocol_version: CURRENT_PROTOCOL_VERSION,
+            metadata: self.metadata,
         };
 
         let header_proto: protos::batch::BatchHeader = header
@@ -264,31 +683,177 @@ impl BatchBuilder {
             .write_to_bytes()
             .map_err(|e| BatchBuildError::SerializationError(format!("{}", e)))?;
 
-        let header_signature = hex::encode(
-            signer
-                .sign(&header_bytes)
-                .map_err(|e| BatchBuildError::SigningError(format!("{}", e)))?,
-        );
-
-        let batch = Batch {
-            header: header_bytes,
-            header_signature,
+        Ok(UnsignedBatch {
+            header,
+            header_bytes,
             transactions,
             trace,
-        };
-
-        Ok(BatchPair { batch, header })
+        })
     }
 
     pub fn build(self, signer: &signing::Signer) -> Result<Batch, BatchBuildError> {
         Ok(self.build_pair(signer)?.batch)
     }
+
+    pub fn build_with_cosigners(
+        self,
+        signer: &signing::Signer,
+        cosigners: &[&signing::Signer],
+    ) -> Result<Batch, BatchBuildError> {
+        Ok(self.build_pair_with_cosigners(signer, cosigners)?.batch)
+    }
+
+    /// Builds the batch, awaiting the header signature from an asynchronous signer.
+    #[cfg(feature = "async")]
+    pub async fn build_pair_async(
+        self,
+        signer: &dyn AsyncSigner,
+    ) -> Result<BatchPair, BatchBuildError> {
+        let unsigned = self.build_unsigned(signer.public_key().to_vec(), signer.algorithm())?;
+        let header_signature = id::sign_header_async(unsigned.header_bytes(), signer)
+            .await
+            .map_err(|e| BatchBuildError::SigningError(format!("{}", e)))?;
+
+        Ok(unsigned.into_pair(header_signature))
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn build_async(self, signer: &dyn AsyncSigner) -> Result<Batch, BatchBuildError> {
+        Ok(self.build_pair_async(signer).await?.batch)
+    }
+}
+
+#[cfg(feature = "serde")]
+mod json {
+    use std::collections::BTreeMap;
+
+    use serde::de::Error as DeError;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{Batch, BatchHeader, BatchSignature, Transaction};
+    use crate::protocol::json::{decode_hex_list, encode_hex_list};
+    use crate::protocol::version::ProtocolVersion;
+    use crate::signing::SignatureAlgorithm;
+
+    #[derive(Serialize, Deserialize)]
+    struct BatchHeaderJson {
+        signer_public_key: String,
+        transaction_ids: Vec<String>,
+        #[serde(default = "default_signature_algorithm")]
+        signature_algorithm: String,
+        #[serde(default = "default_protocol_version")]
+        protocol_version: String,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        metadata: BTreeMap<String, String>,
+    }
+
+    fn default_signature_algorithm() -> String {
+        SignatureAlgorithm::Secp256k1.name().to_string()
+    }
+
+    fn default_protocol_version() -> String {
+        ProtocolVersion::default().to_string()
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct BatchSignatureJson {
+        signer_public_key: String,
+        signature: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct BatchJson {
+        header: String,
+        header_signature: String,
+        transactions: Vec<Transaction>,
+        trace: bool,
+        #[serde(default)]
+        cosignatures: Vec<BatchSignatureJson>,
+    }
+
+    impl Serialize for BatchHeader {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            BatchHeaderJson {
+                signer_public_key: hex::encode(&self.signer_public_key),
+                transaction_ids: encode_hex_list(&self.transaction_ids),
+                signature_algorithm: self.signature_algorithm.name().to_string(),
+                protocol_version: self.protocol_version.to_string(),
+                metadata: self.metadata.clone(),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for BatchHeader {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let json = BatchHeaderJson::deserialize(deserializer)?;
+            Ok(BatchHeader {
+                signer_public_key: hex::decode(&json.signer_public_key)
+                    .map_err(D::Error::custom)?,
+                transaction_ids: decode_hex_list(&json.transaction_ids)
+                    .map_err(D::Error::custom)?,
+                signature_algorithm: SignatureAlgorithm::from_name(&json.signature_algorithm)
+                    .ok_or_else(|| {
+                        D::Error::custom(format!(
+                            "unknown signature algorithm: {}",
+                            json.signature_algorithm
+                        ))
+                    })?,
+                protocol_version: ProtocolVersion::parse(&json.protocol_version)
+                    .map_err(D::Error::custom)?,
+                metadata: json.metadata,
+            })
+        }
+    }
+
+    impl Serialize for Batch {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            BatchJson {
+                header: hex::encode(&self.header),
+                header_signature: self.header_signature.clone(),
+                transactions: self.transactions.clone(),
+                trace: self.trace,
+                cosignatures: self
+                    .cosignatures
+                    .iter()
+                    .map(|sig| BatchSignatureJson {
+                        signer_public_key: hex::encode(&sig.signer_public_key),
+                        signature: sig.signature.clone(),
+                    })
+                    .collect(),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Batch {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let json = BatchJson::deserialize(deserializer)?;
+            Ok(Batch {
+                header: hex::decode(&json.header).map_err(D::Error::custom)?,
+                header_signature: json.header_signature,
+                transactions: json.transactions,
+                trace: json.trace,
+                cosignatures: json
+                    .cosignatures
+                    .into_iter()
+                    .map(|sig| -> Result<BatchSignature, D::Error> {
+                        Ok(BatchSignature {
+                            signer_public_key: hex::decode(&sig.signer_public_key)
+                                .map_err(D::Error::custom)?,
+                            signature: sig.signature,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+            })
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::signing::hash::HashSigner;
+    use crate::signing::hash::{HashSigner, HashVerifier};
     use crate::signing::Signer;
     #[cfg(feature = "sawtooth-compat")]
     use protobuf::Message;
@@ -385,11 +950,84 @@ mod tests {
         check_builder_batch(&signer, &pair);
     }
 
+    #[test]
+    // test that a batch assembled from a detached signature is identical to one built with the
+    // signer, and that a signature of other bytes is rejected
+    fn batch_builder_detached_signature() {
+        let signer = HashSigner::new();
+        let builder = BatchBuilder::new()
+            .with_transactions(vec![
+                Transaction::new(
+                    BYTES2.to_vec(),
+                    hex::encode(SIGNATURE2.to_string()),
+                    BYTES3.to_vec(),
+                ),
+                Transaction::new(
+                    BYTES4.to_vec(),
+                    hex::encode(SIGNATURE3.to_string()),
+                    BYTES5.to_vec(),
+                ),
+            ])
+            .with_trace(true);
+
+        let unsigned = builder
+            .clone()
+            .build_unsigned(signer.public_key().to_vec(), signer.algorithm())
+            .unwrap();
+        let signature = signer.sign(unsigned.header_bytes()).unwrap();
+        let pair = unsigned
+            .clone()
+            .assemble(&signature, &HashVerifier::new())
+            .unwrap();
+
+        check_builder_batch(&signer, &pair);
+        assert_eq!(builder.build(&signer).unwrap(), *pair.batch());
+
+        let wrong_signature = signer.sign(b"other bytes").unwrap();
+        match unsigned.assemble(&wrong_signature, &HashVerifier::new()) {
+            Err(BatchBuildError::InvalidSignature(_)) => (),
+            res => panic!("expected InvalidSignature, got {:?}", res),
+        }
+    }
+
+    #[test]
+    // test that metadata tags set on the builder survive the header bytes and are empty by
+    // default
+    fn batch_builder_metadata() {
+        let signer = HashSigner::new();
+        let transactions = vec![Transaction::new(
+            BYTES2.to_vec(),
+            hex::encode(SIGNATURE2.to_string()),
+            BYTES3.to_vec(),
+        )];
+
+        let pair = BatchBuilder::new()
+            .with_transactions(transactions.clone())
+            .build_pair(&signer)
+            .unwrap();
+        assert!(pair.metadata().is_empty());
+
+        let pair = BatchBuilder::new()
+            .with_transactions(transactions)
+            .with_metadata_entry("tenant".into(), "acme".into())
+            .with_metadata_entry("request".into(), "42".into())
+            .build_pair(&signer)
+            .unwrap();
+        let header = pair.batch().parse_header().unwrap();
+        assert_eq!(pair.header(), &header);
+        assert_eq!(Some(&"acme".to_string()), header.metadata().get("tenant"));
+        assert_eq!(Some(&"42".to_string()), header.metadata().get("request"));
+        assert_eq!(2, header.metadata().len());
+    }
+
     #[test]
     fn batch_header_fields() {
         let header = BatchHeader {
             signer_public_key: hex::decode(KEY1).unwrap(),
             transaction_ids: vec![hex::decode(KEY2).unwrap(), hex::decode(KEY3).unwrap()],
+            signature_algorithm: SignatureAlgorithm::Secp256k1,
+            protocol_version: ProtocolVersion::default(),
+            metadata: BTreeMap::new(),
         };
 
         assert_eq!(KEY1, hex::encode(header.signer_public_key()));
@@ -405,6 +1043,9 @@ mod tests {
         let original = BatchHeader {
             signer_public_key: hex::decode(KEY1).unwrap(),
             transaction_ids: vec![hex::decode(KEY2).unwrap(), hex::decode(KEY3).unwrap()],
+            signature_algorithm: SignatureAlgorithm::Secp256k1,
+            protocol_version: ProtocolVersion::default(),
+            metadata: BTreeMap::new(),
         };
 
         let header_bytes = original.clone().into_bytes().unwrap();
@@ -453,6 +1094,7 @@ mod tests {
                 Transaction::new(BYTES4.to_vec(), SIGNATURE3.to_string(), BYTES5.to_vec()),
             ],
             trace: true,
+            cosignatures: vec![],
         };
 
         assert_eq!(BYTES1.to_vec(), batch.header());
@@ -470,6 +1112,175 @@ mod tests {
     #[cfg(feature = "sawtooth-compat")]
     #[test]
     fn batch_sawtooth10_compatibility() {}
+
+    #[cfg(feature = "serde")]
+    #[test]
+    // test that batches round trip through their canonical JSON form
+    fn batch_json() {
+        let header = BatchHeader {
+            signer_public_key: hex::decode(KEY1).unwrap(),
+            transaction_ids: vec![hex::decode(KEY2).unwrap(), hex::decode(KEY3).unwrap()],
+            signature_algorithm: SignatureAlgorithm::Secp256k1,
+            protocol_version: ProtocolVersion::default(),
+            metadata: BTreeMap::new(),
+        };
+        let json = serde_json::to_value(&header).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "signer_public_key": KEY1,
+                "transaction_ids": [KEY2, KEY3],
+                "signature_algorithm": "secp256k1",
+                "protocol_version": "1.0",
+            }),
+            json
+        );
+        assert_eq!(header, serde_json::from_value::<BatchHeader>(json).unwrap());
+
+        let batch = Batch {
+            header: BYTES1.to_vec(),
+            header_signature: SIGNATURE1.to_string(),
+            transactions: vec![Transaction::new(
+                BYTES2.to_vec(),
+                SIGNATURE2.to_string(),
+                BYTES3.to_vec(),
+            )],
+            trace: true,
+            cosignatures: vec![BatchSignature::new(
+                hex::decode(KEY2).unwrap(),
+                SIGNATURE3.to_string(),
+            )],
+        };
+        let json = serde_json::to_value(&batch).unwrap();
+        assert_eq!("01020304", json["header"]);
+        assert_eq!("05060708", json["transactions"][0]["header"]);
+        assert_eq!(KEY2, json["cosignatures"][0]["signer_public_key"]);
+        assert_eq!(batch, serde_json::from_value::<Batch>(json).unwrap());
+    }
+
+    // A HashSigner with a configurable public key, so that co-signers can be told apart
+    struct KeyedHashSigner {
+        inner: HashSigner,
+        public_key: Vec<u8>,
+    }
+
+    impl KeyedHashSigner {
+        fn new(public_key: &str) -> Self {
+            KeyedHashSigner {
+                inner: HashSigner::new(),
+                public_key: public_key.as_bytes().to_vec(),
+            }
+        }
+    }
+
+    impl Signer for KeyedHashSigner {
+        fn sign(&self, message: &[u8]) -> Result<Vec<u8>, signing::Error> {
+            self.inner.sign(message)
+        }
+
+        fn public_key(&self) -> &[u8] {
+            &self.public_key
+        }
+
+        fn algorithm(&self) -> signing::SignatureAlgorithm {
+            self.inner.algorithm()
+        }
+    }
+
+    fn cosigned_batch() -> Batch {
+        let signer = KeyedHashSigner::new("signer");
+        let cosigner1 = KeyedHashSigner::new("cosigner1");
+        let cosigner2 = KeyedHashSigner::new("cosigner2");
+
+        BatchBuilder::new()
+            .with_transactions(vec![Transaction::new(
+                BYTES2.to_vec(),
+                hex::encode(SIGNATURE2.to_string()),
+                BYTES3.to_vec(),
+            )])
+            .build_with_cosigners(&signer, &[&cosigner1, &cosigner2])
+            .unwrap()
+    }
+
+    #[test]
+    // test that co-signatures are attached by the builder and counted towards a threshold
+    fn batch_builder_cosigners() {
+        let batch = cosigned_batch();
+
+        assert_eq!(2, batch.cosignatures().len());
+        assert_eq!(b"cosigner1", batch.cosignatures()[0].signer_public_key());
+        assert_eq!(b"cosigner2", batch.cosignatures()[1].signer_public_key());
+
+        let allowed = vec![
+            b"signer".to_vec(),
+            b"cosigner1".to_vec(),
+            b"cosigner2".to_vec(),
+        ];
+        assert!(batch
+            .verify_signatures(&HashVerifier::new(), &allowed, 3)
+            .is_ok());
+
+        match batch.verify_signatures(&HashVerifier::new(), &allowed[1..], 3) {
+            Err(BatchVerificationError::ThresholdNotMet(_)) => (),
+            res => panic!("Expected ThresholdNotMet, got {:?}", res),
+        }
+    }
+
+    #[test]
+    // test that a co-signer signing twice is only counted once, and bad signatures are rejected
+    fn batch_verify_signatures() {
+        let mut batch = cosigned_batch();
+        batch.cosign(&KeyedHashSigner::new("cosigner1")).unwrap();
+
+        let allowed = vec![b"cosigner1".to_vec(), b"cosigner2".to_vec()];
+        match batch.verify_signatures(&HashVerifier::new(), &allowed, 3) {
+            Err(BatchVerificationError::ThresholdNotMet(_)) => (),
+            res => panic!("Expected ThresholdNotMet, got {:?}", res),
+        }
+
+        batch.cosignatures.push(BatchSignature::new(
+            b"cosigner3".to_vec(),
+            hex::encode(SIGNATURE1),
+        ));
+        match batch.verify_signatures(&HashVerifier::new(), &allowed, 1) {
+            Err(BatchVerificationError::InvalidSignature(_)) => (),
+            res => panic!("Expected InvalidSignature, got {:?}", res),
+        }
+    }
+
+    #[test]
+    // test that signatures are only checked by a verifier for the header's signature algorithm
+    fn batch_verify_signatures_unsupported_algorithm() {
+        let batch = cosigned_batch();
+        let allowed = vec![b"signer".to_vec()];
+
+        assert!(batch
+            .verify_signatures(
+                &signing::VerifierRegistry::new().with_verifier(Box::new(HashVerifier::new())),
+                &allowed,
+                1
+            )
+            .is_ok());
+
+        match batch.verify_signatures(&signing::VerifierRegistry::new(), &allowed, 1) {
+            Err(BatchVerificationError::UnsupportedAlgorithm(_)) => (),
+            res => panic!("Expected UnsupportedAlgorithm, got {:?}", res),
+        }
+    }
+
+    #[test]
+    // test that a batch with a malformed co-signature fails to convert, rather than losing it
+    fn batch_from_proto_malformed_cosignature() {
+        let mut proto = protos::batch::Batch::from_native(cosigned_batch()).unwrap();
+        let mut cosignature = protos::batch::BatchSignature::new();
+        cosignature.set_signer_public_key("not hex".to_string());
+        cosignature.set_signature(hex::encode(SIGNATURE1));
+        proto.mut_cosignatures().push(cosignature);
+
+        assert!(Batch::from_proto(proto.clone()).is_err());
+
+        // The infallible conversion keeps the batch and drops the malformed co-signature
+        assert_eq!(cosigned_batch(), Batch::from(proto));
+    }
 }
 
 #[cfg(all(feature = "nightly", test))]
@@ -504,6 +1315,7 @@ mod benchmarks {
                 Transaction::new(BYTES4.to_vec(), SIGNATURE3.to_string(), BYTES5.to_vec()),
             ],
             trace: true,
+            cosignatures: vec![],
         });
     }
 
@@ -543,6 +1355,9 @@ mod benchmarks {
         let native_header = BatchHeader {
             signer_public_key: hex::decode(KEY1).unwrap(),
             transaction_ids: vec![hex::decode(KEY2).unwrap(), hex::decode(KEY3).unwrap()],
+            signature_algorithm: SignatureAlgorithm::Secp256k1,
+            protocol_version: ProtocolVersion::default(),
+            metadata: BTreeMap::new(),
         };
         b.iter(|| native_header.clone().into_proto());
     }
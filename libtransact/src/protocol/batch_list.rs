@@ -0,0 +1,850 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Serialization of lists of batches.
+//!
+//! A list of batches is serialized as a protobuf `BatchList`.  With the `batch-compression`
+//! feature enabled, the serialized list may also be compressed with gzip or zstd.  Compressed
+//! lists are recognized by the magic prefix of their compression format, and are decompressed
+//! transparently by `batch_list_from_bytes`; an uncompressed `BatchList` never begins with either
+//! prefix.  A decompressed list may be no larger than `DEFAULT_MAX_DECOMPRESSED_SIZE`, or the
+//! limit given to `batch_list_from_bytes_with_limit`, so that a small hostile payload cannot
+//! exhaust memory.
+//!
+//! Large batch lists can be read and written one batch at a time with `BatchListReader` and
+//! `BatchListWriter`.  Each batch is written as a length-delimited `batches` field of a
+//! `BatchList`, so a stream produced by the writer is itself a valid, uncompressed `BatchList`,
+//! and the output of `batch_list_to_bytes` can be read by the reader.  Compressed streams may be
+//! handled by wrapping the underlying reader or writer in a decoder or encoder.
+//!
+//! `BatchListBuilder` checks a list of batches before it is serialized: that it does not exceed a
+//! maximum number of batches, that no transaction appears more than once, and that every
+//! transaction is ordered after the transactions it depends on.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error as StdError;
+use std::io::{Read, Write};
+
+use protobuf::Message;
+
+use crate::protos;
+use crate::protos::{FromNative, FromProto, ProtoConversionError};
+
+use super::batch::Batch;
+use super::id;
+
+#[cfg(feature = "batch-compression")]
+static GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+#[cfg(feature = "batch-compression")]
+static ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The largest size, in bytes, to which `batch_list_from_bytes` decompresses a batch list.
+#[cfg(feature = "batch-compression")]
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum BatchListError {
+    SerializationError(String),
+    DeserializationError(String),
+    CompressionError(String),
+    IoError(String),
+}
+
+impl StdError for BatchListError {
+    fn description(&self) -> &str {
+        match *self {
+            BatchListError::SerializationError(ref msg) => msg,
+            BatchListError::DeserializationError(ref msg) => msg,
+            BatchListError::CompressionError(ref msg) => msg,
+            BatchListError::IoError(ref msg) => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for BatchListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            BatchListError::SerializationError(ref s) => write!(f, "SerializationError: {}", s),
+            BatchListError::DeserializationError(ref s) => {
+                write!(f, "DeserializationError: {}", s)
+            }
+            BatchListError::CompressionError(ref s) => write!(f, "CompressionError: {}", s),
+            BatchListError::IoError(ref s) => write!(f, "IoError: {}", s),
+        }
+    }
+}
+
+impl From<ProtoConversionError> for BatchListError {
+    fn from(e: ProtoConversionError) -> Self {
+        BatchListError::SerializationError(format!("{}", e))
+    }
+}
+
+impl From<std::io::Error> for BatchListError {
+    fn from(e: std::io::Error) -> Self {
+        BatchListError::IoError(format!("{}", e))
+    }
+}
+
+/// The key of the `batches` field of a `BatchList`: field 1, length-delimited.
+const BATCHES_FIELD_KEY: u64 = (1 << 3) | 2;
+
+/// Serializes the batches as an uncompressed protobuf `BatchList`.
+pub fn batch_list_to_bytes(batches: Vec<Batch>) -> Result<Vec<u8>, BatchListError> {
+    let mut proto_list = protos::batch::BatchList::new();
+    proto_list.set_batches(
+        batches
+            .into_iter()
+            .map(protos::batch::Batch::from_native)
+            .collect::<Result<protobuf::RepeatedField<_>, _>>()?,
+    );
+    proto_list
+        .write_to_bytes()
+        .map_err(|e| BatchListError::SerializationError(format!("{}", e)))
+}
+
+/// Deserializes a list of batches, decompressing it first if it is compressed.
+pub fn batch_list_from_bytes(bytes: &[u8]) -> Result<Vec<Batch>, BatchListError> {
+    #[cfg(feature = "batch-compression")]
+    {
+        if let Some(compression) = Compression::detect(bytes) {
+            return parse_batch_list(
+                &compression.decompress(bytes, DEFAULT_MAX_DECOMPRESSED_SIZE)?,
+            );
+        }
+    }
+
+    parse_batch_list(bytes)
+}
+
+/// Deserializes a list of batches, decompressing it first if it is compressed.  Returns a
+/// `CompressionError` if the list decompresses to more than `max_size` bytes.
+#[cfg(feature = "batch-compression")]
+pub fn batch_list_from_bytes_with_limit(
+    bytes: &[u8],
+    max_size: u64,
+) -> Result<Vec<Batch>, BatchListError> {
+    match Compression::detect(bytes) {
+        Some(compression) => parse_batch_list(&compression.decompress(bytes, max_size)?),
+        None => parse_batch_list(bytes),
+    }
+}
+
+fn parse_batch_list(bytes: &[u8]) -> Result<Vec<Batch>, BatchListError> {
+    let proto_list: protos::batch::BatchList = protobuf::parse_from_bytes(bytes)
+        .map_err(|e| BatchListError::DeserializationError(format!("{}", e)))?;
+    proto_list
+        .get_batches()
+        .to_vec()
+        .into_iter()
+        .map(|batch| {
+            Batch::from_proto(batch)
+                .map_err(|e| BatchListError::DeserializationError(format!("{}", e)))
+        })
+        .collect()
+}
+
+/// The compression formats supported for serialized batch lists.
+#[cfg(feature = "batch-compression")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+#[cfg(feature = "batch-compression")]
+impl Compression {
+    fn detect(bytes: &[u8]) -> Option<Compression> {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    fn compress(self, bytes: &[u8]) -> Result<Vec<u8>, BatchListError> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder
+                    .write_all(bytes)
+                    .and_then(|_| encoder.finish())
+                    .map_err(|e| BatchListError::CompressionError(format!("{}", e)))
+            }
+            Compression::Zstd => zstd::encode_all(bytes, 0)
+                .map_err(|e| BatchListError::CompressionError(format!("{}", e))),
+        }
+    }
+
+    /// Decompresses the bytes, failing once the output exceeds `max_size` bytes.
+    fn decompress(self, bytes: &[u8], max_size: u64) -> Result<Vec<u8>, BatchListError> {
+        // Read one byte past the limit, so that output of exactly `max_size` bytes is accepted
+        // and anything longer is detected without decompressing the rest.
+        let limit = max_size.saturating_add(1);
+        let mut decompressed = Vec::new();
+        match self {
+            Compression::Gzip => flate2::read::GzDecoder::new(bytes)
+                .take(limit)
+                .read_to_end(&mut decompressed),
+            Compression::Zstd => zstd::stream::Decoder::new(bytes)
+                .and_then(|decoder| decoder.take(limit).read_to_end(&mut decompressed)),
+        }
+        .map_err(|e| BatchListError::CompressionError(format!("{}", e)))?;
+
+        if decompressed.len() as u64 > max_size {
+            return Err(BatchListError::CompressionError(format!(
+                "batch list decompresses to more than {} bytes",
+                max_size
+            )));
+        }
+        Ok(decompressed)
+    }
+}
+
+/// Serializes the batches as a protobuf `BatchList`, compressed with the given format.
+#[cfg(feature = "batch-compression")]
+pub fn batch_list_to_compressed_bytes(
+    batches: Vec<Batch>,
+    compression: Compression,
+) -> Result<Vec<u8>, BatchListError> {
+    compression.compress(&batch_list_to_bytes(batches)?)
+}
+
+/// Writes batches to a stream one at a time.
+pub struct BatchListWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> BatchListWriter<W> {
+    pub fn new(writer: W) -> Self {
+        BatchListWriter { writer }
+    }
+
+    /// Writes a single batch to the stream.
+    pub fn write_batch(&mut self, batch: &Batch) -> Result<(), BatchListError> {
+        let bytes = protos::batch::Batch::from_native(batch.clone())?
+            .write_to_bytes()
+            .map_err(|e| BatchListError::SerializationError(format!("{}", e)))?;
+
+        write_varint(&mut self.writer, BATCHES_FIELD_KEY)?;
+        write_varint(&mut self.writer, bytes.len() as u64)?;
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), BatchListError> {
+        self.writer.flush().map_err(BatchListError::from)
+    }
+
+    /// Flushes the stream and returns the underlying writer.
+    pub fn into_inner(mut self) -> Result<W, BatchListError> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads batches from a stream one at a time.
+///
+/// The reader is an iterator over the batches in the stream; iteration ends at the end of the
+/// stream, or after the first error.
+pub struct BatchListReader<R: Read> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: Read> BatchListReader<R> {
+    pub fn new(reader: R) -> Self {
+        BatchListReader {
+            reader,
+            done: false,
+        }
+    }
+
+    fn read_batch(&mut self) -> Result<Option<Batch>, BatchListError> {
+        let key = match read_varint(&mut self.reader, true)? {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        if key != BATCHES_FIELD_KEY {
+            return Err(BatchListError::DeserializationError(format!(
+                "unexpected field key in batch list: {}",
+                key
+            )));
+        }
+
+        let len = read_varint(&mut self.reader, false)?.unwrap_or(0);
+        // Read through `take`, rather than into a buffer of the declared length, so that a
+        // corrupt length cannot cause an arbitrarily large allocation.
+        let mut bytes = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(BatchListError::DeserializationError(
+                "batch list ended in the middle of a batch".into(),
+            ));
+        }
+
+        let proto_batch: protos::batch::Batch = protobuf::parse_from_bytes(&bytes)
+            .map_err(|e| BatchListError::DeserializationError(format!("{}", e)))?;
+        Batch::from_proto(proto_batch)
+            .map(Some)
+            .map_err(|e| BatchListError::DeserializationError(format!("{}", e)))
+    }
+}
+
+impl<R: Read> Iterator for BatchListReader<R> {
+    type Item = Result<Batch, BatchListError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_batch() {
+            Ok(Some(batch)) => Some(Ok(batch)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// The reason a list of batches was rejected by `BatchListBuilder`.
+#[derive(Debug, PartialEq)]
+pub enum BatchListBuildError {
+    /// The list contains more batches than the configured maximum.
+    TooManyBatches {
+        count: usize,
+        max: usize,
+    },
+    /// A transaction appears more than once; `batch_ids` lists each batch containing it, in list
+    /// order.
+    DuplicateTransaction {
+        transaction_id: String,
+        batch_ids: Vec<String>,
+    },
+    /// A transaction depends on a transaction that appears after it in the list.
+    MisorderedDependency {
+        transaction_id: String,
+        batch_id: String,
+        dependency: String,
+        dependency_batch_id: String,
+    },
+    /// A transaction depends on a transaction that is not in the list, and external dependencies
+    /// are not allowed.
+    MissingDependency {
+        transaction_id: String,
+        batch_id: String,
+        dependency: String,
+    },
+    /// A transaction header could not be deserialized.
+    InvalidHeader {
+        transaction_id: String,
+        batch_id: String,
+        reason: String,
+    },
+    SerializationError(String),
+}
+
+impl StdError for BatchListBuildError {
+    fn description(&self) -> &str {
+        match *self {
+            BatchListBuildError::TooManyBatches { .. } => "too many batches in list",
+            BatchListBuildError::DuplicateTransaction { .. } => "duplicate transaction in list",
+            BatchListBuildError::MisorderedDependency { .. } => {
+                "transaction is ordered before its dependency"
+            }
+            BatchListBuildError::MissingDependency { .. } => "transaction dependency not in list",
+            BatchListBuildError::InvalidHeader { .. } => "header could not be deserialized",
+            BatchListBuildError::SerializationError(ref msg) => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for BatchListBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            BatchListBuildError::TooManyBatches { count, max } => {
+                write!(f, "TooManyBatches: {} batches, maximum is {}", count, max)
+            }
+            BatchListBuildError::DuplicateTransaction {
+                ref transaction_id,
+                ref batch_ids,
+            } => write!(
+                f,
+                "DuplicateTransaction: {} in batches {}",
+                transaction_id,
+                batch_ids.join(", ")
+            ),
+            BatchListBuildError::MisorderedDependency {
+                ref transaction_id,
+                ref batch_id,
+                ref dependency,
+                ref dependency_batch_id,
+            } => write!(
+                f,
+                "MisorderedDependency: {} in batch {} depends on later {} in batch {}",
+                transaction_id, batch_id, dependency, dependency_batch_id
+            ),
+            BatchListBuildError::MissingDependency {
+                ref transaction_id,
+                ref batch_id,
+                ref dependency,
+            } => write!(
+                f,
+                "MissingDependency: transaction {} in batch {} depends on {}",
+                transaction_id, batch_id, dependency
+            ),
+            BatchListBuildError::InvalidHeader {
+                ref transaction_id,
+                ref batch_id,
+                ref reason,
+            } => write!(
+                f,
+                "InvalidHeader: transaction {} in batch {}: {}",
+                transaction_id, batch_id, reason
+            ),
+            BatchListBuildError::SerializationError(ref s) => {
+                write!(f, "SerializationError: {}", s)
+            }
+        }
+    }
+}
+
+impl From<BatchListError> for BatchListBuildError {
+    fn from(e: BatchListError) -> Self {
+        BatchListBuildError::SerializationError(format!("{}", e))
+    }
+}
+
+/// Builds a validated list of batches.
+///
+/// Dependencies on transactions that are not in the list are assumed to refer to transactions
+/// that have already been committed, and are allowed unless `with_external_dependencies(false)`
+/// is set.  A dependency on a transaction in the list must be satisfied by a transaction earlier
+/// in the list, either in an earlier batch or earlier in the same batch.
+#[derive(Clone)]
+pub struct BatchListBuilder {
+    batches: Vec<Batch>,
+    max_batches: Option<usize>,
+    external_dependencies: bool,
+}
+
+impl Default for BatchListBuilder {
+    fn default() -> Self {
+        BatchListBuilder {
+            batches: Vec::new(),
+            max_batches: None,
+            external_dependencies: true,
+        }
+    }
+}
+
+impl BatchListBuilder {
+    pub fn new() -> Self {
+        BatchListBuilder::default()
+    }
+
+    pub fn with_batches(mut self, batches: Vec<Batch>) -> BatchListBuilder {
+        self.batches = batches;
+        self
+    }
+
+    /// Appends a batch to the end of the list.
+    pub fn with_batch(mut self, batch: Batch) -> BatchListBuilder {
+        self.batches.push(batch);
+        self
+    }
+
+    pub fn with_max_batches(mut self, max_batches: usize) -> BatchListBuilder {
+        self.max_batches = Some(max_batches);
+        self
+    }
+
+    /// Sets whether transactions may depend on transactions that are not in the list.
+    pub fn with_external_dependencies(mut self, allowed: bool) -> BatchListBuilder {
+        self.external_dependencies = allowed;
+        self
+    }
+
+    /// Checks the list of batches, returning the first problem found.
+    pub fn validate(&self) -> Result<(), BatchListBuildError> {
+        if let Some(max) = self.max_batches {
+            if self.batches.len() > max {
+                return Err(BatchListBuildError::TooManyBatches {
+                    count: self.batches.len(),
+                    max,
+                });
+            }
+        }
+
+        let mut locations: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (index, batch) in self.batches.iter().enumerate() {
+            for transaction in batch.transactions() {
+                locations
+                    .entry(transaction.header_signature())
+                    .or_insert_with(Vec::new)
+                    .push(index);
+            }
+        }
+
+        for batch in &self.batches {
+            for transaction in batch.transactions() {
+                let batch_indices = &locations[transaction.header_signature()];
+                if batch_indices.len() > 1 {
+                    let mut batch_ids: Vec<String> = batch_indices
+                        .iter()
+                        .map(|index| self.batches[*index].header_signature().to_string())
+                        .collect();
+                    batch_ids.dedup();
+                    return Err(BatchListBuildError::DuplicateTransaction {
+                        transaction_id: transaction.header_signature().to_string(),
+                        batch_ids,
+                    });
+                }
+            }
+        }
+
+        let mut seen: HashSet<&str> = HashSet::new();
+        for batch in &self.batches {
+            for transaction in batch.transactions() {
+                let header =
+                    transaction
+                        .parse_header()
+                        .map_err(|e| BatchListBuildError::InvalidHeader {
+                            transaction_id: transaction.header_signature().to_string(),
+                            batch_id: batch.header_signature().to_string(),
+                            reason: format!("{}", e),
+                        })?;
+
+                for dependency in header.dependencies() {
+                    let dependency = id::id_from_bytes(dependency);
+                    if seen.contains(dependency.as_str()) {
+                        continue;
+                    }
+                    if let Some(batch_indices) = locations.get(dependency.as_str()) {
+                        return Err(BatchListBuildError::MisorderedDependency {
+                            transaction_id: transaction.header_signature().to_string(),
+                            batch_id: batch.header_signature().to_string(),
+                            dependency_batch_id: self.batches[batch_indices[0]]
+                                .header_signature()
+                                .to_string(),
+                            dependency,
+                        });
+                    }
+                    if !self.external_dependencies {
+                        return Err(BatchListBuildError::MissingDependency {
+                            transaction_id: transaction.header_signature().to_string(),
+                            batch_id: batch.header_signature().to_string(),
+                            dependency,
+                        });
+                    }
+                }
+
+                seen.insert(transaction.header_signature());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates the list and returns its batches.
+    pub fn build_batches(self) -> Result<Vec<Batch>, BatchListBuildError> {
+        self.validate()?;
+        Ok(self.batches)
+    }
+
+    /// Validates the list and serializes it as an uncompressed protobuf `BatchList`.
+    pub fn build(self) -> Result<Vec<u8>, BatchListBuildError> {
+        Ok(batch_list_to_bytes(self.build_batches()?)?)
+    }
+}
+
+fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> Result<(), BatchListError> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    writer.write_all(&buf[..len]).map_err(BatchListError::from)
+}
+
+/// Reads a varint from the stream.  Returns `None` if the stream ends before the first byte and
+/// `eof_ok` is set.
+fn read_varint<R: Read>(reader: &mut R, eof_ok: bool) -> Result<Option<u64>, BatchListError> {
+    let mut value = 0u64;
+    for i in 0..10 {
+        let mut byte = [0u8; 1];
+        if reader.read(&mut byte)? == 0 {
+            if i == 0 && eof_ok {
+                return Ok(None);
+            }
+            return Err(BatchListError::DeserializationError(
+                "batch list ended in the middle of a varint".into(),
+            ));
+        }
+        value |= u64::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+
+    Err(BatchListError::DeserializationError(
+        "varint in batch list is too long".into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::{HashMethod, Transaction, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
+
+    fn make_batches() -> Vec<Batch> {
+        let signer = HashSigner::new();
+        (0..3)
+            .map(|i| {
+                let transaction = TransactionBuilder::new()
+                    .with_family_name("test".to_string())
+                    .with_family_version("1.0".to_string())
+                    .with_inputs(vec![vec![i]])
+                    .with_outputs(vec![vec![i]])
+                    .with_payload_hash_method(HashMethod::SHA512)
+                    .with_payload(vec![i; 64])
+                    .build(&signer)
+                    .unwrap();
+                BatchBuilder::new()
+                    .with_transactions(vec![transaction])
+                    .build(&signer)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batch_list_bytes() {
+        let batches = make_batches();
+        let bytes = batch_list_to_bytes(batches.clone()).unwrap();
+        assert_eq!(batches, batch_list_from_bytes(&bytes).unwrap());
+    }
+
+    #[test]
+    // test that streamed batches can be read back one at a time, and that a streamed list is
+    // interchangeable with a serialized BatchList
+    fn batch_list_stream() {
+        let batches = make_batches();
+
+        let mut writer = BatchListWriter::new(Vec::new());
+        for batch in &batches {
+            writer.write_batch(batch).unwrap();
+        }
+        let bytes = writer.into_inner().unwrap();
+
+        assert_eq!(batch_list_to_bytes(batches.clone()).unwrap(), bytes);
+        assert_eq!(batches, batch_list_from_bytes(&bytes).unwrap());
+
+        let read = BatchListReader::new(&bytes[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches, read);
+
+        let mut truncated = BatchListReader::new(&bytes[..bytes.len() - 1]);
+        assert!(truncated.next().unwrap().is_ok());
+        assert!(truncated.next().unwrap().is_ok());
+        assert!(truncated.next().unwrap().is_err());
+        assert!(truncated.next().is_none());
+    }
+
+    fn make_transaction(signer: &HashSigner, i: u8, dependencies: Vec<Vec<u8>>) -> Transaction {
+        TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![vec![i]])
+            .with_outputs(vec![vec![i]])
+            .with_dependencies(dependencies)
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(vec![i; 64])
+            .build(signer)
+            .unwrap()
+    }
+
+    fn make_batch(signer: &HashSigner, transactions: Vec<Transaction>) -> Batch {
+        BatchBuilder::new()
+            .with_transactions(transactions)
+            .build(signer)
+            .unwrap()
+    }
+
+    fn dependency_on(transaction: &Transaction) -> Vec<u8> {
+        id::id_to_bytes(transaction.header_signature()).unwrap()
+    }
+
+    #[test]
+    // test that a well-formed list, including dependencies across batches, is built
+    fn batch_list_builder() {
+        let signer = HashSigner::new();
+        let first = make_transaction(&signer, 1, vec![]);
+        let second = make_transaction(&signer, 2, vec![dependency_on(&first)]);
+        let third = make_transaction(&signer, 3, vec![dependency_on(&second)]);
+        let batches = vec![
+            make_batch(&signer, vec![first]),
+            make_batch(&signer, vec![second, third]),
+        ];
+
+        let bytes = BatchListBuilder::new()
+            .with_batches(batches.clone())
+            .with_max_batches(2)
+            .with_external_dependencies(false)
+            .build()
+            .unwrap();
+        assert_eq!(batches, batch_list_from_bytes(&bytes).unwrap());
+
+        assert_eq!(
+            Err(BatchListBuildError::TooManyBatches { count: 2, max: 1 }),
+            BatchListBuilder::new()
+                .with_batches(batches)
+                .with_max_batches(1)
+                .validate()
+        );
+    }
+
+    #[test]
+    // test that a transaction appearing in more than one batch is reported with every batch
+    // containing it
+    fn batch_list_builder_duplicate() {
+        let signer = HashSigner::new();
+        let first = make_transaction(&signer, 1, vec![]);
+        let second = make_transaction(&signer, 2, vec![]);
+        let batch1 = make_batch(&signer, vec![first.clone()]);
+        let batch2 = make_batch(&signer, vec![second, first.clone()]);
+
+        assert_eq!(
+            Err(BatchListBuildError::DuplicateTransaction {
+                transaction_id: first.header_signature().to_string(),
+                batch_ids: vec![
+                    batch1.header_signature().to_string(),
+                    batch2.header_signature().to_string(),
+                ],
+            }),
+            BatchListBuilder::new()
+                .with_batch(batch1)
+                .with_batch(batch2)
+                .validate()
+        );
+    }
+
+    #[test]
+    // test that dependencies must be ordered first, and that external dependencies can be
+    // rejected
+    fn batch_list_builder_dependencies() {
+        let signer = HashSigner::new();
+        let first = make_transaction(&signer, 1, vec![]);
+        let second = make_transaction(&signer, 2, vec![dependency_on(&first)]);
+        let batch1 = make_batch(&signer, vec![second.clone()]);
+        let batch2 = make_batch(&signer, vec![first.clone()]);
+
+        assert_eq!(
+            Err(BatchListBuildError::MisorderedDependency {
+                transaction_id: second.header_signature().to_string(),
+                batch_id: batch1.header_signature().to_string(),
+                dependency: first.header_signature().to_string(),
+                dependency_batch_id: batch2.header_signature().to_string(),
+            }),
+            BatchListBuilder::new()
+                .with_batches(vec![batch1.clone(), batch2])
+                .validate()
+        );
+
+        assert!(BatchListBuilder::new()
+            .with_batch(batch1.clone())
+            .validate()
+            .is_ok());
+        assert_eq!(
+            Err(BatchListBuildError::MissingDependency {
+                transaction_id: second.header_signature().to_string(),
+                batch_id: batch1.header_signature().to_string(),
+                dependency: first.header_signature().to_string(),
+            }),
+            BatchListBuilder::new()
+                .with_batch(batch1)
+                .with_external_dependencies(false)
+                .validate()
+        );
+    }
+
+    #[cfg(feature = "batch-compression")]
+    #[test]
+    // test that compressed lists are detected and decompressed transparently
+    fn batch_list_compressed_bytes() {
+        let batches = make_batches();
+        let uncompressed = batch_list_to_bytes(batches.clone()).unwrap();
+
+        for compression in &[Compression::Gzip, Compression::Zstd] {
+            let bytes = batch_list_to_compressed_bytes(batches.clone(), *compression).unwrap();
+            assert_eq!(Some(*compression), Compression::detect(&bytes));
+            assert_ne!(uncompressed, bytes);
+            assert_eq!(batches, batch_list_from_bytes(&bytes).unwrap());
+        }
+    }
+
+    #[cfg(feature = "batch-compression")]
+    #[test]
+    // test that a list decompressing to exactly the limit is accepted, and that a list or a
+    // highly compressed payload decompressing to more than the limit is rejected
+    fn batch_list_decompression_limit() {
+        let batches = make_batches();
+        let uncompressed = batch_list_to_bytes(batches.clone()).unwrap();
+        let size = uncompressed.len() as u64;
+        let bomb = vec![0; 16 * 1024 * 1024];
+
+        for compression in &[Compression::Gzip, Compression::Zstd] {
+            let bytes = batch_list_to_compressed_bytes(batches.clone(), *compression).unwrap();
+            assert_eq!(
+                batches,
+                batch_list_from_bytes_with_limit(&bytes, size).unwrap()
+            );
+            match batch_list_from_bytes_with_limit(&bytes, size - 1) {
+                Err(BatchListError::CompressionError(_)) => (),
+                res => panic!("Expected CompressionError, got {:?}", res),
+            }
+
+            let bytes = compression.compress(&bomb).unwrap();
+            assert!(bytes.len() < 64 * 1024);
+            match batch_list_from_bytes_with_limit(&bytes, 1024 * 1024) {
+                Err(BatchListError::CompressionError(_)) => (),
+                res => panic!("Expected CompressionError, got {:?}", res),
+            }
+        }
+    }
+}
@@ -0,0 +1,338 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Splitting of transactions into batches.
+//!
+//! A `BatchSplitter` groups a sequence of signed transactions, in order, into signed batches that
+//! respect a maximum number of transactions and a maximum size per batch.  The size of a batch is
+//! measured as the serialized size of its transactions; the batch header adds a small overhead
+//! per transaction, which callers with a hard limit on the full batch size should leave room for.
+
+use std::error::Error as StdError;
+use std::iter::Peekable;
+
+use crate::signing;
+
+use super::batch::{Batch, BatchBuildError, BatchBuilder};
+use super::transaction::Transaction;
+
+#[derive(Debug)]
+pub enum BatchSplitError {
+    /// A limit of zero was given, so no batch could hold any transaction.
+    InvalidLimit(String),
+    /// A single transaction is larger than the maximum batch size.
+    TransactionTooLarge {
+        transaction_id: String,
+        size: usize,
+        max_bytes: usize,
+    },
+    BuildError(BatchBuildError),
+}
+
+impl StdError for BatchSplitError {
+    fn description(&self) -> &str {
+        match *self {
+            BatchSplitError::InvalidLimit(ref msg) => msg,
+            BatchSplitError::TransactionTooLarge { .. } => "transaction exceeds maximum batch size",
+            BatchSplitError::BuildError(ref err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            BatchSplitError::InvalidLimit(_) => None,
+            BatchSplitError::TransactionTooLarge { .. } => None,
+            BatchSplitError::BuildError(ref err) => Some(err),
+        }
+    }
+}
+
+impl std::fmt::Display for BatchSplitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            BatchSplitError::InvalidLimit(ref s) => write!(f, "InvalidLimit: {}", s),
+            BatchSplitError::TransactionTooLarge {
+                ref transaction_id,
+                size,
+                max_bytes,
+            } => write!(
+                f,
+                "TransactionTooLarge: {} is {} bytes, maximum is {}",
+                transaction_id, size, max_bytes
+            ),
+            BatchSplitError::BuildError(ref err) => write!(f, "BuildError: {}", err),
+        }
+    }
+}
+
+impl From<BatchBuildError> for BatchSplitError {
+    fn from(e: BatchBuildError) -> Self {
+        BatchSplitError::BuildError(e)
+    }
+}
+
+/// The limits on the batches produced by a `BatchSplitter`.  By default there is no limit.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BatchLimits {
+    max_transactions: Option<usize>,
+    max_bytes: Option<usize>,
+}
+
+impl BatchLimits {
+    pub fn new() -> Self {
+        BatchLimits::default()
+    }
+
+    pub fn with_max_transactions(mut self, max_transactions: usize) -> BatchLimits {
+        self.max_transactions = Some(max_transactions);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> BatchLimits {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    fn validate(&self) -> Result<(), BatchSplitError> {
+        if self.max_transactions == Some(0) {
+            return Err(BatchSplitError::InvalidLimit(
+                "max_transactions must be at least 1".into(),
+            ));
+        }
+        if self.max_bytes == Some(0) {
+            return Err(BatchSplitError::InvalidLimit(
+                "max_bytes must be at least 1".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the batches formed from a sequence of transactions.
+///
+/// Transactions keep their order, and each batch is filled as far as the limits allow before the
+/// next is started.  Iteration stops after the first error; a transaction that alone exceeds the
+/// maximum size is reported as `TransactionTooLarge` rather than being placed in a batch.
+pub struct BatchSplitter<'a, I: Iterator<Item = Transaction>> {
+    transactions: Peekable<I>,
+    limits: BatchLimits,
+    signer: &'a signing::Signer,
+    done: bool,
+}
+
+impl<'a, I: Iterator<Item = Transaction>> BatchSplitter<'a, I> {
+    pub fn new<T>(transactions: T, limits: BatchLimits, signer: &'a signing::Signer) -> Self
+    where
+        T: IntoIterator<Item = Transaction, IntoIter = I>,
+    {
+        BatchSplitter {
+            transactions: transactions.into_iter().peekable(),
+            limits,
+            signer,
+            done: false,
+        }
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Batch>, BatchSplitError> {
+        self.limits.validate()?;
+
+        let mut batch_transactions = Vec::new();
+        let mut batch_size = 0;
+        while let Some(transaction) = self.transactions.peek() {
+            if let Some(max_transactions) = self.limits.max_transactions {
+                if batch_transactions.len() >= max_transactions {
+                    break;
+                }
+            }
+
+            let size = serialized_size(transaction);
+            if let Some(max_bytes) = self.limits.max_bytes {
+                if size > max_bytes {
+                    if !batch_transactions.is_empty() {
+                        // Finish the current batch; the oversized transaction is reported on the
+                        // next call.
+                        break;
+                    }
+                    return Err(BatchSplitError::TransactionTooLarge {
+                        transaction_id: transaction.header_signature().to_string(),
+                        size,
+                        max_bytes,
+                    });
+                }
+                if batch_size + size > max_bytes {
+                    break;
+                }
+            }
+
+            batch_size += size;
+            batch_transactions.extend(self.transactions.next());
+        }
+
+        if batch_transactions.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            BatchBuilder::new()
+                .with_transactions(batch_transactions)
+                .build(self.signer)?,
+        ))
+    }
+}
+
+impl<'a, I: Iterator<Item = Transaction>> Iterator for BatchSplitter<'a, I> {
+    type Item = Result<Batch, BatchSplitError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_batch() {
+            Ok(Some(batch)) => Some(Ok(batch)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Splits the transactions into signed batches within the given limits.
+pub fn split_into_batches<T>(
+    transactions: T,
+    limits: BatchLimits,
+    signer: &signing::Signer,
+) -> Result<Vec<Batch>, BatchSplitError>
+where
+    T: IntoIterator<Item = Transaction>,
+{
+    BatchSplitter::new(transactions, limits, signer).collect()
+}
+
+/// The size of the transaction as a length-delimited field of a serialized batch.
+fn serialized_size(transaction: &Transaction) -> usize {
+    delimited_size(
+        delimited_size(transaction.header().len())
+            + delimited_size(transaction.header_signature().len())
+            + delimited_size(transaction.payload().len()),
+    )
+}
+
+/// The size of a length-delimited protobuf field with a single-byte key.
+fn delimited_size(len: usize) -> usize {
+    let mut varint_size = 1;
+    let mut value = len >> 7;
+    while value > 0 {
+        varint_size += 1;
+        value >>= 7;
+    }
+    1 + varint_size + len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
+
+    fn make_transactions(signer: &signing::Signer, payload_sizes: &[usize]) -> Vec<Transaction> {
+        payload_sizes
+            .iter()
+            .map(|size| {
+                TransactionBuilder::new()
+                    .with_family_name("test".to_string())
+                    .with_family_version("1.0".to_string())
+                    .with_inputs(vec![vec![0x01]])
+                    .with_outputs(vec![vec![0x01]])
+                    .with_payload_hash_method(HashMethod::SHA512)
+                    .with_payload(vec![0; *size])
+                    .build(signer)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn batch_sizes(batches: &[Batch]) -> Vec<usize> {
+        batches.iter().map(|b| b.transactions().len()).collect()
+    }
+
+    #[test]
+    fn split_by_transaction_count() {
+        let signer = HashSigner::new();
+        let transactions = make_transactions(&signer, &[1; 5]);
+
+        let batches = split_into_batches(
+            transactions.clone(),
+            BatchLimits::new().with_max_transactions(2),
+            &signer,
+        )
+        .unwrap();
+        assert_eq!(vec![2, 2, 1], batch_sizes(&batches));
+
+        let split = batches
+            .iter()
+            .flat_map(|b| b.transactions().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(transactions, split);
+    }
+
+    #[test]
+    // test that batches are filled up to the size limit, and that an oversized transaction
+    // ends the split after the batches before it
+    fn split_by_size() {
+        let signer = HashSigner::new();
+        let transactions = make_transactions(&signer, &[100, 100, 100, 5000]);
+        let size = serialized_size(&transactions[0]);
+
+        let mut splitter = BatchSplitter::new(
+            transactions,
+            BatchLimits::new().with_max_bytes(size * 2 + 1),
+            &signer,
+        );
+        assert_eq!(2, splitter.next().unwrap().unwrap().transactions().len());
+        assert_eq!(1, splitter.next().unwrap().unwrap().transactions().len());
+        match splitter.next() {
+            Some(Err(BatchSplitError::TransactionTooLarge { max_bytes, .. })) => {
+                assert_eq!(size * 2 + 1, max_bytes)
+            }
+            _ => panic!("expected TransactionTooLarge"),
+        }
+        assert!(splitter.next().is_none());
+    }
+
+    #[test]
+    fn split_invalid_limit() {
+        let signer = HashSigner::new();
+        let transactions = make_transactions(&signer, &[1]);
+
+        assert!(split_into_batches(
+            transactions,
+            BatchLimits::new().with_max_transactions(0),
+            &signer
+        )
+        .is_err());
+        assert!(split_into_batches(vec![], BatchLimits::new(), &signer)
+            .unwrap()
+            .is_empty());
+    }
+}
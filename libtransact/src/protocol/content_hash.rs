@@ -0,0 +1,345 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Content hashes of protocol objects.
+//!
+//! The serialized form of a header is not a reliable cache key: protobuf preserves unknown
+//! fields, so two encodings of the same header may differ.  A content hash is instead computed
+//! from the parsed fields, and is the same for every encoding of the same content.  Signatures
+//! are not part of the content, so an object re-signed with a non-deterministic signature scheme
+//! keeps its content hash.
+//!
+//! The content hash is the SHA-512 digest of the following encoding.  A byte string is written
+//! as its length, as a big-endian `u64`, followed by its bytes; a string is written as its UTF-8
+//! bytes; a list is written as its length, as a big-endian `u64`, followed by its items; and an
+//! optional value is written as a `0` byte if absent, or a `1` byte followed by the value.  Each
+//! encoding starts with a string naming the object type and encoding version.
+//!
+//! * Transaction: `"transact/transaction/1"`, then the header's batcher public key,
+//!   dependencies, family name, family version, inputs, outputs, nonce, payload hash method
+//!   (`"SHA512"` or `"SHA256"`), payload hash, signer public key, signature algorithm name and
+//!   protocol version (as `"major.minor"`), the optional fee (the fee and the priority as
+//!   big-endian `u64` and `u32`), and finally the payload.
+//! * Batch: `"transact/batch/1"`, then the header's signer public key, signature algorithm name,
+//!   protocol version and metadata (a list of key and value pairs, in key order), and finally
+//!   the list of the content hashes of its transactions.  The batch's trace flag and
+//!   co-signatures are not included.
+//! * Transaction receipt: `"transact/receipt/1"`, then the transaction id, the list of state
+//!   changes (a `0` byte followed by the key and value of a set, or a `1` byte followed by the
+//!   key of a delete), the list of events (the event type, the list of attribute key and value
+//!   pairs, and the data) and the list of data.
+
+use sha2::{Digest, Sha512};
+
+use crate::protos::ProtoConversionError;
+
+use super::batch::{Batch, BatchHeader, BatchPair};
+use super::receipt::{StateChange, TransactionReceipt};
+use super::transaction::{HashMethod, Transaction, TransactionHeader, TransactionPair};
+
+const TRANSACTION_TAG: &str = "transact/transaction/1";
+const BATCH_TAG: &str = "transact/batch/1";
+const RECEIPT_TAG: &str = "transact/receipt/1";
+
+/// An object with a digest of its content, independent of its wire encoding.
+pub trait ContentHash {
+    /// Returns the SHA-512 content hash of the object.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ProtoConversionError` if a header of the object cannot be deserialized.
+    fn content_hash(&self) -> Result<Vec<u8>, ProtoConversionError>;
+
+    /// Returns the content hash of the object, encoded as lowercase hex.
+    fn content_hash_hex(&self) -> Result<String, ProtoConversionError> {
+        Ok(hex::encode(self.content_hash()?))
+    }
+}
+
+impl ContentHash for TransactionPair {
+    fn content_hash(&self) -> Result<Vec<u8>, ProtoConversionError> {
+        Ok(transaction_hash(
+            self.header(),
+            self.transaction().payload(),
+        ))
+    }
+}
+
+impl ContentHash for Transaction {
+    fn content_hash(&self) -> Result<Vec<u8>, ProtoConversionError> {
+        Ok(transaction_hash(&self.parse_header()?, self.payload()))
+    }
+}
+
+impl ContentHash for BatchPair {
+    fn content_hash(&self) -> Result<Vec<u8>, ProtoConversionError> {
+        batch_hash(self.header(), self.batch().transactions())
+    }
+}
+
+impl ContentHash for Batch {
+    fn content_hash(&self) -> Result<Vec<u8>, ProtoConversionError> {
+        batch_hash(&self.parse_header()?, self.transactions())
+    }
+}
+
+impl ContentHash for TransactionReceipt {
+    fn content_hash(&self) -> Result<Vec<u8>, ProtoConversionError> {
+        let mut encoder = ContentEncoder::new(RECEIPT_TAG);
+        encoder.str(&self.transaction_id);
+        encoder.len(self.state_changes.len());
+        for state_change in &self.state_changes {
+            match state_change {
+                StateChange::Set { key, value } => {
+                    encoder.byte(0);
+                    encoder.str(key);
+                    encoder.bytes(value);
+                }
+                StateChange::Delete { key } => {
+                    encoder.byte(1);
+                    encoder.str(key);
+                }
+            }
+        }
+        encoder.len(self.events.len());
+        for event in &self.events {
+            encoder.str(&event.event_type);
+            encoder.len(event.attributes.len());
+            for (key, value) in &event.attributes {
+                encoder.str(key);
+                encoder.str(value);
+            }
+            encoder.bytes(&event.data);
+        }
+        encoder.list(&self.data);
+        Ok(encoder.finish())
+    }
+}
+
+fn transaction_hash(header: &TransactionHeader, payload: &[u8]) -> Vec<u8> {
+    let mut encoder = ContentEncoder::new(TRANSACTION_TAG);
+    encoder.bytes(header.batcher_public_key());
+    encoder.list(header.dependencies());
+    encoder.str(header.family_name());
+    encoder.str(header.family_version());
+    encoder.list(header.inputs());
+    encoder.list(header.outputs());
+    encoder.bytes(header.nonce());
+    encoder.str(match header.payload_hash_method() {
+        HashMethod::SHA256 => "SHA256",
+        HashMethod::SHA512 => "SHA512",
+    });
+    encoder.bytes(header.payload_hash());
+    encoder.bytes(header.signer_public_key());
+    encoder.str(header.signature_algorithm().name());
+    encoder.str(&header.protocol_version().to_string());
+    match header.fee() {
+        Some(fee) => {
+            encoder.byte(1);
+            encoder.hasher.input(&fee.fee().to_be_bytes());
+            encoder.hasher.input(&fee.priority().to_be_bytes());
+        }
+        None => encoder.byte(0),
+    }
+    encoder.bytes(payload);
+    encoder.finish()
+}
+
+fn batch_hash(
+    header: &BatchHeader,
+    transactions: &[Transaction],
+) -> Result<Vec<u8>, ProtoConversionError> {
+    let mut encoder = ContentEncoder::new(BATCH_TAG);
+    encoder.bytes(header.signer_public_key());
+    encoder.str(header.signature_algorithm().name());
+    encoder.str(&header.protocol_version().to_string());
+    encoder.len(header.metadata().len());
+    for (key, value) in header.metadata() {
+        encoder.str(key);
+        encoder.str(value);
+    }
+    encoder.len(transactions.len());
+    for transaction in transactions {
+        encoder.bytes(&transaction.content_hash()?);
+    }
+    Ok(encoder.finish())
+}
+
+/// Writes the content encoding directly into the hasher.
+struct ContentEncoder {
+    hasher: Sha512,
+}
+
+impl ContentEncoder {
+    fn new(tag: &str) -> Self {
+        let mut encoder = ContentEncoder {
+            hasher: Sha512::new(),
+        };
+        encoder.str(tag);
+        encoder
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.hasher.input(&[byte]);
+    }
+
+    fn len(&mut self, len: usize) {
+        self.hasher.input(&(len as u64).to_be_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.hasher.input(bytes);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    fn list(&mut self, items: &[Vec<u8>]) {
+        self.len(items.len());
+        for item in items {
+            self.bytes(item);
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.hasher.result().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::receipt::TransactionReceiptBuilder;
+    use crate::protocol::transaction::TransactionBuilder;
+    use crate::signing::hash::HashSigner;
+
+    fn make_transaction(payload: &[u8]) -> Transaction {
+        TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![vec![0x01]])
+            .with_outputs(vec![vec![0x01]])
+            .with_nonce(b"nonce".to_vec())
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(payload.to_vec())
+            .build(&HashSigner::new())
+            .unwrap()
+    }
+
+    // Appends an unknown varint field (number 99) to the serialized message
+    fn with_unknown_field(bytes: &[u8]) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        bytes.extend_from_slice(&[0x98, 0x06, 0x01]);
+        bytes
+    }
+
+    #[test]
+    // test that the content hash ignores unknown fields and signatures, but not content
+    fn transaction_content_hash() {
+        let transaction = make_transaction(b"payload");
+        let hash = transaction.content_hash().unwrap();
+        assert_eq!(64, hash.len());
+        assert_eq!(
+            hash,
+            transaction
+                .clone()
+                .into_pair()
+                .unwrap()
+                .content_hash()
+                .unwrap()
+        );
+
+        let noisy = Transaction::new(
+            with_unknown_field(transaction.header()),
+            "abcd".to_string(),
+            transaction.payload().to_vec(),
+        );
+        assert_ne!(transaction.header(), noisy.header());
+        assert_eq!(hash, noisy.content_hash().unwrap());
+
+        assert_ne!(
+            hash,
+            make_transaction(b"other payload").content_hash().unwrap()
+        );
+        assert_eq!(hex::encode(&hash), transaction.content_hash_hex().unwrap());
+    }
+
+    #[test]
+    // test that batches hash by their header content and their transactions' content
+    fn batch_content_hash() {
+        let signer = HashSigner::new();
+        let batch = BatchBuilder::new()
+            .with_transactions(vec![make_transaction(b"one"), make_transaction(b"two")])
+            .build_pair(&signer)
+            .unwrap();
+        let hash = batch.content_hash().unwrap();
+        assert_eq!(hash, batch.batch().content_hash().unwrap());
+
+        let traced = BatchBuilder::new()
+            .with_transactions(vec![make_transaction(b"one"), make_transaction(b"two")])
+            .with_trace(true)
+            .build(&signer)
+            .unwrap();
+        assert_eq!(hash, traced.content_hash().unwrap());
+
+        let reordered = BatchBuilder::new()
+            .with_transactions(vec![make_transaction(b"two"), make_transaction(b"one")])
+            .build(&signer)
+            .unwrap();
+        assert_ne!(hash, reordered.content_hash().unwrap());
+
+        let tagged = BatchBuilder::new()
+            .with_transactions(vec![make_transaction(b"one"), make_transaction(b"two")])
+            .with_metadata_entry("tenant".into(), "acme".into())
+            .build(&signer)
+            .unwrap();
+        assert_ne!(hash, tagged.content_hash().unwrap());
+    }
+
+    #[test]
+    // test that receipts with different content hash differently
+    fn receipt_content_hash() {
+        let receipt = TransactionReceiptBuilder::new()
+            .with_transaction_id("abcd".to_string())
+            .with_state_changes(vec![
+                StateChange::Set {
+                    key: "01".to_string(),
+                    value: b"value".to_vec(),
+                },
+                StateChange::Delete {
+                    key: "02".to_string(),
+                },
+            ])
+            .with_data(vec![b"data".to_vec()])
+            .build()
+            .unwrap();
+        let hash = receipt.content_hash().unwrap();
+        assert_eq!(hash, receipt.clone().content_hash().unwrap());
+
+        let mut other = receipt.clone();
+        other.data = vec![b"da".to_vec(), b"ta".to_vec()];
+        assert_ne!(hash, other.content_hash().unwrap());
+
+        let mut other = receipt.clone();
+        other.state_changes.reverse();
+        assert_ne!(hash, other.content_hash().unwrap());
+    }
+}
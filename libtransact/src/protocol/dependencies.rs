@@ -0,0 +1,149 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Computation of transaction dependencies.
+//!
+//! A transaction must declare a dependency on each earlier transaction in its batch that it
+//! conflicts with: one that writes an address it reads or writes, or that reads an address it
+//! writes.  Addresses in inputs and outputs may be namespace wildcards, so two addresses overlap
+//! when some address is covered by both (see `protocol::namespace`).
+
+use super::namespace::any_intersects;
+use super::transaction::{TransactionBuildError, TransactionPair};
+
+/// The id and declared addresses of a transaction that a new transaction may depend on.
+#[derive(Clone, Debug)]
+pub(crate) struct PriorTransaction {
+    id: String,
+    inputs: Vec<Vec<u8>>,
+    outputs: Vec<Vec<u8>>,
+}
+
+impl PriorTransaction {
+    pub(crate) fn from_pair(pair: &TransactionPair) -> Self {
+        PriorTransaction {
+            id: pair.transaction().header_signature().to_string(),
+            inputs: pair.header().inputs().to_vec(),
+            outputs: pair.header().outputs().to_vec(),
+        }
+    }
+
+    fn conflicts_with(&self, inputs: &[Vec<u8>], outputs: &[Vec<u8>]) -> bool {
+        any_intersects(&self.outputs, inputs)
+            || any_intersects(&self.outputs, outputs)
+            || any_intersects(&self.inputs, outputs)
+    }
+}
+
+/// Returns the ids of the prior transactions that a transaction with the given inputs and
+/// outputs conflicts with, in the order of `prior`.
+pub fn compute_dependencies(
+    prior: &[TransactionPair],
+    inputs: &[Vec<u8>],
+    outputs: &[Vec<u8>],
+) -> Result<Vec<Vec<u8>>, TransactionBuildError> {
+    let prior = prior
+        .iter()
+        .map(PriorTransaction::from_pair)
+        .collect::<Vec<_>>();
+    dependencies_on(&prior, inputs, outputs)
+}
+
+pub(crate) fn dependencies_on(
+    prior: &[PriorTransaction],
+    inputs: &[Vec<u8>],
+    outputs: &[Vec<u8>],
+) -> Result<Vec<Vec<u8>>, TransactionBuildError> {
+    prior
+        .iter()
+        .filter(|txn| txn.conflicts_with(inputs, outputs))
+        .map(|txn| {
+            hex::decode(&txn.id).map_err(|e| {
+                TransactionBuildError::DeserializationError(format!(
+                    "invalid transaction id {}: {}",
+                    txn.id, e
+                ))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
+
+    fn make_pair(inputs: Vec<Vec<u8>>, outputs: Vec<Vec<u8>>) -> TransactionPair {
+        TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(inputs)
+            .with_outputs(outputs)
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(b"payload".to_vec())
+            .build_pair(&HashSigner::new())
+            .unwrap()
+    }
+
+    fn id(pair: &TransactionPair) -> Vec<u8> {
+        hex::decode(pair.transaction().header_signature()).unwrap()
+    }
+
+    #[test]
+    // test that only conflicting prior transactions become dependencies
+    fn compute_dependencies_conflicts() {
+        let writes_a = make_pair(vec![vec![0xaa]], vec![vec![0xaa, 0x01]]);
+        let reads_b = make_pair(vec![vec![0xbb]], vec![]);
+        let writes_c = make_pair(vec![], vec![vec![0xcc]]);
+        let prior = vec![writes_a, reads_b, writes_c];
+
+        // reads a prefix of a written address, and writes an address that was read
+        let deps = compute_dependencies(&prior, &[vec![0xaa]], &[vec![0xbb, 0x02]]).unwrap();
+        assert_eq!(vec![id(&prior[0]), id(&prior[1])], deps);
+
+        // reads an address that was only read
+        let deps = compute_dependencies(&prior, &[vec![0xbb]], &[]).unwrap();
+        assert!(deps.is_empty());
+    }
+
+    #[test]
+    // test that the builder merges computed dependencies with declared ones
+    fn builder_dependencies_from() {
+        let first = make_pair(vec![vec![0xaa]], vec![vec![0xaa]]);
+        let second = make_pair(vec![vec![0xbb]], vec![vec![0xbb]]);
+        let prior = vec![first, second];
+
+        let pair = TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_dependencies(vec![id(&prior[1])])
+            .with_dependencies_from(&prior)
+            .with_inputs(vec![vec![0xaa], vec![0xbb]])
+            .with_outputs(vec![])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(b"payload".to_vec())
+            .build_pair(&HashSigner::new())
+            .unwrap();
+
+        assert_eq!(
+            &[id(&prior[1]), id(&prior[0])][..],
+            pair.header().dependencies()
+        );
+    }
+}
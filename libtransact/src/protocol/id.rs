@@ -0,0 +1,112 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Derivation of transaction and batch identifiers.
+//!
+//! The identifier of a transaction or batch is its header signature: the signature of the
+//! serialized header by the header's signer, encoded as lowercase hex.  Headers refer to other
+//! transactions (in `dependencies` and `transaction_ids`) by the raw bytes of the signature,
+//! i.e. the hex-decoded identifier.
+//!
+//! Some services need an identifier that does not depend on who signed the object, for instance
+//! to deduplicate objects signed with a non-deterministic signature scheme.  The content-hash
+//! identifier is the lowercase hex SHA-512 hash of the serialized header.  Because the header
+//! commits to the payload hash (for transactions) or the transaction ids (for batches), the
+//! content-hash identifier commits to the whole object.  It hashes the header's wire bytes; for
+//! a hash that is independent of the encoding, see `protocol::content_hash`.
+//!
+//! These functions operate on the serialized header, so that identifiers can be computed without
+//! constructing `Transaction` or `Batch` objects.
+
+use sha2::{Digest, Sha512};
+
+use crate::signing;
+#[cfg(feature = "async")]
+use crate::signing::async_signer::AsyncSigner;
+
+/// Returns the identifier of a transaction or batch with the given header signature.
+pub fn id_from_signature(header_signature: &[u8]) -> String {
+    hex::encode(header_signature)
+}
+
+/// Signs the serialized header, returning the resulting identifier.
+pub fn sign_header(header: &[u8], signer: &signing::Signer) -> Result<String, signing::Error> {
+    Ok(id_from_signature(&signer.sign(header)?))
+}
+
+/// Signs the serialized header with an asynchronous signer, returning the resulting identifier.
+#[cfg(feature = "async")]
+pub async fn sign_header_async(
+    header: &[u8],
+    signer: &dyn AsyncSigner,
+) -> Result<String, signing::Error> {
+    Ok(id_from_signature(&signer.sign(header).await?))
+}
+
+/// Returns the content-hash identifier of a transaction or batch with the given serialized
+/// header.
+pub fn content_hash_id(header: &[u8]) -> String {
+    let mut hasher = Sha512::new();
+    hasher.input(header);
+    hex::encode(hasher.result())
+}
+
+/// Returns the form in which an identifier is referenced from other headers.
+pub fn id_to_bytes(id: &str) -> Result<Vec<u8>, hex::FromHexError> {
+    hex::decode(id)
+}
+
+/// Returns the identifier referenced by the given header bytes.
+pub fn id_from_bytes(id: &[u8]) -> String {
+    hex::encode(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
+
+    #[test]
+    // test that the id derivation matches the ids of built transactions
+    fn transaction_ids() {
+        let signer = HashSigner::new();
+        let transaction = TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![])
+            .with_outputs(vec![])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(b"payload".to_vec())
+            .build(&signer)
+            .unwrap();
+
+        assert_eq!(
+            transaction.header_signature(),
+            sign_header(transaction.header(), &signer).unwrap()
+        );
+
+        let id_bytes = id_to_bytes(transaction.header_signature()).unwrap();
+        assert_eq!(transaction.header_signature(), id_from_bytes(&id_bytes));
+        assert_eq!(transaction.header_signature(), id_from_signature(&id_bytes));
+
+        let content_id = content_hash_id(transaction.header());
+        assert_eq!(128, content_id.len());
+        assert_eq!(content_id, content_hash_id(transaction.header()));
+    }
+}
@@ -0,0 +1,58 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Canonical JSON representations of the protocol types.
+//!
+//! With the `serde` feature enabled, `TransactionHeader`, `Transaction`, `BatchHeader`, `Batch`,
+//! `TransactionReceipt`, `StateChange` and `Event` implement `Serialize` and `Deserialize`.  The
+//! field names and encodings below are fixed, so that services which do not use protobuf can
+//! consume them.  The implementations are not specific to JSON, and may be used with any serde
+//! format, such as CBOR.
+//!
+//! All byte fields (keys, addresses, hashes, payloads, serialized headers and data) are encoded as
+//! lowercase hex strings.  Signatures and transaction ids are already hex strings and are passed
+//! through unchanged.
+//!
+//! * `TransactionHeader`: `batcher_public_key`, `dependencies` (list), `family_name`,
+//!   `family_version`, `inputs` (list), `outputs` (list), `nonce` (the UTF-8 nonce, not hex),
+//!   `payload_hash`, `payload_hash_method` (`"SHA512"` or `"SHA256"`), `signer_public_key`,
+//!   `signature_algorithm` (e.g. `"secp256k1"`, the default when absent), `protocol_version`
+//!   (e.g. `"1.0"`, the default when absent) and, if the transaction declares one, `fee` (an
+//!   object with numeric `fee` and `priority`).
+//! * `Transaction`: `header` (the signed header bytes), `header_signature` and `payload`.
+//! * `BatchHeader`: `signer_public_key`, `transaction_ids` (list), `signature_algorithm`,
+//!   `protocol_version` and `metadata` (an object of string tags, omitted when empty).
+//! * `Batch`: `header` (the signed header bytes), `header_signature`, `transactions` (list of
+//!   transactions), `trace` (boolean) and `cosignatures` (list of objects with
+//!   `signer_public_key` and `signature`).
+//! * `TransactionReceipt`: `transaction_id`, `state_changes` (list of objects with `type` set to
+//!   `"set"` or `"delete"`, a `key` and, for `"set"`, a `value`), `events` (list of objects with
+//!   `event_type`, `attributes` as a list of `key`/`value` objects, and `data`) and `data` (list).
+//! * `StateChange` and `Event`: as within `TransactionReceipt`.
+//! * `RedactedTransaction` and `RedactedBatch` (see `protocol::redact`): as `Transaction` and
+//!   `Batch`, with `payload_hash` in place of each transaction's `payload`.
+//!
+//! Headers are carried as their signed bytes, rather than in expanded form, so that signatures
+//! remain verifiable after a round trip through JSON.
+
+pub(crate) fn decode_hex_list(values: &[String]) -> Result<Vec<Vec<u8>>, hex::FromHexError> {
+    values.iter().map(hex::decode).collect()
+}
+
+pub(crate) fn encode_hex_list(values: &[Vec<u8>]) -> Vec<String> {
+    values.iter().map(hex::encode).collect()
+}
@@ -21,5 +21,22 @@
 //! scheduled and executed.  The resuls of execution are stored in transaction receipts.
 
 pub mod batch;
+pub mod batch_list;
+pub mod batch_split;
+pub mod content_hash;
+pub mod dependencies;
+pub mod id;
+#[cfg(feature = "serde")]
+mod json;
+pub mod namespace;
+pub mod nonce;
 pub mod receipt;
+pub mod receipt_compact;
+pub mod receipt_merkle;
+pub mod redact;
+pub mod signed_receipt;
+pub mod structure;
 pub mod transaction;
+pub mod verify;
+pub mod version;
+pub mod view;
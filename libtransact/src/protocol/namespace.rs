@@ -0,0 +1,107 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Namespace semantics for the addresses declared in transaction headers.
+//!
+//! A transaction declares the addresses it may read as its inputs, and those it may write as its
+//! outputs.  A declared address shorter than a full address is a namespace wildcard, covering
+//! every address that starts with it; a declared address of full length covers only itself.
+//! Dependency computation (see `protocol::dependencies`), `TransactionHeader::may_read` and
+//! `may_write`, and the context manager's checks on the reads and writes of a transaction's
+//! context all use these rules.
+
+/// The length in bytes of a full Merkle-Radix address.
+pub const ADDRESS_LENGTH: usize = 35;
+
+/// Returns true if the declared address is a namespace wildcard, rather than a full address.
+pub fn is_wildcard(declared: &[u8]) -> bool {
+    declared.len() < ADDRESS_LENGTH
+}
+
+/// Returns true if the declared address, or namespace, covers the given address.
+pub fn covers(declared: &[u8], address: &[u8]) -> bool {
+    if is_wildcard(declared) {
+        address.starts_with(declared)
+    } else {
+        declared == address
+    }
+}
+
+/// Returns true if some address is covered by both declared addresses.
+pub fn intersects(a: &[u8], b: &[u8]) -> bool {
+    covers(a, b) || covers(b, a)
+}
+
+/// Returns true if any of the declared addresses covers the given address.
+pub fn any_covers(declared: &[Vec<u8>], address: &[u8]) -> bool {
+    declared.iter().any(|d| covers(d, address))
+}
+
+/// Returns true if any address declared in `a` intersects any address declared in `b`.
+pub fn any_intersects(a: &[Vec<u8>], b: &[Vec<u8>]) -> bool {
+    a.iter().any(|x| b.iter().any(|y| intersects(x, y)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(prefix: &[u8]) -> Vec<u8> {
+        let mut address = prefix.to_vec();
+        address.resize(ADDRESS_LENGTH, 0xab);
+        address
+    }
+
+    #[test]
+    // test that short addresses cover their namespace and full addresses only themselves
+    fn namespace_covers() {
+        let full = address(&[0x01, 0x02]);
+        let other = address(&[0x01, 0x03]);
+
+        assert!(is_wildcard(&[0x01]));
+        assert!(!is_wildcard(&full));
+
+        assert!(covers(&[0x01], &full));
+        assert!(covers(&[0x01, 0x02], &full));
+        assert!(!covers(&[0x02], &full));
+        assert!(covers(&full, &full));
+        assert!(!covers(&full, &other));
+        assert!(covers(&[], &full));
+
+        assert!(any_covers(&[vec![0x02], vec![0x01]], &full));
+        assert!(!any_covers(&[vec![0x02], other.clone()], &full));
+    }
+
+    #[test]
+    // test that declared addresses intersect when some address is covered by both
+    fn namespace_intersects() {
+        let full = address(&[0x01, 0x02]);
+        let other = address(&[0x01, 0x03]);
+
+        assert!(intersects(&[0x01], &[0x01, 0x02]));
+        assert!(intersects(&[0x01, 0x02], &[0x01]));
+        assert!(!intersects(&[0x01, 0x02], &[0x01, 0x03]));
+        assert!(intersects(&[0x01], &full));
+        assert!(intersects(&full, &[0x01]));
+        assert!(!intersects(&[0x01, 0x03], &full));
+        assert!(intersects(&full, &full));
+        assert!(!intersects(&full, &other));
+
+        assert!(any_intersects(&[vec![0x02], vec![0x01]], &[full.clone()]));
+        assert!(!any_intersects(&[vec![0x02], other], &[full]));
+    }
+}
@@ -0,0 +1,194 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Strategies for generating transaction nonces.
+//!
+//! A `NonceStrategy` may be supplied to a `TransactionBuilder`, which will use it to generate the
+//! nonce of each transaction it builds when a nonce has not been set explicitly.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use uuid::Uuid;
+
+static DEFAULT_NONCE_SIZE: usize = 32;
+
+/// Generates nonces for transaction headers.
+///
+/// Nonces are stored as strings in the serialized header, so implementations must produce valid
+/// UTF-8.
+pub trait NonceStrategy: Send + Sync {
+    fn next_nonce(&self) -> Vec<u8>;
+}
+
+/// Generates random alphanumeric nonces.
+///
+/// This is the strategy used by a `TransactionBuilder` when no other strategy has been provided.
+#[derive(Clone)]
+pub struct RandomNonce {
+    size: usize,
+}
+
+impl RandomNonce {
+    pub fn new() -> Self {
+        RandomNonce::default()
+    }
+
+    /// Creates a strategy which generates nonces of `size` characters.
+    pub fn with_size(size: usize) -> Self {
+        RandomNonce { size }
+    }
+}
+
+impl Default for RandomNonce {
+    fn default() -> Self {
+        RandomNonce {
+            size: DEFAULT_NONCE_SIZE,
+        }
+    }
+}
+
+impl NonceStrategy for RandomNonce {
+    fn next_nonce(&self) -> Vec<u8> {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(self.size)
+            .collect::<String>()
+            .into_bytes()
+    }
+}
+
+/// Generates random (version 4) UUID nonces, in their hyphenated form.
+#[derive(Clone, Default)]
+pub struct UuidNonce;
+
+impl UuidNonce {
+    pub fn new() -> Self {
+        UuidNonce::default()
+    }
+}
+
+impl NonceStrategy for UuidNonce {
+    fn next_nonce(&self) -> Vec<u8> {
+        Uuid::new_v4().to_hyphenated().to_string().into_bytes()
+    }
+}
+
+/// Generates nonces from a monotonically increasing counter.
+///
+/// The counter is atomic, so a single `CounterNonce` may be shared between threads (for example,
+/// behind an `Arc`) and every transaction built with it will receive a distinct nonce.  An
+/// optional prefix keeps nonces from different generators apart.
+#[derive(Default)]
+pub struct CounterNonce {
+    prefix: String,
+    counter: AtomicUsize,
+}
+
+impl CounterNonce {
+    pub fn new() -> Self {
+        CounterNonce::default()
+    }
+
+    /// Creates a counter which prepends `prefix` to each nonce and starts counting at `start`.
+    pub fn with_prefix(prefix: &str, start: usize) -> Self {
+        CounterNonce {
+            prefix: prefix.to_string(),
+            counter: AtomicUsize::new(start),
+        }
+    }
+}
+
+impl NonceStrategy for CounterNonce {
+    fn next_nonce(&self) -> Vec<u8> {
+        let count = self.counter.fetch_add(1, Ordering::SeqCst);
+        format!("{}{}", self.prefix, count).into_bytes()
+    }
+}
+
+/// Generates nonces from the current system time, as nanoseconds since the Unix epoch.
+///
+/// Transactions built within the same clock tick will share a nonce; use a `CounterNonce` where
+/// that is a concern.
+#[derive(Clone, Default)]
+pub struct TimestampNonce;
+
+impl TimestampNonce {
+    pub fn new() -> Self {
+        TimestampNonce::default()
+    }
+}
+
+impl NonceStrategy for TimestampNonce {
+    fn next_nonce(&self) -> Vec<u8> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        nanos.to_string().into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn random_nonce_size() {
+        assert_eq!(32, RandomNonce::new().next_nonce().len());
+        assert_eq!(8, RandomNonce::with_size(8).next_nonce().len());
+    }
+
+    #[test]
+    fn uuid_nonce_is_uuid() {
+        let nonce = String::from_utf8(UuidNonce::new().next_nonce()).unwrap();
+        assert!(Uuid::parse_str(&nonce).is_ok());
+    }
+
+    #[test]
+    fn counter_nonce_sequence() {
+        let strategy = CounterNonce::with_prefix("load-", 5);
+        assert_eq!(b"load-5".to_vec(), strategy.next_nonce());
+        assert_eq!(b"load-6".to_vec(), strategy.next_nonce());
+    }
+
+    #[test]
+    // test that a shared counter hands out distinct nonces across threads
+    fn counter_nonce_threads() {
+        let strategy = Arc::new(CounterNonce::new());
+
+        let handles = (0..4)
+            .map(|_| {
+                let strategy = strategy.clone();
+                thread::spawn(move || (0..100).map(|_| strategy.next_nonce()).collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+
+        let nonces = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<HashSet<_>>();
+
+        assert_eq!(400, nonces.len());
+    }
+}
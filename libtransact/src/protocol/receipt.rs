@@ -22,7 +22,9 @@ use crate::protos;
 use crate::protos::{
     FromBytes, FromNative, FromProto, IntoBytes, IntoNative, IntoProto, ProtoConversionError,
 };
+use std::collections::BTreeMap;
 use std::error::Error as StdError;
+use std::str::FromStr;
 
 /// A change to be applied to state, in terms of keys and values.
 ///
@@ -143,6 +145,43 @@ pub struct TransactionReceipt {
     pub data: Vec<Vec<u8>>,
 
     pub transaction_id: String,
+
+    /// Metadata tags of the batch that contained this transaction; empty if it had none.
+    pub batch_metadata: BTreeMap<String, String>,
+    /// Whether the batch that contained this transaction requested tracing.
+    pub trace: bool,
+}
+
+impl TransactionReceipt {
+    /// Returns the events of this receipt which match the given query.
+    pub fn events_matching<'a>(&'a self, query: &'a EventQuery) -> impl Iterator<Item = &'a Event> {
+        self.events.iter().filter(move |event| query.matches(event))
+    }
+
+    /// Returns the events of this receipt with the given event type.
+    pub fn events_of_type<'a>(&'a self, event_type: &'a str) -> impl Iterator<Item = &'a Event> {
+        self.events
+            .iter()
+            .filter(move |event| event.event_type == event_type)
+    }
+}
+
+/// Queries the events of a list of receipts.
+///
+/// The matching events are returned in receipt order, each paired with the id of the transaction
+/// that produced it.
+pub fn query_events<'a>(
+    receipts: &'a [TransactionReceipt],
+    query: &'a EventQuery,
+) -> impl Iterator<Item = ReceiptEvent<'a>> {
+    receipts.iter().flat_map(move |receipt| {
+        receipt
+            .events_matching(query)
+            .map(move |event| ReceiptEvent {
+                transaction_id: &receipt.transaction_id,
+                event,
+            })
+    })
 }
 
 impl FromProto<protos::transaction_receipt::TransactionReceipt> for TransactionReceipt {
@@ -164,6 +203,12 @@ impl FromProto<protos::transaction_receipt::TransactionReceipt> for TransactionR
                 .collect::<Result<Vec<Event>, ProtoConversionError>>()?,
             data: transaction_receipt.get_data().to_vec(),
             transaction_id: transaction_receipt.get_transaction_id().to_string(),
+            batch_metadata: transaction_receipt
+                .get_batch_metadata()
+                .iter()
+                .map(|entry| (entry.get_key().to_string(), entry.get_value().to_string()))
+                .collect(),
+            trace: transaction_receipt.get_trace(),
         })
     }
 }
@@ -188,6 +233,19 @@ impl FromNative<TransactionReceipt> for protos::transaction_receipt::Transaction
         proto_transaction_receipt
             .set_data(protobuf::RepeatedField::from_vec(transaction_receipt.data));
         proto_transaction_receipt.set_transaction_id(transaction_receipt.transaction_id);
+        proto_transaction_receipt.set_batch_metadata(
+            transaction_receipt
+                .batch_metadata
+                .into_iter()
+                .map(|(key, value)| {
+                    let mut entry = protos::batch::BatchMetadataEntry::new();
+                    entry.set_key(key);
+                    entry.set_value(value);
+                    entry
+                })
+                .collect(),
+        );
+        proto_transaction_receipt.set_trace(transaction_receipt.trace);
         Ok(proto_transaction_receipt)
     }
 }
@@ -293,6 +351,123 @@ impl IntoBytes for Event {
 impl IntoProto<protos::events::Event> for Event {}
 impl IntoNative<Event> for protos::events::Event {}
 
+impl Event {
+    /// Returns the value of the first attribute with the given key, if any.
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns true if the event has an attribute with the given key and value.
+    pub fn has_attribute(&self, key: &str, value: &str) -> bool {
+        self.attributes.iter().any(|(k, v)| k == key && v == value)
+    }
+
+    /// Returns the values of every attribute with the given key, in order.
+    pub fn attribute_values<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.attributes
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the key/value pairs of the attributes whose keys start with the given prefix, in
+    /// order.
+    pub fn attributes_matching<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.attributes
+            .iter()
+            .filter(move |(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Parses the value of the first attribute with the given key.
+    ///
+    /// Returns `None` if the event has no such attribute, or the parse error if its value is not
+    /// a valid `T`.
+    pub fn parse_attribute<T: FromStr>(&self, key: &str) -> Option<Result<T, T::Err>> {
+        self.attribute(key).map(str::parse)
+    }
+}
+
+/// Criteria for selecting events from transaction receipts.
+///
+/// An event matches if it has the query's event type (when set), and satisfies every attribute
+/// criterion.  An attribute criterion with no value only requires the key to be present.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventQuery {
+    event_type: Option<String>,
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl EventQuery {
+    pub fn new() -> Self {
+        EventQuery::default()
+    }
+
+    pub fn with_event_type(mut self, event_type: &str) -> EventQuery {
+        self.event_type = Some(event_type.to_string());
+        self
+    }
+
+    pub fn with_attribute(mut self, key: &str, value: &str) -> EventQuery {
+        self.attributes
+            .push((key.to_string(), Some(value.to_string())));
+        self
+    }
+
+    pub fn with_attribute_key(mut self, key: &str) -> EventQuery {
+        self.attributes.push((key.to_string(), None));
+        self
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        if let Some(ref event_type) = self.event_type {
+            if &event.event_type != event_type {
+                return false;
+            }
+        }
+
+        self.attributes.iter().all(|(key, value)| match value {
+            Some(value) => event.has_attribute(key, value),
+            None => event.attribute(key).is_some(),
+        })
+    }
+}
+
+/// An event found by `query_events`, along with the transaction that produced it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReceiptEvent<'a> {
+    transaction_id: &'a str,
+    event: &'a Event,
+}
+
+impl<'a> ReceiptEvent<'a> {
+    pub fn transaction_id(&self) -> &'a str {
+        self.transaction_id
+    }
+
+    pub fn event(&self) -> &'a Event {
+        self.event
+    }
+
+    pub fn event_type(&self) -> &'a str {
+        &self.event.event_type
+    }
+
+    pub fn attribute(&self, key: &str) -> Option<&'a str> {
+        self.event.attribute(key)
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        &self.event.data
+    }
+}
+
 #[derive(Debug)]
 pub enum EventBuilderError {
     MissingField(String),
@@ -342,6 +517,11 @@ impl EventBuilder {
         self
     }
 
+    pub fn with_attribute(mut self, key: String, value: String) -> EventBuilder {
+        self.attributes.push((key, value));
+        self
+    }
+
     pub fn with_data(mut self, data: Vec<u8>) -> EventBuilder {
         self.data = data;
         self
@@ -393,6 +573,8 @@ pub struct TransactionReceiptBuilder {
     pub events: Vec<Event>,
     pub data: Vec<Vec<u8>>,
     pub transaction_id: Option<String>,
+    pub batch_metadata: BTreeMap<String, String>,
+    pub trace: bool,
 }
 
 impl TransactionReceiptBuilder {
@@ -423,6 +605,21 @@ impl TransactionReceiptBuilder {
         self
     }
 
+    /// Sets the metadata tags of the batch that contained the transaction.
+    pub fn with_batch_metadata(
+        mut self,
+        batch_metadata: BTreeMap<String, String>,
+    ) -> TransactionReceiptBuilder {
+        self.batch_metadata = batch_metadata;
+        self
+    }
+
+    /// Sets whether the batch that contained the transaction requested tracing.
+    pub fn with_trace(mut self, trace: bool) -> TransactionReceiptBuilder {
+        self.trace = trace;
+        self
+    }
+
     pub fn build(self) -> Result<TransactionReceipt, TransactionReceiptBuilderError> {
         let transaction_id = self.transaction_id.ok_or_else(|| {
             TransactionReceiptBuilderError::MissingField(
@@ -435,10 +632,701 @@ impl TransactionReceiptBuilder {
             events: self.events,
             data: self.data,
             transaction_id,
+            batch_metadata: self.batch_metadata,
+            trace: self.trace,
         })
     }
 }
 
+#[cfg(feature = "serde")]
+mod json {
+    use std::collections::BTreeMap;
+
+    use serde::de::Error as DeError;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{Event, StateChange, TransactionReceipt};
+    use crate::protocol::json::{decode_hex_list, encode_hex_list};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "lowercase")]
+    enum StateChangeJson {
+        Set { key: String, value: String },
+        Delete { key: String },
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct AttributeJson {
+        key: String,
+        value: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct EventJson {
+        event_type: String,
+        attributes: Vec<AttributeJson>,
+        data: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TransactionReceiptJson {
+        transaction_id: String,
+        state_changes: Vec<StateChangeJson>,
+        events: Vec<EventJson>,
+        data: Vec<String>,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        batch_metadata: BTreeMap<String, String>,
+        #[serde(default, skip_serializing_if = "is_false")]
+        trace: bool,
+    }
+
+    fn is_false(value: &bool) -> bool {
+        !*value
+    }
+
+    impl From<&StateChange> for StateChangeJson {
+        fn from(state_change: &StateChange) -> Self {
+            match state_change {
+                StateChange::Set { key, value } => StateChangeJson::Set {
+                    key: key.clone(),
+                    value: hex::encode(value),
+                },
+                StateChange::Delete { key } => StateChangeJson::Delete { key: key.clone() },
+            }
+        }
+    }
+
+    impl StateChangeJson {
+        fn into_state_change(self) -> Result<StateChange, hex::FromHexError> {
+            Ok(match self {
+                StateChangeJson::Set { key, value } => StateChange::Set {
+                    key,
+                    value: hex::decode(&value)?,
+                },
+                StateChangeJson::Delete { key } => StateChange::Delete { key },
+            })
+        }
+    }
+
+    impl From<&Event> for EventJson {
+        fn from(event: &Event) -> Self {
+            EventJson {
+                event_type: event.event_type.clone(),
+                attributes: event
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| AttributeJson {
+                        key: key.clone(),
+                        value: value.clone(),
+                    })
+                    .collect(),
+                data: hex::encode(&event.data),
+            }
+        }
+    }
+
+    impl EventJson {
+        fn into_event(self) -> Result<Event, hex::FromHexError> {
+            Ok(Event {
+                event_type: self.event_type,
+                attributes: self
+                    .attributes
+                    .into_iter()
+                    .map(|attr| (attr.key, attr.value))
+                    .collect(),
+                data: hex::decode(&self.data)?,
+            })
+        }
+    }
+
+    impl Serialize for StateChange {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            StateChangeJson::from(self).serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for StateChange {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            StateChangeJson::deserialize(deserializer)?
+                .into_state_change()
+                .map_err(D::Error::custom)
+        }
+    }
+
+    impl Serialize for Event {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            EventJson::from(self).serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Event {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            EventJson::deserialize(deserializer)?
+                .into_event()
+                .map_err(D::Error::custom)
+        }
+    }
+
+    impl Serialize for TransactionReceipt {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            TransactionReceiptJson {
+                transaction_id: self.transaction_id.clone(),
+                state_changes: self
+                    .state_changes
+                    .iter()
+                    .map(StateChangeJson::from)
+                    .collect(),
+                events: self.events.iter().map(EventJson::from).collect(),
+                data: encode_hex_list(&self.data),
+                batch_metadata: self.batch_metadata.clone(),
+                trace: self.trace,
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for TransactionReceipt {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let json = TransactionReceiptJson::deserialize(deserializer)?;
+            Ok(TransactionReceipt {
+                state_changes: json
+                    .state_changes
+                    .into_iter()
+                    .map(StateChangeJson::into_state_change)
+                    .collect::<Result<_, _>>()
+                    .map_err(D::Error::custom)?,
+                events: json
+                    .events
+                    .into_iter()
+                    .map(EventJson::into_event)
+                    .collect::<Result<_, _>>()
+                    .map_err(D::Error::custom)?,
+                data: decode_hex_list(&json.data).map_err(D::Error::custom)?,
+                transaction_id: json.transaction_id,
+                batch_metadata: json.batch_metadata,
+                trace: json.trace,
+            })
+        }
+    }
+}
+
+#[cfg(feature = "receipt-cbor")]
+pub use self::cbor_format::ReceiptCborError;
+
+/// Canonical CBOR encoding of receipts.
+///
+/// A receipt is encoded as a map with the text keys `transaction_id`, `state_changes`, `events`
+/// and `data`.  State changes are maps with a `type` of `"set"` or `"delete"`, a `key` and, for
+/// `"set"`, a byte string `value`.  Events are maps with an `event_type`, `attributes` as an
+/// array of `[key, value]` pairs, and byte string `data`.
+///
+/// A receipt whose batch had metadata tags also has a `batch_metadata` key, an array of
+/// `[key, value]` pairs sorted by key, and one whose batch requested tracing has a `trace` key of
+/// `true`.  Receipts without either encode as they did before these keys existed.
+///
+/// The encoding follows the canonical CBOR rules of RFC 7049 §3.9: lengths are written in their
+/// shortest form, indefinite-length items are not used, and map keys are sorted by length and
+/// then bytewise.  Lists keep their order, so equal receipts encode to identical bytes.  Input
+/// that breaks any of these rules, repeats a key, has unknown keys or writes `batch_metadata` or
+/// `trace` with an empty or false value is rejected when decoding.
+#[cfg(feature = "receipt-cbor")]
+mod cbor_format {
+    use std::collections::BTreeMap;
+    use std::error::Error as StdError;
+
+    use super::{Event, StateChange, TransactionReceipt};
+
+    const MAJOR_BYTES: u8 = 2;
+    const MAJOR_TEXT: u8 = 3;
+    const MAJOR_ARRAY: u8 = 4;
+    const MAJOR_MAP: u8 = 5;
+    const MAJOR_SIMPLE: u8 = 7;
+
+    const SIMPLE_FALSE: u64 = 20;
+    const SIMPLE_TRUE: u64 = 21;
+
+    /// How deeply arrays and maps may be nested in decoded input.
+    const MAX_DEPTH: usize = 8;
+
+    #[derive(Debug)]
+    pub enum ReceiptCborError {
+        EncodingError(String),
+        DecodingError(String),
+    }
+
+    impl StdError for ReceiptCborError {
+        fn description(&self) -> &str {
+            match *self {
+                ReceiptCborError::EncodingError(ref msg) => msg,
+                ReceiptCborError::DecodingError(ref msg) => msg,
+            }
+        }
+    }
+
+    impl std::fmt::Display for ReceiptCborError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match *self {
+                ReceiptCborError::EncodingError(ref s) => write!(f, "EncodingError: {}", s),
+                ReceiptCborError::DecodingError(ref s) => write!(f, "DecodingError: {}", s),
+            }
+        }
+    }
+
+    /// The subset of CBOR used by receipts.
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Bytes(Vec<u8>),
+        Text(String),
+        Array(Vec<Value>),
+        Map(Vec<(String, Value)>),
+        Bool(bool),
+    }
+
+    impl TransactionReceipt {
+        /// Encodes the receipt as canonical CBOR.
+        pub fn to_cbor(&self) -> Result<Vec<u8>, ReceiptCborError> {
+            let mut entries = vec![
+                ("transaction_id", text(&self.transaction_id)),
+                (
+                    "state_changes",
+                    Value::Array(self.state_changes.iter().map(state_change_value).collect()),
+                ),
+                (
+                    "events",
+                    Value::Array(self.events.iter().map(event_value).collect()),
+                ),
+                (
+                    "data",
+                    Value::Array(self.data.iter().map(|data| bytes(data)).collect()),
+                ),
+            ];
+            if !self.batch_metadata.is_empty() {
+                entries.push(("batch_metadata", pairs_value(&self.batch_metadata)));
+            }
+            if self.trace {
+                entries.push(("trace", Value::Bool(true)));
+            }
+            let value = map(entries);
+
+            let mut encoded = Vec::new();
+            encode(&value, &mut encoded);
+            Ok(encoded)
+        }
+
+        /// Decodes a receipt from canonical CBOR, as produced by `to_cbor`.
+        pub fn from_cbor(bytes: &[u8]) -> Result<TransactionReceipt, ReceiptCborError> {
+            let mut decoder = Decoder { bytes, position: 0 };
+            let value = decoder.value(0)?;
+            if decoder.position != bytes.len() {
+                return Err(decoding_error("trailing bytes after receipt"));
+            }
+
+            let mut fields = Fields::new(value, "receipt")?;
+            let receipt = TransactionReceipt {
+                transaction_id: into_text(fields.take("transaction_id")?, "transaction_id")?,
+                state_changes: into_array(fields.take("state_changes")?, "state_changes")?
+                    .into_iter()
+                    .map(state_change_from_value)
+                    .collect::<Result<_, _>>()?,
+                events: into_array(fields.take("events")?, "events")?
+                    .into_iter()
+                    .map(event_from_value)
+                    .collect::<Result<_, _>>()?,
+                data: into_array(fields.take("data")?, "data")?
+                    .into_iter()
+                    .map(|value| into_bytes(value, "data"))
+                    .collect::<Result<_, _>>()?,
+                batch_metadata: match fields.take_optional("batch_metadata") {
+                    Some(value) => {
+                        let batch_metadata = pairs_from_value(value, "batch_metadata")?;
+                        if batch_metadata.is_empty() {
+                            return Err(decoding_error("batch_metadata is empty"));
+                        }
+                        batch_metadata
+                    }
+                    None => BTreeMap::new(),
+                },
+                trace: match fields.take_optional("trace") {
+                    Some(Value::Bool(true)) => true,
+                    Some(Value::Bool(false)) => return Err(decoding_error("trace is false")),
+                    Some(_) => return Err(invalid_type("trace", "boolean")),
+                    None => false,
+                },
+            };
+            fields.finish()?;
+            Ok(receipt)
+        }
+    }
+
+    fn state_change_value(state_change: &StateChange) -> Value {
+        match state_change {
+            StateChange::Set { key, value } => map(vec![
+                ("type", text("set")),
+                ("key", text(key)),
+                ("value", bytes(value)),
+            ]),
+            StateChange::Delete { key } => map(vec![("type", text("delete")), ("key", text(key))]),
+        }
+    }
+
+    fn state_change_from_value(value: Value) -> Result<StateChange, ReceiptCborError> {
+        let mut fields = Fields::new(value, "state change")?;
+        let key = into_text(fields.take("key")?, "key")?;
+        let state_change = match into_text(fields.take("type")?, "type")?.as_str() {
+            "set" => StateChange::Set {
+                key,
+                value: into_bytes(fields.take("value")?, "value")?,
+            },
+            "delete" => StateChange::Delete { key },
+            other => {
+                return Err(ReceiptCborError::DecodingError(format!(
+                    "unknown state change type: {}",
+                    other
+                )))
+            }
+        };
+        fields.finish()?;
+        Ok(state_change)
+    }
+
+    fn event_value(event: &Event) -> Value {
+        map(vec![
+            ("event_type", text(&event.event_type)),
+            (
+                "attributes",
+                Value::Array(
+                    event
+                        .attributes
+                        .iter()
+                        .map(|(k, v)| Value::Array(vec![text(k), text(v)]))
+                        .collect(),
+                ),
+            ),
+            ("data", bytes(&event.data)),
+        ])
+    }
+
+    fn event_from_value(value: Value) -> Result<Event, ReceiptCborError> {
+        let mut fields = Fields::new(value, "event")?;
+        let event = Event {
+            event_type: into_text(fields.take("event_type")?, "event_type")?,
+            attributes: into_array(fields.take("attributes")?, "attributes")?
+                .into_iter()
+                .map(|attribute| {
+                    let mut pair = into_array(attribute, "attribute")?.into_iter();
+                    match (pair.next(), pair.next(), pair.next()) {
+                        (Some(k), Some(v), None) => {
+                            Ok((into_text(k, "attribute")?, into_text(v, "attribute")?))
+                        }
+                        _ => Err(decoding_error("attribute is not a key/value pair")),
+                    }
+                })
+                .collect::<Result<_, _>>()?,
+            data: into_bytes(fields.take("data")?, "data")?,
+        };
+        fields.finish()?;
+        Ok(event)
+    }
+
+    fn pairs_value(pairs: &BTreeMap<String, String>) -> Value {
+        Value::Array(
+            pairs
+                .iter()
+                .map(|(k, v)| Value::Array(vec![text(k), text(v)]))
+                .collect(),
+        )
+    }
+
+    /// Decodes an array of `[key, value]` pairs, which must be sorted by key without repeats.
+    fn pairs_from_value(
+        value: Value,
+        name: &str,
+    ) -> Result<BTreeMap<String, String>, ReceiptCborError> {
+        let mut pairs = BTreeMap::new();
+        for pair in into_array(value, name)? {
+            let mut pair = into_array(pair, name)?.into_iter();
+            let (key, value) = match (pair.next(), pair.next(), pair.next()) {
+                (Some(k), Some(v), None) => (into_text(k, name)?, into_text(v, name)?),
+                _ => {
+                    return Err(ReceiptCborError::DecodingError(format!(
+                        "{} entry is not a key/value pair",
+                        name
+                    )))
+                }
+            };
+            if pairs.keys().next_back().map_or(false, |last| *last >= key) {
+                return Err(ReceiptCborError::DecodingError(format!(
+                    "{} keys are repeated or not sorted",
+                    name
+                )));
+            }
+            pairs.insert(key, value);
+        }
+        Ok(pairs)
+    }
+
+    /// Returns a map of the given entries, sorted into canonical key order.
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        let mut entries = entries
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| canonical_key_order(a, b));
+        Value::Map(entries)
+    }
+
+    /// Orders text keys as their encodings are ordered by RFC 7049 §3.9: shorter keys first, and
+    /// keys of the same length bytewise.  A longer text always has a head at least as long, so
+    /// comparing the texts is equivalent to comparing their encodings.
+    fn canonical_key_order(a: &str, b: &str) -> std::cmp::Ordering {
+        a.len()
+            .cmp(&b.len())
+            .then_with(|| a.as_bytes().cmp(b.as_bytes()))
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    fn bytes(b: &[u8]) -> Value {
+        Value::Bytes(b.to_vec())
+    }
+
+    fn encode(value: &Value, out: &mut Vec<u8>) {
+        match value {
+            Value::Bytes(b) => {
+                encode_head(MAJOR_BYTES, b.len() as u64, out);
+                out.extend_from_slice(b);
+            }
+            Value::Text(s) => {
+                encode_head(MAJOR_TEXT, s.len() as u64, out);
+                out.extend_from_slice(s.as_bytes());
+            }
+            Value::Array(values) => {
+                encode_head(MAJOR_ARRAY, values.len() as u64, out);
+                for value in values {
+                    encode(value, out);
+                }
+            }
+            Value::Map(entries) => {
+                encode_head(MAJOR_MAP, entries.len() as u64, out);
+                for (key, value) in entries {
+                    encode_head(MAJOR_TEXT, key.len() as u64, out);
+                    out.extend_from_slice(key.as_bytes());
+                    encode(value, out);
+                }
+            }
+            Value::Bool(b) => {
+                let simple = if *b { SIMPLE_TRUE } else { SIMPLE_FALSE };
+                encode_head(MAJOR_SIMPLE, simple, out);
+            }
+        }
+    }
+
+    /// Writes the head of an item with its argument in the shortest form.
+    fn encode_head(major: u8, argument: u64, out: &mut Vec<u8>) {
+        let major = major << 5;
+        if argument < 24 {
+            out.push(major | argument as u8);
+        } else if argument <= u64::from(u8::max_value()) {
+            out.push(major | 24);
+            out.push(argument as u8);
+        } else if argument <= u64::from(u16::max_value()) {
+            out.push(major | 25);
+            out.extend_from_slice(&(argument as u16).to_be_bytes());
+        } else if argument <= u64::from(u32::max_value()) {
+            out.push(major | 26);
+            out.extend_from_slice(&(argument as u32).to_be_bytes());
+        } else {
+            out.push(major | 27);
+            out.extend_from_slice(&argument.to_be_bytes());
+        }
+    }
+
+    struct Decoder<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl<'a> Decoder<'a> {
+        fn value(&mut self, depth: usize) -> Result<Value, ReceiptCborError> {
+            if depth > MAX_DEPTH {
+                return Err(decoding_error("items are nested too deeply"));
+            }
+
+            let (major, argument) = self.head()?;
+            match major {
+                MAJOR_BYTES => Ok(Value::Bytes(self.take(argument)?.to_vec())),
+                MAJOR_TEXT => self.text(argument).map(Value::Text),
+                MAJOR_ARRAY => {
+                    let len = self.count(argument)?;
+                    let mut values = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        values.push(self.value(depth + 1)?);
+                    }
+                    Ok(Value::Array(values))
+                }
+                MAJOR_MAP => {
+                    let len = self.count(argument)?;
+                    let mut entries: Vec<(String, Value)> = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        let key = match self.head()? {
+                            (MAJOR_TEXT, key_len) => self.text(key_len)?,
+                            _ => return Err(decoding_error("map key is not text")),
+                        };
+                        if let Some((previous, _)) = entries.last() {
+                            if canonical_key_order(previous, &key) != std::cmp::Ordering::Less {
+                                return Err(decoding_error(
+                                    "map keys are repeated or not in canonical order",
+                                ));
+                            }
+                        }
+                        let value = self.value(depth + 1)?;
+                        entries.push((key, value));
+                    }
+                    Ok(Value::Map(entries))
+                }
+                MAJOR_SIMPLE => match argument {
+                    SIMPLE_FALSE => Ok(Value::Bool(false)),
+                    SIMPLE_TRUE => Ok(Value::Bool(true)),
+                    _ => Err(decoding_error(
+                        "simple values other than booleans are not allowed",
+                    )),
+                },
+                _ => Err(ReceiptCborError::DecodingError(format!(
+                    "unexpected major type {}",
+                    major
+                ))),
+            }
+        }
+
+        /// Reads the head of an item, rejecting arguments not written in their shortest form and
+        /// indefinite lengths.
+        fn head(&mut self) -> Result<(u8, u64), ReceiptCborError> {
+            let initial = self.take(1)?[0];
+            let major = initial >> 5;
+            let (argument, minimum) = match initial & 0x1f {
+                info @ 0..=23 => return Ok((major, u64::from(info))),
+                24 => (u64::from(self.take(1)?[0]), 24),
+                25 => (self.uint(2)?, u64::from(u8::max_value()) + 1),
+                26 => (self.uint(4)?, u64::from(u16::max_value()) + 1),
+                27 => (self.uint(8)?, u64::from(u32::max_value()) + 1),
+                _ => {
+                    return Err(decoding_error(
+                        "indefinite lengths and reserved values are not allowed",
+                    ))
+                }
+            };
+            if argument < minimum {
+                return Err(decoding_error("argument is not in its shortest form"));
+            }
+            Ok((major, argument))
+        }
+
+        fn uint(&mut self, len: usize) -> Result<u64, ReceiptCborError> {
+            Ok(self
+                .take(len as u64)?
+                .iter()
+                .fold(0, |value, byte| (value << 8) | u64::from(*byte)))
+        }
+
+        /// Checks the number of items in an array or map against the remaining input, in which
+        /// each item takes at least one byte.
+        fn count(&self, argument: u64) -> Result<usize, ReceiptCborError> {
+            if argument > (self.bytes.len() - self.position) as u64 {
+                return Err(decoding_error("unexpected end of input"));
+            }
+            Ok(argument as usize)
+        }
+
+        fn text(&mut self, len: u64) -> Result<String, ReceiptCborError> {
+            String::from_utf8(self.take(len)?.to_vec())
+                .map_err(|_| decoding_error("text is not valid UTF-8"))
+        }
+
+        fn take(&mut self, len: u64) -> Result<&'a [u8], ReceiptCborError> {
+            let len = self.count(len)?;
+            let bytes = self.bytes;
+            let taken = &bytes[self.position..self.position + len];
+            self.position += len;
+            Ok(taken)
+        }
+    }
+
+    /// The entries of a decoded map, taken out by name.
+    struct Fields {
+        entries: Vec<(String, Value)>,
+        name: &'static str,
+    }
+
+    impl Fields {
+        fn new(value: Value, name: &'static str) -> Result<Self, ReceiptCborError> {
+            match value {
+                Value::Map(entries) => Ok(Fields { entries, name }),
+                _ => Err(invalid_type(name, "map")),
+            }
+        }
+
+        fn take(&mut self, key: &str) -> Result<Value, ReceiptCborError> {
+            let index = self
+                .entries
+                .iter()
+                .position(|(k, _)| k == key)
+                .ok_or_else(|| {
+                    ReceiptCborError::DecodingError(format!("missing field: {}", key))
+                })?;
+            Ok(self.entries.remove(index).1)
+        }
+
+        fn take_optional(&mut self, key: &str) -> Option<Value> {
+            self.entries
+                .iter()
+                .position(|(k, _)| k == key)
+                .map(|index| self.entries.remove(index).1)
+        }
+
+        /// Rejects any entries that were not taken.
+        fn finish(self) -> Result<(), ReceiptCborError> {
+            match self.entries.first() {
+                Some((key, _)) => Err(ReceiptCborError::DecodingError(format!(
+                    "unknown field in {}: {}",
+                    self.name, key
+                ))),
+                None => Ok(()),
+            }
+        }
+    }
+
+    fn into_array(value: Value, name: &str) -> Result<Vec<Value>, ReceiptCborError> {
+        match value {
+            Value::Array(values) => Ok(values),
+            _ => Err(invalid_type(name, "array")),
+        }
+    }
+
+    fn into_text(value: Value, name: &str) -> Result<String, ReceiptCborError> {
+        match value {
+            Value::Text(s) => Ok(s),
+            _ => Err(invalid_type(name, "text")),
+        }
+    }
+
+    fn into_bytes(value: Value, name: &str) -> Result<Vec<u8>, ReceiptCborError> {
+        match value {
+            Value::Bytes(b) => Ok(b),
+            _ => Err(invalid_type(name, "byte string")),
+        }
+    }
+
+    fn decoding_error(msg: &str) -> ReceiptCborError {
+        ReceiptCborError::DecodingError(msg.to_string())
+    }
+
+    fn invalid_type(name: &str, expected: &str) -> ReceiptCborError {
+        ReceiptCborError::DecodingError(format!("{} is not a {}", name, expected))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -543,6 +1431,8 @@ mod tests {
             events: vec![make_event_1(), make_event_2()],
             data: vec![BYTES1.to_vec(), BYTES2.to_vec(), BYTES3.to_vec()],
             transaction_id: TRANSACTION_ID.to_string(),
+            batch_metadata: BTreeMap::new(),
+            trace: false,
         };
 
         check_transaction_receipt(transaction_receipt)
@@ -564,6 +1454,8 @@ mod tests {
             events: vec![make_event_1(), make_event_2()],
             data: vec![BYTES1.to_vec(), BYTES2.to_vec(), BYTES3.to_vec()],
             transaction_id: TRANSACTION_ID.to_string(),
+            batch_metadata: BTreeMap::new(),
+            trace: false,
         };
 
         let receipt_bytes = original.clone().into_bytes().unwrap();
@@ -573,6 +1465,34 @@ mod tests {
         assert_eq!(original.transaction_id, receipt.transaction_id);
     }
 
+    #[test]
+    // test that the tags of a receipt's batch survive its bytes, and are empty by default
+    fn transaction_receipt_batch_tags() {
+        let untagged = TransactionReceiptBuilder::new()
+            .with_transaction_id(TRANSACTION_ID.to_string())
+            .build()
+            .unwrap();
+        assert!(untagged.batch_metadata.is_empty());
+        assert!(!untagged.trace);
+
+        let mut batch_metadata = BTreeMap::new();
+        batch_metadata.insert("tenant".to_string(), "acme".to_string());
+        batch_metadata.insert("request".to_string(), "42".to_string());
+        let tagged = TransactionReceiptBuilder::new()
+            .with_transaction_id(TRANSACTION_ID.to_string())
+            .with_batch_metadata(batch_metadata)
+            .with_trace(true)
+            .build()
+            .unwrap();
+
+        let tagged_bytes = tagged.clone().into_bytes().unwrap();
+        assert_eq!(
+            tagged,
+            TransactionReceipt::from_bytes(&tagged_bytes).unwrap()
+        );
+        assert_ne!(untagged.into_bytes().unwrap(), tagged_bytes);
+    }
+
     fn check_transaction_receipt(transaction_receipt: TransactionReceipt) {
         for state_change in transaction_receipt.state_changes {
             check_state_change(state_change)
@@ -633,6 +1553,267 @@ mod tests {
         assert_eq!(vec!(BYTES2.to_vec(),), transaction_receipt.data);
     }
 
+    #[test]
+    // test that events can be selected by type and attributes, within and across receipts
+    fn transaction_receipt_event_query() {
+        let receipt1 = TransactionReceiptBuilder::new()
+            .with_events(vec![make_event_1(), make_event_2()])
+            .with_transaction_id("txn1".to_string())
+            .build()
+            .unwrap();
+        let receipt2 = TransactionReceiptBuilder::new()
+            .with_events(vec![make_event_2()])
+            .with_transaction_id("txn2".to_string())
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            vec![&make_event_1()],
+            receipt1.events_of_type(EVENT_TYPE1).collect::<Vec<_>>()
+        );
+
+        let query = EventQuery::new().with_attribute(ATTR2.0, ATTR2.1);
+        assert_eq!(
+            vec![&make_event_1()],
+            receipt1.events_matching(&query).collect::<Vec<_>>()
+        );
+
+        let query = EventQuery::new().with_attribute(ATTR2.0, "4");
+        assert_eq!(0, receipt1.events_matching(&query).count());
+
+        let query = EventQuery::new()
+            .with_event_type(EVENT_TYPE2)
+            .with_attribute_key(ATTR3.0);
+        let receipts = vec![receipt1, receipt2];
+        let found = query_events(&receipts, &query).collect::<Vec<_>>();
+        assert_eq!(2, found.len());
+        assert_eq!("txn1", found[0].transaction_id());
+        assert_eq!("txn2", found[1].transaction_id());
+        assert_eq!(Some(ATTR3.1), found[1].attribute(ATTR3.0));
+        assert_eq!(&BYTES3, found[1].data());
+    }
+
+    #[cfg(feature = "receipt-cbor")]
+    #[test]
+    // test that receipts round trip through CBOR, and that equal receipts encode identically
+    fn transaction_receipt_cbor() {
+        let receipt = TransactionReceiptBuilder::new()
+            .with_state_changes(vec![
+                StateChange::Set {
+                    key: ADDRESS.to_string(),
+                    value: BYTES1.to_vec(),
+                },
+                StateChange::Delete {
+                    key: ADDRESS.to_string(),
+                },
+            ])
+            .with_events(vec![make_event_1(), make_event_2()])
+            .with_data(vec![BYTES2.to_vec(), BYTES3.to_vec()])
+            .with_transaction_id(TRANSACTION_ID.to_string())
+            .build()
+            .unwrap();
+
+        let bytes = receipt.to_cbor().unwrap();
+        assert_eq!(bytes, receipt.clone().to_cbor().unwrap());
+        assert_eq!(receipt, TransactionReceipt::from_cbor(&bytes).unwrap());
+
+        assert!(TransactionReceipt::from_cbor(&BYTES1).is_err());
+    }
+
+    #[cfg(feature = "receipt-cbor")]
+    #[test]
+    // test that receipts encode to canonical CBOR, with map keys sorted by length and then
+    // bytewise, and that non-canonical input is rejected
+    fn transaction_receipt_cbor_canonical() {
+        let receipt = TransactionReceipt {
+            state_changes: vec![StateChange::Set {
+                key: "k".to_string(),
+                value: vec![0x01],
+            }],
+            events: vec![Event {
+                event_type: "e".to_string(),
+                attributes: vec![("a".to_string(), "b".to_string())],
+                data: vec![],
+            }],
+            data: vec![vec![0x02]],
+            transaction_id: "t".to_string(),
+            batch_metadata: BTreeMap::new(),
+            trace: false,
+        };
+        let expected = concat!(
+            "a46464617461814102666576656e747381a36464617461406a617474726962757465738182616161",
+            "626a6576656e745f7479706561656d73746174655f6368616e67657381a3636b6579616b64747970",
+            "65637365746576616c756541016e7472616e73616374696f6e5f69646174",
+        );
+
+        let bytes = receipt.to_cbor().unwrap();
+        assert_eq!(expected, hex::encode(&bytes));
+        assert_eq!(receipt, TransactionReceipt::from_cbor(&bytes).unwrap());
+
+        // The transaction id's length written in two bytes rather than one
+        let mut long_length = bytes[..bytes.len() - 2].to_vec();
+        long_length.extend_from_slice(&[0x78, 0x01, b't']);
+        assert!(TransactionReceipt::from_cbor(&long_length).is_err());
+
+        // {"b": h'', "a": h''}, with its keys out of order
+        assert!(
+            TransactionReceipt::from_cbor(&[0xa2, 0x61, b'b', 0x40, 0x61, b'a', 0x40]).is_err()
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0x00);
+        assert!(TransactionReceipt::from_cbor(&trailing).is_err());
+    }
+
+    #[cfg(feature = "receipt-cbor")]
+    #[test]
+    // test that batch tags round trip through CBOR, and that tags written with their default
+    // values are rejected
+    fn transaction_receipt_cbor_batch_tags() {
+        let untagged = TransactionReceiptBuilder::new()
+            .with_transaction_id("t".to_string())
+            .build()
+            .unwrap();
+        let mut batch_metadata = BTreeMap::new();
+        batch_metadata.insert("k".to_string(), "v".to_string());
+        let tagged = TransactionReceiptBuilder::new()
+            .with_transaction_id("t".to_string())
+            .with_batch_metadata(batch_metadata)
+            .with_trace(true)
+            .build()
+            .unwrap();
+
+        let bytes = tagged.to_cbor().unwrap();
+        assert_ne!(untagged.to_cbor().unwrap(), bytes);
+        assert_eq!(tagged, TransactionReceipt::from_cbor(&bytes).unwrap());
+
+        let hex_bytes = hex::encode(&bytes);
+        // "trace": false
+        let trace_false = hex_bytes.replace("657472616365f5", "657472616365f4");
+        assert_ne!(hex_bytes, trace_false);
+        assert!(TransactionReceipt::from_cbor(&hex::decode(trace_false).unwrap()).is_err());
+        // "batch_metadata": []
+        let batch_metadata_key = format!("6e{}", hex::encode("batch_metadata"));
+        let empty_metadata = hex_bytes.replace(
+            &format!("{}8182616b6176", batch_metadata_key),
+            &format!("{}80", batch_metadata_key),
+        );
+        assert_ne!(hex_bytes, empty_metadata);
+        assert!(TransactionReceipt::from_cbor(&hex::decode(empty_metadata).unwrap()).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    // test that state changes and events serialize on their own as they do within a receipt
+    fn state_change_and_event_json() {
+        let state_change = StateChange::Set {
+            key: ADDRESS.to_string(),
+            value: BYTES1.to_vec(),
+        };
+        let json = serde_json::to_value(&state_change).unwrap();
+        assert_eq!(
+            serde_json::json!({"type": "set", "key": ADDRESS, "value": "01020304"}),
+            json
+        );
+        assert_eq!(
+            state_change,
+            serde_json::from_value::<StateChange>(json).unwrap()
+        );
+
+        let event = make_event_2();
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "event_type": EVENT_TYPE2,
+                "attributes": [{"key": ATTR3.0, "value": ATTR3.1}],
+                "data": "090a0b0c",
+            }),
+            json
+        );
+        assert_eq!(event, serde_json::from_value::<Event>(json).unwrap());
+
+        assert!(serde_json::from_value::<StateChange>(
+            serde_json::json!({"type": "set", "key": ADDRESS, "value": "not hex"})
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    // test that receipts round trip through their canonical JSON form
+    fn transaction_receipt_json() {
+        let receipt = TransactionReceiptBuilder::new()
+            .with_state_changes(vec![
+                StateChange::Set {
+                    key: ADDRESS.to_string(),
+                    value: BYTES1.to_vec(),
+                },
+                StateChange::Delete {
+                    key: ADDRESS.to_string(),
+                },
+            ])
+            .with_events(vec![make_event_2()])
+            .with_data(vec![BYTES2.to_vec()])
+            .with_transaction_id(TRANSACTION_ID.to_string())
+            .build()
+            .unwrap();
+
+        let json = serde_json::to_value(&receipt).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "transaction_id": TRANSACTION_ID,
+                "state_changes": [
+                    {"type": "set", "key": ADDRESS, "value": "01020304"},
+                    {"type": "delete", "key": ADDRESS},
+                ],
+                "events": [{
+                    "event_type": EVENT_TYPE2,
+                    "attributes": [{"key": ATTR3.0, "value": ATTR3.1}],
+                    "data": "090a0b0c",
+                }],
+                "data": ["05060708"],
+            }),
+            json
+        );
+        assert_eq!(
+            receipt,
+            serde_json::from_value::<TransactionReceipt>(json).unwrap()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    // test that batch tags appear in a receipt's JSON only when set
+    fn transaction_receipt_json_batch_tags() {
+        let receipt = TransactionReceiptBuilder::new()
+            .with_transaction_id(TRANSACTION_ID.to_string())
+            .with_batch_metadata(
+                vec![("tenant".to_string(), "acme".to_string())]
+                    .into_iter()
+                    .collect(),
+            )
+            .with_trace(true)
+            .build()
+            .unwrap();
+
+        let json = serde_json::to_value(&receipt).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "transaction_id": TRANSACTION_ID,
+                "state_changes": [],
+                "events": [],
+                "data": [],
+                "batch_metadata": {"tenant": "acme"},
+                "trace": true,
+            }),
+            json
+        );
+        assert_eq!(
+            receipt,
+            serde_json::from_value::<TransactionReceipt>(json).unwrap()
+        );
+    }
+
     #[test]
     fn transaction_receipt_builder_chain() {
         let transaction_receipt = TransactionReceiptBuilder::new()
@@ -726,6 +1907,39 @@ mod tests {
 
         check_event(event);
     }
+
+    #[test]
+    // test the typed and prefix attribute accessors on an event built one attribute at a time
+    fn event_attribute_accessors() {
+        let event = EventBuilder::new()
+            .with_event_type("xo/game-updated".to_string())
+            .with_attribute("game".to_string(), "g1".to_string())
+            .with_attribute("player.1".to_string(), "alice".to_string())
+            .with_attribute("player.2".to_string(), "bob".to_string())
+            .with_attribute("turn".to_string(), "4".to_string())
+            .with_attribute("game".to_string(), "g2".to_string())
+            .build()
+            .unwrap();
+
+        assert_eq!(Some("g1"), event.attribute("game"));
+        assert_eq!(None, event.attribute("missing"));
+        assert_eq!(
+            vec!["g1", "g2"],
+            event.attribute_values("game").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![("player.1", "alice"), ("player.2", "bob")],
+            event.attributes_matching("player.").collect::<Vec<_>>()
+        );
+        assert_eq!(0, event.attributes_matching("winner").count());
+
+        assert_eq!(Some(Ok(4)), event.parse_attribute::<u32>("turn"));
+        assert!(event.parse_attribute::<u32>("game").unwrap().is_err());
+        assert!(event.parse_attribute::<u32>("missing").is_none());
+
+        let bytes = event.clone().into_bytes().unwrap();
+        assert_eq!(event, Event::from_bytes(&bytes).unwrap());
+    }
 }
 
 #[cfg(all(feature = "nightly", test))]
@@ -769,6 +1983,8 @@ mod benchmarks {
             events: vec![make_event_1(), make_event_2()],
             data: vec![BYTES1.to_vec(), BYTES2.to_vec(), BYTES3.to_vec()],
             transaction_id: TRANSACTION_ID.to_string(),
+            batch_metadata: BTreeMap::new(),
+            trace: false,
         });
     }
 
@@ -835,6 +2051,8 @@ mod benchmarks {
             events: vec![make_event_1(), make_event_2()],
             data: vec![BYTES1.to_vec(), BYTES2.to_vec(), BYTES3.to_vec()],
             transaction_id: TRANSACTION_ID.to_string(),
+            batch_metadata: BTreeMap::new(),
+            trace: false,
         };
 
         b.iter(|| transaction_receipt.clone().into_proto());
@@ -0,0 +1,466 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! A compact binary encoding of transaction receipts.
+//!
+//! Receipts of data-heavy families may hold thousands of state changes whose addresses share long
+//! prefixes, and many of which set the same value.  The compact encoding stores each address as
+//! the length of the prefix it shares with the previous address plus the remaining suffix, and
+//! stores each distinct value once, with state changes referring to values by index.  Events and
+//! receipt data are stored in their protobuf encoding.
+//!
+//! The encoding, with all integers as unsigned LEB128 varints and all strings and byte strings as
+//! a varint length followed by the bytes, is:
+//!
+//! * The magic bytes `0xff 'T' 'R' 0x01`.
+//! * The transaction id.
+//! * The number of distinct values, followed by the values in order of first use.
+//! * The number of state changes, followed by, for each, a tag (`0` for set, `1` for delete), the
+//!   shared prefix length, the address suffix and, for a set, the value index.
+//! * The protobuf encoding of a receipt holding only the events, data, batch metadata and trace
+//!   flag.
+//!
+//! A protobuf-encoded receipt can never begin with the magic bytes, so `receipt_from_bytes`
+//! accepts either encoding and expands compact receipts transparently.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error as StdError;
+
+use crate::protos::{FromBytes, IntoBytes};
+
+use super::receipt::{StateChange, TransactionReceipt};
+
+const MAGIC: &[u8] = &[0xff, b'T', b'R', 0x01];
+
+const TAG_SET: u64 = 0;
+const TAG_DELETE: u64 = 1;
+
+#[derive(Debug)]
+pub enum CompactReceiptError {
+    EncodingError(String),
+    DecodingError(String),
+}
+
+impl StdError for CompactReceiptError {
+    fn description(&self) -> &str {
+        match *self {
+            CompactReceiptError::EncodingError(ref msg) => msg,
+            CompactReceiptError::DecodingError(ref msg) => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for CompactReceiptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            CompactReceiptError::EncodingError(ref s) => write!(f, "EncodingError: {}", s),
+            CompactReceiptError::DecodingError(ref s) => write!(f, "DecodingError: {}", s),
+        }
+    }
+}
+
+/// Encodes the receipt in the compact encoding.
+pub fn receipt_to_compact_bytes(
+    receipt: &TransactionReceipt,
+) -> Result<Vec<u8>, CompactReceiptError> {
+    let mut values: Vec<&[u8]> = Vec::new();
+    let mut value_indices: HashMap<&[u8], usize> = HashMap::new();
+    for change in &receipt.state_changes {
+        if let StateChange::Set { ref value, .. } = *change {
+            if !value_indices.contains_key(&value[..]) {
+                value_indices.insert(value, values.len());
+                values.push(value);
+            }
+        }
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    write_bytes(&mut bytes, receipt.transaction_id.as_bytes());
+
+    write_varint(&mut bytes, values.len() as u64);
+    for value in &values {
+        write_bytes(&mut bytes, value);
+    }
+
+    write_varint(&mut bytes, receipt.state_changes.len() as u64);
+    let mut previous_key = "";
+    for change in &receipt.state_changes {
+        let key = match *change {
+            StateChange::Set { ref key, .. } => {
+                write_varint(&mut bytes, TAG_SET);
+                key.as_str()
+            }
+            StateChange::Delete { ref key } => {
+                write_varint(&mut bytes, TAG_DELETE);
+                key.as_str()
+            }
+        };
+        let shared = shared_prefix_len(previous_key, key);
+        write_varint(&mut bytes, shared as u64);
+        write_bytes(&mut bytes, key[shared..].as_bytes());
+        if let StateChange::Set { ref value, .. } = *change {
+            write_varint(&mut bytes, value_indices[&value[..]] as u64);
+        }
+        previous_key = key;
+    }
+
+    let rest = TransactionReceipt {
+        state_changes: vec![],
+        events: receipt.events.clone(),
+        data: receipt.data.clone(),
+        transaction_id: String::new(),
+        batch_metadata: receipt.batch_metadata.clone(),
+        trace: receipt.trace,
+    }
+    .into_bytes()
+    .map_err(|e| CompactReceiptError::EncodingError(format!("{}", e)))?;
+    bytes.extend_from_slice(&rest);
+
+    Ok(bytes)
+}
+
+/// Decodes a receipt in either the compact or the protobuf encoding.
+pub fn receipt_from_bytes(bytes: &[u8]) -> Result<TransactionReceipt, CompactReceiptError> {
+    if !is_compact(bytes) {
+        return TransactionReceipt::from_bytes(bytes)
+            .map_err(|e| CompactReceiptError::DecodingError(format!("{}", e)));
+    }
+
+    let mut reader = Reader {
+        bytes,
+        position: MAGIC.len(),
+    };
+
+    let transaction_id = reader.read_string()?;
+
+    let value_count = reader.read_count()?;
+    let mut values = Vec::with_capacity(value_count);
+    for _ in 0..value_count {
+        values.push(reader.read_bytes()?.to_vec());
+    }
+
+    let change_count = reader.read_count()?;
+    let mut state_changes = Vec::with_capacity(change_count);
+    let mut previous_key = String::new();
+    for _ in 0..change_count {
+        let tag = reader.read_varint()?;
+        let shared = reader.read_varint()? as usize;
+        if shared > previous_key.len() || !previous_key.is_char_boundary(shared) {
+            return Err(CompactReceiptError::DecodingError(
+                "shared prefix is longer than the previous address".to_string(),
+            ));
+        }
+        let mut key = previous_key[..shared].to_string();
+        key.push_str(&reader.read_string()?);
+
+        let change = match tag {
+            TAG_SET => {
+                let index = reader.read_varint()? as usize;
+                let value = values.get(index).ok_or_else(|| {
+                    CompactReceiptError::DecodingError(format!("unknown value index: {}", index))
+                })?;
+                StateChange::Set {
+                    key: key.clone(),
+                    value: value.clone(),
+                }
+            }
+            TAG_DELETE => StateChange::Delete { key: key.clone() },
+            _ => {
+                return Err(CompactReceiptError::DecodingError(format!(
+                    "unknown state change tag: {}",
+                    tag
+                )))
+            }
+        };
+        state_changes.push(change);
+        previous_key = key;
+    }
+
+    let rest = TransactionReceipt::from_bytes(&bytes[reader.position..])
+        .map_err(|e| CompactReceiptError::DecodingError(format!("{}", e)))?;
+
+    Ok(TransactionReceipt {
+        state_changes,
+        events: rest.events,
+        data: rest.data,
+        transaction_id,
+        batch_metadata: rest.batch_metadata,
+        trace: rest.trace,
+    })
+}
+
+/// Returns true if the bytes are a receipt in the compact encoding.
+pub fn is_compact(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Sizes of a receipt in the protobuf and compact encodings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionStats {
+    protobuf_size: usize,
+    compact_size: usize,
+    state_changes: usize,
+    distinct_values: usize,
+    shared_prefix_bytes: usize,
+}
+
+impl CompactionStats {
+    /// Encodes the receipt both ways and reports the sizes.
+    pub fn for_receipt(receipt: &TransactionReceipt) -> Result<Self, CompactReceiptError> {
+        let protobuf_size = receipt
+            .clone()
+            .into_bytes()
+            .map_err(|e| CompactReceiptError::EncodingError(format!("{}", e)))?
+            .len();
+        let compact_size = receipt_to_compact_bytes(receipt)?.len();
+
+        let mut distinct_values = HashSet::new();
+        let mut shared_prefix_bytes = 0;
+        let mut previous_key = "";
+        for change in &receipt.state_changes {
+            let key = match *change {
+                StateChange::Set { ref key, ref value } => {
+                    distinct_values.insert(&value[..]);
+                    key.as_str()
+                }
+                StateChange::Delete { ref key } => key.as_str(),
+            };
+            shared_prefix_bytes += shared_prefix_len(previous_key, key);
+            previous_key = key;
+        }
+
+        Ok(CompactionStats {
+            protobuf_size,
+            compact_size,
+            state_changes: receipt.state_changes.len(),
+            distinct_values: distinct_values.len(),
+            shared_prefix_bytes,
+        })
+    }
+
+    pub fn protobuf_size(&self) -> usize {
+        self.protobuf_size
+    }
+
+    pub fn compact_size(&self) -> usize {
+        self.compact_size
+    }
+
+    pub fn state_changes(&self) -> usize {
+        self.state_changes
+    }
+
+    /// The number of distinct values set by the receipt's state changes.
+    pub fn distinct_values(&self) -> usize {
+        self.distinct_values
+    }
+
+    /// The number of address bytes elided by prefix sharing.
+    pub fn shared_prefix_bytes(&self) -> usize {
+        self.shared_prefix_bytes
+    }
+
+    /// The compact size as a fraction of the protobuf size.
+    pub fn ratio(&self) -> f64 {
+        if self.protobuf_size == 0 {
+            1.0
+        } else {
+            self.compact_size as f64 / self.protobuf_size as f64
+        }
+    }
+}
+
+/// The length of the longest common prefix of the strings, at a character boundary.
+fn shared_prefix_len(a: &str, b: &str) -> usize {
+    let mut len = a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+    while !b.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    write_varint(bytes, value.len() as u64);
+    bytes.extend_from_slice(value);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_varint(&mut self) -> Result<u64, CompactReceiptError> {
+        let mut value = 0u64;
+        for i in 0..10 {
+            let byte = *self.bytes.get(self.position).ok_or_else(|| {
+                CompactReceiptError::DecodingError("receipt ended in a varint".to_string())
+            })?;
+            self.position += 1;
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(CompactReceiptError::DecodingError(
+            "varint in receipt is too long".to_string(),
+        ))
+    }
+
+    /// Reads a count of following items; each item takes at least one byte, so a count larger
+    /// than the remaining bytes is rejected before anything is allocated for it.
+    fn read_count(&mut self) -> Result<usize, CompactReceiptError> {
+        let count = self.read_varint()?;
+        if count > (self.bytes.len() - self.position) as u64 {
+            return Err(CompactReceiptError::DecodingError(format!(
+                "count exceeds receipt length: {}",
+                count
+            )));
+        }
+        Ok(count as usize)
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], CompactReceiptError> {
+        let len = self.read_varint()?;
+        if len > (self.bytes.len() - self.position) as u64 {
+            return Err(CompactReceiptError::DecodingError(
+                "receipt ended in a byte string".to_string(),
+            ));
+        }
+        let start = self.position;
+        self.position += len as usize;
+        Ok(&self.bytes[start..self.position])
+    }
+
+    fn read_string(&mut self) -> Result<String, CompactReceiptError> {
+        String::from_utf8(self.read_bytes()?.to_vec())
+            .map_err(|e| CompactReceiptError::DecodingError(format!("{}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::protocol::receipt::Event;
+
+    fn make_receipt() -> TransactionReceipt {
+        let prefix = "1cf126".to_string() + &"ab".repeat(29);
+        let mut state_changes: Vec<StateChange> = (0..100)
+            .map(|i| StateChange::Set {
+                key: format!("{}{:06}", prefix, i),
+                value: if i % 2 == 0 {
+                    b"even".to_vec()
+                } else {
+                    b"odd".to_vec()
+                },
+            })
+            .collect();
+        state_changes.push(StateChange::Delete {
+            key: format!("{}{:06}", prefix, 7),
+        });
+        state_changes.push(StateChange::Set {
+            key: "ff".repeat(35),
+            value: vec![],
+        });
+
+        TransactionReceipt {
+            state_changes,
+            events: vec![Event {
+                event_type: "test/event".to_string(),
+                attributes: vec![("key".to_string(), "value".to_string())],
+                data: b"data".to_vec(),
+            }],
+            data: vec![b"receipt data".to_vec()],
+            transaction_id: "a".repeat(128),
+            batch_metadata: BTreeMap::new(),
+            trace: false,
+        }
+    }
+
+    #[test]
+    // test that a receipt round trips through the compact encoding, and that protobuf receipts
+    // are still read
+    fn compact_receipt_round_trip() {
+        let receipt = make_receipt();
+
+        let compact = receipt_to_compact_bytes(&receipt).unwrap();
+        assert!(is_compact(&compact));
+        assert_eq!(receipt, receipt_from_bytes(&compact).unwrap());
+
+        let protobuf = receipt.clone().into_bytes().unwrap();
+        assert!(!is_compact(&protobuf));
+        assert_eq!(receipt, receipt_from_bytes(&protobuf).unwrap());
+
+        let empty = TransactionReceipt {
+            state_changes: vec![],
+            events: vec![],
+            data: vec![],
+            transaction_id: String::new(),
+            batch_metadata: BTreeMap::new(),
+            trace: false,
+        };
+        assert_eq!(
+            empty,
+            receipt_from_bytes(&receipt_to_compact_bytes(&empty).unwrap()).unwrap()
+        );
+
+        let mut tagged = make_receipt();
+        tagged.batch_metadata.insert("tenant".into(), "acme".into());
+        tagged.trace = true;
+        assert_eq!(
+            tagged,
+            receipt_from_bytes(&receipt_to_compact_bytes(&tagged).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    // test that the compact encoding shrinks receipts with shared prefixes and repeated values
+    fn compact_receipt_stats() {
+        let receipt = make_receipt();
+        let stats = CompactionStats::for_receipt(&receipt).unwrap();
+
+        assert_eq!(102, stats.state_changes());
+        assert_eq!(3, stats.distinct_values());
+        assert!(stats.shared_prefix_bytes() > 100 * 64);
+        assert!(stats.compact_size() < stats.protobuf_size() / 4);
+        assert!(stats.ratio() < 0.25);
+    }
+
+    #[test]
+    // test that corrupt compact receipts are rejected rather than misread
+    fn compact_receipt_corrupt() {
+        let compact = receipt_to_compact_bytes(&make_receipt()).unwrap();
+        for len in &[MAGIC.len(), MAGIC.len() + 10, compact.len() / 2] {
+            assert!(receipt_from_bytes(&compact[..*len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,332 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Merkle roots and inclusion proofs over ordered lists of transaction receipts.
+//!
+//! The tree follows the construction of RFC 6962 (Certificate Transparency), with SHA-512 as the
+//! hash function:
+//!
+//! * The leaf for a receipt is `SHA-512(0x00 || receipt)`, where `receipt` is the receipt's
+//!   protobuf serialization, as produced by `IntoBytes`.
+//! * An interior node is `SHA-512(0x01 || left || right)`.
+//! * A list of `n > 1` receipts is split into its first `k` receipts, where `k` is the largest
+//!   power of two less than `n`, and the remaining `n - k`; the root is the interior node of the
+//!   roots of the two parts.
+//! * The root of an empty list is `SHA-512` of the empty string.
+//!
+//! The distinct leaf and interior prefixes prevent a leaf from being passed off as an interior
+//! node.  The root commits to the order of the receipts as well as their contents, so a block
+//! header may carry the root of its receipts, and a light client holding the header can check a
+//! single receipt with an `InclusionProof`.
+
+use sha2::{Digest, Sha512};
+
+use crate::protos::{IntoBytes, ProtoConversionError};
+
+use super::receipt::TransactionReceipt;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Returns the leaf hash of a receipt.
+pub fn receipt_leaf_hash(receipt: &TransactionReceipt) -> Result<Vec<u8>, ProtoConversionError> {
+    let mut hasher = Sha512::new();
+    hasher.input(&[LEAF_PREFIX]);
+    hasher.input(&receipt.clone().into_bytes()?);
+    Ok(hasher.result().to_vec())
+}
+
+/// Returns the merkle root of an ordered list of receipts.
+pub fn receipt_merkle_root(
+    receipts: &[TransactionReceipt],
+) -> Result<Vec<u8>, ProtoConversionError> {
+    Ok(ReceiptMerkleTree::new(receipts)?.root().to_vec())
+}
+
+/// The merkle tree of an ordered list of receipts, from which inclusion proofs are produced.
+pub struct ReceiptMerkleTree {
+    transaction_ids: Vec<String>,
+    leaves: Vec<Vec<u8>>,
+    root: Vec<u8>,
+}
+
+impl ReceiptMerkleTree {
+    pub fn new(receipts: &[TransactionReceipt]) -> Result<Self, ProtoConversionError> {
+        let leaves = receipts
+            .iter()
+            .map(receipt_leaf_hash)
+            .collect::<Result<Vec<_>, _>>()?;
+        let root = if leaves.is_empty() {
+            Sha512::digest(&[]).to_vec()
+        } else {
+            subtree_root(&leaves)
+        };
+
+        Ok(ReceiptMerkleTree {
+            transaction_ids: receipts
+                .iter()
+                .map(|receipt| receipt.transaction_id.clone())
+                .collect(),
+            leaves,
+            root,
+        })
+    }
+
+    pub fn root(&self) -> &[u8] {
+        &self.root
+    }
+
+    /// The number of receipts in the tree.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Returns the inclusion proof for the receipt at `index`, or `None` if the index is out of
+    /// range.
+    pub fn proof(&self, index: usize) -> Option<InclusionProof> {
+        if index >= self.leaves.len() {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        audit_path(index, &self.leaves, &mut path);
+        Some(InclusionProof {
+            index,
+            leaf_count: self.leaves.len(),
+            path,
+        })
+    }
+
+    /// Returns the inclusion proof for the first receipt of the given transaction.
+    pub fn proof_for_transaction(&self, transaction_id: &str) -> Option<InclusionProof> {
+        self.transaction_ids
+            .iter()
+            .position(|id| id == transaction_id)
+            .and_then(|index| self.proof(index))
+    }
+}
+
+/// A proof that a receipt is at a given position in a list of receipts with a given root.
+///
+/// The path lists the sibling hashes from the leaf up to the root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InclusionProof {
+    index: usize,
+    leaf_count: usize,
+    path: Vec<Vec<u8>>,
+}
+
+impl InclusionProof {
+    /// Constructs a proof, such as one received from another node.
+    pub fn new(index: usize, leaf_count: usize, path: Vec<Vec<u8>>) -> Self {
+        InclusionProof {
+            index,
+            leaf_count,
+            path,
+        }
+    }
+
+    /// The position of the receipt in the list.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The number of receipts in the list.
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    pub fn path(&self) -> &[Vec<u8>] {
+        &self.path
+    }
+
+    /// Returns true if the proof shows that `receipt` is included in the list with `root`.
+    pub fn verify(
+        &self,
+        receipt: &TransactionReceipt,
+        root: &[u8],
+    ) -> Result<bool, ProtoConversionError> {
+        Ok(self.verify_leaf(&receipt_leaf_hash(receipt)?, root))
+    }
+
+    /// Returns true if the proof shows that the leaf hash is included in the list with `root`.
+    pub fn verify_leaf(&self, leaf_hash: &[u8], root: &[u8]) -> bool {
+        if self.index >= self.leaf_count {
+            return false;
+        }
+
+        // The verification algorithm of RFC 9162, section 2.1.3.2.
+        let mut node = self.index;
+        let mut last_node = self.leaf_count - 1;
+        let mut hash = leaf_hash.to_vec();
+        for sibling in &self.path {
+            if last_node == 0 {
+                return false;
+            }
+            if node & 1 == 1 || node == last_node {
+                hash = node_hash(sibling, &hash);
+                if node & 1 == 0 {
+                    while node & 1 == 0 && node != 0 {
+                        node >>= 1;
+                        last_node >>= 1;
+                    }
+                }
+            } else {
+                hash = node_hash(&hash, sibling);
+            }
+            node >>= 1;
+            last_node >>= 1;
+        }
+
+        last_node == 0 && hash == root
+    }
+}
+
+fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.input(&[NODE_PREFIX]);
+    hasher.input(left);
+    hasher.input(right);
+    hasher.result().to_vec()
+}
+
+/// The largest power of two less than `n`, for `n > 1`.
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+fn subtree_root(leaves: &[Vec<u8>]) -> Vec<u8> {
+    if leaves.len() == 1 {
+        return leaves[0].clone();
+    }
+    let k = split_point(leaves.len());
+    node_hash(&subtree_root(&leaves[..k]), &subtree_root(&leaves[k..]))
+}
+
+/// Appends the audit path of the leaf at `index` to `path`, from the leaf upwards.
+fn audit_path(index: usize, leaves: &[Vec<u8>], path: &mut Vec<Vec<u8>>) {
+    if leaves.len() == 1 {
+        return;
+    }
+    let k = split_point(leaves.len());
+    if index < k {
+        audit_path(index, &leaves[..k], path);
+        path.push(subtree_root(&leaves[k..]));
+    } else {
+        audit_path(index - k, &leaves[k..], path);
+        path.push(subtree_root(&leaves[..k]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::protocol::receipt::StateChange;
+
+    fn make_receipts(count: usize) -> Vec<TransactionReceipt> {
+        (0..count)
+            .map(|i| TransactionReceipt {
+                state_changes: vec![StateChange::Set {
+                    key: format!("{:070}", i),
+                    value: vec![i as u8],
+                }],
+                events: vec![],
+                data: vec![],
+                transaction_id: format!("{:0128}", i),
+                batch_metadata: BTreeMap::new(),
+                trace: false,
+            })
+            .collect()
+    }
+
+    #[test]
+    // test the roots of small trees against the documented construction
+    fn receipt_merkle_root_construction() {
+        assert_eq!(
+            Sha512::digest(&[]).to_vec(),
+            receipt_merkle_root(&[]).unwrap()
+        );
+
+        let receipts = make_receipts(3);
+        let leaves: Vec<Vec<u8>> = receipts
+            .iter()
+            .map(|receipt| receipt_leaf_hash(receipt).unwrap())
+            .collect();
+
+        assert_eq!(leaves[0], receipt_merkle_root(&receipts[..1]).unwrap());
+        assert_eq!(
+            node_hash(&node_hash(&leaves[0], &leaves[1]), &leaves[2]),
+            receipt_merkle_root(&receipts).unwrap()
+        );
+
+        let mut reordered = receipts.clone();
+        reordered.swap(0, 1);
+        assert_ne!(
+            receipt_merkle_root(&receipts).unwrap(),
+            receipt_merkle_root(&reordered).unwrap()
+        );
+    }
+
+    #[test]
+    // test that every receipt's proof verifies, for trees of several sizes
+    fn receipt_inclusion_proofs() {
+        for count in 1..10 {
+            let receipts = make_receipts(count);
+            let tree = ReceiptMerkleTree::new(&receipts).unwrap();
+            assert_eq!(count, tree.len());
+
+            for (index, receipt) in receipts.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert!(proof.verify(receipt, tree.root()).unwrap());
+            }
+            assert!(tree.proof(count).is_none());
+        }
+    }
+
+    #[test]
+    // test that proofs are rejected for the wrong receipt, position or root
+    fn receipt_inclusion_proof_rejected() {
+        let receipts = make_receipts(5);
+        let tree = ReceiptMerkleTree::new(&receipts).unwrap();
+        let proof = tree
+            .proof_for_transaction(&receipts[2].transaction_id)
+            .unwrap();
+        assert_eq!(2, proof.index());
+
+        assert!(!proof.verify(&receipts[3], tree.root()).unwrap());
+        assert!(!proof
+            .verify(&receipts[2], &receipt_merkle_root(&receipts[..4]).unwrap())
+            .unwrap());
+
+        let moved = InclusionProof::new(3, proof.leaf_count(), proof.path().to_vec());
+        assert!(!moved.verify(&receipts[2], tree.root()).unwrap());
+
+        let truncated = InclusionProof::new(2, 5, proof.path()[1..].to_vec());
+        assert!(!truncated.verify(&receipts[2], tree.root()).unwrap());
+    }
+}
@@ -0,0 +1,304 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Redacted views of transactions and batches.
+//!
+//! A redacted transaction keeps the signed header bytes and header signature of the original,
+//! but replaces the payload with its hash, computed with the payload hash method named in the
+//! header.  The header signature therefore remains verifiable, and the header's own payload hash
+//! can be compared with the redacted hash, while the payload contents are not disclosed.  A
+//! redacted batch is a batch whose transactions are all redacted.
+//!
+//! With the `serde` feature enabled, the redacted views serialize as their unredacted
+//! counterparts do, with `payload_hash` (hex) in place of `payload`.
+
+use crate::protos::{FromBytes, ProtoConversionError};
+
+use super::batch::{Batch, BatchHeader, BatchSignature};
+use super::transaction::{Transaction, TransactionHeader};
+
+/// A transaction with its payload replaced by the payload's hash.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct RedactedTransaction {
+    header: Vec<u8>,
+    header_signature: String,
+    payload_hash: Vec<u8>,
+}
+
+impl RedactedTransaction {
+    /// Redacts the transaction, hashing its payload with the method named in its header.
+    pub fn from_transaction(transaction: &Transaction) -> Result<Self, ProtoConversionError> {
+        let header = transaction.parse_header()?;
+        Ok(RedactedTransaction {
+            header: transaction.header().to_vec(),
+            header_signature: transaction.header_signature().to_string(),
+            payload_hash: header.payload_hash_method().hash(transaction.payload()),
+        })
+    }
+
+    /// The serialized header of the original transaction, exactly as it was signed.
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    pub fn header_signature(&self) -> &str {
+        &self.header_signature
+    }
+
+    pub fn payload_hash(&self) -> &[u8] {
+        &self.payload_hash
+    }
+
+    pub fn parse_header(&self) -> Result<TransactionHeader, ProtoConversionError> {
+        TransactionHeader::from_bytes(&self.header)
+    }
+
+    /// Returns true if the hash of the redacted payload matches the payload hash in the header.
+    pub fn payload_hash_matches_header(&self) -> Result<bool, ProtoConversionError> {
+        Ok(self.parse_header()?.payload_hash() == &self.payload_hash[..])
+    }
+
+    /// Returns true if this is a redaction of the given transaction.
+    pub fn is_redaction_of(&self, transaction: &Transaction) -> Result<bool, ProtoConversionError> {
+        Ok(*self == RedactedTransaction::from_transaction(transaction)?)
+    }
+}
+
+/// A batch with the payloads of its transactions replaced by their hashes.
+#[derive(Debug, Clone, Eq, Hash, PartialEq)]
+pub struct RedactedBatch {
+    header: Vec<u8>,
+    header_signature: String,
+    transactions: Vec<RedactedTransaction>,
+    trace: bool,
+    cosignatures: Vec<BatchSignature>,
+}
+
+impl RedactedBatch {
+    pub fn from_batch(batch: &Batch) -> Result<Self, ProtoConversionError> {
+        Ok(RedactedBatch {
+            header: batch.header().to_vec(),
+            header_signature: batch.header_signature().to_string(),
+            transactions: batch
+                .transactions()
+                .iter()
+                .map(RedactedTransaction::from_transaction)
+                .collect::<Result<_, _>>()?,
+            trace: batch.trace(),
+            cosignatures: batch.cosignatures().to_vec(),
+        })
+    }
+
+    /// The serialized header of the original batch, exactly as it was signed.
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    pub fn header_signature(&self) -> &str {
+        &self.header_signature
+    }
+
+    pub fn transactions(&self) -> &[RedactedTransaction] {
+        &self.transactions
+    }
+
+    pub fn trace(&self) -> bool {
+        self.trace
+    }
+
+    pub fn cosignatures(&self) -> &[BatchSignature] {
+        &self.cosignatures
+    }
+
+    pub fn parse_header(&self) -> Result<BatchHeader, ProtoConversionError> {
+        BatchHeader::from_bytes(&self.header)
+    }
+}
+
+#[cfg(feature = "serde")]
+mod json {
+    use serde::de::Error as DeError;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{RedactedBatch, RedactedTransaction};
+    use crate::protocol::batch::BatchSignature;
+
+    #[derive(Serialize, Deserialize)]
+    struct RedactedTransactionJson {
+        header: String,
+        header_signature: String,
+        payload_hash: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct BatchSignatureJson {
+        signer_public_key: String,
+        signature: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct RedactedBatchJson {
+        header: String,
+        header_signature: String,
+        transactions: Vec<RedactedTransaction>,
+        trace: bool,
+        #[serde(default)]
+        cosignatures: Vec<BatchSignatureJson>,
+    }
+
+    impl Serialize for RedactedTransaction {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            RedactedTransactionJson {
+                header: hex::encode(&self.header),
+                header_signature: self.header_signature.clone(),
+                payload_hash: hex::encode(&self.payload_hash),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for RedactedTransaction {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let json = RedactedTransactionJson::deserialize(deserializer)?;
+            Ok(RedactedTransaction {
+                header: hex::decode(&json.header).map_err(D::Error::custom)?,
+                header_signature: json.header_signature,
+                payload_hash: hex::decode(&json.payload_hash).map_err(D::Error::custom)?,
+            })
+        }
+    }
+
+    impl Serialize for RedactedBatch {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            RedactedBatchJson {
+                header: hex::encode(&self.header),
+                header_signature: self.header_signature.clone(),
+                transactions: self.transactions.clone(),
+                trace: self.trace,
+                cosignatures: self
+                    .cosignatures
+                    .iter()
+                    .map(|sig| BatchSignatureJson {
+                        signer_public_key: hex::encode(sig.signer_public_key()),
+                        signature: sig.signature().to_string(),
+                    })
+                    .collect(),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for RedactedBatch {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let json = RedactedBatchJson::deserialize(deserializer)?;
+            Ok(RedactedBatch {
+                header: hex::decode(&json.header).map_err(D::Error::custom)?,
+                header_signature: json.header_signature,
+                transactions: json.transactions,
+                trace: json.trace,
+                cosignatures: json
+                    .cosignatures
+                    .into_iter()
+                    .map(|sig| -> Result<BatchSignature, D::Error> {
+                        Ok(BatchSignature::new(
+                            hex::decode(&sig.signer_public_key).map_err(D::Error::custom)?,
+                            sig.signature,
+                        ))
+                    })
+                    .collect::<Result<_, _>>()?,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
+
+    fn make_batch() -> Batch {
+        let signer = HashSigner::new();
+        let transactions = [HashMethod::SHA512, HashMethod::SHA256]
+            .iter()
+            .map(|method| {
+                TransactionBuilder::new()
+                    .with_family_name("test".to_string())
+                    .with_family_version("1.0".to_string())
+                    .with_inputs(vec![vec![0x01]])
+                    .with_outputs(vec![vec![0x01]])
+                    .with_payload_hash_method(method.clone())
+                    .with_payload(b"secret payload".to_vec())
+                    .build(&signer)
+                    .unwrap()
+            })
+            .collect();
+        BatchBuilder::new()
+            .with_transactions(transactions)
+            .build(&signer)
+            .unwrap()
+    }
+
+    #[test]
+    // test that redaction keeps the signed headers and replaces payloads with the hash from the
+    // header
+    fn redact_batch() {
+        let batch = make_batch();
+        let redacted = RedactedBatch::from_batch(&batch).unwrap();
+
+        assert_eq!(batch.header(), redacted.header());
+        assert_eq!(batch.header_signature(), redacted.header_signature());
+        assert_eq!(
+            batch.parse_header().unwrap(),
+            redacted.parse_header().unwrap()
+        );
+
+        for (transaction, redacted) in batch.transactions().iter().zip(redacted.transactions()) {
+            let header = transaction.parse_header().unwrap();
+            assert_eq!(transaction.header(), redacted.header());
+            assert_eq!(transaction.header_signature(), redacted.header_signature());
+            assert_eq!(header.payload_hash(), redacted.payload_hash());
+            assert!(redacted.payload_hash_matches_header().unwrap());
+            assert!(redacted.is_redaction_of(transaction).unwrap());
+        }
+
+        let other = batch.transactions()[1].clone();
+        assert!(!redacted.transactions()[0].is_redaction_of(&other).unwrap());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    // test that redacted batches round trip through JSON without a payload field
+    fn redacted_batch_json() {
+        let redacted = RedactedBatch::from_batch(&make_batch()).unwrap();
+
+        let json = serde_json::to_value(&redacted).unwrap();
+        let transaction = &json["transactions"][0];
+        assert!(transaction.get("payload").is_none());
+        assert_eq!(
+            hex::encode(redacted.transactions()[0].payload_hash()),
+            transaction["payload_hash"]
+        );
+
+        assert_eq!(
+            redacted,
+            serde_json::from_value::<RedactedBatch>(json).unwrap()
+        );
+    }
+}
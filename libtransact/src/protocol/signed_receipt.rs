@@ -0,0 +1,291 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Signed transaction receipts.
+//!
+//! A `ReceiptSigner` signs receipts with a configured key, so that they may be presented to third
+//! parties as attestations of the results of execution.  The signature covers the serialized
+//! receipt, which is carried alongside it in the `SignedReceipt`, so that verification does not
+//! depend on re-serializing the receipt identically.
+
+use std::error::Error as StdError;
+
+use protobuf::Message;
+
+use crate::protos;
+use crate::protos::{
+    FromBytes, FromNative, FromProto, IntoBytes, IntoNative, IntoProto, ProtoConversionError,
+};
+use crate::signing;
+use crate::signing::SignatureAlgorithm;
+
+use super::receipt::TransactionReceipt;
+use super::transaction::{signature_algorithm_from_proto, signature_algorithm_to_proto};
+
+#[derive(Debug)]
+pub enum SignedReceiptError {
+    SerializationError(String),
+    SigningError(String),
+    /// No verifier is available for the algorithm of the receipt's signature.
+    UnsupportedAlgorithm(String),
+    /// The signature is not valid for the receipt and signer public key.
+    InvalidSignature(String),
+}
+
+impl StdError for SignedReceiptError {
+    fn description(&self) -> &str {
+        match *self {
+            SignedReceiptError::SerializationError(ref msg) => msg,
+            SignedReceiptError::SigningError(ref msg) => msg,
+            SignedReceiptError::UnsupportedAlgorithm(ref msg) => msg,
+            SignedReceiptError::InvalidSignature(ref msg) => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for SignedReceiptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            SignedReceiptError::SerializationError(ref s) => write!(f, "SerializationError: {}", s),
+            SignedReceiptError::SigningError(ref s) => write!(f, "SigningError: {}", s),
+            SignedReceiptError::UnsupportedAlgorithm(ref s) => {
+                write!(f, "UnsupportedAlgorithm: {}", s)
+            }
+            SignedReceiptError::InvalidSignature(ref s) => write!(f, "InvalidSignature: {}", s),
+        }
+    }
+}
+
+impl From<ProtoConversionError> for SignedReceiptError {
+    fn from(e: ProtoConversionError) -> Self {
+        SignedReceiptError::SerializationError(format!("{}", e))
+    }
+}
+
+/// A transaction receipt with the signature of the node that produced it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SignedReceipt {
+    receipt: TransactionReceipt,
+    receipt_bytes: Vec<u8>,
+    signer_public_key: Vec<u8>,
+    signature: Vec<u8>,
+    signature_algorithm: SignatureAlgorithm,
+}
+
+impl SignedReceipt {
+    pub fn receipt(&self) -> &TransactionReceipt {
+        &self.receipt
+    }
+
+    /// The serialized receipt, exactly as it was signed.
+    pub fn receipt_bytes(&self) -> &[u8] {
+        &self.receipt_bytes
+    }
+
+    pub fn signer_public_key(&self) -> &[u8] {
+        &self.signer_public_key
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.signature
+    }
+
+    pub fn signature_algorithm(&self) -> SignatureAlgorithm {
+        self.signature_algorithm
+    }
+
+    /// Verifies that the receipt was signed by its signer public key.
+    pub fn verify(&self, verifier: &signing::VerifierSelector) -> Result<(), SignedReceiptError> {
+        let verifier = verifier.select(self.signature_algorithm).ok_or_else(|| {
+            SignedReceiptError::UnsupportedAlgorithm(self.signature_algorithm.name().to_string())
+        })?;
+
+        let is_valid = verifier
+            .verify(
+                &self.receipt_bytes,
+                &self.signature,
+                &self.signer_public_key,
+            )
+            .map_err(|e| SignedReceiptError::SigningError(format!("{}", e)))?;
+        if is_valid {
+            Ok(())
+        } else {
+            Err(SignedReceiptError::InvalidSignature(
+                self.receipt.transaction_id.clone(),
+            ))
+        }
+    }
+
+    /// Verifies the receipt, and that it was signed by one of the trusted public keys.
+    pub fn verify_signed_by(
+        &self,
+        verifier: &signing::VerifierSelector,
+        trusted_public_keys: &[Vec<u8>],
+    ) -> Result<(), SignedReceiptError> {
+        if !trusted_public_keys
+            .iter()
+            .any(|key| key.as_slice() == self.signer_public_key.as_slice())
+        {
+            return Err(SignedReceiptError::InvalidSignature(format!(
+                "{} is not signed by a trusted key",
+                self.receipt.transaction_id
+            )));
+        }
+        self.verify(verifier)
+    }
+
+    pub fn into_receipt(self) -> TransactionReceipt {
+        self.receipt
+    }
+}
+
+impl FromProto<protos::transaction_receipt::SignedTransactionReceipt> for SignedReceipt {
+    fn from_proto(
+        signed: protos::transaction_receipt::SignedTransactionReceipt,
+    ) -> Result<Self, ProtoConversionError> {
+        Ok(SignedReceipt {
+            receipt: TransactionReceipt::from_bytes(signed.get_receipt())?,
+            receipt_bytes: signed.get_receipt().to_vec(),
+            signer_public_key: signed.get_signer_public_key().to_vec(),
+            signature: signed.get_signature().to_vec(),
+            signature_algorithm: signature_algorithm_from_proto(signed.get_signature_algorithm())?,
+        })
+    }
+}
+
+impl FromNative<SignedReceipt> for protos::transaction_receipt::SignedTransactionReceipt {
+    fn from_native(signed: SignedReceipt) -> Result<Self, ProtoConversionError> {
+        let mut proto_signed = protos::transaction_receipt::SignedTransactionReceipt::new();
+        proto_signed.set_receipt(signed.receipt_bytes);
+        proto_signed.set_signer_public_key(signed.signer_public_key);
+        proto_signed.set_signature(signed.signature);
+        proto_signed
+            .set_signature_algorithm(signature_algorithm_to_proto(signed.signature_algorithm));
+        Ok(proto_signed)
+    }
+}
+
+impl FromBytes<SignedReceipt> for SignedReceipt {
+    fn from_bytes(bytes: &[u8]) -> Result<SignedReceipt, ProtoConversionError> {
+        let proto: protos::transaction_receipt::SignedTransactionReceipt =
+            protobuf::parse_from_bytes(bytes).map_err(|_| {
+                ProtoConversionError::SerializationError(
+                    "Unable to get SignedTransactionReceipt from bytes".to_string(),
+                )
+            })?;
+        proto.into_native()
+    }
+}
+
+impl IntoBytes for SignedReceipt {
+    fn into_bytes(self) -> Result<Vec<u8>, ProtoConversionError> {
+        let proto = self.into_proto()?;
+        let bytes = proto.write_to_bytes().map_err(|_| {
+            ProtoConversionError::SerializationError(
+                "Unable to get bytes from SignedTransactionReceipt".to_string(),
+            )
+        })?;
+        Ok(bytes)
+    }
+}
+
+impl IntoProto<protos::transaction_receipt::SignedTransactionReceipt> for SignedReceipt {}
+impl IntoNative<SignedReceipt> for protos::transaction_receipt::SignedTransactionReceipt {}
+
+/// Signs receipts with a configured signer.
+pub struct ReceiptSigner {
+    signer: Box<dyn signing::Signer + Send>,
+}
+
+impl ReceiptSigner {
+    pub fn new(signer: Box<dyn signing::Signer + Send>) -> Self {
+        ReceiptSigner { signer }
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.signer.public_key()
+    }
+
+    pub fn sign(&self, receipt: TransactionReceipt) -> Result<SignedReceipt, SignedReceiptError> {
+        let receipt_bytes = receipt.clone().into_bytes()?;
+        let signature = self
+            .signer
+            .sign(&receipt_bytes)
+            .map_err(|e| SignedReceiptError::SigningError(format!("{}", e)))?;
+
+        Ok(SignedReceipt {
+            receipt,
+            receipt_bytes,
+            signer_public_key: self.signer.public_key().to_vec(),
+            signature,
+            signature_algorithm: self.signer.algorithm(),
+        })
+    }
+
+    /// Signs each of the receipts, in order.
+    pub fn sign_all(
+        &self,
+        receipts: Vec<TransactionReceipt>,
+    ) -> Result<Vec<SignedReceipt>, SignedReceiptError> {
+        receipts
+            .into_iter()
+            .map(|receipt| self.sign(receipt))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::receipt::{StateChange, TransactionReceiptBuilder};
+    use crate::signing::hash::{HashSigner, HashVerifier};
+
+    fn make_receipt() -> TransactionReceipt {
+        TransactionReceiptBuilder::new()
+            .with_state_changes(vec![StateChange::Set {
+                key: "abcd".to_string(),
+                value: vec![0x01, 0x02],
+            }])
+            .with_transaction_id("txn".to_string())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    // test that signed receipts verify, survive a round trip through bytes, and reject
+    // untrusted signers
+    fn signed_receipt_verify() {
+        let signer = ReceiptSigner::new(Box::new(HashSigner::new()));
+        let signed = signer.sign(make_receipt()).unwrap();
+        assert_eq!(&make_receipt(), signed.receipt());
+        assert_eq!(SignatureAlgorithm::Secp256k1, signed.signature_algorithm());
+        signed.verify(&HashVerifier::new()).unwrap();
+
+        let bytes = signed.clone().into_bytes().unwrap();
+        let parsed = SignedReceipt::from_bytes(&bytes).unwrap();
+        assert_eq!(signed, parsed);
+        parsed
+            .verify_signed_by(&HashVerifier::new(), &[signer.public_key().to_vec()])
+            .unwrap();
+
+        match parsed.verify_signed_by(&HashVerifier::new(), &[vec![0x00]]) {
+            Err(SignedReceiptError::InvalidSignature(_)) => (),
+            res => panic!("expected InvalidSignature, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,434 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Structural checks of batches.
+//!
+//! These checks need no signature verifier: they confirm that a batch is internally consistent
+//! and within size limits, so that a malformed batch can be rejected before it is submitted.
+//! They do not check signatures; see `protocol::verify` for that.
+
+use std::collections::HashSet;
+
+use super::batch::{Batch, BatchHeader};
+use super::id;
+use super::transaction::TransactionHeader;
+
+/// A structural problem found in a batch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StructureViolation {
+    /// The batch header could not be deserialized; no further checks are made.
+    InvalidBatchHeader(String),
+    /// The batch header lists a different number of transactions than the batch contains.
+    TransactionCountMismatch {
+        header_count: usize,
+        transaction_count: usize,
+    },
+    /// The transaction id at `index` in the batch header is not the id of the transaction at
+    /// that position.
+    TransactionIdMismatch {
+        index: usize,
+        expected: String,
+        actual: String,
+    },
+    /// The transaction appears more than once in the batch.
+    DuplicateTransaction { transaction_id: String },
+    /// The transaction header could not be deserialized.
+    InvalidTransactionHeader {
+        transaction_id: String,
+        reason: String,
+    },
+    /// A transaction header field, or the payload, exceeds its size limit.
+    FieldTooLarge {
+        transaction_id: String,
+        field: String,
+        size: usize,
+        max: usize,
+    },
+    /// The transaction payload does not match the payload hash in its header.
+    PayloadHashMismatch { transaction_id: String },
+    /// The transaction names a different batcher than the batch signer.
+    BatcherMismatch { transaction_id: String },
+}
+
+impl std::fmt::Display for StructureViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            StructureViolation::InvalidBatchHeader(ref s) => {
+                write!(f, "InvalidBatchHeader: {}", s)
+            }
+            StructureViolation::TransactionCountMismatch {
+                header_count,
+                transaction_count,
+            } => write!(
+                f,
+                "TransactionCountMismatch: header lists {}, batch contains {}",
+                header_count, transaction_count
+            ),
+            StructureViolation::TransactionIdMismatch {
+                index,
+                ref expected,
+                ref actual,
+            } => write!(
+                f,
+                "TransactionIdMismatch: at {} header lists {}, batch contains {}",
+                index, expected, actual
+            ),
+            StructureViolation::DuplicateTransaction { ref transaction_id } => {
+                write!(f, "DuplicateTransaction: {}", transaction_id)
+            }
+            StructureViolation::InvalidTransactionHeader {
+                ref transaction_id,
+                ref reason,
+            } => write!(
+                f,
+                "InvalidTransactionHeader: {}: {}",
+                transaction_id, reason
+            ),
+            StructureViolation::FieldTooLarge {
+                ref transaction_id,
+                ref field,
+                size,
+                max,
+            } => write!(
+                f,
+                "FieldTooLarge: {} of {} is {}, maximum is {}",
+                field, transaction_id, size, max
+            ),
+            StructureViolation::PayloadHashMismatch { ref transaction_id } => {
+                write!(f, "PayloadHashMismatch: {}", transaction_id)
+            }
+            StructureViolation::BatcherMismatch { ref transaction_id } => {
+                write!(f, "BatcherMismatch: {}", transaction_id)
+            }
+        }
+    }
+}
+
+/// Size limits for the fields of transaction headers and payloads.
+///
+/// The defaults are generous bounds for well-formed transactions: family names of 64 bytes,
+/// family versions of 32 bytes, nonces of 256 bytes, 1024 inputs, outputs and dependencies, and
+/// addresses of 35 bytes (the length of a full Merkle-Radix address).  Payloads are not limited
+/// by default.
+#[derive(Clone)]
+pub struct StructureLimits {
+    max_family_name_len: Option<usize>,
+    max_family_version_len: Option<usize>,
+    max_nonce_len: Option<usize>,
+    max_addresses: Option<usize>,
+    max_address_len: Option<usize>,
+    max_dependencies: Option<usize>,
+    max_payload_len: Option<usize>,
+}
+
+impl Default for StructureLimits {
+    fn default() -> Self {
+        StructureLimits {
+            max_family_name_len: Some(64),
+            max_family_version_len: Some(32),
+            max_nonce_len: Some(256),
+            max_addresses: Some(1024),
+            max_address_len: Some(35),
+            max_dependencies: Some(1024),
+            max_payload_len: None,
+        }
+    }
+}
+
+impl StructureLimits {
+    pub fn new() -> Self {
+        StructureLimits::default()
+    }
+
+    /// Limits that accept fields of any size.
+    pub fn unlimited() -> Self {
+        StructureLimits {
+            max_family_name_len: None,
+            max_family_version_len: None,
+            max_nonce_len: None,
+            max_addresses: None,
+            max_address_len: None,
+            max_dependencies: None,
+            max_payload_len: None,
+        }
+    }
+
+    pub fn with_max_family_name_len(mut self, max: usize) -> StructureLimits {
+        self.max_family_name_len = Some(max);
+        self
+    }
+
+    pub fn with_max_family_version_len(mut self, max: usize) -> StructureLimits {
+        self.max_family_version_len = Some(max);
+        self
+    }
+
+    pub fn with_max_nonce_len(mut self, max: usize) -> StructureLimits {
+        self.max_nonce_len = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of inputs, and separately of outputs.
+    pub fn with_max_addresses(mut self, max: usize) -> StructureLimits {
+        self.max_addresses = Some(max);
+        self
+    }
+
+    /// Sets the maximum length, in bytes, of each input and output.
+    pub fn with_max_address_len(mut self, max: usize) -> StructureLimits {
+        self.max_address_len = Some(max);
+        self
+    }
+
+    pub fn with_max_dependencies(mut self, max: usize) -> StructureLimits {
+        self.max_dependencies = Some(max);
+        self
+    }
+
+    pub fn with_max_payload_len(mut self, max: usize) -> StructureLimits {
+        self.max_payload_len = Some(max);
+        self
+    }
+
+    fn check(
+        &self,
+        transaction_id: &str,
+        header: &TransactionHeader,
+        payload_len: usize,
+        violations: &mut Vec<StructureViolation>,
+    ) {
+        let mut check_size = |field: &str, size: usize, max: Option<usize>| {
+            if let Some(max) = max {
+                if size > max {
+                    violations.push(StructureViolation::FieldTooLarge {
+                        transaction_id: transaction_id.to_string(),
+                        field: field.to_string(),
+                        size,
+                        max,
+                    });
+                }
+            }
+        };
+
+        check_size(
+            "family_name",
+            header.family_name().len(),
+            self.max_family_name_len,
+        );
+        check_size(
+            "family_version",
+            header.family_version().len(),
+            self.max_family_version_len,
+        );
+        check_size("nonce", header.nonce().len(), self.max_nonce_len);
+        check_size("inputs", header.inputs().len(), self.max_addresses);
+        check_size("outputs", header.outputs().len(), self.max_addresses);
+        check_size(
+            "dependencies",
+            header.dependencies().len(),
+            self.max_dependencies,
+        );
+        check_size("payload", payload_len, self.max_payload_len);
+
+        let longest_input = header.inputs().iter().map(Vec::len).max().unwrap_or(0);
+        check_size("input", longest_input, self.max_address_len);
+        let longest_output = header.outputs().iter().map(Vec::len).max().unwrap_or(0);
+        check_size("output", longest_output, self.max_address_len);
+    }
+}
+
+/// Returns every structural violation found in the batch, in the order the checks are made.
+pub(crate) fn structure_violations(
+    batch: &Batch,
+    limits: &StructureLimits,
+) -> Vec<StructureViolation> {
+    let mut violations = Vec::new();
+
+    let batch_header: BatchHeader = match batch.parse_header() {
+        Ok(header) => header,
+        Err(err) => {
+            violations.push(StructureViolation::InvalidBatchHeader(format!("{}", err)));
+            return violations;
+        }
+    };
+
+    let transactions = batch.transactions();
+    let header_ids = batch_header.transaction_ids();
+    if header_ids.len() != transactions.len() {
+        violations.push(StructureViolation::TransactionCountMismatch {
+            header_count: header_ids.len(),
+            transaction_count: transactions.len(),
+        });
+    }
+    for (index, (header_id, transaction)) in header_ids.iter().zip(transactions).enumerate() {
+        let expected = id::id_from_bytes(header_id);
+        if expected != transaction.header_signature() {
+            violations.push(StructureViolation::TransactionIdMismatch {
+                index,
+                expected,
+                actual: transaction.header_signature().to_string(),
+            });
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for transaction in transactions {
+        let transaction_id = transaction.header_signature();
+        if !seen.insert(transaction_id) {
+            violations.push(StructureViolation::DuplicateTransaction {
+                transaction_id: transaction_id.to_string(),
+            });
+        }
+
+        let header = match transaction.parse_header() {
+            Ok(header) => header,
+            Err(err) => {
+                violations.push(StructureViolation::InvalidTransactionHeader {
+                    transaction_id: transaction_id.to_string(),
+                    reason: format!("{}", err),
+                });
+                continue;
+            }
+        };
+
+        limits.check(
+            transaction_id,
+            &header,
+            transaction.payload().len(),
+            &mut violations,
+        );
+
+        if header.payload_hash_method().hash(transaction.payload()) != header.payload_hash() {
+            violations.push(StructureViolation::PayloadHashMismatch {
+                transaction_id: transaction_id.to_string(),
+            });
+        }
+        if header.batcher_public_key() != batch_header.signer_public_key() {
+            violations.push(StructureViolation::BatcherMismatch {
+                transaction_id: transaction_id.to_string(),
+            });
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::{HashMethod, Transaction, TransactionBuilder};
+    use crate::protos;
+    use crate::protos::{FromNative, FromProto};
+    use crate::signing::hash::HashSigner;
+
+    fn make_transaction(signer: &HashSigner, family_name: &str, payload: &[u8]) -> Transaction {
+        TransactionBuilder::new()
+            .with_family_name(family_name.to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![vec![0x01]])
+            .with_outputs(vec![vec![0x01]])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(payload.to_vec())
+            .build(signer)
+            .unwrap()
+    }
+
+    #[test]
+    // test that a well-formed batch has no violations
+    fn valid_structure() {
+        let signer = HashSigner::new();
+        let batch = BatchBuilder::new()
+            .with_transactions(vec![
+                make_transaction(&signer, "test", b"one"),
+                make_transaction(&signer, "test", b"two"),
+            ])
+            .build(&signer)
+            .unwrap();
+
+        assert!(batch.validate_structure().is_empty());
+    }
+
+    #[test]
+    // test that every violation in a malformed batch is reported
+    fn invalid_structure() {
+        let signer = HashSigner::new();
+        let first = make_transaction(&signer, "test", b"one");
+        let second = make_transaction(&signer, &"x".repeat(65), b"two");
+        let tampered = Transaction::new(
+            first.header().to_vec(),
+            first.header_signature().to_string(),
+            b"tampered".to_vec(),
+        );
+
+        // The batch header lists the transactions the batch was built with; the signature
+        // is valid, but the contents are then replaced.
+        let template = BatchBuilder::new()
+            .with_transactions(vec![first.clone(), second.clone()])
+            .build(&signer)
+            .unwrap();
+        let mut proto = protos::batch::Batch::from_native(template).unwrap();
+        proto.set_transactions(
+            vec![second.clone(), tampered, first.clone()]
+                .into_iter()
+                .map(|t| protos::transaction::Transaction::from_native(t).unwrap())
+                .collect(),
+        );
+        let batch = Batch::from_proto(proto).unwrap();
+
+        let violations = batch.validate_structure();
+        assert_eq!(
+            vec![
+                StructureViolation::TransactionCountMismatch {
+                    header_count: 2,
+                    transaction_count: 3,
+                },
+                StructureViolation::TransactionIdMismatch {
+                    index: 0,
+                    expected: first.header_signature().to_string(),
+                    actual: second.header_signature().to_string(),
+                },
+                StructureViolation::TransactionIdMismatch {
+                    index: 1,
+                    expected: second.header_signature().to_string(),
+                    actual: first.header_signature().to_string(),
+                },
+                StructureViolation::FieldTooLarge {
+                    transaction_id: second.header_signature().to_string(),
+                    field: "family_name".to_string(),
+                    size: 65,
+                    max: 64,
+                },
+                StructureViolation::PayloadHashMismatch {
+                    transaction_id: first.header_signature().to_string(),
+                },
+                StructureViolation::DuplicateTransaction {
+                    transaction_id: first.header_signature().to_string(),
+                },
+            ],
+            violations
+        );
+
+        assert_eq!(
+            5,
+            batch
+                .validate_structure_with_limits(&StructureLimits::unlimited())
+                .len()
+        );
+    }
+}
@@ -22,26 +22,54 @@
 
 use hex;
 use protobuf::Message;
-use sha2::{Digest, Sha512};
+use sha2::{Digest, Sha256, Sha512};
 use std;
 use std::error::Error as StdError;
-
-use rand::distributions::Alphanumeric;
-use rand::Rng;
+use std::sync::Arc;
 
 use crate::protos;
 use crate::protos::{
     FromBytes, FromNative, FromProto, IntoBytes, IntoNative, IntoProto, ProtoConversionError,
 };
 use crate::signing;
-
-static DEFAULT_NONCE_SIZE: usize = 32;
-
+#[cfg(feature = "async")]
+use crate::signing::async_signer::AsyncSigner;
+use crate::signing::SignatureAlgorithm;
+
+use super::dependencies::{dependencies_on, PriorTransaction};
+use super::id;
+use super::namespace;
+use super::nonce::{NonceStrategy, RandomNonce};
+use super::version::{ProtocolVersion, CURRENT_PROTOCOL_VERSION};
+
+/// The method used to hash a transaction's payload.
+///
+/// SHA-512 is the default, and the only method understood by Sawtooth; SHA-256 is provided for
+/// interoperability with chains that standardized on it.
 #[derive(Debug, PartialEq, Clone)]
 pub enum HashMethod {
+    SHA256,
     SHA512,
 }
 
+impl HashMethod {
+    /// Hashes the given bytes with this method.
+    pub fn hash(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            HashMethod::SHA256 => {
+                let mut hasher = Sha256::new();
+                hasher.input(bytes);
+                hasher.result().to_vec()
+            }
+            HashMethod::SHA512 => {
+                let mut hasher = Sha512::new();
+                hasher.input(bytes);
+                hasher.result().to_vec()
+            }
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct TransactionHeader {
     batcher_public_key: Vec<u8>,
@@ -54,6 +82,9 @@ pub struct TransactionHeader {
     payload_hash: Vec<u8>,
     payload_hash_method: HashMethod,
     signer_public_key: Vec<u8>,
+    signature_algorithm: SignatureAlgorithm,
+    protocol_version: ProtocolVersion,
+    fee: Option<TransactionFee>,
 }
 
 impl TransactionHeader {
@@ -96,6 +127,65 @@ impl TransactionHeader {
     pub fn signer_public_key(&self) -> &[u8] {
         &self.signer_public_key
     }
+
+    pub fn signature_algorithm(&self) -> SignatureAlgorithm {
+        self.signature_algorithm
+    }
+
+    pub fn protocol_version(&self) -> &ProtocolVersion {
+        &self.protocol_version
+    }
+
+    /// The fee and priority of the transaction, if it declares them.
+    pub fn fee(&self) -> Option<&TransactionFee> {
+        self.fee.as_ref()
+    }
+
+    /// Returns true if the transaction's inputs cover the given address.
+    ///
+    /// Inputs shorter than a full address are namespace wildcards; see `protocol::namespace`.
+    pub fn may_read(&self, address: &[u8]) -> bool {
+        namespace::any_covers(&self.inputs, address)
+    }
+
+    /// Returns true if the transaction's outputs cover the given address.
+    pub fn may_write(&self, address: &[u8]) -> bool {
+        namespace::any_covers(&self.outputs, address)
+    }
+
+    /// Returns true if the transaction conflicts with the other: one writes an address that the
+    /// other reads or writes.
+    pub fn conflicts_with(&self, other: &TransactionHeader) -> bool {
+        namespace::any_intersects(&self.outputs, &other.inputs)
+            || namespace::any_intersects(&self.outputs, &other.outputs)
+            || namespace::any_intersects(&self.inputs, &other.outputs)
+    }
+}
+
+/// The fee and priority declared by a transaction.
+///
+/// This is an optional header extension for permissioned deployments that order transactions by
+/// cost.  Transact does not charge the fee; it is available to schedulers and to the
+/// application.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct TransactionFee {
+    fee: u64,
+    priority: u32,
+}
+
+impl TransactionFee {
+    pub fn new(fee: u64, priority: u32) -> Self {
+        TransactionFee { fee, priority }
+    }
+
+    pub fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    /// The priority of the transaction; higher values should be scheduled first.
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
 }
 
 impl From<hex::FromHexError> for ProtoConversionError {
@@ -114,6 +204,13 @@ impl FromProto<protos::transaction::TransactionHeader> for TransactionHeader {
     fn from_proto(
         header: protos::transaction::TransactionHeader,
     ) -> Result<Self, ProtoConversionError> {
+        // Only one of the payload hash fields is set; Sawtooth headers only have payload_sha512.
+        let (payload_hash_method, payload_hash) = if header.get_payload_sha256().is_empty() {
+            (HashMethod::SHA512, header.get_payload_sha512())
+        } else {
+            (HashMethod::SHA256, header.get_payload_sha256())
+        };
+
         Ok(TransactionHeader {
             family_name: header.get_family_name().to_string(),
             family_version: header.get_family_version().to_string(),
@@ -134,9 +231,19 @@ impl FromProto<protos::transaction::TransactionHeader> for TransactionHeader {
                 .iter()
                 .map(|d| hex::decode(d).map_err(ProtoConversionError::from))
                 .collect::<Result<_, _>>()?,
-            payload_hash: hex::decode(header.get_payload_sha512())?,
-            payload_hash_method: HashMethod::SHA512,
+            payload_hash: hex::decode(payload_hash)?,
+            payload_hash_method,
             signer_public_key: hex::decode(header.get_signer_public_key())?,
+            signature_algorithm: signature_algorithm_from_proto(header.get_signature_algorithm())?,
+            protocol_version: protocol_version_from_proto(header.get_protocol_version())?,
+            fee: if header.has_fee() {
+                Some(TransactionFee::new(
+                    header.get_fee().get_fee(),
+                    header.get_fee().get_priority(),
+                ))
+            } else {
+                None
+            },
         })
     }
 }
@@ -151,12 +258,70 @@ impl FromNative<TransactionHeader> for protos::transaction::TransactionHeader {
         proto_header.set_inputs(header.inputs().iter().map(hex::encode).collect());
         proto_header.set_nonce(String::from_utf8(header.nonce().to_vec())?);
         proto_header.set_outputs(header.outputs().iter().map(hex::encode).collect());
-        proto_header.set_payload_sha512(hex::encode(header.payload_hash()));
+        let payload_hash = hex::encode(header.payload_hash());
+        match header.payload_hash_method() {
+            HashMethod::SHA256 => proto_header.set_payload_sha256(payload_hash),
+            HashMethod::SHA512 => proto_header.set_payload_sha512(payload_hash),
+        }
         proto_header.set_signer_public_key(hex::encode(header.signer_public_key()));
+        proto_header
+            .set_signature_algorithm(signature_algorithm_to_proto(header.signature_algorithm()));
+        proto_header.set_protocol_version(protocol_version_to_proto(header.protocol_version()));
+        if let Some(fee) = header.fee() {
+            let mut proto_fee = protos::transaction::TransactionFee::new();
+            proto_fee.set_fee(fee.fee());
+            proto_fee.set_priority(fee.priority());
+            proto_header.set_fee(proto_fee);
+        }
         Ok(proto_header)
     }
 }
 
+/// Parses the signature algorithm field of a header, where an empty field means secp256k1.
+pub(crate) fn signature_algorithm_from_proto(
+    name: &str,
+) -> Result<SignatureAlgorithm, ProtoConversionError> {
+    if name.is_empty() {
+        return Ok(SignatureAlgorithm::Secp256k1);
+    }
+    SignatureAlgorithm::from_name(name).ok_or_else(|| {
+        ProtoConversionError::InvalidTypeError(format!("Unknown signature algorithm: {}", name))
+    })
+}
+
+/// Formats the signature algorithm field of a header.
+///
+/// Secp256k1 is left empty, so that headers remain byte-for-byte compatible with Sawtooth.
+pub(crate) fn signature_algorithm_to_proto(algorithm: SignatureAlgorithm) -> String {
+    match algorithm {
+        SignatureAlgorithm::Secp256k1 => String::new(),
+        _ => algorithm.name().to_string(),
+    }
+}
+
+/// Parses the protocol version field of a header, where an empty field means version 1.0.
+pub(crate) fn protocol_version_from_proto(
+    version: &str,
+) -> Result<ProtocolVersion, ProtoConversionError> {
+    if version.is_empty() {
+        return Ok(ProtocolVersion::default());
+    }
+    ProtocolVersion::parse(version)
+        .map_err(|e| ProtoConversionError::InvalidTypeError(format!("{}", e)))
+}
+
+/// Formats the protocol version field of a header.
+///
+/// Version 1.0 is left empty, so that headers remain byte-for-byte compatible with those written
+/// before the field was added.
+pub(crate) fn protocol_version_to_proto(version: &ProtocolVersion) -> String {
+    if *version == ProtocolVersion::default() {
+        String::new()
+    } else {
+        version.to_string()
+    }
+}
+
 impl FromBytes<TransactionHeader> for TransactionHeader {
     fn from_bytes(bytes: &[u8]) -> Result<TransactionHeader, ProtoConversionError> {
         let proto: protos::transaction::TransactionHeader = protobuf::parse_from_bytes(bytes)
@@ -200,10 +365,20 @@ impl Transaction {
         }
     }
 
+    /// The serialized header, exactly as it was signed.
+    ///
+    /// Signatures and hashes must be computed over these bytes.  Re-serializing a parsed
+    /// `TransactionHeader` is not guaranteed to reproduce them, as a header produced by another
+    /// implementation may order or encode its fields differently.
     pub fn header(&self) -> &[u8] {
         &self.header
     }
 
+    /// Parses the serialized header into its expanded form.
+    pub fn parse_header(&self) -> Result<TransactionHeader, ProtoConversionError> {
+        TransactionHeader::from_bytes(&self.header)
+    }
+
     pub fn header_signature(&self) -> &str {
         &self.header_signature
     }
@@ -212,8 +387,9 @@ impl Transaction {
         &self.payload
     }
 
+    /// Pairs the transaction with its parsed header.
     pub fn into_pair(self) -> Result<TransactionPair, TransactionBuildError> {
-        let header = TransactionHeader::from_bytes(&self.header)?;
+        let header = self.parse_header()?;
 
         Ok(TransactionPair {
             transaction: self,
@@ -232,6 +408,18 @@ impl From<protos::transaction::Transaction> for Transaction {
     }
 }
 
+impl FromNative<Transaction> for protos::transaction::Transaction {
+    fn from_native(transaction: Transaction) -> Result<Self, ProtoConversionError> {
+        let mut proto_transaction = protos::transaction::Transaction::new();
+        proto_transaction.set_header(transaction.header);
+        proto_transaction.set_header_signature(transaction.header_signature);
+        proto_transaction.set_payload(transaction.payload);
+        Ok(proto_transaction)
+    }
+}
+
+impl IntoProto<protos::transaction::Transaction> for Transaction {}
+
 #[derive(Debug)]
 pub struct TransactionPair {
     transaction: Transaction,
@@ -247,14 +435,90 @@ impl TransactionPair {
         &self.header
     }
 
+    /// The serialized header of the transaction, exactly as it was signed.
+    pub fn header_bytes(&self) -> &[u8] {
+        self.transaction.header()
+    }
+
     pub fn take(self) -> (Transaction, TransactionHeader) {
         (self.transaction, self.header)
     }
 }
 
+/// A transaction whose header has been built but not yet signed.
+///
+/// This supports signing outside the process, such as by a wallet or a signing service: the
+/// `header_bytes` are sent to the signer, and the detached signature it returns is passed to
+/// `assemble`, which checks it before producing the transaction.
+#[derive(Debug, Clone)]
+pub struct UnsignedTransaction {
+    header: TransactionHeader,
+    header_bytes: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl UnsignedTransaction {
+    pub fn header(&self) -> &TransactionHeader {
+        &self.header
+    }
+
+    /// The serialized header; these are the exact bytes that must be signed.
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.header_bytes
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Assembles the transaction from a detached signature of the header bytes.
+    ///
+    /// The signature is checked against the header's signer public key with the verifier for
+    /// the header's signature algorithm.
+    pub fn assemble(
+        self,
+        signature: &[u8],
+        verifier: &signing::VerifierSelector,
+    ) -> Result<TransactionPair, TransactionBuildError> {
+        let algorithm = self.header.signature_algorithm;
+        let verifier = verifier.select(algorithm).ok_or_else(|| {
+            TransactionBuildError::SigningError(format!(
+                "no verifier available for {}",
+                algorithm.name()
+            ))
+        })?;
+        let valid = verifier
+            .verify(
+                &self.header_bytes,
+                signature,
+                &self.header.signer_public_key,
+            )
+            .map_err(|e| TransactionBuildError::SigningError(format!("{}", e)))?;
+        if !valid {
+            return Err(TransactionBuildError::InvalidSignature(
+                "signature does not match the transaction header".to_string(),
+            ));
+        }
+
+        Ok(self.into_pair(id::id_from_signature(signature)))
+    }
+
+    fn into_pair(self, header_signature: String) -> TransactionPair {
+        TransactionPair {
+            transaction: Transaction {
+                header: self.header_bytes,
+                header_signature,
+                payload: self.payload,
+            },
+            header: self.header,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum TransactionBuildError {
     DeserializationError(String),
+    InvalidSignature(String),
     MissingField(String),
     SerializationError(String),
     SigningError(String),
@@ -264,6 +528,7 @@ impl StdError for TransactionBuildError {
     fn description(&self) -> &str {
         match *self {
             TransactionBuildError::DeserializationError(ref msg) => msg,
+            TransactionBuildError::InvalidSignature(ref msg) => msg,
             TransactionBuildError::MissingField(ref msg) => msg,
             TransactionBuildError::SerializationError(ref msg) => msg,
             TransactionBuildError::SigningError(ref msg) => msg,
@@ -273,6 +538,7 @@ impl StdError for TransactionBuildError {
     fn cause(&self) -> Option<&StdError> {
         match *self {
             TransactionBuildError::DeserializationError(_) => None,
+            TransactionBuildError::InvalidSignature(_) => None,
             TransactionBuildError::MissingField(_) => None,
             TransactionBuildError::SerializationError(_) => None,
             TransactionBuildError::SigningError(_) => None,
@@ -286,6 +552,7 @@ impl std::fmt::Display for TransactionBuildError {
             TransactionBuildError::DeserializationError(ref s) => {
                 write!(f, "DeserializationError: {}", s)
             }
+            TransactionBuildError::InvalidSignature(ref s) => write!(f, "InvalidSignature: {}", s),
             TransactionBuildError::MissingField(ref s) => write!(f, "MissingField: {}", s),
             TransactionBuildError::SerializationError(ref s) => {
                 write!(f, "SerializationError: {}", s)
@@ -305,13 +572,16 @@ impl From<ProtoConversionError> for TransactionBuildError {
 pub struct TransactionBuilder {
     batcher_public_key: Option<Vec<u8>>,
     dependencies: Option<Vec<Vec<u8>>>,
+    prior_transactions: Vec<PriorTransaction>,
     family_name: Option<String>,
     family_version: Option<String>,
     inputs: Option<Vec<Vec<u8>>>,
     outputs: Option<Vec<Vec<u8>>>,
     nonce: Option<Vec<u8>>,
+    nonce_strategy: Option<Arc<dyn NonceStrategy>>,
     payload_hash_method: Option<HashMethod>,
     payload: Option<Vec<u8>>,
+    fee: Option<TransactionFee>,
 }
 
 impl TransactionBuilder {
@@ -329,6 +599,18 @@ impl TransactionBuilder {
         self
     }
 
+    /// Adds a dependency on each of the given prior transactions that conflicts with this one.
+    ///
+    /// A prior transaction conflicts if it writes an address this transaction reads or writes,
+    /// or reads an address this transaction writes.  The dependencies are computed from the
+    /// inputs and outputs when the transaction is built, and follow any set with
+    /// `with_dependencies`.
+    pub fn with_dependencies_from(mut self, prior: &[TransactionPair]) -> TransactionBuilder {
+        self.prior_transactions
+            .extend(prior.iter().map(PriorTransaction::from_pair));
+        self
+    }
+
     pub fn with_family_name(mut self, family_name: String) -> TransactionBuilder {
         self.family_name = Some(family_name);
         self
@@ -354,6 +636,18 @@ impl TransactionBuilder {
         self
     }
 
+    /// Sets the strategy used to generate the nonce, if one is not set with `with_nonce`.
+    ///
+    /// The strategy is shared by clones of the builder, so a counter-based strategy will yield
+    /// distinct nonces for each transaction built from a common template builder.
+    pub fn with_nonce_strategy(
+        mut self,
+        nonce_strategy: Arc<dyn NonceStrategy>,
+    ) -> TransactionBuilder {
+        self.nonce_strategy = Some(nonce_strategy);
+        self
+    }
+
     pub fn with_payload_hash_method(
         mut self,
         payload_hash_method: HashMethod,
@@ -367,14 +661,36 @@ impl TransactionBuilder {
         self
     }
 
+    /// Declares the fee and priority of the transaction.
+    pub fn with_fee(mut self, fee: TransactionFee) -> TransactionBuilder {
+        self.fee = Some(fee);
+        self
+    }
+
     pub fn build_pair(
         self,
         signer: &signing::Signer,
     ) -> Result<TransactionPair, TransactionBuildError> {
+        let unsigned = self.build_unsigned(signer.public_key().to_vec(), signer.algorithm())?;
+        let header_signature = id::sign_header(unsigned.header_bytes(), signer)
+            .map_err(|e| TransactionBuildError::SigningError(format!("{}", e)))?;
+
+        Ok(unsigned.into_pair(header_signature))
+    }
+
+    /// Builds the transaction header for the given signer, without signing it.
+    ///
+    /// The batcher public key defaults to `signer_public_key`.  The header is signed elsewhere
+    /// and the transaction completed with `UnsignedTransaction::assemble`.
+    pub fn build_unsigned(
+        self,
+        signer_public_key: Vec<u8>,
+        signature_algorithm: SignatureAlgorithm,
+    ) -> Result<UnsignedTransaction, TransactionBuildError> {
         let batcher_public_key = self
             .batcher_public_key
-            .unwrap_or_else(|| signer.public_key().to_vec());
-        let dependencies = self.dependencies.unwrap_or_else(|| vec![]);
+            .unwrap_or_else(|| signer_public_key.clone());
+        let mut dependencies = self.dependencies.unwrap_or_else(|| vec![]);
         let family_name = self.family_name.ok_or_else(|| {
             TransactionBuildError::MissingField("'family_name' field is required".to_string())
         })?;
@@ -387,13 +703,15 @@ impl TransactionBuilder {
         let outputs = self.outputs.ok_or_else(|| {
             TransactionBuildError::MissingField("'outputs' field is required".to_string())
         })?;
-        let nonce = self.nonce.unwrap_or_else(|| {
-            rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(DEFAULT_NONCE_SIZE)
-                .collect::<String>()
-                .as_bytes()
-                .to_vec()
+        for dependency in dependencies_on(&self.prior_transactions, &inputs, &outputs)? {
+            if !dependencies.contains(&dependency) {
+                dependencies.push(dependency);
+            }
+        }
+        let nonce_strategy = self.nonce_strategy;
+        let nonce = self.nonce.unwrap_or_else(|| match nonce_strategy {
+            Some(strategy) => strategy.next_nonce(),
+            None => RandomNonce::new().next_nonce(),
         });
         let payload_hash_method = self.payload_hash_method.ok_or_else(|| {
             TransactionBuildError::MissingField(
@@ -403,15 +721,7 @@ impl TransactionBuilder {
         let payload = self.payload.ok_or_else(|| {
             TransactionBuildError::MissingField("'payload' field is required".to_string())
         })?;
-        let signer_public_key = signer.public_key().to_vec();
-
-        let payload_hash = match payload_hash_method {
-            HashMethod::SHA512 => {
-                let mut hasher = Sha512::new();
-                hasher.input(&payload);
-                hasher.result().to_vec()
-            }
-        };
+        let payload_hash = payload_hash_method.hash(&payload);
 
         let header = TransactionHeader {
             batcher_public_key,
@@ -424,6 +734,9 @@ impl TransactionBuilder {
             payload_hash,
             payload_hash_method,
             signer_public_key,
+            signature_algorithm,
+            protocol_version: CURRENT_PROTOCOL_VERSION,
+            fee: self.fee,
         };
 
         let header_proto: protos::transaction::TransactionHeader = header
@@ -434,36 +747,196 @@ impl TransactionBuilder {
             .write_to_bytes()
             .map_err(|e| TransactionBuildError::SerializationError(format!("{}", e)))?;
 
-        let header_signature = hex::encode(
-            signer
-                .sign(&header_bytes)
-                .map_err(|e| TransactionBuildError::SigningError(format!("{}", e)))?,
-        );
-
-        let transaction = Transaction {
-            header: header_bytes,
-            header_signature,
-            payload,
-        };
-
-        Ok(TransactionPair {
-            transaction,
+        Ok(UnsignedTransaction {
             header,
+            header_bytes,
+            payload,
         })
     }
 
     pub fn build(self, signer: &signing::Signer) -> Result<Transaction, TransactionBuildError> {
         Ok(self.build_pair(signer)?.transaction)
     }
+
+    /// Builds the transaction, awaiting the header signature from an asynchronous signer.
+    #[cfg(feature = "async")]
+    pub async fn build_pair_async(
+        self,
+        signer: &dyn AsyncSigner,
+    ) -> Result<TransactionPair, TransactionBuildError> {
+        let unsigned = self.build_unsigned(signer.public_key().to_vec(), signer.algorithm())?;
+        let header_signature = id::sign_header_async(unsigned.header_bytes(), signer)
+            .await
+            .map_err(|e| TransactionBuildError::SigningError(format!("{}", e)))?;
+
+        Ok(unsigned.into_pair(header_signature))
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn build_async(
+        self,
+        signer: &dyn AsyncSigner,
+    ) -> Result<Transaction, TransactionBuildError> {
+        Ok(self.build_pair_async(signer).await?.transaction)
+    }
+}
+
+#[cfg(feature = "serde")]
+mod json {
+    use serde::de::Error as DeError;
+    use serde::ser::Error as SerError;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{HashMethod, Transaction, TransactionFee, TransactionHeader};
+    use crate::protocol::json::{decode_hex_list, encode_hex_list};
+    use crate::protocol::version::ProtocolVersion;
+    use crate::signing::SignatureAlgorithm;
+
+    #[derive(Serialize, Deserialize)]
+    struct TransactionHeaderJson {
+        batcher_public_key: String,
+        dependencies: Vec<String>,
+        family_name: String,
+        family_version: String,
+        inputs: Vec<String>,
+        outputs: Vec<String>,
+        nonce: String,
+        payload_hash: String,
+        payload_hash_method: String,
+        signer_public_key: String,
+        #[serde(default = "default_signature_algorithm")]
+        signature_algorithm: String,
+        #[serde(default = "default_protocol_version")]
+        protocol_version: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fee: Option<TransactionFeeJson>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TransactionFeeJson {
+        fee: u64,
+        priority: u32,
+    }
+
+    fn default_signature_algorithm() -> String {
+        SignatureAlgorithm::Secp256k1.name().to_string()
+    }
+
+    fn default_protocol_version() -> String {
+        ProtocolVersion::default().to_string()
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct TransactionJson {
+        header: String,
+        header_signature: String,
+        payload: String,
+    }
+
+    fn hash_method_name(hash_method: &HashMethod) -> &'static str {
+        match hash_method {
+            HashMethod::SHA256 => "SHA256",
+            HashMethod::SHA512 => "SHA512",
+        }
+    }
+
+    fn hash_method_from_name(name: &str) -> Result<HashMethod, String> {
+        match name {
+            "SHA256" => Ok(HashMethod::SHA256),
+            "SHA512" => Ok(HashMethod::SHA512),
+            _ => Err(format!("unknown payload hash method: {}", name)),
+        }
+    }
+
+    impl Serialize for TransactionHeader {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            TransactionHeaderJson {
+                batcher_public_key: hex::encode(&self.batcher_public_key),
+                dependencies: encode_hex_list(&self.dependencies),
+                family_name: self.family_name.clone(),
+                family_version: self.family_version.clone(),
+                inputs: encode_hex_list(&self.inputs),
+                outputs: encode_hex_list(&self.outputs),
+                nonce: String::from_utf8(self.nonce.clone()).map_err(S::Error::custom)?,
+                payload_hash: hex::encode(&self.payload_hash),
+                payload_hash_method: hash_method_name(&self.payload_hash_method).to_string(),
+                signer_public_key: hex::encode(&self.signer_public_key),
+                signature_algorithm: self.signature_algorithm.name().to_string(),
+                protocol_version: self.protocol_version.to_string(),
+                fee: self.fee.map(|fee| TransactionFeeJson {
+                    fee: fee.fee,
+                    priority: fee.priority,
+                }),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for TransactionHeader {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let json = TransactionHeaderJson::deserialize(deserializer)?;
+            let signature_algorithm = SignatureAlgorithm::from_name(&json.signature_algorithm)
+                .ok_or_else(|| {
+                    D::Error::custom(format!(
+                        "unknown signature algorithm: {}",
+                        json.signature_algorithm
+                    ))
+                })?;
+            Ok(TransactionHeader {
+                batcher_public_key: hex::decode(&json.batcher_public_key)
+                    .map_err(D::Error::custom)?,
+                dependencies: decode_hex_list(&json.dependencies).map_err(D::Error::custom)?,
+                family_name: json.family_name,
+                family_version: json.family_version,
+                inputs: decode_hex_list(&json.inputs).map_err(D::Error::custom)?,
+                outputs: decode_hex_list(&json.outputs).map_err(D::Error::custom)?,
+                nonce: json.nonce.into_bytes(),
+                payload_hash: hex::decode(&json.payload_hash).map_err(D::Error::custom)?,
+                payload_hash_method: hash_method_from_name(&json.payload_hash_method)
+                    .map_err(D::Error::custom)?,
+                signer_public_key: hex::decode(&json.signer_public_key)
+                    .map_err(D::Error::custom)?,
+                signature_algorithm,
+                protocol_version: ProtocolVersion::parse(&json.protocol_version)
+                    .map_err(D::Error::custom)?,
+                fee: json
+                    .fee
+                    .map(|fee| TransactionFee::new(fee.fee, fee.priority)),
+            })
+        }
+    }
+
+    impl Serialize for Transaction {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            TransactionJson {
+                header: hex::encode(&self.header),
+                header_signature: self.header_signature.clone(),
+                payload: hex::encode(&self.payload),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Transaction {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let json = TransactionJson::deserialize(deserializer)?;
+            Ok(Transaction {
+                header: hex::decode(&json.header).map_err(D::Error::custom)?,
+                header_signature: json.header_signature,
+                payload: hex::decode(&json.payload).map_err(D::Error::custom)?,
+            })
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::protocol::version::check_compatibility;
     #[cfg(feature = "sawtooth-compat")]
     use crate::protos;
-    use crate::signing::hash::HashSigner;
+    use crate::signing::hash::{HashSigner, HashVerifier};
     use crate::signing::Signer;
 
     #[cfg(feature = "sawtooth-compat")]
@@ -491,6 +964,11 @@ mod tests {
 
     fn check_builder_transaction(signer: &Signer, pair: &TransactionPair) {
         let payload_hash = match pair.header().payload_hash_method() {
+            HashMethod::SHA256 => {
+                let mut hasher = Sha256::new();
+                hasher.input(&pair.transaction().payload());
+                hasher.result().to_vec()
+            }
             HashMethod::SHA512 => {
                 let mut hasher = Sha512::new();
                 hasher.input(&pair.transaction().payload());
@@ -576,6 +1054,258 @@ mod tests {
         check_builder_transaction(&signer, &pair);
     }
 
+    #[test]
+    // test that a shared nonce strategy is used for each transaction built from cloned builders
+    fn transaction_builder_nonce_strategy() {
+        let signer = HashSigner::new();
+        let strategy = Arc::new(crate::protocol::nonce::CounterNonce::with_prefix("txn-", 0));
+
+        let builder = TransactionBuilder::new()
+            .with_family_name(FAMILY_NAME.to_string())
+            .with_family_version(FAMILY_VERSION.to_string())
+            .with_inputs(vec![hex::decode(KEY4).unwrap()])
+            .with_outputs(vec![hex::decode(KEY6).unwrap()])
+            .with_nonce_strategy(strategy)
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(BYTES2.to_vec());
+
+        let first = builder.clone().build_pair(&signer).unwrap();
+        let second = builder.build_pair(&signer).unwrap();
+
+        assert_eq!(b"txn-0", first.header().nonce());
+        assert_eq!(b"txn-1", second.header().nonce());
+    }
+
+    #[test]
+    // test that a transaction assembled from a detached signature is identical to one built with
+    // the signer, and that a signature of other bytes is rejected
+    fn transaction_builder_detached_signature() {
+        let signer = HashSigner::new();
+        let builder = TransactionBuilder::new()
+            .with_family_name(FAMILY_NAME.to_string())
+            .with_family_version(FAMILY_VERSION.to_string())
+            .with_inputs(vec![hex::decode(KEY4).unwrap()])
+            .with_outputs(vec![hex::decode(KEY6).unwrap()])
+            .with_nonce(NONCE.to_string().into_bytes())
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(BYTES2.to_vec());
+
+        let unsigned = builder
+            .clone()
+            .build_unsigned(signer.public_key().to_vec(), signer.algorithm())
+            .unwrap();
+        let signature = signer.sign(unsigned.header_bytes()).unwrap();
+        let pair = unsigned
+            .clone()
+            .assemble(&signature, &HashVerifier::new())
+            .unwrap();
+
+        let expected = builder.build_pair(&signer).unwrap();
+        assert_eq!(expected.transaction(), pair.transaction());
+        assert_eq!(expected.header(), pair.header());
+
+        let wrong_signature = signer.sign(b"other bytes").unwrap();
+        match unsigned.assemble(&wrong_signature, &HashVerifier::new()) {
+            Err(TransactionBuildError::InvalidSignature(_)) => (),
+            res => panic!("expected InvalidSignature, got {:?}", res),
+        }
+    }
+
+    #[test]
+    // test that short inputs and outputs act as namespace wildcards when checking access and
+    // conflicts, while full addresses cover only themselves
+    fn transaction_header_namespaces() {
+        let build = |inputs: Vec<Vec<u8>>, outputs: Vec<Vec<u8>>| {
+            TransactionBuilder::new()
+                .with_family_name(FAMILY_NAME.to_string())
+                .with_family_version(FAMILY_VERSION.to_string())
+                .with_inputs(inputs)
+                .with_outputs(outputs)
+                .with_payload_hash_method(HashMethod::SHA512)
+                .with_payload(BYTES2.to_vec())
+                .build_pair(&HashSigner::new())
+                .unwrap()
+                .take()
+                .1
+        };
+        let mut full = vec![0x1c, 0xf1, 0x26];
+        full.resize(namespace::ADDRESS_LENGTH, 0x01);
+        let mut other = full.clone();
+        other[namespace::ADDRESS_LENGTH - 1] = 0x02;
+
+        let header = build(vec![vec![0x1c, 0xf1]], vec![full.clone()]);
+        assert!(header.may_read(&full));
+        assert!(header.may_read(&other));
+        assert!(!header.may_read(&[0x1c]));
+        assert!(header.may_write(&full));
+        assert!(!header.may_write(&other));
+
+        assert!(header.conflicts_with(&build(vec![full.clone()], vec![])));
+        assert!(!header.conflicts_with(&build(vec![other.clone()], vec![])));
+        assert!(header.conflicts_with(&build(vec![], vec![other.clone()])));
+        assert!(!header.conflicts_with(&build(vec![vec![0x1c, 0x00]], vec![])));
+        assert!(!header.conflicts_with(&build(vec![], vec![vec![0x00]])));
+    }
+
+    #[test]
+    // test that a declared fee is carried in the header through serialization, and that headers
+    // without one have no fee
+    fn transaction_builder_fee() {
+        let signer = HashSigner::new();
+        let builder = TransactionBuilder::new()
+            .with_family_name(FAMILY_NAME.to_string())
+            .with_family_version(FAMILY_VERSION.to_string())
+            .with_inputs(vec![hex::decode(KEY4).unwrap()])
+            .with_outputs(vec![hex::decode(KEY6).unwrap()])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(BYTES2.to_vec());
+
+        let pair = builder
+            .clone()
+            .with_fee(TransactionFee::new(250, 7))
+            .build_pair(&signer)
+            .unwrap();
+        assert_eq!(Some(&TransactionFee::new(250, 7)), pair.header().fee());
+        let header = pair.transaction().parse_header().unwrap();
+        assert_eq!(250, header.fee().unwrap().fee());
+        assert_eq!(7, header.fee().unwrap().priority());
+
+        let pair = builder.build_pair(&signer).unwrap();
+        assert_eq!(None, pair.transaction().parse_header().unwrap().fee());
+    }
+
+    #[test]
+    // test that a SHA-256 payload hash is recorded in the header and survives serialization
+    fn transaction_builder_sha256() {
+        let signer = HashSigner::new();
+        let pair = TransactionBuilder::new()
+            .with_family_name(FAMILY_NAME.to_string())
+            .with_family_version(FAMILY_VERSION.to_string())
+            .with_inputs(vec![hex::decode(KEY4).unwrap()])
+            .with_outputs(vec![hex::decode(KEY6).unwrap()])
+            .with_payload_hash_method(HashMethod::SHA256)
+            .with_payload(BYTES2.to_vec())
+            .build_pair(&signer)
+            .unwrap();
+
+        assert_eq!(HashMethod::SHA256, *pair.header().payload_hash_method());
+        assert_eq!(32, pair.header().payload_hash().len());
+        assert_eq!(
+            HashMethod::SHA256.hash(&BYTES2),
+            pair.header().payload_hash()
+        );
+
+        let header = pair.transaction().parse_header().unwrap();
+        assert_eq!(pair.header(), &header);
+    }
+
+    #[test]
+    // test that a transaction signed by the HashSigner has the same header bytes, and therefore
+    // the same id, as before signature algorithms were recorded in headers
+    fn transaction_builder_hash_signer_compatibility() {
+        let header = concat!(
+            "0a16363836313733363835663733363936373665363537321a04746573742203312e302a02616132",
+            "056e6f6e63653a0261614a8001373062333363653963393034376533306639313765376561313365",
+            "34326637373637303038633366346639633962616634396534333930666336323535343965393632",
+            "35656565333962393435343530373465386131383234636633663233383436336231316263303364",
+            "39373334386530666332393939636131666666376652163638363137333638356637333639363736",
+            "6536353732",
+        );
+        let header_signature = concat!(
+            "fad6b5d11a96ef9e0acee8dbce05df241f1fbeafbcbc906e15c30c04383f981cdaf2ae3270b7bb82",
+            "2a6e630b0e176de52904fa18fef903643d2297a98e78aaea",
+        );
+
+        let pair = TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![vec![0xaa]])
+            .with_outputs(vec![vec![0xaa]])
+            .with_nonce(b"nonce".to_vec())
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(b"payload".to_vec())
+            .build_pair(&HashSigner::new())
+            .unwrap();
+
+        assert_eq!(
+            SignatureAlgorithm::Secp256k1,
+            pair.header().signature_algorithm()
+        );
+        assert_eq!(header, hex::encode(pair.transaction().header()));
+        assert_eq!(header_signature, pair.transaction().header_signature());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    // test that headers and transactions round trip through their canonical JSON form
+    fn transaction_json() {
+        let header = TransactionHeader {
+            batcher_public_key: hex::decode(KEY1).unwrap(),
+            dependencies: vec![hex::decode(KEY2).unwrap()],
+            family_name: FAMILY_NAME.to_string(),
+            family_version: FAMILY_VERSION.to_string(),
+            inputs: vec![hex::decode(KEY4).unwrap()],
+            nonce: NONCE.to_string().into_bytes(),
+            outputs: vec![hex::decode(KEY6).unwrap()],
+            payload_hash: hex::decode(HASH).unwrap(),
+            payload_hash_method: HashMethod::SHA512,
+            signer_public_key: hex::decode(KEY8).unwrap(),
+            signature_algorithm: SignatureAlgorithm::Secp256k1,
+            protocol_version: ProtocolVersion::default(),
+            fee: None,
+        };
+
+        let json = serde_json::to_value(&header).unwrap();
+        assert_eq!(KEY1, json["batcher_public_key"]);
+        assert_eq!(NONCE, json["nonce"]);
+        assert_eq!("SHA512", json["payload_hash_method"]);
+        assert_eq!(
+            header,
+            serde_json::from_value::<TransactionHeader>(json).unwrap()
+        );
+
+        let transaction =
+            Transaction::new(BYTES1.to_vec(), SIGNATURE1.to_string(), BYTES2.to_vec());
+        let json = serde_json::to_value(&transaction).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "header": "01020304",
+                "header_signature": SIGNATURE1,
+                "payload": "05060708",
+            }),
+            json
+        );
+        assert_eq!(
+            transaction,
+            serde_json::from_value::<Transaction>(json).unwrap()
+        );
+    }
+
+    #[test]
+    // test that the signed header bytes and the parsed header agree
+    fn transaction_header_views() {
+        let signer = HashSigner::new();
+        let pair = TransactionBuilder::new()
+            .with_family_name(FAMILY_NAME.to_string())
+            .with_family_version(FAMILY_VERSION.to_string())
+            .with_inputs(vec![hex::decode(KEY4).unwrap()])
+            .with_outputs(vec![hex::decode(KEY6).unwrap()])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(BYTES2.to_vec())
+            .build_pair(&signer)
+            .unwrap();
+
+        assert_eq!(pair.transaction().header(), pair.header_bytes());
+        assert_eq!(pair.header(), &pair.transaction().parse_header().unwrap());
+        assert_eq!(
+            signer.sign(pair.header_bytes()).unwrap(),
+            hex::decode(pair.transaction().header_signature()).unwrap()
+        );
+
+        let (transaction, header) = pair.take();
+        assert_eq!(header, *transaction.into_pair().unwrap().header());
+    }
+
     #[test]
     fn transaction_header_fields() {
         let header = TransactionHeader {
@@ -595,6 +1325,9 @@ mod tests {
             payload_hash: hex::decode(HASH).unwrap(),
             payload_hash_method: HashMethod::SHA512,
             signer_public_key: hex::decode(KEY8).unwrap(),
+            signature_algorithm: SignatureAlgorithm::Secp256k1,
+            protocol_version: ProtocolVersion::default(),
+            fee: None,
         };
         assert_eq!(KEY1, hex::encode(header.batcher_public_key()));
         assert_eq!(
@@ -642,6 +1375,9 @@ mod tests {
             payload_hash: hex::decode(HASH).unwrap(),
             payload_hash_method: HashMethod::SHA512,
             signer_public_key: hex::decode(KEY8).unwrap(),
+            signature_algorithm: SignatureAlgorithm::Secp256k1,
+            protocol_version: ProtocolVersion::default(),
+            fee: None,
         };
 
         let header_bytes = original.clone().into_bytes().unwrap();
@@ -668,6 +1404,18 @@ mod tests {
             hex::encode(original.signer_public_key()),
             hex::encode(header.signer_public_key())
         );
+        assert_eq!(original.protocol_version(), header.protocol_version());
+
+        // a header with a newer minor version keeps its version through serialization
+        let newer = TransactionHeader {
+            protocol_version: ProtocolVersion::new(1, 3),
+            ..original
+        };
+        let header = TransactionHeader::from_bytes(&newer.clone().into_bytes().unwrap()).unwrap();
+        assert_eq!(&ProtocolVersion::new(1, 3), header.protocol_version());
+        assert!(
+            check_compatibility(header.protocol_version(), &[CURRENT_PROTOCOL_VERSION]).is_ok()
+        );
     }
 
     #[cfg(feature = "sawtooth-compat")]
@@ -842,6 +1590,9 @@ mod benchmarks {
             payload_hash: hex::decode(HASH).unwrap(),
             payload_hash_method: HashMethod::SHA512,
             signer_public_key: hex::decode(KEY8).unwrap(),
+            signature_algorithm: SignatureAlgorithm::Secp256k1,
+            protocol_version: ProtocolVersion::default(),
+            fee: None,
         };
 
         b.iter(|| header.clone().into_proto());
@@ -0,0 +1,327 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Offline verification of transactions and batches.
+//!
+//! These functions check that a transaction or batch is internally consistent and was signed by
+//! the public keys declared in its headers, without needing any state or network access:
+//!
+//! * the header signature is a valid signature of the header bytes by the header's signer;
+//! * a transaction's payload hashes to the header's payload hash;
+//! * a batch's header lists exactly the ids of its transactions, in order;
+//! * each transaction in a batch names the batch signer as its batcher.
+//!
+//! Signatures are checked with the verifier selected for the algorithm recorded in each header.
+//! Any `SignatureVerifier` may be passed where a single algorithm is expected, or a
+//! `signing::VerifierRegistry` where several are in use.
+
+use std::error::Error as StdError;
+
+use crate::protos::{FromBytes, ProtoConversionError};
+use crate::signing;
+
+use super::batch::{Batch, BatchHeader};
+use super::transaction::{Transaction, TransactionHeader};
+
+/// The reason a transaction or batch failed verification.
+#[derive(Debug, PartialEq)]
+pub enum VerificationError {
+    /// The header bytes of the transaction or batch could not be deserialized.
+    InvalidHeader { id: String, reason: String },
+    /// The header signature is not valid for the header's signer public key.
+    InvalidSignature { id: String },
+    /// The transaction payload does not match the payload hash in its header.
+    PayloadHashMismatch { transaction_id: String },
+    /// The batch header's transaction ids do not match the batch's transactions.
+    TransactionIdMismatch { batch_id: String },
+    /// A transaction in the batch names a different batcher than the batch signer.
+    BatcherMismatch {
+        batch_id: String,
+        transaction_id: String,
+    },
+    /// No verifier is available for the signature algorithm recorded in the header.
+    UnsupportedAlgorithm { id: String, algorithm: String },
+    /// The signature verifier itself returned an error.
+    VerifierError(String),
+}
+
+impl StdError for VerificationError {
+    fn description(&self) -> &str {
+        match *self {
+            VerificationError::InvalidHeader { .. } => "header could not be deserialized",
+            VerificationError::InvalidSignature { .. } => "header signature is not valid",
+            VerificationError::PayloadHashMismatch { .. } => "payload does not match its hash",
+            VerificationError::TransactionIdMismatch { .. } => {
+                "transaction ids do not match batch header"
+            }
+            VerificationError::BatcherMismatch { .. } => "batcher does not match batch signer",
+            VerificationError::UnsupportedAlgorithm { .. } => "signature algorithm not supported",
+            VerificationError::VerifierError(ref msg) => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            VerificationError::InvalidHeader { ref id, ref reason } => {
+                write!(f, "InvalidHeader: {}: {}", id, reason)
+            }
+            VerificationError::InvalidSignature { ref id } => write!(f, "InvalidSignature: {}", id),
+            VerificationError::PayloadHashMismatch { ref transaction_id } => {
+                write!(f, "PayloadHashMismatch: {}", transaction_id)
+            }
+            VerificationError::TransactionIdMismatch { ref batch_id } => {
+                write!(f, "TransactionIdMismatch: {}", batch_id)
+            }
+            VerificationError::BatcherMismatch {
+                ref batch_id,
+                ref transaction_id,
+            } => write!(
+                f,
+                "BatcherMismatch: transaction {} in batch {}",
+                transaction_id, batch_id
+            ),
+            VerificationError::UnsupportedAlgorithm {
+                ref id,
+                ref algorithm,
+            } => write!(f, "UnsupportedAlgorithm: {}: {}", id, algorithm),
+            VerificationError::VerifierError(ref s) => write!(f, "VerifierError: {}", s),
+        }
+    }
+}
+
+/// Verifies a transaction's header signature and payload hash.
+///
+/// Returns the parsed header on success.
+pub fn verify_transaction(
+    transaction: &Transaction,
+    verifier: &signing::VerifierSelector,
+) -> Result<TransactionHeader, VerificationError> {
+    let id = transaction.header_signature();
+    let header = TransactionHeader::from_bytes(transaction.header())
+        .map_err(|err| invalid_header(id, err))?;
+
+    verify_signature(
+        verifier,
+        header.signature_algorithm(),
+        id,
+        transaction.header(),
+        header.signer_public_key(),
+    )?;
+
+    if header.payload_hash_method().hash(transaction.payload()) != header.payload_hash() {
+        return Err(VerificationError::PayloadHashMismatch {
+            transaction_id: id.to_string(),
+        });
+    }
+
+    Ok(header)
+}
+
+/// Verifies a batch's header signature, and each of its transactions.
+///
+/// Returns the parsed header on success.
+pub fn verify_batch(
+    batch: &Batch,
+    verifier: &signing::VerifierSelector,
+) -> Result<BatchHeader, VerificationError> {
+    let id = batch.header_signature();
+    let header = BatchHeader::from_bytes(batch.header()).map_err(|err| invalid_header(id, err))?;
+
+    verify_signature(
+        verifier,
+        header.signature_algorithm(),
+        id,
+        batch.header(),
+        header.signer_public_key(),
+    )?;
+
+    let ids_match = header.transaction_ids().len() == batch.transactions().len()
+        && header
+            .transaction_ids()
+            .iter()
+            .zip(batch.transactions())
+            .all(|(txn_id, txn)| hex::encode(txn_id) == txn.header_signature());
+    if !ids_match {
+        return Err(VerificationError::TransactionIdMismatch {
+            batch_id: id.to_string(),
+        });
+    }
+
+    for transaction in batch.transactions() {
+        let txn_header = verify_transaction(transaction, verifier)?;
+        if txn_header.batcher_public_key() != header.signer_public_key() {
+            return Err(VerificationError::BatcherMismatch {
+                batch_id: id.to_string(),
+                transaction_id: transaction.header_signature().to_string(),
+            });
+        }
+    }
+
+    Ok(header)
+}
+
+fn verify_signature(
+    verifier: &signing::VerifierSelector,
+    algorithm: signing::SignatureAlgorithm,
+    id: &str,
+    header: &[u8],
+    public_key: &[u8],
+) -> Result<(), VerificationError> {
+    let verifier =
+        verifier
+            .select(algorithm)
+            .ok_or_else(|| VerificationError::UnsupportedAlgorithm {
+                id: id.to_string(),
+                algorithm: algorithm.name().to_string(),
+            })?;
+    let signature =
+        hex::decode(id).map_err(|_| VerificationError::InvalidSignature { id: id.to_string() })?;
+
+    let is_valid = verifier
+        .verify(header, &signature, public_key)
+        .map_err(|err| VerificationError::VerifierError(format!("{}", err)))?;
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(VerificationError::InvalidSignature { id: id.to_string() })
+    }
+}
+
+fn invalid_header(id: &str, err: ProtoConversionError) -> VerificationError {
+    VerificationError::InvalidHeader {
+        id: id.to_string(),
+        reason: format!("{}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::signing::hash::{HashSigner, HashVerifier};
+    use crate::signing::VerifierRegistry;
+
+    fn make_transaction(signer: &signing::Signer) -> Transaction {
+        make_transaction_with_hash(signer, HashMethod::SHA512)
+    }
+
+    fn make_transaction_with_hash(signer: &signing::Signer, method: HashMethod) -> Transaction {
+        TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![vec![0x01]])
+            .with_outputs(vec![vec![0x01]])
+            .with_payload_hash_method(method)
+            .with_payload(b"payload".to_vec())
+            .build(signer)
+            .unwrap()
+    }
+
+    #[test]
+    fn verify_valid_batch() {
+        let signer = HashSigner::new();
+        let batch = BatchBuilder::new()
+            .with_transactions(vec![make_transaction(&signer), make_transaction(&signer)])
+            .build(&signer)
+            .unwrap();
+
+        let header = verify_batch(&batch, &HashVerifier::new()).unwrap();
+        assert_eq!(2, header.transaction_ids().len());
+    }
+
+    #[test]
+    // test that a payload which does not match the header hash is rejected
+    fn verify_payload_mismatch() {
+        let signer = HashSigner::new();
+        let transaction = make_transaction(&signer);
+        let tampered = Transaction::new(
+            transaction.header().to_vec(),
+            transaction.header_signature().to_string(),
+            b"other payload".to_vec(),
+        );
+
+        assert_eq!(
+            Err(VerificationError::PayloadHashMismatch {
+                transaction_id: transaction.header_signature().to_string()
+            }),
+            verify_transaction(&tampered, &HashVerifier::new()).map(|_| ())
+        );
+    }
+
+    #[test]
+    // test that the payload is checked with the hash method recorded in the header
+    fn verify_sha256_payload() {
+        let signer = HashSigner::new();
+        let transaction = make_transaction_with_hash(&signer, HashMethod::SHA256);
+        assert!(verify_transaction(&transaction, &HashVerifier::new()).is_ok());
+
+        let tampered = Transaction::new(
+            transaction.header().to_vec(),
+            transaction.header_signature().to_string(),
+            b"other payload".to_vec(),
+        );
+        assert_eq!(
+            Err(VerificationError::PayloadHashMismatch {
+                transaction_id: transaction.header_signature().to_string()
+            }),
+            verify_transaction(&tampered, &HashVerifier::new()).map(|_| ())
+        );
+    }
+
+    #[test]
+    // test that a signature which does not match the header is rejected
+    fn verify_signature_mismatch() {
+        let signer = HashSigner::new();
+        let transaction = make_transaction(&signer);
+        let other = make_transaction(&signer);
+        let tampered = Transaction::new(
+            transaction.header().to_vec(),
+            other.header_signature().to_string(),
+            transaction.payload().to_vec(),
+        );
+
+        assert_eq!(
+            Err(VerificationError::InvalidSignature {
+                id: other.header_signature().to_string()
+            }),
+            verify_transaction(&tampered, &HashVerifier::new()).map(|_| ())
+        );
+    }
+
+    #[test]
+    // test that the verifier is selected by the algorithm recorded in the header
+    fn verify_selects_verifier_by_algorithm() {
+        let signer = HashSigner::new();
+        let transaction = make_transaction(&signer);
+
+        let registry = VerifierRegistry::new().with_verifier(Box::new(HashVerifier::new()));
+        assert!(verify_transaction(&transaction, &registry).is_ok());
+
+        assert_eq!(
+            Err(VerificationError::UnsupportedAlgorithm {
+                id: transaction.header_signature().to_string(),
+                algorithm: "secp256k1".to_string(),
+            }),
+            verify_transaction(&transaction, &VerifierRegistry::new()).map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,178 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Protocol versions of transaction and batch headers.
+//!
+//! Each header records the version of the protocol it was encoded with, as `major.minor`.  A
+//! change in the minor version only adds fields that older readers may safely ignore, so a
+//! header is supported by any reader of the same major version; a change in the major version is
+//! incompatible.  Headers without a version are version 1.0.
+
+use std::error::Error as StdError;
+
+/// The protocol version written by this library.
+pub const CURRENT_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolVersionError {
+    /// The version string is not of the form `major.minor`.
+    InvalidVersion(String),
+    /// The version is not supported by this reader.
+    UnsupportedVersion(String),
+}
+
+impl StdError for ProtocolVersionError {
+    fn description(&self) -> &str {
+        match *self {
+            ProtocolVersionError::InvalidVersion(ref msg) => msg,
+            ProtocolVersionError::UnsupportedVersion(ref msg) => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for ProtocolVersionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ProtocolVersionError::InvalidVersion(ref s) => write!(f, "InvalidVersion: {}", s),
+            ProtocolVersionError::UnsupportedVersion(ref s) => {
+                write!(f, "UnsupportedVersion: {}", s)
+            }
+        }
+    }
+}
+
+/// A protocol version, ordered by major and then minor version.
+#[derive(Debug, Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ProtocolVersion {
+    major: u32,
+    minor: u32,
+}
+
+impl ProtocolVersion {
+    pub fn new(major: u32, minor: u32) -> Self {
+        ProtocolVersion { major, minor }
+    }
+
+    /// Parses a version of the form `major.minor`.
+    ///
+    /// Any minor version is accepted, including ones newer than this library knows of.
+    pub fn parse(version: &str) -> Result<ProtocolVersion, ProtocolVersionError> {
+        let invalid = || ProtocolVersionError::InvalidVersion(version.to_string());
+
+        let mut parts = version.splitn(2, '.');
+        let major = parts.next().ok_or_else(invalid)?;
+        let minor = parts.next().ok_or_else(invalid)?;
+        Ok(ProtocolVersion {
+            major: major.parse().map_err(|_| invalid())?,
+            minor: minor.parse().map_err(|_| invalid())?,
+        })
+    }
+
+    pub fn major(&self) -> u32 {
+        self.major
+    }
+
+    pub fn minor(&self) -> u32 {
+        self.minor
+    }
+
+    /// Returns true if a reader of the `supported` version can read this version.
+    pub fn is_compatible_with(&self, supported: &ProtocolVersion) -> bool {
+        self.major == supported.major
+    }
+}
+
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        ProtocolVersion::new(1, 0)
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Checks that a reader of one of the `supported` versions can read `version`.
+pub fn check_compatibility(
+    version: &ProtocolVersion,
+    supported: &[ProtocolVersion],
+) -> Result<(), ProtocolVersionError> {
+    if supported.iter().any(|s| version.is_compatible_with(s)) {
+        Ok(())
+    } else {
+        Err(ProtocolVersionError::UnsupportedVersion(format!(
+            "{} is not supported",
+            version
+        )))
+    }
+}
+
+/// Selects the version two peers should use from the versions each supports.
+///
+/// The highest major version that both support is chosen, at the lower of the two peers' minor
+/// versions for it.  Returns `None` if the peers have no major version in common.
+pub fn negotiate(local: &[ProtocolVersion], remote: &[ProtocolVersion]) -> Option<ProtocolVersion> {
+    local
+        .iter()
+        .filter_map(|l| {
+            remote
+                .iter()
+                .filter(|r| r.major == l.major)
+                .max()
+                .map(|r| std::cmp::min(*l, *r))
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protocol_version_parse() {
+        assert_eq!(
+            ProtocolVersion::new(1, 7),
+            ProtocolVersion::parse("1.7").unwrap()
+        );
+        assert_eq!("1.7", ProtocolVersion::parse("1.7").unwrap().to_string());
+        assert!(ProtocolVersion::parse("1").is_err());
+        assert!(ProtocolVersion::parse("1.x").is_err());
+        assert!(ProtocolVersion::parse("").is_err());
+    }
+
+    #[test]
+    // test that newer minor versions are tolerated and other major versions are rejected
+    fn protocol_version_compatibility() {
+        let supported = [CURRENT_PROTOCOL_VERSION];
+        assert!(check_compatibility(&ProtocolVersion::new(1, 9), &supported).is_ok());
+        assert!(check_compatibility(&ProtocolVersion::new(2, 0), &supported).is_err());
+    }
+
+    #[test]
+    fn protocol_version_negotiate() {
+        let local = [ProtocolVersion::new(1, 3), ProtocolVersion::new(2, 1)];
+        let remote = [ProtocolVersion::new(1, 1), ProtocolVersion::new(2, 4)];
+        assert_eq!(Some(ProtocolVersion::new(2, 1)), negotiate(&local, &remote));
+
+        let remote = [ProtocolVersion::new(1, 5), ProtocolVersion::new(3, 0)];
+        assert_eq!(Some(ProtocolVersion::new(1, 3)), negotiate(&local, &remote));
+
+        assert_eq!(None, negotiate(&local, &[ProtocolVersion::new(3, 0)]));
+    }
+}
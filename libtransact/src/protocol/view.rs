@@ -0,0 +1,566 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Borrowed views of serialized transactions and batches.
+//!
+//! Deserializing a transaction or batch copies every field into owned strings and vectors, which
+//! is wasted work for components that only inspect a few header fields, such as schedulers
+//! reading inputs and outputs or gossip layers reading signatures.  The views in this module
+//! borrow from the wire bytes instead.  Parsing a view checks the framing of its message and
+//! that its string fields are UTF-8; a `TransactionRef` does not parse its header until
+//! `header` is called.
+//!
+//! Keys, addresses and transaction ids are returned as they appear on the wire, as hex strings.
+//! The views follow protobuf semantics: unknown fields are skipped, and the last occurrence of a
+//! singular field wins.
+
+use crate::protos;
+use crate::protos::{FromProto, ProtoConversionError};
+
+use super::batch::Batch;
+use super::transaction::{HashMethod, Transaction};
+
+/// A view of a serialized `Transaction`.
+#[derive(Debug, Clone, Copy)]
+pub struct TransactionRef<'a> {
+    bytes: &'a [u8],
+    header: &'a [u8],
+    header_signature: &'a str,
+    payload: &'a [u8],
+}
+
+impl<'a> TransactionRef<'a> {
+    /// Parses a view of the serialized transaction.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ProtoConversionError> {
+        let mut view = TransactionRef {
+            bytes,
+            header: &[],
+            header_signature: "",
+            payload: &[],
+        };
+        for field in Fields::new(bytes) {
+            match field? {
+                (1, value) => view.header = value.bytes(1)?,
+                (2, value) => view.header_signature = value.string(2)?,
+                (3, value) => view.payload = value.bytes(3)?,
+                _ => (),
+            }
+        }
+        Ok(view)
+    }
+
+    /// The serialized transaction this view borrows from.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn header_bytes(&self) -> &'a [u8] {
+        self.header
+    }
+
+    /// The transaction's id.
+    pub fn header_signature(&self) -> &'a str {
+        self.header_signature
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// Parses a view of the transaction's header.
+    pub fn header(&self) -> Result<TransactionHeaderRef<'a>, ProtoConversionError> {
+        TransactionHeaderRef::parse(self.header)
+    }
+
+    /// Copies the viewed transaction into an owned `Transaction`.
+    pub fn to_transaction(&self) -> Transaction {
+        Transaction::new(
+            self.header.to_vec(),
+            self.header_signature.to_string(),
+            self.payload.to_vec(),
+        )
+    }
+}
+
+/// A view of a serialized `TransactionHeader`.
+#[derive(Debug, Clone, Copy)]
+pub struct TransactionHeaderRef<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> TransactionHeaderRef<'a> {
+    /// Parses a view of the serialized header.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ProtoConversionError> {
+        for field in Fields::new(bytes) {
+            match field? {
+                (number @ 1..=13, value) => {
+                    value.string(number)?;
+                }
+                (14, value) => {
+                    value.bytes(14)?;
+                }
+                _ => (),
+            }
+        }
+        Ok(TransactionHeaderRef { bytes })
+    }
+
+    pub fn batcher_public_key(&self) -> &'a str {
+        last_string(self.bytes, 1)
+    }
+
+    pub fn dependencies(&self) -> impl Iterator<Item = &'a str> {
+        strings(self.bytes, 2)
+    }
+
+    pub fn family_name(&self) -> &'a str {
+        last_string(self.bytes, 3)
+    }
+
+    pub fn family_version(&self) -> &'a str {
+        last_string(self.bytes, 4)
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = &'a str> {
+        strings(self.bytes, 5)
+    }
+
+    pub fn nonce(&self) -> &'a str {
+        last_string(self.bytes, 6)
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = &'a str> {
+        strings(self.bytes, 7)
+    }
+
+    /// The payload hash and the method that produced it.
+    pub fn payload_hash(&self) -> (HashMethod, &'a str) {
+        match last_string(self.bytes, 13) {
+            "" => (HashMethod::SHA512, last_string(self.bytes, 9)),
+            hash => (HashMethod::SHA256, hash),
+        }
+    }
+
+    pub fn signer_public_key(&self) -> &'a str {
+        last_string(self.bytes, 10)
+    }
+
+    /// The name of the signature algorithm; empty for secp256k1.
+    pub fn signature_algorithm(&self) -> &'a str {
+        last_string(self.bytes, 11)
+    }
+
+    /// The protocol version, as `major.minor`; empty for 1.0.
+    pub fn protocol_version(&self) -> &'a str {
+        last_string(self.bytes, 12)
+    }
+}
+
+/// A view of a serialized `Batch`.
+///
+/// Co-signatures are not exposed by the view; convert the batch to an owned `Batch` to read
+/// them.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchRef<'a> {
+    bytes: &'a [u8],
+    header: &'a [u8],
+    header_signature: &'a str,
+    transaction_count: usize,
+    trace: bool,
+}
+
+impl<'a> BatchRef<'a> {
+    /// Parses a view of the serialized batch, including the views of its transactions.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ProtoConversionError> {
+        let mut view = BatchRef {
+            bytes,
+            header: &[],
+            header_signature: "",
+            transaction_count: 0,
+            trace: false,
+        };
+        for field in Fields::new(bytes) {
+            match field? {
+                (1, value) => view.header = value.bytes(1)?,
+                (2, value) => view.header_signature = value.string(2)?,
+                (3, value) => {
+                    TransactionRef::parse(value.bytes(3)?)?;
+                    view.transaction_count += 1;
+                }
+                (4, Value::Varint(trace)) => view.trace = trace != 0,
+                (4, _) => return Err(wrong_type(4)),
+                (5, value) => {
+                    value.bytes(5)?;
+                }
+                _ => (),
+            }
+        }
+        Ok(view)
+    }
+
+    /// The serialized batch this view borrows from.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn header_bytes(&self) -> &'a [u8] {
+        self.header
+    }
+
+    /// The batch's id.
+    pub fn header_signature(&self) -> &'a str {
+        self.header_signature
+    }
+
+    pub fn trace(&self) -> bool {
+        self.trace
+    }
+
+    /// The views of the batch's transactions, in order.
+    pub fn transactions(&self) -> impl Iterator<Item = TransactionRef<'a>> {
+        Fields::new(self.bytes)
+            .filter_map(Result::ok)
+            .filter_map(|field| match field {
+                (3, Value::Bytes(bytes)) => TransactionRef::parse(bytes).ok(),
+                _ => None,
+            })
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.transaction_count
+    }
+
+    /// Parses a view of the batch's header.
+    pub fn header(&self) -> Result<BatchHeaderRef<'a>, ProtoConversionError> {
+        BatchHeaderRef::parse(self.header)
+    }
+
+    /// Deserializes the viewed batch into an owned `Batch`.
+    pub fn to_batch(&self) -> Result<Batch, ProtoConversionError> {
+        let proto: protos::batch::Batch = protobuf::parse_from_bytes(self.bytes).map_err(|_| {
+            ProtoConversionError::SerializationError("Unable to get Batch from bytes".to_string())
+        })?;
+        Batch::from_proto(proto)
+    }
+}
+
+/// A view of a serialized `BatchHeader`.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchHeaderRef<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> BatchHeaderRef<'a> {
+    /// Parses a view of the serialized header.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ProtoConversionError> {
+        for field in Fields::new(bytes) {
+            match field? {
+                (number @ 1..=4, value) => {
+                    value.string(number)?;
+                }
+                (5, value) => {
+                    for entry_field in Fields::new(value.bytes(5)?) {
+                        if let (number @ 1..=2, value) = entry_field? {
+                            value.string(number)?;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(BatchHeaderRef { bytes })
+    }
+
+    pub fn signer_public_key(&self) -> &'a str {
+        last_string(self.bytes, 1)
+    }
+
+    pub fn transaction_ids(&self) -> impl Iterator<Item = &'a str> {
+        strings(self.bytes, 2)
+    }
+
+    /// The name of the signature algorithm; empty for secp256k1.
+    pub fn signature_algorithm(&self) -> &'a str {
+        last_string(self.bytes, 3)
+    }
+
+    /// The protocol version, as `major.minor`; empty for 1.0.
+    pub fn protocol_version(&self) -> &'a str {
+        last_string(self.bytes, 4)
+    }
+
+    /// The metadata tags, as key and value pairs in wire order.
+    pub fn metadata(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        fields_numbered(self.bytes, 5).map(|entry| (last_string(entry, 1), last_string(entry, 2)))
+    }
+}
+
+/// A field value, as framed on the wire.
+#[derive(Debug, Clone, Copy)]
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl<'a> Value<'a> {
+    fn bytes(self, number: u32) -> Result<&'a [u8], ProtoConversionError> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err(wrong_type(number)),
+        }
+    }
+
+    fn string(self, number: u32) -> Result<&'a str, ProtoConversionError> {
+        std::str::from_utf8(self.bytes(number)?).map_err(|_| {
+            ProtoConversionError::SerializationError(format!("field {} is not UTF-8", number))
+        })
+    }
+}
+
+/// Iterates over the fields of a serialized message, stopping after the first framing error.
+struct Fields<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Fields { bytes, pos: 0 }
+    }
+
+    fn read_field(&mut self) -> Result<(u32, Value<'a>), ProtoConversionError> {
+        let key = self.read_varint()?;
+        let number = key >> 3;
+        if number == 0 || number > u64::from(u32::max_value() >> 3) {
+            return Err(malformed(format!("invalid field number {}", number)));
+        }
+        let value = match key & 0x07 {
+            0 => Value::Varint(self.read_varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.read_varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            wire_type => return Err(malformed(format!("unsupported wire type {}", wire_type))),
+        };
+        Ok((number as u32, value))
+    }
+
+    fn read_varint(&mut self) -> Result<u64, ProtoConversionError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(malformed("varint is too long".to_string()))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ProtoConversionError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| malformed("message is truncated".to_string()))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u32, Value<'a>), ProtoConversionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.bytes.len() {
+            return None;
+        }
+        let field = self.read_field();
+        if field.is_err() {
+            self.pos = self.bytes.len();
+        }
+        Some(field)
+    }
+}
+
+// The accessors below are only used on messages that have been checked by a view's `parse`, so
+// framing and UTF-8 errors cannot occur.
+
+fn fields_numbered<'a>(bytes: &'a [u8], number: u32) -> impl Iterator<Item = &'a [u8]> {
+    Fields::new(bytes)
+        .filter_map(Result::ok)
+        .filter_map(move |field| match field {
+            (n, Value::Bytes(bytes)) if n == number => Some(bytes),
+            _ => None,
+        })
+}
+
+fn strings<'a>(bytes: &'a [u8], number: u32) -> impl Iterator<Item = &'a str> {
+    fields_numbered(bytes, number).map(|bytes| std::str::from_utf8(bytes).unwrap_or_default())
+}
+
+fn last_string(bytes: &[u8], number: u32) -> &str {
+    strings(bytes, number).last().unwrap_or_default()
+}
+
+fn wrong_type(number: u32) -> ProtoConversionError {
+    malformed(format!("field {} has the wrong wire type", number))
+}
+
+fn malformed(msg: String) -> ProtoConversionError {
+    ProtoConversionError::SerializationError(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use protobuf::Message;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::TransactionBuilder;
+    use crate::protos::FromNative;
+    use crate::signing::hash::HashSigner;
+
+    fn make_transaction(payload: &[u8]) -> Transaction {
+        TransactionBuilder::new()
+            .with_family_name("intkey".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![vec![0x1c, 0xf1], vec![0x1c, 0xf2]])
+            .with_outputs(vec![vec![0x1c, 0xf1]])
+            .with_nonce(b"nonce".to_vec())
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(payload.to_vec())
+            .build(&HashSigner::new())
+            .unwrap()
+    }
+
+    #[test]
+    // test that a transaction view exposes the same fields as the deserialized transaction
+    fn transaction_ref_fields() {
+        let transaction = make_transaction(b"payload");
+        let bytes = protos::transaction::Transaction::from_native(transaction.clone())
+            .unwrap()
+            .write_to_bytes()
+            .unwrap();
+
+        let view = TransactionRef::parse(&bytes).unwrap();
+        assert_eq!(transaction.header(), view.header_bytes());
+        assert_eq!(transaction.header_signature(), view.header_signature());
+        assert_eq!(transaction.payload(), view.payload());
+        assert_eq!(transaction, view.to_transaction());
+
+        let header = transaction.parse_header().unwrap();
+        let header_view = view.header().unwrap();
+        assert_eq!("intkey", header_view.family_name());
+        assert_eq!("1.0", header_view.family_version());
+        assert_eq!(
+            vec!["1cf1", "1cf2"],
+            header_view.inputs().collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["1cf1"], header_view.outputs().collect::<Vec<_>>());
+        assert_eq!(0, header_view.dependencies().count());
+        assert_eq!("nonce", header_view.nonce());
+        let (method, hash) = header_view.payload_hash();
+        assert_eq!(HashMethod::SHA512, method);
+        assert_eq!(hex::encode(header.payload_hash()), hash);
+        assert_eq!(
+            hex::encode(header.signer_public_key()),
+            header_view.signer_public_key()
+        );
+        assert_eq!(
+            header_view.signer_public_key(),
+            header_view.batcher_public_key()
+        );
+    }
+
+    #[test]
+    // test that a batch view exposes its header and the views of its transactions
+    fn batch_ref_fields() {
+        let batch = BatchBuilder::new()
+            .with_transactions(vec![make_transaction(b"one"), make_transaction(b"two")])
+            .with_trace(true)
+            .with_metadata_entry("tenant".into(), "acme".into())
+            .build(&HashSigner::new())
+            .unwrap();
+        let bytes = protos::batch::Batch::from_native(batch.clone())
+            .unwrap()
+            .write_to_bytes()
+            .unwrap();
+
+        let view = BatchRef::parse(&bytes).unwrap();
+        assert_eq!(batch.header_signature(), view.header_signature());
+        assert_eq!(batch.header(), view.header_bytes());
+        assert!(view.trace());
+        assert_eq!(2, view.transaction_count());
+        assert_eq!(
+            batch
+                .transactions()
+                .iter()
+                .map(Transaction::header_signature)
+                .collect::<Vec<_>>(),
+            view.transactions()
+                .map(|transaction| transaction.header_signature())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(batch, view.to_batch().unwrap());
+
+        let header_view = view.header().unwrap();
+        assert_eq!(
+            batch
+                .transactions()
+                .iter()
+                .map(Transaction::header_signature)
+                .collect::<Vec<_>>(),
+            header_view.transaction_ids().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![("tenant", "acme")],
+            header_view.metadata().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    // test that malformed messages are rejected when viewed
+    fn views_reject_malformed_bytes() {
+        let transaction = make_transaction(b"payload");
+        let bytes = protos::transaction::Transaction::from_native(transaction.clone())
+            .unwrap()
+            .write_to_bytes()
+            .unwrap();
+
+        assert!(TransactionRef::parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(BatchRef::parse(&[0x0a, 0x05, 0x01]).is_err());
+        // field 2 (the header signature) is not UTF-8
+        assert!(TransactionRef::parse(&[0x12, 0x01, 0xff]).is_err());
+        // field 4 (trace) is not a varint
+        assert!(BatchRef::parse(&[0x22, 0x00]).is_err());
+
+        let view = TransactionRef::parse(&[0x0a, 0x02, 0x1a, 0x05]).unwrap();
+        assert!(view.header().is_err());
+    }
+}
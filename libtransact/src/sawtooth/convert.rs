@@ -0,0 +1,503 @@
+// Copyright 2019 Cargill Incorporated
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversions between Transact's protocol types and the Sawtooth SDK's messages.
+//!
+//! Transact's transactions, batches and receipts share their wire format with Sawtooth's, so an
+//! object converted here keeps its signed header bytes unchanged and remains valid.  The
+//! conversions are implemented with the `FromProto` and `FromNative` traits, treating the Sawtooth
+//! messages as an alternative protobuf representation:
+//!
+//!     # use transact::protocol::transaction::Transaction;
+//!     # use transact::protos::{FromNative, FromProto};
+//!     # use sawtooth_sdk::messages::transaction::Transaction as SawtoothTransaction;
+//!     # let transaction = Transaction::new(vec![], "abcd".into(), vec![]);
+//!     let sawtooth_transaction = SawtoothTransaction::from_native(transaction.clone()).unwrap();
+//!     assert_eq!(transaction, Transaction::from_proto(sawtooth_transaction).unwrap());
+//!
+//! Some Transact extensions cannot be represented in Sawtooth: headers using a SHA-256 payload
+//! hash, a signature algorithm other than secp256k1, a protocol version other than 1.0 or a fee,
+//! batch headers with metadata, batches with co-signatures and receipts with batch metadata.
+//! Converting these to Sawtooth messages fails with an `InvalidTypeError`.  A receipt's trace
+//! flag is dropped, as Sawtooth carries it on the batch instead.
+
+use std::collections::BTreeMap;
+
+use protobuf::RepeatedField;
+use sawtooth_sdk::messages::batch::{
+    Batch as SawtoothBatch, BatchHeader as SawtoothBatchHeader, BatchList as SawtoothBatchList,
+};
+use sawtooth_sdk::messages::events::{Event as SawtoothEvent, Event_Attribute};
+use sawtooth_sdk::messages::transaction::{
+    Transaction as SawtoothTransaction, TransactionHeader as SawtoothTransactionHeader,
+};
+use sawtooth_sdk::messages::transaction_receipt::{
+    StateChange as SawtoothStateChange, StateChange_Type,
+    TransactionReceipt as SawtoothTransactionReceipt,
+};
+
+use crate::protocol::batch::{Batch, BatchHeader};
+use crate::protocol::receipt::{Event, StateChange, TransactionReceipt};
+use crate::protocol::transaction::{HashMethod, Transaction, TransactionHeader};
+use crate::protocol::version::ProtocolVersion;
+use crate::protos;
+use crate::protos::{FromNative, FromProto, IntoNative, ProtoConversionError};
+use crate::signing::SignatureAlgorithm;
+
+impl FromProto<SawtoothTransaction> for Transaction {
+    fn from_proto(transaction: SawtoothTransaction) -> Result<Self, ProtoConversionError> {
+        Ok(Transaction::new(
+            transaction.get_header().to_vec(),
+            transaction.get_header_signature().to_string(),
+            transaction.get_payload().to_vec(),
+        ))
+    }
+}
+
+impl FromNative<Transaction> for SawtoothTransaction {
+    fn from_native(transaction: Transaction) -> Result<Self, ProtoConversionError> {
+        let mut sawtooth_transaction = SawtoothTransaction::new();
+        sawtooth_transaction.set_header(transaction.header().to_vec());
+        sawtooth_transaction.set_header_signature(transaction.header_signature().to_string());
+        sawtooth_transaction.set_payload(transaction.payload().to_vec());
+        Ok(sawtooth_transaction)
+    }
+}
+
+impl IntoNative<Transaction> for SawtoothTransaction {}
+
+impl FromProto<SawtoothTransactionHeader> for TransactionHeader {
+    fn from_proto(header: SawtoothTransactionHeader) -> Result<Self, ProtoConversionError> {
+        let mut proto_header = protos::transaction::TransactionHeader::new();
+        proto_header.set_batcher_public_key(header.get_batcher_public_key().to_string());
+        proto_header.set_dependencies(header.get_dependencies().to_vec().into());
+        proto_header.set_family_name(header.get_family_name().to_string());
+        proto_header.set_family_version(header.get_family_version().to_string());
+        proto_header.set_inputs(header.get_inputs().to_vec().into());
+        proto_header.set_nonce(header.get_nonce().to_string());
+        proto_header.set_outputs(header.get_outputs().to_vec().into());
+        proto_header.set_payload_sha512(header.get_payload_sha512().to_string());
+        proto_header.set_signer_public_key(header.get_signer_public_key().to_string());
+        TransactionHeader::from_proto(proto_header)
+    }
+}
+
+impl FromNative<TransactionHeader> for SawtoothTransactionHeader {
+    fn from_native(header: TransactionHeader) -> Result<Self, ProtoConversionError> {
+        if *header.payload_hash_method() != HashMethod::SHA512 {
+            return Err(ProtoConversionError::InvalidTypeError(
+                "Sawtooth transaction headers only support SHA-512 payload hashes".to_string(),
+            ));
+        }
+        if header.fee().is_some() {
+            return Err(ProtoConversionError::InvalidTypeError(
+                "Sawtooth transaction headers cannot carry a fee".to_string(),
+            ));
+        }
+        check_sawtooth_signature(header.signature_algorithm(), header.protocol_version())?;
+
+        let proto_header = protos::transaction::TransactionHeader::from_native(header)?;
+        let mut sawtooth_header = SawtoothTransactionHeader::new();
+        sawtooth_header.set_batcher_public_key(proto_header.get_batcher_public_key().to_string());
+        sawtooth_header.set_dependencies(proto_header.get_dependencies().to_vec().into());
+        sawtooth_header.set_family_name(proto_header.get_family_name().to_string());
+        sawtooth_header.set_family_version(proto_header.get_family_version().to_string());
+        sawtooth_header.set_inputs(proto_header.get_inputs().to_vec().into());
+        sawtooth_header.set_nonce(proto_header.get_nonce().to_string());
+        sawtooth_header.set_outputs(proto_header.get_outputs().to_vec().into());
+        sawtooth_header.set_payload_sha512(proto_header.get_payload_sha512().to_string());
+        sawtooth_header.set_signer_public_key(proto_header.get_signer_public_key().to_string());
+        Ok(sawtooth_header)
+    }
+}
+
+impl IntoNative<TransactionHeader> for SawtoothTransactionHeader {}
+
+impl FromProto<SawtoothBatch> for Batch {
+    fn from_proto(batch: SawtoothBatch) -> Result<Self, ProtoConversionError> {
+        let mut proto_batch = protos::batch::Batch::new();
+        proto_batch.set_header(batch.get_header().to_vec());
+        proto_batch.set_header_signature(batch.get_header_signature().to_string());
+        proto_batch.set_transactions(
+            batch
+                .get_transactions()
+                .iter()
+                .map(|transaction| {
+                    let mut proto_transaction = protos::transaction::Transaction::new();
+                    proto_transaction.set_header(transaction.get_header().to_vec());
+                    proto_transaction
+                        .set_header_signature(transaction.get_header_signature().to_string());
+                    proto_transaction.set_payload(transaction.get_payload().to_vec());
+                    proto_transaction
+                })
+                .collect(),
+        );
+        proto_batch.set_trace(batch.get_trace());
+        Batch::from_proto(proto_batch)
+    }
+}
+
+impl FromNative<Batch> for SawtoothBatch {
+    fn from_native(batch: Batch) -> Result<Self, ProtoConversionError> {
+        if !batch.cosignatures().is_empty() {
+            return Err(ProtoConversionError::InvalidTypeError(
+                "Sawtooth batches cannot carry co-signatures".to_string(),
+            ));
+        }
+
+        let mut sawtooth_batch = SawtoothBatch::new();
+        sawtooth_batch.set_header(batch.header().to_vec());
+        sawtooth_batch.set_header_signature(batch.header_signature().to_string());
+        sawtooth_batch.set_transactions(
+            batch
+                .transactions()
+                .iter()
+                .cloned()
+                .map(SawtoothTransaction::from_native)
+                .collect::<Result<RepeatedField<_>, _>>()?,
+        );
+        sawtooth_batch.set_trace(batch.trace());
+        Ok(sawtooth_batch)
+    }
+}
+
+impl IntoNative<Batch> for SawtoothBatch {}
+
+impl FromProto<SawtoothBatchHeader> for BatchHeader {
+    fn from_proto(header: SawtoothBatchHeader) -> Result<Self, ProtoConversionError> {
+        let mut proto_header = protos::batch::BatchHeader::new();
+        proto_header.set_signer_public_key(header.get_signer_public_key().to_string());
+        proto_header.set_transaction_ids(header.get_transaction_ids().to_vec().into());
+        BatchHeader::from_proto(proto_header)
+    }
+}
+
+impl FromNative<BatchHeader> for SawtoothBatchHeader {
+    fn from_native(header: BatchHeader) -> Result<Self, ProtoConversionError> {
+        check_sawtooth_signature(header.signature_algorithm(), header.protocol_version())?;
+        if !header.metadata().is_empty() {
+            return Err(ProtoConversionError::InvalidTypeError(
+                "Sawtooth batch headers cannot carry metadata".to_string(),
+            ));
+        }
+
+        let proto_header = protos::batch::BatchHeader::from_native(header)?;
+        let mut sawtooth_header = SawtoothBatchHeader::new();
+        sawtooth_header.set_signer_public_key(proto_header.get_signer_public_key().to_string());
+        sawtooth_header.set_transaction_ids(proto_header.get_transaction_ids().to_vec().into());
+        Ok(sawtooth_header)
+    }
+}
+
+impl IntoNative<BatchHeader> for SawtoothBatchHeader {}
+
+impl FromProto<SawtoothStateChange> for StateChange {
+    fn from_proto(state_change: SawtoothStateChange) -> Result<Self, ProtoConversionError> {
+        match state_change.get_field_type() {
+            StateChange_Type::SET => Ok(StateChange::Set {
+                key: state_change.get_address().to_string(),
+                value: state_change.get_value().to_vec(),
+            }),
+            StateChange_Type::DELETE => Ok(StateChange::Delete {
+                key: state_change.get_address().to_string(),
+            }),
+            StateChange_Type::TYPE_UNSET => Err(ProtoConversionError::InvalidTypeError(
+                "Cannot convert Sawtooth StateChange with type unset".to_string(),
+            )),
+        }
+    }
+}
+
+impl FromNative<StateChange> for SawtoothStateChange {
+    fn from_native(state_change: StateChange) -> Result<Self, ProtoConversionError> {
+        let mut sawtooth_state_change = SawtoothStateChange::new();
+        match state_change {
+            StateChange::Set { key, value } => {
+                sawtooth_state_change.set_address(key);
+                sawtooth_state_change.set_value(value);
+                sawtooth_state_change.set_field_type(StateChange_Type::SET);
+            }
+            StateChange::Delete { key } => {
+                sawtooth_state_change.set_address(key);
+                sawtooth_state_change.set_field_type(StateChange_Type::DELETE);
+            }
+        }
+        Ok(sawtooth_state_change)
+    }
+}
+
+impl IntoNative<StateChange> for SawtoothStateChange {}
+
+impl FromProto<SawtoothEvent> for Event {
+    fn from_proto(event: SawtoothEvent) -> Result<Self, ProtoConversionError> {
+        Ok(Event {
+            event_type: event.get_event_type().to_string(),
+            attributes: event
+                .get_attributes()
+                .iter()
+                .map(|attr| (attr.get_key().to_string(), attr.get_value().to_string()))
+                .collect(),
+            data: event.get_data().to_vec(),
+        })
+    }
+}
+
+impl FromNative<Event> for SawtoothEvent {
+    fn from_native(event: Event) -> Result<Self, ProtoConversionError> {
+        let mut sawtooth_event = SawtoothEvent::new();
+        sawtooth_event.set_event_type(event.event_type);
+        sawtooth_event.set_attributes(
+            event
+                .attributes
+                .into_iter()
+                .map(|(key, value)| {
+                    let mut attr = Event_Attribute::new();
+                    attr.set_key(key);
+                    attr.set_value(value);
+                    attr
+                })
+                .collect(),
+        );
+        sawtooth_event.set_data(event.data);
+        Ok(sawtooth_event)
+    }
+}
+
+impl IntoNative<Event> for SawtoothEvent {}
+
+impl FromProto<SawtoothTransactionReceipt> for TransactionReceipt {
+    fn from_proto(receipt: SawtoothTransactionReceipt) -> Result<Self, ProtoConversionError> {
+        Ok(TransactionReceipt {
+            state_changes: receipt
+                .get_state_changes()
+                .iter()
+                .cloned()
+                .map(StateChange::from_proto)
+                .collect::<Result<_, _>>()?,
+            events: receipt
+                .get_events()
+                .iter()
+                .cloned()
+                .map(Event::from_proto)
+                .collect::<Result<_, _>>()?,
+            data: receipt.get_data().to_vec(),
+            transaction_id: receipt.get_transaction_id().to_string(),
+            batch_metadata: BTreeMap::new(),
+            trace: false,
+        })
+    }
+}
+
+impl FromNative<TransactionReceipt> for SawtoothTransactionReceipt {
+    fn from_native(receipt: TransactionReceipt) -> Result<Self, ProtoConversionError> {
+        if !receipt.batch_metadata.is_empty() {
+            return Err(ProtoConversionError::InvalidTypeError(
+                "Sawtooth receipts cannot carry batch metadata".to_string(),
+            ));
+        }
+
+        let mut sawtooth_receipt = SawtoothTransactionReceipt::new();
+        sawtooth_receipt.set_state_changes(
+            receipt
+                .state_changes
+                .into_iter()
+                .map(SawtoothStateChange::from_native)
+                .collect::<Result<RepeatedField<_>, _>>()?,
+        );
+        sawtooth_receipt.set_events(
+            receipt
+                .events
+                .into_iter()
+                .map(SawtoothEvent::from_native)
+                .collect::<Result<RepeatedField<_>, _>>()?,
+        );
+        sawtooth_receipt.set_data(RepeatedField::from_vec(receipt.data));
+        sawtooth_receipt.set_transaction_id(receipt.transaction_id);
+        Ok(sawtooth_receipt)
+    }
+}
+
+impl IntoNative<TransactionReceipt> for SawtoothTransactionReceipt {}
+
+/// Converts a list of batches to a Sawtooth `BatchList`, as submitted to a Sawtooth validator.
+pub fn batches_to_sawtooth_list(
+    batches: Vec<Batch>,
+) -> Result<SawtoothBatchList, ProtoConversionError> {
+    let mut batch_list = SawtoothBatchList::new();
+    batch_list.set_batches(
+        batches
+            .into_iter()
+            .map(SawtoothBatch::from_native)
+            .collect::<Result<RepeatedField<_>, _>>()?,
+    );
+    Ok(batch_list)
+}
+
+/// Converts a Sawtooth `BatchList` to a list of batches.
+pub fn batches_from_sawtooth_list(
+    mut batch_list: SawtoothBatchList,
+) -> Result<Vec<Batch>, ProtoConversionError> {
+    batch_list
+        .take_batches()
+        .into_iter()
+        .map(Batch::from_proto)
+        .collect()
+}
+
+fn check_sawtooth_signature(
+    algorithm: SignatureAlgorithm,
+    protocol_version: &ProtocolVersion,
+) -> Result<(), ProtoConversionError> {
+    if algorithm != SignatureAlgorithm::Secp256k1 {
+        return Err(ProtoConversionError::InvalidTypeError(format!(
+            "Sawtooth headers only support secp256k1 signatures, not {}",
+            algorithm.name()
+        )));
+    }
+    if *protocol_version != ProtocolVersion::default() {
+        return Err(ProtoConversionError::InvalidTypeError(format!(
+            "Sawtooth headers only support protocol version {}, not {}",
+            ProtocolVersion::default(),
+            protocol_version
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use protobuf::Message;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::TransactionBuilder;
+    use crate::signing::hash::HashSigner;
+
+    static KEY1: &str = "111111111111111111111111111111111111111111111111111111111111111111";
+    static KEY2: &str = "222222222222222222222222222222222222222222222222222222222222222222";
+    static KEY3: &str = "333333333333333333333333333333333333333333333333333333333333333333";
+    static HASH: &str = "44444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444444";
+
+    fn make_batch() -> Batch {
+        let signer = HashSigner::new();
+        let transaction = TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![vec![0x01]])
+            .with_outputs(vec![vec![0x01]])
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(b"payload".to_vec())
+            .build(&signer)
+            .unwrap();
+        BatchBuilder::new()
+            .with_transactions(vec![transaction])
+            .with_trace(true)
+            .build(&signer)
+            .unwrap()
+    }
+
+    #[test]
+    // test that a batch round trips through the Sawtooth message, and that the Sawtooth message
+    // serializes to the same bytes as Transact's
+    fn sawtooth_batch_round_trip() {
+        let batch = make_batch();
+
+        let sawtooth_batch = SawtoothBatch::from_native(batch.clone()).unwrap();
+        assert_eq!(
+            protos::batch::Batch::from_native(batch.clone())
+                .unwrap()
+                .write_to_bytes()
+                .unwrap(),
+            sawtooth_batch.write_to_bytes().unwrap()
+        );
+        assert_eq!(batch, Batch::from_proto(sawtooth_batch).unwrap());
+
+        let batch_list = batches_to_sawtooth_list(vec![batch.clone()]).unwrap();
+        assert_eq!(vec![batch], batches_from_sawtooth_list(batch_list).unwrap());
+    }
+
+    #[test]
+    // test that headers round trip through the Sawtooth messages, and that Transact-only
+    // extensions are rejected
+    fn sawtooth_header_round_trip() {
+        let mut sawtooth_header = SawtoothTransactionHeader::new();
+        sawtooth_header.set_batcher_public_key(KEY1.to_string());
+        sawtooth_header.set_dependencies(vec![KEY2.to_string()].into());
+        sawtooth_header.set_family_name("test".to_string());
+        sawtooth_header.set_family_version("1.0".to_string());
+        sawtooth_header.set_inputs(vec![KEY2.to_string(), KEY3.to_string()].into());
+        sawtooth_header.set_nonce("nonce".to_string());
+        sawtooth_header.set_outputs(vec![KEY3.to_string()].into());
+        sawtooth_header.set_payload_sha512(HASH.to_string());
+        sawtooth_header.set_signer_public_key(KEY1.to_string());
+
+        let header = TransactionHeader::from_proto(sawtooth_header.clone()).unwrap();
+        assert_eq!(KEY1, hex::encode(header.signer_public_key()));
+        assert_eq!(b"nonce", header.nonce());
+        assert_eq!(
+            sawtooth_header,
+            SawtoothTransactionHeader::from_native(header).unwrap()
+        );
+
+        let mut sawtooth_batch_header = SawtoothBatchHeader::new();
+        sawtooth_batch_header.set_signer_public_key(KEY1.to_string());
+        sawtooth_batch_header.set_transaction_ids(vec![KEY2.to_string()].into());
+        let batch_header = BatchHeader::from_proto(sawtooth_batch_header.clone()).unwrap();
+        assert_eq!(
+            sawtooth_batch_header,
+            SawtoothBatchHeader::from_native(batch_header).unwrap()
+        );
+
+        // Batch metadata cannot be represented in a Sawtooth header
+        let batch = BatchBuilder::new()
+            .with_transactions(make_batch().transactions().to_vec())
+            .with_metadata_entry("origin".to_string(), "test".to_string())
+            .build(&HashSigner::new())
+            .unwrap();
+        let header = batch.parse_header().unwrap();
+        assert!(SawtoothBatchHeader::from_native(header).is_err());
+    }
+
+    #[test]
+    // test that receipts round trip through the Sawtooth message
+    fn sawtooth_receipt_round_trip() {
+        let receipt = TransactionReceipt {
+            state_changes: vec![
+                StateChange::Set {
+                    key: KEY1.to_string(),
+                    value: b"value".to_vec(),
+                },
+                StateChange::Delete {
+                    key: KEY2.to_string(),
+                },
+            ],
+            events: vec![Event {
+                event_type: "test/event".to_string(),
+                attributes: vec![("key".to_string(), "value".to_string())],
+                data: b"data".to_vec(),
+            }],
+            data: vec![b"receipt data".to_vec()],
+            transaction_id: KEY3.to_string(),
+            batch_metadata: BTreeMap::new(),
+            trace: false,
+        };
+
+        let sawtooth_receipt = SawtoothTransactionReceipt::from_native(receipt.clone()).unwrap();
+        assert_eq!(
+            receipt,
+            TransactionReceipt::from_proto(sawtooth_receipt).unwrap()
+        );
+    }
+}
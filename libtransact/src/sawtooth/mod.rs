@@ -19,9 +19,14 @@
 //! allow the use of existing Sawtooth transaction families, implemented with the [Rust
 //! SDK](https://crates.io/crates/sawtooth-sdk), in an application built with Transact.
 //!
+//! The `convert` module converts transactions, batches and receipts to and from the Sawtooth
+//! SDK's message types, so that objects can be exchanged with Sawtooth clients and validators.
+//!
 //! Note, to use this module, the Transact library must have the `"sawtooth-compat"` feature
 //! enabled.
 
+pub mod convert;
+
 use std::fmt::Write as FmtWrite;
 
 use sawtooth_sdk::messages::processor::TpProcessRequest;
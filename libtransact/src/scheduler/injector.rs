@@ -0,0 +1,464 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Injection of system batches around user batches.
+//!
+//! A `BatchInjector` supplies batches that must be executed alongside the batches submitted by
+//! users, such as block-info or settings updates.  An `InjectingScheduler` wraps another
+//! `Scheduler` and consults its injectors each time a batch is added: the batches returned by
+//! `inject_before` are added to the inner scheduler immediately before the user batch, and those
+//! returned by `inject_after` immediately after it, in the order the injectors were added.  The
+//! inner scheduler therefore orders injected batches relative to the user batch exactly as it
+//! orders batches added one after another.
+//!
+//! Every injector is consulted before any batch is added to the inner scheduler, so an injector
+//! error leaves the inner scheduler unchanged.  If the inner scheduler rejects one of the batches,
+//! the remaining batches are not added; those it already accepted stay scheduled, as they would
+//! for any sequence of `add_batch` calls.
+//!
+//! The results of injected batches are attributed to the injector that supplied them, and are
+//! delivered to the injected result callback rather than to the result callback.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::protocol::batch::BatchPair;
+
+use super::{
+    BatchExecutionResult, ExecutionTask, ExecutionTaskCompletionNotifier, Scheduler, SchedulerError,
+};
+
+/// Supplies system batches to be executed before or after each user batch.
+pub trait BatchInjector: Send {
+    /// The name to which the results of this injector's batches are attributed.
+    fn name(&self) -> &str;
+
+    /// Returns the batches to execute immediately before the given batch.
+    fn inject_before(&mut self, _batch: &BatchPair) -> Result<Vec<BatchPair>, SchedulerError> {
+        Ok(vec![])
+    }
+
+    /// Returns the batches to execute immediately after the given batch.
+    fn inject_after(&mut self, _batch: &BatchPair) -> Result<Vec<BatchPair>, SchedulerError> {
+        Ok(vec![])
+    }
+}
+
+/// The result of executing a batch supplied by a `BatchInjector`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InjectedBatchResult {
+    /// The name of the injector that supplied the batch.
+    pub injector: String,
+    pub result: BatchExecutionResult,
+}
+
+type ResultCallback = Box<Fn(Option<BatchExecutionResult>) + Send>;
+type InjectedResultCallback = Box<Fn(InjectedBatchResult) + Send>;
+
+/// A `Scheduler` that adds the batches of its injectors around each batch added to it.
+pub struct InjectingScheduler<S: Scheduler> {
+    inner: S,
+    injectors: Vec<Box<dyn BatchInjector>>,
+    // Maps the ids of injected batches that have not yet completed to their injector's name.
+    injected: Arc<Mutex<HashMap<String, String>>>,
+    result_callback: Arc<Mutex<ResultCallback>>,
+    injected_result_callback: Arc<Mutex<InjectedResultCallback>>,
+}
+
+impl<S: Scheduler> InjectingScheduler<S> {
+    pub fn new(mut inner: S) -> Result<Self, SchedulerError> {
+        let injected: Arc<Mutex<HashMap<String, String>>> = Arc::new(Mutex::new(HashMap::new()));
+        let result_callback: Arc<Mutex<ResultCallback>> =
+            Arc::new(Mutex::new(Box::new(super::default_result_callback)));
+        let injected_result_callback: Arc<Mutex<InjectedResultCallback>> =
+            Arc::new(Mutex::new(Box::new(default_injected_result_callback)));
+
+        let routing_injected = injected.clone();
+        let routing_result_callback = result_callback.clone();
+        let routing_injected_result_callback = injected_result_callback.clone();
+        inner.set_result_callback(Box::new(move |batch_result| {
+            let injector = batch_result.as_ref().and_then(|result| {
+                routing_injected
+                    .lock()
+                    .ok()?
+                    .remove(result.batch.batch().header_signature())
+            });
+
+            match (injector, batch_result) {
+                (Some(injector), Some(result)) => match routing_injected_result_callback.lock() {
+                    Ok(callback) => (*callback)(InjectedBatchResult { injector, result }),
+                    Err(_) => error!("Injected result callback lock poisoned"),
+                },
+                (_, batch_result) => match routing_result_callback.lock() {
+                    Ok(callback) => (*callback)(batch_result),
+                    Err(_) => error!("Result callback lock poisoned"),
+                },
+            }
+        }))?;
+
+        Ok(InjectingScheduler {
+            inner,
+            injectors: vec![],
+            injected,
+            result_callback,
+            injected_result_callback,
+        })
+    }
+
+    /// Adds an injector.  Injectors are consulted in the order they were added.
+    pub fn with_injector(mut self, injector: Box<dyn BatchInjector>) -> Self {
+        self.injectors.push(injector);
+        self
+    }
+
+    /// Sets a callback to receive the results of injected batches.
+    pub fn set_injected_result_callback(
+        &mut self,
+        callback: Box<Fn(InjectedBatchResult) + Send>,
+    ) -> Result<(), SchedulerError> {
+        *self.injected_result_callback.lock().map_err(|_| {
+            SchedulerError::Internal("Injected result callback lock poisoned".into())
+        })? = callback;
+        Ok(())
+    }
+
+    /// Adds the given batches to the inner scheduler in order.  Each injected batch is paired
+    /// with the name of its injector, and user batches with `None`.
+    fn schedule(
+        &mut self,
+        batches: Vec<(Option<String>, BatchPair)>,
+    ) -> Result<(), SchedulerError> {
+        let injected_ids = batches
+            .iter()
+            .map(|(injector, batch)| {
+                injector
+                    .as_ref()
+                    .map(|_| batch.batch().header_signature().to_string())
+            })
+            .collect::<Vec<_>>();
+
+        // Injected batches are tracked before they are added, since their results may arrive as
+        // soon as they are scheduled
+        {
+            let mut injected = self.lock_injected()?;
+            for ((injector, _), id) in batches.iter().zip(&injected_ids) {
+                if let (Some(injector), Some(id)) = (injector, id) {
+                    injected.insert(id.clone(), injector.clone());
+                }
+            }
+        }
+
+        for (i, (_, batch)) in batches.into_iter().enumerate() {
+            if let Err(err) = self.inner.add_batch(batch) {
+                // The remaining batches were never scheduled, so no results will arrive to
+                // remove their entries
+                let mut injected = self.lock_injected()?;
+                for id in injected_ids[i..].iter().flatten() {
+                    injected.remove(id);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn lock_injected(&self) -> Result<MutexGuard<HashMap<String, String>>, SchedulerError> {
+        self.injected
+            .lock()
+            .map_err(|_| SchedulerError::Internal("Injected batch lock poisoned".into()))
+    }
+
+    /// Returns the inner scheduler.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Scheduler> Scheduler for InjectingScheduler<S> {
+    fn set_result_callback(
+        &mut self,
+        callback: Box<Fn(Option<BatchExecutionResult>) + Send>,
+    ) -> Result<(), SchedulerError> {
+        *self
+            .result_callback
+            .lock()
+            .map_err(|_| SchedulerError::Internal("Result callback lock poisoned".into()))? =
+            callback;
+        Ok(())
+    }
+
+    fn set_error_callback(
+        &mut self,
+        callback: Box<Fn(SchedulerError) + Send>,
+    ) -> Result<(), SchedulerError> {
+        self.inner.set_error_callback(callback)
+    }
+
+    fn add_batch(&mut self, batch: BatchPair) -> Result<(), SchedulerError> {
+        let mut before = vec![];
+        let mut after = vec![];
+        for injector in self.injectors.iter_mut() {
+            let name = injector.name().to_string();
+            before.extend(
+                injector
+                    .inject_before(&batch)?
+                    .into_iter()
+                    .map(|injected| (Some(name.clone()), injected)),
+            );
+            after.extend(
+                injector
+                    .inject_after(&batch)?
+                    .into_iter()
+                    .map(|injected| (Some(name.clone()), injected)),
+            );
+        }
+
+        let mut batches = before;
+        batches.push((None, batch));
+        batches.extend(after);
+        self.schedule(batches)
+    }
+
+    /// Cancels the inner scheduler, returning only the user batches that were dropped.
+    fn cancel(&mut self) -> Result<Vec<BatchPair>, SchedulerError> {
+        let dropped = self.inner.cancel()?;
+        let mut injected = self.lock_injected()?;
+        Ok(dropped
+            .into_iter()
+            .filter(|batch| injected.remove(batch.batch().header_signature()).is_none())
+            .collect())
+    }
+
+    fn finalize(&mut self) -> Result<(), SchedulerError> {
+        self.inner.finalize()
+    }
+
+    fn take_task_iterator(
+        &mut self,
+    ) -> Result<Box<dyn Iterator<Item = ExecutionTask> + Send>, SchedulerError> {
+        self.inner.take_task_iterator()
+    }
+
+    fn new_notifier(&mut self) -> Result<Box<dyn ExecutionTaskCompletionNotifier>, SchedulerError> {
+        self.inner.new_notifier()
+    }
+}
+
+fn default_injected_result_callback(injected_result: InjectedBatchResult) {
+    debug!(
+        "No injected result callback set; dropping result of batch {} from injector {}",
+        injected_result.result.batch.batch().header_signature(),
+        injected_result.injector
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scheduler::ExecutionTaskCompletionNotification;
+    use crate::workload::xo::XoBatchWorkload;
+    use crate::workload::BatchWorkload;
+
+    /// A scheduler that records the batches added to it, and returns a valid result for each
+    /// when finalized.  If `reject` is set, every batch added to it is rejected.
+    #[derive(Default)]
+    struct RecordingScheduler {
+        batches: Vec<BatchPair>,
+        result_callback: Option<Box<Fn(Option<BatchExecutionResult>) + Send>>,
+        reject: bool,
+    }
+
+    /// A notifier that discards its notifications.
+    #[derive(Clone)]
+    struct NoopNotifier;
+
+    impl ExecutionTaskCompletionNotifier for NoopNotifier {
+        fn notify(&self, _notification: ExecutionTaskCompletionNotification) {}
+
+        fn clone_box(&self) -> Box<dyn ExecutionTaskCompletionNotifier> {
+            Box::new(self.clone())
+        }
+    }
+
+    impl Scheduler for RecordingScheduler {
+        fn set_result_callback(
+            &mut self,
+            callback: Box<Fn(Option<BatchExecutionResult>) + Send>,
+        ) -> Result<(), SchedulerError> {
+            self.result_callback = Some(callback);
+            Ok(())
+        }
+
+        fn set_error_callback(
+            &mut self,
+            _callback: Box<Fn(SchedulerError) + Send>,
+        ) -> Result<(), SchedulerError> {
+            Ok(())
+        }
+
+        fn add_batch(&mut self, batch: BatchPair) -> Result<(), SchedulerError> {
+            if self.reject {
+                return Err(SchedulerError::Internal("batch rejected".into()));
+            }
+            self.batches.push(batch);
+            Ok(())
+        }
+
+        fn cancel(&mut self) -> Result<Vec<BatchPair>, SchedulerError> {
+            Ok(self.batches.drain(..).collect())
+        }
+
+        fn finalize(&mut self) -> Result<(), SchedulerError> {
+            let callback = self.result_callback.as_ref().unwrap();
+            for batch in &self.batches {
+                callback(crate::scheduler::tests::valid_result_from_batch(
+                    batch.clone(),
+                ));
+            }
+            callback(None);
+            Ok(())
+        }
+
+        fn take_task_iterator(
+            &mut self,
+        ) -> Result<Box<dyn Iterator<Item = ExecutionTask> + Send>, SchedulerError> {
+            Err(SchedulerError::NoTaskIterator)
+        }
+
+        fn new_notifier(
+            &mut self,
+        ) -> Result<Box<dyn ExecutionTaskCompletionNotifier>, SchedulerError> {
+            Ok(Box::new(NoopNotifier))
+        }
+    }
+
+    /// An injector that injects one of the given batches before each user batch.  If
+    /// `fail_after` is set, it returns an error when asked for the batches to inject after.
+    struct BlockInfoInjector {
+        batches: Vec<BatchPair>,
+        fail_after: bool,
+    }
+
+    impl BlockInfoInjector {
+        fn new(seed: u64) -> Self {
+            let mut workload = XoBatchWorkload::new_with_seed(seed);
+            BlockInfoInjector {
+                batches: (0..4).map(|_| workload.next_batch().unwrap()).collect(),
+                fail_after: false,
+            }
+        }
+    }
+
+    impl BatchInjector for BlockInfoInjector {
+        fn name(&self) -> &str {
+            "block_info"
+        }
+
+        fn inject_before(&mut self, _batch: &BatchPair) -> Result<Vec<BatchPair>, SchedulerError> {
+            self.batches
+                .pop()
+                .map(|batch| vec![batch])
+                .ok_or_else(|| SchedulerError::Internal("no batches left to inject".into()))
+        }
+
+        fn inject_after(&mut self, _batch: &BatchPair) -> Result<Vec<BatchPair>, SchedulerError> {
+            if self.fail_after {
+                Err(SchedulerError::Internal("injection failed".into()))
+            } else {
+                Ok(vec![])
+            }
+        }
+    }
+
+    #[test]
+    // test that injected batches are added before the user batch, and that their results are
+    // attributed to the injector
+    fn injecting_scheduler() {
+        let inner = RecordingScheduler::default();
+        let mut scheduler = InjectingScheduler::new(inner)
+            .unwrap()
+            .with_injector(Box::new(BlockInfoInjector::new(1)));
+
+        let results = Arc::new(Mutex::new(vec![]));
+        let injected_results = Arc::new(Mutex::new(vec![]));
+        let results_clone = results.clone();
+        scheduler
+            .set_result_callback(Box::new(move |result| {
+                results_clone.lock().unwrap().push(result)
+            }))
+            .unwrap();
+        let injected_results_clone = injected_results.clone();
+        scheduler
+            .set_injected_result_callback(Box::new(move |result| {
+                injected_results_clone.lock().unwrap().push(result)
+            }))
+            .unwrap();
+
+        let user_batch = XoBatchWorkload::new_with_seed(2).next_batch().unwrap();
+        scheduler.add_batch(user_batch.clone()).unwrap();
+        scheduler.finalize().unwrap();
+
+        let added = &scheduler.inner.batches;
+        assert_eq!(2, added.len());
+        assert_eq!(user_batch, added[1]);
+
+        let results = results.lock().unwrap();
+        assert_eq!(2, results.len());
+        assert_eq!(user_batch, results[0].as_ref().unwrap().batch);
+        assert!(results[1].is_none());
+
+        let injected_results = injected_results.lock().unwrap();
+        assert_eq!(1, injected_results.len());
+        assert_eq!("block_info", injected_results[0].injector);
+        assert_eq!(added[0], injected_results[0].result.batch);
+    }
+
+    #[test]
+    // test that an injected batch rejected by the inner scheduler is no longer tracked, so that
+    // its id is not attributed to the injector later
+    fn injecting_scheduler_rejected_batch() {
+        let inner = RecordingScheduler {
+            reject: true,
+            ..RecordingScheduler::default()
+        };
+        let mut scheduler = InjectingScheduler::new(inner)
+            .unwrap()
+            .with_injector(Box::new(BlockInfoInjector::new(1)));
+
+        let user_batch = XoBatchWorkload::new_with_seed(2).next_batch().unwrap();
+        assert!(scheduler.add_batch(user_batch).is_err());
+        assert!(scheduler.injected.lock().unwrap().is_empty());
+        assert!(scheduler.inner.batches.is_empty());
+    }
+
+    #[test]
+    // test that no batches are scheduled when an injector fails after its batches to inject
+    // before the user batch have been collected
+    fn injecting_scheduler_injector_error() {
+        let mut injector = BlockInfoInjector::new(1);
+        injector.fail_after = true;
+        let mut scheduler = InjectingScheduler::new(RecordingScheduler::default())
+            .unwrap()
+            .with_injector(Box::new(injector));
+
+        let user_batch = XoBatchWorkload::new_with_seed(2).next_batch().unwrap();
+        assert!(scheduler.add_batch(user_batch).is_err());
+        assert!(scheduler.injected.lock().unwrap().is_empty());
+        assert!(scheduler.inner.batches.is_empty());
+    }
+}
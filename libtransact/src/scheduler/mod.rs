@@ -27,14 +27,18 @@
 //! must be consumed by a component responsible for iterating over the `Transaction`s and providing
 //! `TransactionExecutionResult`s back to the `Scheduler` via the `SchedulerExecutionInterface`.
 
+pub mod injector;
 pub mod multi;
 pub mod parallel;
+pub mod priority;
 pub mod serial;
 
+use std::collections::BTreeMap;
+
 use crate::context::ContextId;
 use crate::protocol::batch::BatchPair;
 use crate::protocol::receipt::TransactionReceipt;
-use crate::protocol::transaction::TransactionPair;
+use crate::protocol::transaction::{TransactionFee, TransactionPair};
 
 /// A transation and associated information required to execute it.
 pub struct ExecutionTask {
@@ -58,6 +62,11 @@ impl ExecutionTask {
         &self.context_id
     }
 
+    /// The fee and priority declared by the transaction, if any.
+    pub fn fee(&self) -> Option<&TransactionFee> {
+        self.pair.header().fee()
+    }
+
     /// Decompose into its components.
     pub fn take(self) -> (TransactionPair, ContextId) {
         (self.pair, self.context_id)
@@ -98,6 +107,24 @@ pub struct BatchExecutionResult {
     pub results: Vec<TransactionExecutionResult>,
 }
 
+impl BatchExecutionResult {
+    /// The ID of the executed batch.
+    pub fn batch_id(&self) -> &str {
+        self.batch.batch().header_signature()
+    }
+
+    /// Whether the executed batch requested tracing.
+    pub fn trace(&self) -> bool {
+        self.batch.batch().trace()
+    }
+
+    /// The metadata tags from the executed batch's header, which are also copied into each of
+    /// its receipts.
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        self.batch.metadata()
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ExecutionTaskCompletionNotification {
     /// The transation was invalid.
@@ -239,6 +266,8 @@ mod tests {
                     events: vec![],
                     data: vec![],
                     transaction_id: txn.header_signature().into(),
+                    batch_metadata: BTreeMap::new(),
+                    trace: false,
                 })
             })
             .collect();
@@ -0,0 +1,159 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Fee and priority information for cost-based scheduling policies.
+//!
+//! Transactions may declare a `TransactionFee` in their headers.  A scheduler that orders work by
+//! cost can read the fee of each `ExecutionTask`, or summarize a whole batch with
+//! `BatchPriority` before it is added.  Transactions that declare no fee have a fee and priority
+//! of zero.
+
+use std::cmp::Ordering;
+
+use crate::protocol::batch::BatchPair;
+use crate::protos::ProtoConversionError;
+
+/// The combined fee and priority of the transactions in a batch.
+///
+/// A batch's priority is the highest priority of its transactions, and its fee is the sum of
+/// their fees.  Priorities order first by priority and then by fee, so that sorting in
+/// descending order gives the order in which batches should be scheduled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchPriority {
+    priority: u32,
+    total_fee: u64,
+}
+
+impl BatchPriority {
+    pub fn from_batch(batch: &BatchPair) -> Result<Self, ProtoConversionError> {
+        let mut batch_priority = BatchPriority::default();
+        for transaction in batch.batch().transactions() {
+            if let Some(fee) = transaction.parse_header()?.fee() {
+                batch_priority.priority = batch_priority.priority.max(fee.priority());
+                batch_priority.total_fee = batch_priority.total_fee.saturating_add(fee.fee());
+            }
+        }
+        Ok(batch_priority)
+    }
+
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
+
+    /// The sum of the transaction fees, saturating at `u64::MAX`.
+    pub fn total_fee(&self) -> u64 {
+        self.total_fee
+    }
+}
+
+impl Ord for BatchPriority {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then(self.total_fee.cmp(&other.total_fee))
+    }
+}
+
+impl PartialOrd for BatchPriority {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Sorts batches into scheduling order: highest priority first, keeping the submission order of
+/// batches with equal priority.
+pub fn sort_by_priority(batches: Vec<BatchPair>) -> Result<Vec<BatchPair>, ProtoConversionError> {
+    let mut prioritized = batches
+        .into_iter()
+        .map(|batch| Ok((BatchPriority::from_batch(&batch)?, batch)))
+        .collect::<Result<Vec<_>, ProtoConversionError>>()?;
+    prioritized.sort_by(|(a, _), (b, _)| b.cmp(a));
+    Ok(prioritized.into_iter().map(|(_, batch)| batch).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder, TransactionFee};
+    use crate::signing::hash::HashSigner;
+
+    fn make_batch(fees: &[Option<TransactionFee>]) -> BatchPair {
+        let signer = HashSigner::new();
+        let transactions = fees
+            .iter()
+            .map(|fee| {
+                let builder = TransactionBuilder::new()
+                    .with_family_name("test".to_string())
+                    .with_family_version("1.0".to_string())
+                    .with_inputs(vec![vec![0x01]])
+                    .with_outputs(vec![vec![0x01]])
+                    .with_payload_hash_method(HashMethod::SHA512)
+                    .with_payload(vec![]);
+                match fee {
+                    Some(fee) => builder.with_fee(*fee),
+                    None => builder,
+                }
+                .build(&signer)
+                .unwrap()
+            })
+            .collect();
+        BatchBuilder::new()
+            .with_transactions(transactions)
+            .build_pair(&signer)
+            .unwrap()
+    }
+
+    #[test]
+    // test that a batch's priority combines the fees of its transactions
+    fn batch_priority() {
+        let batch = make_batch(&[
+            Some(TransactionFee::new(10, 1)),
+            None,
+            Some(TransactionFee::new(5, 3)),
+        ]);
+        let priority = BatchPriority::from_batch(&batch).unwrap();
+        assert_eq!(3, priority.priority());
+        assert_eq!(15, priority.total_fee());
+
+        assert_eq!(
+            BatchPriority::default(),
+            BatchPriority::from_batch(&make_batch(&[None])).unwrap()
+        );
+    }
+
+    #[test]
+    // test that batches are sorted by priority, then fee, then submission order
+    fn batch_sort_by_priority() {
+        let unpaid = make_batch(&[None]);
+        let cheap = make_batch(&[Some(TransactionFee::new(1, 1))]);
+        let expensive = make_batch(&[Some(TransactionFee::new(100, 1))]);
+        let urgent = make_batch(&[Some(TransactionFee::new(0, 2))]);
+        let also_unpaid = make_batch(&[None, None]);
+
+        let sorted = sort_by_priority(vec![
+            unpaid.clone(),
+            cheap.clone(),
+            expensive.clone(),
+            urgent.clone(),
+            also_unpaid.clone(),
+        ])
+        .unwrap();
+        assert_eq!(vec![urgent, expensive, cheap, unpaid, also_unpaid], sorted);
+    }
+}
@@ -231,12 +231,27 @@ impl SchedulerCore {
             }
         };
 
-        let context_id = match self.previous_context {
-            Some(previous_context_id) => self
-                .context_lifecycle
-                .create_context(&[previous_context_id], &self.state_id),
-            None => self.context_lifecycle.create_context(&[], &self.state_id),
+        // The transaction may only read and write the addresses its header declares
+        let dependent_contexts = match self.previous_context {
+            Some(previous_context_id) => vec![previous_context_id],
+            None => vec![],
         };
+        let context_id = self.context_lifecycle.create_context_with_permissions(
+            &dependent_contexts,
+            &self.state_id,
+            transaction_pair.header().inputs(),
+            transaction_pair.header().outputs(),
+        );
+
+        if let Some(ref batch) = self.current_batch {
+            if batch.batch().trace() {
+                debug!(
+                    "TRACE: scheduling transaction {} of batch {}",
+                    transaction_pair.transaction().header_signature(),
+                    batch.batch().header_signature()
+                );
+            }
+        }
 
         self.current_txn = Some(transaction_pair.transaction().header_signature().into());
         self.execution_tx
@@ -323,6 +338,13 @@ impl SchedulerCore {
         let mut results = vec![];
         std::mem::swap(&mut results, &mut self.txn_results);
 
+        if batch.batch().trace() {
+            debug!(
+                "TRACE: sending result of batch {}",
+                batch.batch().header_signature()
+            );
+        }
+
         let batch_result = BatchExecutionResult { batch, results };
 
         self.shared_lock.lock()?.result_callback()(Some(batch_result));
@@ -358,12 +380,18 @@ impl SchedulerCore {
                             }
                             self.current_txn = None;
                             self.previous_context = Some(context_id);
-                            self.txn_results.push(TransactionExecutionResult::Valid(
-                                self.context_lifecycle.get_transaction_receipt(
-                                    &context_id,
-                                    &hex::encode(transaction_id),
-                                )?,
-                            ));
+                            let mut receipt = self.context_lifecycle.get_transaction_receipt(
+                                &context_id,
+                                &hex::encode(transaction_id),
+                            )?;
+                            // Receipts carry the tags of their batch, so that they can be
+                            // followed without it
+                            if let Some(ref batch) = self.current_batch {
+                                receipt.batch_metadata = batch.metadata().clone();
+                                receipt.trace = batch.batch().trace();
+                            }
+                            self.txn_results
+                                .push(TransactionExecutionResult::Valid(receipt));
                         }
                         ExecutionTaskCompletionNotification::Invalid(_context_id, result) => {
                             if &result.transaction_id != current_txn_id {
@@ -180,7 +180,17 @@ impl Scheduler for SerialScheduler {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::context::manager::sync::ContextManager;
+    use crate::protocol::batch::BatchBuilder;
     use crate::scheduler::tests::*;
+    use crate::scheduler::{ExecutionTaskCompletionNotification, TransactionExecutionResult};
+    use crate::signing::hash::HashSigner;
+    use crate::state::hashmap::HashMapState;
+    use crate::workload::xo::XoBatchWorkload;
+    use crate::workload::BatchWorkload;
+
+    use std::collections::HashMap;
+    use std::sync::mpsc;
 
     /// This test will hang if join() fails within the scheduler.
     #[test]
@@ -221,4 +231,73 @@ mod tests {
         test_scheduler_flow_with_one_transaction(&mut scheduler);
         scheduler.shutdown();
     }
+
+    #[test]
+    // test that the receipts of a batch carry its metadata tags and trace flag
+    fn test_serial_scheduler_receipt_batch_tags() {
+        let state_id = HashMapState::state_id(&HashMap::new());
+        let context_manager = ContextManager::new(Box::new(HashMapState::new()));
+        let mut scheduler = SerialScheduler::new(Box::new(context_manager), state_id)
+            .expect("Failed to create scheduler");
+
+        let (result_tx, result_rx) = mpsc::channel();
+        scheduler
+            .set_result_callback(Box::new(move |batch_result| {
+                result_tx
+                    .send(batch_result)
+                    .expect("Failed to send batch result");
+            }))
+            .expect("Failed to set result callback");
+
+        let task_iterator = scheduler
+            .take_task_iterator()
+            .expect("Failed to get task iterator");
+        let notifier = scheduler
+            .new_notifier()
+            .expect("Failed to get new notifier");
+        std::thread::spawn(move || {
+            for task in task_iterator {
+                notifier.notify(ExecutionTaskCompletionNotification::Valid(
+                    *task.context_id(),
+                    task.pair().transaction().header_signature().to_string(),
+                ));
+            }
+        });
+
+        let transactions = XoBatchWorkload::new_with_seed(3)
+            .next_batch()
+            .unwrap()
+            .batch()
+            .transactions()
+            .to_vec();
+        let batch = BatchBuilder::new()
+            .with_transactions(transactions)
+            .with_metadata_entry("tenant".into(), "acme".into())
+            .with_trace(true)
+            .build_pair(&HashSigner::new())
+            .unwrap();
+        scheduler
+            .add_batch(batch.clone())
+            .expect("Failed to add batch");
+
+        let batch_result = result_rx
+            .recv()
+            .expect("Failed to receive batch result")
+            .expect("Expected a batch result");
+        assert_eq!(batch.batch().header_signature(), batch_result.batch_id());
+        assert!(!batch_result.results.is_empty());
+        for result in batch_result.results {
+            match result {
+                TransactionExecutionResult::Valid(receipt) => {
+                    assert_eq!(batch.metadata(), &receipt.batch_metadata);
+                    assert!(receipt.trace);
+                }
+                TransactionExecutionResult::Invalid(result) => {
+                    panic!("Unexpected invalid result: {:?}", result)
+                }
+            }
+        }
+
+        scheduler.shutdown();
+    }
 }
@@ -0,0 +1,168 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Signers that complete asynchronously.
+//!
+//! A remote signing service answers over the network, and waiting for it would block the thread
+//! building the transaction or batch.  An `AsyncSigner` instead returns a future of the
+//! signature, which `TransactionBuilder::build_async` and `BatchBuilder::build_async` await.  A
+//! `Signer` may be used wherever an `AsyncSigner` is expected by wrapping it in a `SyncSigner`.
+//!
+//! This module is only available with the `async` feature.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::signing::{Error, SignatureAlgorithm, Signer};
+
+/// The future of a signature produced by an `AsyncSigner`.
+pub type SignatureFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>> + Send + 'a>>;
+
+/// A signer whose signatures are produced asynchronously, such as by a remote signing service.
+pub trait AsyncSigner: Send + Sync {
+    fn sign<'a>(&'a self, message: &'a [u8]) -> SignatureFuture<'a>;
+    fn public_key(&self) -> &[u8];
+
+    /// The algorithm of the signatures produced by this signer.
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::Secp256k1
+    }
+}
+
+/// Adapts a `Signer` to the `AsyncSigner` trait; its futures are ready immediately.
+pub struct SyncSigner<S> {
+    signer: S,
+}
+
+impl<S: Signer + Send + Sync> SyncSigner<S> {
+    pub fn new(signer: S) -> Self {
+        SyncSigner { signer }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.signer
+    }
+}
+
+impl<S: Signer + Send + Sync> AsyncSigner for SyncSigner<S> {
+    fn sign<'a>(&'a self, message: &'a [u8]) -> SignatureFuture<'a> {
+        let signature = self.signer.sign(message);
+        Box::pin(async move { signature })
+    }
+
+    fn public_key(&self) -> &[u8] {
+        self.signer.public_key()
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.signer.algorithm()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    use futures::channel::oneshot;
+    use futures::executor::block_on;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::{HashMethod, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
+
+    /// Signs on another thread, as a signer waiting on a remote service would.
+    struct ThreadSigner {
+        signer: Arc<HashSigner>,
+    }
+
+    impl AsyncSigner for ThreadSigner {
+        fn sign<'a>(&'a self, message: &'a [u8]) -> SignatureFuture<'a> {
+            let (sender, receiver) = oneshot::channel();
+            let signer = Arc::clone(&self.signer);
+            let message = message.to_vec();
+            thread::spawn(move || {
+                let _ = sender.send(signer.sign(&message));
+            });
+            Box::pin(async move {
+                receiver
+                    .await
+                    .map_err(|_| Error::SigningError("signing thread stopped".to_string()))?
+            })
+        }
+
+        fn public_key(&self) -> &[u8] {
+            self.signer.public_key()
+        }
+
+        fn algorithm(&self) -> SignatureAlgorithm {
+            self.signer.algorithm()
+        }
+    }
+
+    fn transaction_builder() -> TransactionBuilder {
+        TransactionBuilder::new()
+            .with_family_name("test".to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(vec![vec![0xaa]])
+            .with_outputs(vec![vec![0xaa]])
+            .with_nonce(b"nonce".to_vec())
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(b"payload".to_vec())
+    }
+
+    #[test]
+    // test that transactions and batches built with an asynchronous signer are identical to those
+    // built with the equivalent synchronous signer
+    fn build_async_matches_build() {
+        let signer = HashSigner::new();
+        let async_signer = ThreadSigner {
+            signer: Arc::new(HashSigner::new()),
+        };
+
+        let transaction = block_on(transaction_builder().build_async(&async_signer)).unwrap();
+        assert_eq!(transaction_builder().build(&signer).unwrap(), transaction);
+
+        let batch_builder = BatchBuilder::new().with_transactions(vec![transaction]);
+        let pair = block_on(batch_builder.clone().build_pair_async(&async_signer)).unwrap();
+        assert_eq!(batch_builder.build(&signer).unwrap(), *pair.batch());
+        assert_eq!(signer.public_key(), pair.header().signer_public_key());
+    }
+
+    #[test]
+    // test that a synchronous signer can be used through the adapter
+    fn sync_signer_adapter() {
+        let signer = SyncSigner::new(HashSigner::new());
+        assert_eq!(b"hash_signer", signer.public_key());
+        assert_eq!(SignatureAlgorithm::Secp256k1, signer.algorithm());
+
+        let pair = block_on(transaction_builder().build_pair_async(&signer)).unwrap();
+        assert_eq!(
+            transaction_builder()
+                .build_pair(&HashSigner::new())
+                .unwrap()
+                .transaction(),
+            pair.transaction()
+        );
+        assert_eq!(
+            block_on(signer.sign(b"message")).unwrap(),
+            signer.into_inner().sign(b"message").unwrap()
+        );
+    }
+}
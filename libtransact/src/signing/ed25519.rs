@@ -0,0 +1,123 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Ed25519 signing and verification.
+//!
+//! This module is only available with the `ed25519` feature.
+
+use std::convert::TryFrom;
+
+use ed25519_dalek::Signer as _;
+use ed25519_dalek::Verifier as _;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature};
+use rand::Rng;
+
+use crate::signing::{Error, SignatureAlgorithm, SignatureVerifier, Signer};
+
+/// Signs messages with an Ed25519 private key.
+pub struct Ed25519Signer {
+    keypair: Keypair,
+    public_key: Vec<u8>,
+}
+
+impl Ed25519Signer {
+    /// Creates a signer from a 32-byte Ed25519 secret key.
+    pub fn new(secret_key: &[u8]) -> Result<Self, Error> {
+        let secret = SecretKey::from_bytes(secret_key)
+            .map_err(|err| Error::SigningError(format!("Invalid Ed25519 secret key: {}", err)))?;
+        let public = PublicKey::from(&secret);
+
+        Ok(Ed25519Signer {
+            keypair: Keypair { secret, public },
+            public_key: public.to_bytes().to_vec(),
+        })
+    }
+
+    /// Creates a signer with a new random secret key.
+    pub fn generate() -> Result<Self, Error> {
+        let secret_key: [u8; 32] = rand::thread_rng().gen();
+        Ed25519Signer::new(&secret_key)
+    }
+
+    pub fn secret_key(&self) -> &[u8] {
+        self.keypair.secret.as_bytes()
+    }
+}
+
+impl Signer for Ed25519Signer {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(self.keypair.sign(message).to_bytes().to_vec())
+    }
+
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::Ed25519
+    }
+}
+
+/// Verifies Ed25519 signatures.
+#[derive(Default)]
+pub struct Ed25519Verifier;
+
+impl Ed25519Verifier {
+    pub fn new() -> Self {
+        Ed25519Verifier::default()
+    }
+}
+
+impl SignatureVerifier for Ed25519Verifier {
+    fn verify(&self, message: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool, Error> {
+        let public_key = PublicKey::from_bytes(public_key)
+            .map_err(|err| Error::SigningError(format!("Invalid Ed25519 public key: {}", err)))?;
+        let signature = match Signature::try_from(signature) {
+            Ok(signature) => signature,
+            Err(_) => return Ok(false),
+        };
+
+        Ok(public_key.verify(message, &signature).is_ok())
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::Ed25519
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ed25519_sign_and_verify() {
+        let signer = Ed25519Signer::generate().unwrap();
+        let verifier = Ed25519Verifier::new();
+
+        let signature = signer.sign(b"hello").unwrap();
+        assert_eq!(64, signature.len());
+        assert!(verifier
+            .verify(b"hello", &signature, signer.public_key())
+            .unwrap());
+        assert!(!verifier
+            .verify(b"goodbye", &signature, signer.public_key())
+            .unwrap());
+
+        let copy = Ed25519Signer::new(signer.secret_key()).unwrap();
+        assert_eq!(signer.public_key(), copy.public_key());
+    }
+}
@@ -20,10 +20,19 @@
 //! The HashSigner provides a simple implementation of the Signer trait, by simply producing a
 //! SHA-512 hash of the message bytes.  This implementation allows for the use of transact without
 //! the need of a cryptographic library for public-private key signing.
+//!
+//! The signer reports the default `Secp256k1` algorithm, which leaves the signature algorithm
+//! field of a header empty.  Headers signed by a `HashSigner` are therefore byte-for-byte
+//! identical to those written before the field existed, and keep their ids.
+//!
+//! The matching `HashVerifier` is only available to tests: it ignores the public key, and would
+//! accept forged secp256k1 signatures if it were ever registered outside of them.
 
 use sha2::{Digest, Sha512};
 
 use crate::signing::Error;
+#[cfg(test)]
+use crate::signing::SignatureVerifier;
 use crate::signing::Signer;
 
 pub struct HashSigner {
@@ -46,12 +55,57 @@ impl Default for HashSigner {
 
 impl Signer for HashSigner {
     fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
-        let mut hasher = Sha512::new();
-        hasher.input(message);
-        Ok(hasher.result().to_vec())
+        Ok(hash(message))
     }
 
     fn public_key(&self) -> &[u8] {
         &self.dummy_public_key
     }
 }
+
+/// Verifies signatures produced by a `HashSigner`.
+///
+/// As a `HashSigner` signature is only the SHA-512 hash of the message, the public key is not
+/// considered during verification.
+#[cfg(test)]
+#[derive(Default)]
+pub struct HashVerifier;
+
+#[cfg(test)]
+impl HashVerifier {
+    pub fn new() -> Self {
+        HashVerifier::default()
+    }
+}
+
+#[cfg(test)]
+impl SignatureVerifier for HashVerifier {
+    fn verify(&self, message: &[u8], signature: &[u8], _public_key: &[u8]) -> Result<bool, Error> {
+        Ok(hash(message).as_slice() == signature)
+    }
+}
+
+fn hash(message: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.input(message);
+    hasher.result().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_verifier_verifies_hash_signer() {
+        let signer = HashSigner::new();
+        let verifier = HashVerifier::new();
+
+        let signature = signer.sign(b"hello").unwrap();
+        assert!(verifier
+            .verify(b"hello", &signature, signer.public_key())
+            .unwrap());
+        assert!(!verifier
+            .verify(b"goodbye", &signature, signer.public_key())
+            .unwrap());
+    }
+}
@@ -0,0 +1,313 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! A `Signer` backed by a PKCS#11 token.
+//!
+//! The `Pkcs11Signer` signs messages with a secp256k1 private key held by a hardware security
+//! module (or any other PKCS#11 token), so that the key never leaves the token.  As with
+//! Sawtooth's secp256k1 signatures, the SHA-256 digest of the message is signed, and the
+//! signature is returned in its 64-byte compact (`r || s`) form.
+//!
+//! Signatures are normalized to the lower of the two valid `s` values, as required by verifiers
+//! which reject malleable signatures. Tokens which return DER-encoded signatures rather than the
+//! raw `r || s` form are also supported.
+//!
+//! This module is only available with the `pkcs11` feature.
+
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
+
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use pkcs11::types::{
+    CKA_CLASS, CKA_LABEL, CKF_RW_SESSION, CKF_SERIAL_SESSION, CKM_ECDSA, CKO_PRIVATE_KEY, CKU_USER,
+    CK_ATTRIBUTE, CK_MECHANISM, CK_OBJECT_HANDLE, CK_SESSION_HANDLE, CK_SLOT_ID,
+};
+use pkcs11::Ctx;
+use sha2::{Digest, Sha256};
+
+use crate::signing::{Error, Signer};
+
+/// Signs messages with a private key stored on a PKCS#11 token.
+pub struct Pkcs11Signer {
+    ctx: Ctx,
+    // PKCS#11 sessions may only run one operation at a time
+    session: Mutex<CK_SESSION_HANDLE>,
+    key: CK_OBJECT_HANDLE,
+    public_key: Vec<u8>,
+}
+
+impl Signer for Pkcs11Signer {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut hasher = Sha256::new();
+        hasher.input(message);
+        let digest = hasher.result();
+
+        let session = self
+            .session
+            .lock()
+            .map_err(|_| Error::SigningError("PKCS#11 session lock poisoned".to_string()))?;
+
+        let mechanism = CK_MECHANISM {
+            mechanism: CKM_ECDSA,
+            pParameter: ptr::null_mut(),
+            ulParameterLen: 0,
+        };
+        self.ctx
+            .sign_init(*session, &mechanism, self.key)
+            .map_err(pkcs11_error)?;
+        let signature = self.ctx.sign(*session, &digest).map_err(pkcs11_error)?;
+        compact_signature(&signature)
+    }
+
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+}
+
+impl Drop for Pkcs11Signer {
+    fn drop(&mut self) {
+        if let Ok(session) = self.session.lock() {
+            if let Err(err) = self.ctx.logout(*session) {
+                warn!("Unable to log out of PKCS#11 session: {}", err);
+            }
+            if let Err(err) = self.ctx.close_session(*session) {
+                warn!("Unable to close PKCS#11 session: {}", err);
+            }
+        }
+    }
+}
+
+/// Builds a `Pkcs11Signer` by opening a session on a token and locating the signing key.
+#[derive(Default, Clone)]
+pub struct Pkcs11SignerBuilder {
+    module_path: Option<PathBuf>,
+    slot: Option<CK_SLOT_ID>,
+    pin: Option<String>,
+    key_label: Option<String>,
+    public_key: Option<Vec<u8>>,
+}
+
+impl Pkcs11SignerBuilder {
+    pub fn new() -> Self {
+        Pkcs11SignerBuilder::default()
+    }
+
+    /// Sets the path of the PKCS#11 module (shared library) for the token.
+    pub fn with_module_path<P: AsRef<Path>>(mut self, module_path: P) -> Pkcs11SignerBuilder {
+        self.module_path = Some(module_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn with_slot(mut self, slot: CK_SLOT_ID) -> Pkcs11SignerBuilder {
+        self.slot = Some(slot);
+        self
+    }
+
+    /// Sets the user PIN used to log in to the token.
+    pub fn with_pin(mut self, pin: String) -> Pkcs11SignerBuilder {
+        self.pin = Some(pin);
+        self
+    }
+
+    /// Sets the label of the private key object on the token.
+    pub fn with_key_label(mut self, key_label: String) -> Pkcs11SignerBuilder {
+        self.key_label = Some(key_label);
+        self
+    }
+
+    /// Sets the public key reported by the signer, which must correspond to the token's key.
+    pub fn with_public_key(mut self, public_key: Vec<u8>) -> Pkcs11SignerBuilder {
+        self.public_key = Some(public_key);
+        self
+    }
+
+    pub fn build(self) -> Result<Pkcs11Signer, Error> {
+        let module_path = self
+            .module_path
+            .ok_or_else(|| missing_field("module_path"))?;
+        let slot = self.slot.ok_or_else(|| missing_field("slot"))?;
+        let pin = self.pin.ok_or_else(|| missing_field("pin"))?;
+        let key_label = self.key_label.ok_or_else(|| missing_field("key_label"))?;
+        let public_key = self.public_key.ok_or_else(|| missing_field("public_key"))?;
+
+        let ctx = Ctx::new_and_initialize(module_path).map_err(pkcs11_error)?;
+        let session = ctx
+            .open_session(slot, CKF_SERIAL_SESSION | CKF_RW_SESSION, None, None)
+            .map_err(pkcs11_error)?;
+        ctx.login(session, CKU_USER, Some(&pin))
+            .map_err(pkcs11_error)?;
+
+        let template = vec![
+            CK_ATTRIBUTE::new(CKA_CLASS).with_ck_ulong(&CKO_PRIVATE_KEY),
+            CK_ATTRIBUTE::new(CKA_LABEL).with_string(&key_label),
+        ];
+        ctx.find_objects_init(session, &template)
+            .map_err(pkcs11_error)?;
+        let keys = ctx.find_objects(session, 1).map_err(pkcs11_error)?;
+        ctx.find_objects_final(session).map_err(pkcs11_error)?;
+
+        let key = keys.into_iter().next().ok_or_else(|| {
+            Error::SigningError(format!("No private key labeled {} on token", key_label))
+        })?;
+
+        Ok(Pkcs11Signer {
+            ctx,
+            session: Mutex::new(session),
+            key,
+            public_key,
+        })
+    }
+}
+
+const SCALAR_SIZE: usize = 32;
+
+// The order of the secp256k1 group
+const SECP256K1_ORDER: &str = "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141";
+
+/// Converts a signature returned by the token, in either raw (`r || s`) or DER form, to the
+/// compact form with a low `s` value.
+fn compact_signature(signature: &[u8]) -> Result<Vec<u8>, Error> {
+    let (r, s) = if signature.len() == 2 * SCALAR_SIZE {
+        (
+            BigNum::from_slice(&signature[..SCALAR_SIZE]).map_err(openssl_error)?,
+            BigNum::from_slice(&signature[SCALAR_SIZE..]).map_err(openssl_error)?,
+        )
+    } else {
+        let signature = EcdsaSig::from_der(signature).map_err(|err| {
+            Error::SigningError(format!("Token returned an invalid signature: {}", err))
+        })?;
+        (
+            signature.r().to_owned().map_err(openssl_error)?,
+            signature.s().to_owned().map_err(openssl_error)?,
+        )
+    };
+
+    let order = BigNum::from_hex_str(SECP256K1_ORDER).map_err(openssl_error)?;
+    let mut half_order = BigNum::new().map_err(openssl_error)?;
+    half_order.rshift1(&order).map_err(openssl_error)?;
+
+    let s = if s > half_order {
+        let mut low_s = BigNum::new().map_err(openssl_error)?;
+        low_s.checked_sub(&order, &s).map_err(openssl_error)?;
+        low_s
+    } else {
+        s
+    };
+
+    let mut compact = pad_scalar(&r)?;
+    compact.extend(pad_scalar(&s)?);
+    Ok(compact)
+}
+
+fn pad_scalar(scalar: &BigNum) -> Result<Vec<u8>, Error> {
+    let bytes = scalar.to_vec();
+    if bytes.len() > SCALAR_SIZE {
+        return Err(Error::SigningError(
+            "Token returned a signature scalar larger than the group order".to_string(),
+        ));
+    }
+    let mut padded = vec![0u8; SCALAR_SIZE - bytes.len()];
+    padded.extend(bytes);
+    Ok(padded)
+}
+
+fn openssl_error(err: openssl::error::ErrorStack) -> Error {
+    Error::SigningError(format!("{}", err))
+}
+
+fn missing_field(field: &str) -> Error {
+    Error::SigningError(format!("'{}' field is required", field))
+}
+
+fn pkcs11_error(err: pkcs11::errors::Error) -> Error {
+    Error::SigningError(format!("PKCS#11 error: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+
+    static R: &str = "6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296";
+    static HIGH_S: &str = "c0ae0d2b7e4d4d0eb7f6e2b1d2a1c4bd3a8c5d9f7e0f1a2b3c4d5e6f708192a3";
+    static LOW_S: &str = "3f51f2d481b2b2f148091d4e2d5e3b4180227f47313986108385001d5fb4ae9e";
+
+    fn decode(hex_parts: &[&str]) -> Vec<u8> {
+        hex::decode(hex_parts.concat()).unwrap()
+    }
+
+    #[test]
+    // test that a raw signature with a high s value is normalized, and a low s value is kept
+    fn raw_signature_normalization() {
+        assert_eq!(
+            decode(&[R, LOW_S]),
+            compact_signature(&decode(&[R, HIGH_S])).unwrap()
+        );
+        assert_eq!(
+            decode(&[R, LOW_S]),
+            compact_signature(&decode(&[R, LOW_S])).unwrap()
+        );
+    }
+
+    #[test]
+    // test that DER signatures are converted to the compact form, padding short scalars
+    fn der_signature_conversion() {
+        assert_eq!(
+            decode(&[R, LOW_S]),
+            compact_signature(&decode(&["30450220", R, "022100", HIGH_S])).unwrap()
+        );
+        assert_eq!(
+            decode(&[&"00".repeat(31), "01", LOW_S]),
+            compact_signature(&decode(&["3026020101022100", HIGH_S])).unwrap()
+        );
+
+        assert!(compact_signature(&[0x30, 0x02, 0x01, 0x00]).is_err());
+    }
+
+    #[test]
+    // test that normalized signatures made with a real key still verify
+    fn normalized_signature_verifies() {
+        let group = EcGroup::from_curve_name(Nid::SECP256K1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let digest = Sha256::digest(b"message");
+
+        let mut half_order = BigNum::new().unwrap();
+        half_order
+            .rshift1(&BigNum::from_hex_str(SECP256K1_ORDER).unwrap())
+            .unwrap();
+
+        for _ in 0..16 {
+            let der = EcdsaSig::sign(&digest, &key).unwrap().to_der().unwrap();
+            let compact = compact_signature(&der).unwrap();
+            assert_eq!(2 * SCALAR_SIZE, compact.len());
+
+            let s = BigNum::from_slice(&compact[SCALAR_SIZE..]).unwrap();
+            assert!(s <= half_order);
+
+            let signature = EcdsaSig::from_private_components(
+                BigNum::from_slice(&compact[..SCALAR_SIZE]).unwrap(),
+                s,
+            )
+            .unwrap();
+            assert!(signature.verify(&digest, &key).unwrap());
+        }
+    }
+}
@@ -0,0 +1,542 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Loading and saving of secp256k1 private keys.
+//!
+//! A `KeyStore` provides named private keys to applications which construct `Signer`s.  The
+//! `FileKeyStore` keeps each key in its own file in a directory, either as a hex-encoded private
+//! key (`<name>.priv`, the format used by Sawtooth) or as a PEM-encoded EC private key
+//! (`<name>.pem`), which may be encrypted with a passphrase.
+//!
+//! Key names must be plain file names; names which could refer to a file outside of the store's
+//! directory are rejected. Key files which are accessible by other users are refused rather than
+//! loaded. Private key material held in memory is zeroed when it is dropped.
+
+use std::error::Error as StdError;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint, PointConversionForm};
+use openssl::nid::Nid;
+use openssl::pkey::Private;
+use openssl::symm::Cipher;
+use zeroize::{Zeroize, Zeroizing};
+
+const PRIVATE_KEY_SIZE: usize = 32;
+
+#[derive(Debug)]
+pub enum KeyStoreError {
+    /// The key file could not be read or written.
+    IoError(String),
+    /// The key file does not contain a valid secp256k1 private key.
+    InvalidKey(String),
+    /// The key name cannot be used as a file name in the store.
+    InvalidName(String),
+    /// The key could not be encrypted or decrypted with the passphrase.
+    PassphraseError(String),
+    /// The key file is accessible by users other than its owner.
+    PermissionError(String),
+}
+
+impl StdError for KeyStoreError {
+    fn description(&self) -> &str {
+        match *self {
+            KeyStoreError::IoError(ref msg) => msg,
+            KeyStoreError::InvalidKey(ref msg) => msg,
+            KeyStoreError::InvalidName(ref msg) => msg,
+            KeyStoreError::PassphraseError(ref msg) => msg,
+            KeyStoreError::PermissionError(ref msg) => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for KeyStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            KeyStoreError::IoError(ref s) => write!(f, "IoError: {}", s),
+            KeyStoreError::InvalidKey(ref s) => write!(f, "InvalidKey: {}", s),
+            KeyStoreError::InvalidName(ref s) => write!(f, "InvalidName: {}", s),
+            KeyStoreError::PassphraseError(ref s) => write!(f, "PassphraseError: {}", s),
+            KeyStoreError::PermissionError(ref s) => write!(f, "PermissionError: {}", s),
+        }
+    }
+}
+
+/// A secp256k1 private key.
+///
+/// Private keys are deliberately not comparable, since a derived comparison would not run in
+/// constant time; compare the results of `public_key` instead.
+#[derive(Clone)]
+pub struct PrivateKey {
+    bytes: Vec<u8>,
+}
+
+impl PrivateKey {
+    /// Creates a private key from its 32-byte secret scalar.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, KeyStoreError> {
+        let key = PrivateKey {
+            bytes: bytes.to_vec(),
+        };
+        key.to_ec_key()?;
+        Ok(key)
+    }
+
+    /// Creates a private key from the hex encoding of its secret scalar.
+    pub fn from_hex(hex_key: &str) -> Result<Self, KeyStoreError> {
+        let bytes = Zeroizing::new(
+            hex::decode(hex_key.trim())
+                .map_err(|err| KeyStoreError::InvalidKey(format!("Invalid hex: {}", err)))?,
+        );
+        PrivateKey::from_bytes(&bytes)
+    }
+
+    /// Generates a new random private key.
+    pub fn generate() -> Result<Self, KeyStoreError> {
+        let group = secp256k1_group()?;
+        let ec_key = EcKey::generate(&group).map_err(invalid_key)?;
+        PrivateKey::from_ec_key(&ec_key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn as_hex(&self) -> String {
+        hex::encode(&self.bytes)
+    }
+
+    /// Returns the compressed (33-byte) public key corresponding to this private key.
+    pub fn public_key(&self) -> Result<Vec<u8>, KeyStoreError> {
+        let group = secp256k1_group()?;
+        let mut ctx = BigNumContext::new().map_err(invalid_key)?;
+        self.to_ec_key()?
+            .public_key()
+            .to_bytes(&group, PointConversionForm::COMPRESSED, &mut ctx)
+            .map_err(invalid_key)
+    }
+
+    fn from_ec_key(ec_key: &EcKey<Private>) -> Result<Self, KeyStoreError> {
+        if ec_key.group().curve_name() != Some(Nid::SECP256K1) {
+            return Err(KeyStoreError::InvalidKey(
+                "Key is not a secp256k1 key".to_string(),
+            ));
+        }
+
+        let bytes = Zeroizing::new(ec_key.private_key().to_vec());
+        if bytes.len() > PRIVATE_KEY_SIZE {
+            return Err(KeyStoreError::InvalidKey(
+                "Private key is too large".to_string(),
+            ));
+        }
+        // Restore any leading zero bytes dropped by the big number encoding
+        let mut padded = vec![0u8; PRIVATE_KEY_SIZE];
+        padded[PRIVATE_KEY_SIZE - bytes.len()..].copy_from_slice(&bytes);
+
+        Ok(PrivateKey { bytes: padded })
+    }
+
+    fn to_ec_key(&self) -> Result<EcKey<Private>, KeyStoreError> {
+        if self.bytes.len() != PRIVATE_KEY_SIZE {
+            return Err(KeyStoreError::InvalidKey(format!(
+                "Private key must be {} bytes",
+                PRIVATE_KEY_SIZE
+            )));
+        }
+
+        let group = secp256k1_group()?;
+        let ctx = BigNumContext::new().map_err(invalid_key)?;
+        let private_number = BigNum::from_slice(&self.bytes).map_err(invalid_key)?;
+        let mut public_point = EcPoint::new(&group).map_err(invalid_key)?;
+        public_point
+            .mul_generator(&group, &private_number, &ctx)
+            .map_err(invalid_key)?;
+
+        let ec_key = EcKey::from_private_components(&group, &private_number, &public_point)
+            .map_err(invalid_key)?;
+        ec_key.check_key().map_err(invalid_key)?;
+        Ok(ec_key)
+    }
+}
+
+impl Drop for PrivateKey {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
+// Private keys are deliberately left out of debug output
+impl std::fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PrivateKey(..)")
+    }
+}
+
+/// Provides access to named private keys.
+pub trait KeyStore {
+    /// Loads the key with the given name.
+    fn load_key(&self, name: &str) -> Result<PrivateKey, KeyStoreError>;
+
+    /// Saves the key under the given name, replacing any existing key with that name.
+    fn save_key(&self, name: &str, key: &PrivateKey) -> Result<(), KeyStoreError>;
+}
+
+/// The file formats supported by the `FileKeyStore`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyFormat {
+    /// A hex-encoded private key, stored in `<name>.priv`.
+    Hex,
+    /// A PEM-encoded EC private key, stored in `<name>.pem`.
+    Pem,
+}
+
+/// A `KeyStore` which keeps each key in a file in a directory.
+#[derive(Clone)]
+pub struct FileKeyStore {
+    directory: PathBuf,
+    format: KeyFormat,
+    passphrase: Option<String>,
+}
+
+impl FileKeyStore {
+    /// Creates a key store for hex key files in the given directory.
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        FileKeyStore {
+            directory: directory.as_ref().to_path_buf(),
+            format: KeyFormat::Hex,
+            passphrase: None,
+        }
+    }
+
+    pub fn with_format(mut self, format: KeyFormat) -> FileKeyStore {
+        self.format = format;
+        self
+    }
+
+    /// Sets the passphrase used to encrypt and decrypt keys.
+    ///
+    /// Only PEM key files may be encrypted.
+    pub fn with_passphrase(mut self, passphrase: &str) -> FileKeyStore {
+        self.passphrase = Some(passphrase.to_string());
+        self
+    }
+
+    /// Returns the path of the file holding the key with the given name.
+    ///
+    /// Returns an `InvalidName` error if the name is empty or contains a path separator, `..`,
+    /// or a NUL byte, since such a name could refer to a file outside of the store's directory.
+    pub fn key_path(&self, name: &str) -> Result<PathBuf, KeyStoreError> {
+        if name.is_empty()
+            || name.contains('/')
+            || name.contains('\\')
+            || name.contains("..")
+            || name.contains('\0')
+        {
+            return Err(KeyStoreError::InvalidName(format!(
+                "{:?} is not a valid key name",
+                name
+            )));
+        }
+
+        let extension = match self.format {
+            KeyFormat::Hex => "priv",
+            KeyFormat::Pem => "pem",
+        };
+        Ok(self.directory.join(format!("{}.{}", name, extension)))
+    }
+
+    fn encode(&self, key: &PrivateKey) -> Result<Zeroizing<Vec<u8>>, KeyStoreError> {
+        let encoded = match (self.format, &self.passphrase) {
+            (KeyFormat::Hex, None) => {
+                let hex_key = Zeroizing::new(key.as_hex());
+                let mut encoded = Vec::with_capacity(hex_key.len() + 1);
+                encoded.extend_from_slice(hex_key.as_bytes());
+                encoded.push(b'\n');
+                encoded
+            }
+            (KeyFormat::Hex, Some(_)) => {
+                return Err(KeyStoreError::PassphraseError(
+                    "Hex key files cannot be encrypted".to_string(),
+                ))
+            }
+            (KeyFormat::Pem, None) => key.to_ec_key()?.private_key_to_pem().map_err(invalid_key)?,
+            (KeyFormat::Pem, Some(passphrase)) => key
+                .to_ec_key()?
+                .private_key_to_pem_passphrase(Cipher::aes_256_cbc(), passphrase.as_bytes())
+                .map_err(|err| KeyStoreError::PassphraseError(format!("{}", err)))?,
+        };
+        Ok(Zeroizing::new(encoded))
+    }
+
+    fn decode(&self, contents: &[u8]) -> Result<PrivateKey, KeyStoreError> {
+        match (self.format, &self.passphrase) {
+            (KeyFormat::Hex, None) => {
+                let hex_key = std::str::from_utf8(contents)
+                    .map_err(|err| KeyStoreError::InvalidKey(format!("{}", err)))?;
+                PrivateKey::from_hex(hex_key)
+            }
+            (KeyFormat::Hex, Some(_)) => Err(KeyStoreError::PassphraseError(
+                "Hex key files cannot be encrypted".to_string(),
+            )),
+            (KeyFormat::Pem, None) => PrivateKey::from_ec_key(
+                &EcKey::private_key_from_pem(contents).map_err(invalid_key)?,
+            ),
+            (KeyFormat::Pem, Some(passphrase)) => PrivateKey::from_ec_key(
+                &EcKey::private_key_from_pem_passphrase(contents, passphrase.as_bytes())
+                    .map_err(|err| KeyStoreError::PassphraseError(format!("{}", err)))?,
+            ),
+        }
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn load_key(&self, name: &str) -> Result<PrivateKey, KeyStoreError> {
+        let path = self.key_path(name)?;
+        check_permissions(&path)?;
+        let contents = Zeroizing::new(fs::read(&path).map_err(|err| {
+            KeyStoreError::IoError(format!("Unable to read {}: {}", path.display(), err))
+        })?);
+        self.decode(&contents)
+    }
+
+    fn save_key(&self, name: &str, key: &PrivateKey) -> Result<(), KeyStoreError> {
+        let path = self.key_path(name)?;
+        let contents = self.encode(key)?;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        // The mode only applies to newly created files, so an existing file is restricted
+        // before the key is written to it
+        if path.exists() {
+            restrict_permissions(&path)?;
+        }
+
+        options
+            .open(&path)
+            .and_then(|mut file| file.write_all(&contents))
+            .map_err(|err| {
+                KeyStoreError::IoError(format!("Unable to write {}: {}", path.display(), err))
+            })
+    }
+}
+
+/// Removes any group or other access to the key file at the given path.
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> Result<(), KeyStoreError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let io_error = |err: std::io::Error| {
+        KeyStoreError::IoError(format!(
+            "Unable to set permissions of {}: {}",
+            path.display(),
+            err
+        ))
+    };
+
+    let mut permissions = fs::metadata(path).map_err(io_error)?.permissions();
+    if permissions.mode() & 0o077 != 0 {
+        warn!(
+            "Key file {} is accessible by other users; restricting its permissions",
+            path.display()
+        );
+        permissions.set_mode(permissions.mode() & 0o700);
+        fs::set_permissions(path, permissions).map_err(io_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> Result<(), KeyStoreError> {
+    Ok(())
+}
+
+/// Returns a `PermissionError` if the key file at the given path is accessible by group or other
+/// users.
+///
+/// The file is left unchanged, since its permissions are not the store's to correct when it is
+/// only being read.
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), KeyStoreError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)
+        .map_err(|err| {
+            KeyStoreError::IoError(format!("Unable to read {}: {}", path.display(), err))
+        })?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        return Err(KeyStoreError::PermissionError(format!(
+            "Key file {} is accessible by other users (mode {:o}); restrict it to its owner",
+            path.display(),
+            mode & 0o777
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), KeyStoreError> {
+    Ok(())
+}
+
+fn secp256k1_group() -> Result<EcGroup, KeyStoreError> {
+    EcGroup::from_curve_name(Nid::SECP256K1).map_err(invalid_key)
+}
+
+fn invalid_key(err: openssl::error::ErrorStack) -> KeyStoreError {
+    KeyStoreError::InvalidKey(format!("{}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::thread;
+
+    static PRIVATE_KEY: &str = "2f1e7b7a130d7ba9da0068b3bb0ba1d79e7e77110302c9f746c3c2a63fe40088";
+
+    fn run_test<T>(test: T)
+    where
+        T: FnOnce(&Path),
+    {
+        let mut dir = env::temp_dir();
+        dir.push(format!("key-store-{:?}", thread::current().id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        test(&dir);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn private_key_hex() {
+        let key = PrivateKey::from_hex(PRIVATE_KEY).unwrap();
+        assert_eq!(PRIVATE_KEY, key.as_hex());
+        assert_eq!(33, key.public_key().unwrap().len());
+
+        assert!(PrivateKey::from_hex("0102").is_err());
+        assert!(PrivateKey::from_bytes(&[0u8; 32]).is_err());
+    }
+
+    #[test]
+    fn file_key_store_hex() {
+        run_test(|dir| {
+            let store = FileKeyStore::new(dir);
+            let key = PrivateKey::from_hex(PRIVATE_KEY).unwrap();
+
+            store.save_key("alice", &key).unwrap();
+            assert!(dir.join("alice.priv").exists());
+            assert_eq!(key.as_bytes(), store.load_key("alice").unwrap().as_bytes());
+
+            match store.load_key("bob") {
+                Err(KeyStoreError::IoError(_)) => (),
+                res => panic!("Expected IoError, got {:?}", res),
+            }
+        })
+    }
+
+    #[test]
+    // test that PEM keys round trip, and that encrypted keys require the passphrase
+    fn file_key_store_pem() {
+        run_test(|dir| {
+            let key = PrivateKey::generate().unwrap();
+
+            let store = FileKeyStore::new(dir).with_format(KeyFormat::Pem);
+            store.save_key("plain", &key).unwrap();
+            assert_eq!(key.as_bytes(), store.load_key("plain").unwrap().as_bytes());
+
+            let encrypted = store.clone().with_passphrase("secret");
+            encrypted.save_key("encrypted", &key).unwrap();
+            assert_eq!(
+                key.as_bytes(),
+                encrypted.load_key("encrypted").unwrap().as_bytes()
+            );
+
+            match store.clone().with_passphrase("wrong").load_key("encrypted") {
+                Err(KeyStoreError::PassphraseError(_)) => (),
+                res => panic!("Expected PassphraseError, got {:?}", res),
+            }
+        })
+    }
+
+    #[test]
+    // test that names which could escape the store's directory are rejected
+    fn file_key_store_invalid_names() {
+        run_test(|dir| {
+            let store = FileKeyStore::new(dir.join("keys"));
+            let key = PrivateKey::from_hex(PRIVATE_KEY).unwrap();
+            fs::create_dir_all(dir.join("keys")).unwrap();
+
+            for name in &[
+                "",
+                "../alice",
+                "/tmp/alice",
+                "keys/alice",
+                "..\\alice",
+                "al\0ice",
+            ] {
+                match store.save_key(name, &key) {
+                    Err(KeyStoreError::InvalidName(_)) => (),
+                    res => panic!("Expected InvalidName for {:?}, got {:?}", name, res),
+                }
+                match store.load_key(name) {
+                    Err(KeyStoreError::InvalidName(_)) => (),
+                    res => panic!("Expected InvalidName for {:?}, got {:?}", name, res),
+                }
+            }
+            assert!(!dir.join("alice.priv").exists());
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    // test that key files readable by other users are refused on load and restricted on save
+    fn file_key_store_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        run_test(|dir| {
+            let store = FileKeyStore::new(dir);
+            let key = PrivateKey::from_hex(PRIVATE_KEY).unwrap();
+            let mode =
+                |name: &str| fs::metadata(dir.join(name)).unwrap().permissions().mode() & 0o777;
+
+            store.save_key("alice", &key).unwrap();
+            assert_eq!(0o600, mode("alice.priv"));
+
+            fs::set_permissions(dir.join("alice.priv"), fs::Permissions::from_mode(0o644)).unwrap();
+            match store.load_key("alice") {
+                Err(KeyStoreError::PermissionError(_)) => (),
+                res => panic!("Expected PermissionError, got {:?}", res),
+            }
+            assert_eq!(0o644, mode("alice.priv"));
+
+            fs::write(dir.join("bob.priv"), b"").unwrap();
+            fs::set_permissions(dir.join("bob.priv"), fs::Permissions::from_mode(0o666)).unwrap();
+            store.save_key("bob", &key).unwrap();
+            assert_eq!(0o600, mode("bob.priv"));
+        })
+    }
+}
@@ -17,12 +17,114 @@
 
 //! Simple traits for signing transactions.
 
+#[cfg(feature = "async")]
+pub mod async_signer;
+#[cfg(feature = "ed25519")]
+pub mod ed25519;
 pub mod error;
 pub mod hash;
+#[cfg(feature = "pkcs11")]
+pub mod hsm;
+pub mod key_store;
+
+use std::collections::HashMap;
 
 pub use crate::signing::error::Error;
 
+/// The signature algorithms known to transact.
+///
+/// The algorithm used to sign a transaction or batch header is recorded in the header, so that
+/// verifiers can select the matching `SignatureVerifier`.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub enum SignatureAlgorithm {
+    /// ECDSA over secp256k1, as used by Sawtooth.  This is the default algorithm.
+    Secp256k1,
+    /// EdDSA over Curve25519.
+    Ed25519,
+}
+
+impl SignatureAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignatureAlgorithm::Secp256k1 => "secp256k1",
+            SignatureAlgorithm::Ed25519 => "ed25519",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SignatureAlgorithm> {
+        match name {
+            "secp256k1" => Some(SignatureAlgorithm::Secp256k1),
+            "ed25519" => Some(SignatureAlgorithm::Ed25519),
+            _ => None,
+        }
+    }
+}
+
+impl Default for SignatureAlgorithm {
+    fn default() -> Self {
+        SignatureAlgorithm::Secp256k1
+    }
+}
+
 pub trait Signer {
     fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error>;
     fn public_key(&self) -> &[u8];
+
+    /// The algorithm of the signatures produced by this signer.
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::Secp256k1
+    }
+}
+
+/// Verifies signatures produced by a corresponding `Signer`.
+pub trait SignatureVerifier {
+    /// Returns true if `signature` is a valid signature of `message` by `public_key`.
+    fn verify(&self, message: &[u8], signature: &[u8], public_key: &[u8]) -> Result<bool, Error>;
+
+    /// The algorithm of the signatures checked by this verifier.
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::Secp256k1
+    }
+}
+
+/// Selects the verifier to use for a given signature algorithm.
+///
+/// Every `SignatureVerifier` selects itself for its own algorithm; a `VerifierRegistry` may be
+/// used where objects signed with several algorithms must be verified.
+pub trait VerifierSelector {
+    fn select(&self, algorithm: SignatureAlgorithm) -> Option<&dyn SignatureVerifier>;
+}
+
+impl<V: SignatureVerifier> VerifierSelector for V {
+    fn select(&self, algorithm: SignatureAlgorithm) -> Option<&dyn SignatureVerifier> {
+        if self.algorithm() == algorithm {
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+/// A set of verifiers, one per signature algorithm.
+#[derive(Default)]
+pub struct VerifierRegistry {
+    verifiers: HashMap<SignatureAlgorithm, Box<dyn SignatureVerifier>>,
+}
+
+impl VerifierRegistry {
+    pub fn new() -> Self {
+        VerifierRegistry::default()
+    }
+
+    /// Adds a verifier, replacing any existing verifier for the same algorithm.
+    pub fn with_verifier(mut self, verifier: Box<dyn SignatureVerifier>) -> VerifierRegistry {
+        self.verifiers.insert(verifier.algorithm(), verifier);
+        self
+    }
+}
+
+impl VerifierSelector for VerifierRegistry {
+    fn select(&self, algorithm: SignatureAlgorithm) -> Option<&dyn SignatureVerifier> {
+        self.verifiers.get(&algorithm).map(|verifier| &**verifier)
+    }
 }
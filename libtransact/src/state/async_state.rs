@@ -0,0 +1,497 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! State reads and writes that complete asynchronously.
+//!
+//! Reading or writing state may wait on disk or on a remote database, and doing so on an
+//! executor's thread stalls every other task scheduled on it.  `AsyncRead` and `AsyncWrite` are
+//! the asynchronous counterparts of `Read` and `Write`, returning futures of their results.  A
+//! synchronous backend may be used wherever they are expected by wrapping it in a `SyncState`,
+//! which runs its operations on a pool of worker threads.
+//!
+//! This module is only available with the `async` feature.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use super::{Read, StateChange, StateReadError, StateWriteError, Write};
+
+/// The future of the result of an asynchronous state operation.
+pub type StateFuture<'a, T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'a>>;
+
+/// The asynchronous counterpart of `state::Read`.
+pub trait AsyncRead: Send + Sync {
+    /// A reference to a checkpoint in state. It could be a merkle hash for
+    /// a merkle database.
+    type StateId;
+    /// The Key that is being stored in state.
+    type Key;
+    /// The Value that is being stored in state.
+    type Value;
+
+    /// At a given `StateId`, attempt to retrieve the given slice of keys.
+    ///
+    /// As with `Read::get`, only keys that were found will be in the resulting map.
+    fn get<'a>(
+        &'a self,
+        state_id: &'a Self::StateId,
+        keys: &'a [Self::Key],
+    ) -> StateFuture<'a, HashMap<Self::Key, Self::Value>, StateReadError>;
+}
+
+/// The asynchronous counterpart of `state::Write`.
+pub trait AsyncWrite: Send + Sync {
+    /// A reference to a checkpoint in state. It could be a merkle hash for
+    /// a merkle database.
+    type StateId;
+    /// The Key that is being stored in state.
+    type Key;
+    /// The Value that is being stored in state.
+    type Value;
+
+    /// Given a `StateId` and a slice of `StateChange` values, persist the
+    /// state changes and return the resulting next `StateId` value.
+    fn commit<'a>(
+        &'a self,
+        state_id: &'a Self::StateId,
+        state_changes: &'a [StateChange],
+    ) -> StateFuture<'a, Self::StateId, StateWriteError>;
+
+    /// Given a `StateId` and a slice of `StateChange` values, compute the
+    /// next `StateId` value, without persisting the state changes.
+    fn compute_state_id<'a>(
+        &'a self,
+        state_id: &'a Self::StateId,
+        state_changes: &'a [StateChange],
+    ) -> StateFuture<'a, Self::StateId, StateWriteError>;
+}
+
+/// Adapts a synchronous state backend to the `AsyncRead` and `AsyncWrite` traits.
+///
+/// Each operation runs on one of the adapter's worker threads, against a clone of the backend, so
+/// awaiting it never blocks the awaiting thread.  Clones of a `SyncState` share its workers.
+#[derive(Clone)]
+pub struct SyncState<S> {
+    state: S,
+    workers: Arc<WorkerPool>,
+}
+
+impl<S: Clone + Send + Sync + 'static> SyncState<S> {
+    /// Wraps the given backend, starting the given number of worker threads (at least one).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a worker thread could not be started.
+    pub fn new(state: S, worker_threads: usize) -> io::Result<Self> {
+        Ok(SyncState {
+            state,
+            workers: Arc::new(WorkerPool::start(worker_threads.max(1))?),
+        })
+    }
+
+    /// Returns the wrapped backend.
+    pub fn inner(&self) -> &S {
+        &self.state
+    }
+
+    /// Runs the given operation against a clone of the backend on a worker thread.
+    fn run<T, F>(&self, operation: F) -> Completion<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(S) -> T + Send + 'static,
+    {
+        let (completer, completion) = Completion::new();
+        let state = self.state.clone();
+        // If the pool has stopped, the job and its completer are dropped here, failing the
+        // completion.
+        self.workers.execute(Box::new(move || {
+            completer.complete(operation(state));
+        }));
+        completion
+    }
+}
+
+impl<S> AsyncRead for SyncState<S>
+where
+    S: Read + Clone + Send + Sync + 'static,
+    S::StateId: Clone + Send + Sync + 'static,
+    S::Key: Clone + Send + Sync + 'static,
+    S::Value: Send + 'static,
+{
+    type StateId = S::StateId;
+    type Key = S::Key;
+    type Value = S::Value;
+
+    fn get<'a>(
+        &'a self,
+        state_id: &'a Self::StateId,
+        keys: &'a [Self::Key],
+    ) -> StateFuture<'a, HashMap<Self::Key, Self::Value>, StateReadError> {
+        let state_id = state_id.clone();
+        let keys = keys.to_vec();
+        let completion = self.run(move |state| {
+            state
+                .get(&state_id, &keys)
+                .map_err(TransferredError::from_read_error)
+        });
+        Box::pin(async move {
+            match completion.await {
+                Some(result) => result.map_err(TransferredError::into_read_error),
+                None => Err(StateReadError::StorageError(Box::new(WorkerStopped))),
+            }
+        })
+    }
+}
+
+impl<S> AsyncWrite for SyncState<S>
+where
+    S: Write + Clone + Send + Sync + 'static,
+    S::StateId: Clone + Send + Sync + 'static,
+{
+    type StateId = S::StateId;
+    type Key = S::Key;
+    type Value = S::Value;
+
+    fn commit<'a>(
+        &'a self,
+        state_id: &'a Self::StateId,
+        state_changes: &'a [StateChange],
+    ) -> StateFuture<'a, Self::StateId, StateWriteError> {
+        let state_id = state_id.clone();
+        let state_changes = state_changes.to_vec();
+        let completion = self.run(move |state| {
+            state
+                .commit(&state_id, &state_changes)
+                .map_err(TransferredError::from_write_error)
+        });
+        write_result(completion)
+    }
+
+    fn compute_state_id<'a>(
+        &'a self,
+        state_id: &'a Self::StateId,
+        state_changes: &'a [StateChange],
+    ) -> StateFuture<'a, Self::StateId, StateWriteError> {
+        let state_id = state_id.clone();
+        let state_changes = state_changes.to_vec();
+        let completion = self.run(move |state| {
+            state
+                .compute_state_id(&state_id, &state_changes)
+                .map_err(TransferredError::from_write_error)
+        });
+        write_result(completion)
+    }
+}
+
+fn write_result<'a, T: Send + 'a>(
+    completion: Completion<Result<T, TransferredError>>,
+) -> StateFuture<'a, T, StateWriteError> {
+    Box::pin(async move {
+        match completion.await {
+            Some(result) => result.map_err(TransferredError::into_write_error),
+            None => Err(StateWriteError::StorageError(Box::new(WorkerStopped))),
+        }
+    })
+}
+
+/// A state error in a form that may be sent between threads.
+///
+/// Storage errors are boxed without a `Send` bound, so they are carried as their messages.
+enum TransferredError {
+    InvalidStateId(String),
+    InvalidKey(String),
+    Storage(String),
+    Unsupported(String),
+}
+
+impl TransferredError {
+    fn from_read_error(err: StateReadError) -> Self {
+        match err {
+            StateReadError::InvalidStateId(msg) => TransferredError::InvalidStateId(msg),
+            StateReadError::InvalidKey(msg) => TransferredError::InvalidKey(msg),
+            StateReadError::StorageError(err) => TransferredError::Storage(err.to_string()),
+            StateReadError::Unsupported(msg) => TransferredError::Unsupported(msg),
+        }
+    }
+
+    fn from_write_error(err: StateWriteError) -> Self {
+        match err {
+            StateWriteError::InvalidStateId(msg) => TransferredError::InvalidStateId(msg),
+            StateWriteError::StorageError(err) => TransferredError::Storage(err.to_string()),
+        }
+    }
+
+    fn into_read_error(self) -> StateReadError {
+        match self {
+            TransferredError::InvalidStateId(msg) => StateReadError::InvalidStateId(msg),
+            TransferredError::InvalidKey(msg) => StateReadError::InvalidKey(msg),
+            TransferredError::Storage(msg) => {
+                StateReadError::StorageError(Box::new(StorageErrorMessage(msg)))
+            }
+            TransferredError::Unsupported(msg) => StateReadError::Unsupported(msg),
+        }
+    }
+
+    fn into_write_error(self) -> StateWriteError {
+        match self {
+            TransferredError::InvalidStateId(msg) => StateWriteError::InvalidStateId(msg),
+            TransferredError::InvalidKey(msg)
+            | TransferredError::Storage(msg)
+            | TransferredError::Unsupported(msg) => {
+                StateWriteError::StorageError(Box::new(StorageErrorMessage(msg)))
+            }
+        }
+    }
+}
+
+/// A storage error raised on a worker thread, by its message.
+#[derive(Debug)]
+struct StorageErrorMessage(String);
+
+impl fmt::Display for StorageErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for StorageErrorMessage {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The worker running an operation stopped before completing it.
+#[derive(Debug)]
+struct WorkerStopped;
+
+impl fmt::Display for WorkerStopped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("The state operation's worker thread stopped before completing it")
+    }
+}
+
+impl Error for WorkerStopped {
+    fn description(&self) -> &str {
+        "The state operation's worker thread stopped before completing it"
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed pool of threads running jobs in the order they are submitted.
+///
+/// The threads exit once the pool is dropped and the submitted jobs have run.
+struct WorkerPool {
+    sender: Mutex<Sender<Job>>,
+}
+
+impl WorkerPool {
+    fn start(threads: usize) -> io::Result<Self> {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads {
+            let receiver = Arc::clone(&receiver);
+            thread::Builder::new()
+                .name(format!("StateWorker-{}", i))
+                .spawn(move || run_jobs(&receiver))?;
+        }
+
+        Ok(WorkerPool {
+            sender: Mutex::new(sender),
+        })
+    }
+
+    fn execute(&self, job: Job) {
+        if let Ok(sender) = self.sender.lock() {
+            let _ = sender.send(job);
+        }
+    }
+}
+
+fn run_jobs(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        match job {
+            // A panicking job fails its own completion, and the worker carries on.
+            Ok(job) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+struct Slot<T> {
+    result: Option<T>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// Completes a `Completion` with the result of a job.  If dropped without completing, the
+/// completion resolves to `None`.
+struct Completer<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Completer<T> {
+    fn complete(self, result: T) {
+        if let Ok(mut slot) = self.slot.lock() {
+            slot.result = Some(result);
+        }
+        // Dropping self closes the slot and wakes the task awaiting it
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let waker = match self.slot.lock() {
+            Ok(mut slot) => {
+                slot.closed = true;
+                slot.waker.take()
+            }
+            Err(_) => None,
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// The future of the result of a job, or `None` if the job failed to complete.
+struct Completion<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Completion<T> {
+    fn new() -> (Completer<T>, Self) {
+        let slot = Arc::new(Mutex::new(Slot {
+            result: None,
+            closed: false,
+            waker: None,
+        }));
+        (
+            Completer {
+                slot: Arc::clone(&slot),
+            },
+            Completion { slot },
+        )
+    }
+}
+
+impl<T> Future for Completion<T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut slot = match self.slot.lock() {
+            Ok(slot) => slot,
+            Err(_) => return Poll::Ready(None),
+        };
+        if let Some(result) = slot.result.take() {
+            Poll::Ready(Some(result))
+        } else if slot.closed {
+            Poll::Ready(None)
+        } else {
+            slot.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::block_on;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleRadixTree, MerkleState, INDEXES};
+
+    fn make_state() -> (SyncState<MerkleState>, String) {
+        let db = BTreeDatabase::new(&INDEXES);
+        let initial_root = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let state = SyncState::new(MerkleState::new(Box::new(db)), 2).unwrap();
+        (state, initial_root)
+    }
+
+    #[test]
+    // test that writes and reads through the adapter match those of the wrapped backend
+    fn sync_state_adapter() {
+        let (state, initial_root) = make_state();
+        let changes = vec![StateChange::Set {
+            key: "ab0000".into(),
+            value: b"value".to_vec(),
+        }];
+
+        let computed = block_on(state.compute_state_id(&initial_root, &changes)).unwrap();
+        let root = block_on(state.commit(&initial_root, &changes)).unwrap();
+        assert_eq!(computed, root);
+        assert_eq!(
+            state
+                .inner()
+                .compute_state_id(&initial_root, &changes)
+                .unwrap(),
+            root
+        );
+
+        let keys = vec!["ab0000".to_string(), "cd0000".to_string()];
+        let values = block_on(state.clone().get(&root, &keys)).unwrap();
+        assert_eq!(values, state.inner().get(&root, &keys).unwrap());
+        assert_eq!(values.get("ab0000"), Some(&b"value".to_vec()));
+        assert!(!values.contains_key("cd0000"));
+    }
+
+    #[test]
+    // test that errors of the wrapped backend are returned through the adapter
+    fn sync_state_errors() {
+        let (state, _) = make_state();
+        let missing_root = "00".repeat(32);
+
+        match block_on(state.get(&missing_root, &["ab0000".to_string()])) {
+            Err(StateReadError::StorageError(_)) | Err(StateReadError::InvalidStateId(_)) => (),
+            res => panic!("Expected an error, got {:?}", res),
+        }
+        match block_on(state.commit(&missing_root, &[])) {
+            Err(StateWriteError::StorageError(_)) | Err(StateWriteError::InvalidStateId(_)) => (),
+            res => panic!("Expected an error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    // test that a panicking operation fails its own future without stopping the workers
+    fn panicking_operation() {
+        let (state, initial_root) = make_state();
+        let completion = state.run::<(), _>(|_| panic!("operation failed"));
+        assert_eq!(block_on(completion), None);
+
+        let values = block_on(state.get(&initial_root, &["ab0000".to_string()])).unwrap();
+        assert!(values.is_empty());
+    }
+}
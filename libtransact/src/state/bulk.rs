@@ -0,0 +1,260 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Bulk loading of merkle state, such as for genesis.
+//!
+//! Committing entries one change set at a time reads and rewrites the path from the root for
+//! each address.  A `BulkLoader` instead takes entries in address order and builds the tree from
+//! the bottom up: a node is encoded and hashed once, as soon as no later address can fall under
+//! it, so each node is written exactly once.  The written nodes are gathered into batches, each
+//! written in a single database transaction.
+//!
+//! The loaded tree is recorded in the change log as a single commit from the empty tree.  Nodes
+//! written by a load that does not finish are not referenced by any state root, and may be
+//! removed by garbage collection; see `gc`.
+
+use crate::database::Database;
+
+use super::compression::ValueCompression;
+use super::hash_provider::HashProvider;
+use super::merkle::{
+    encode_and_hash, put_nodes, record_commit, MerkleRadixTree, Node, StateDatabaseError,
+    TOKEN_SIZE,
+};
+
+/// The number of nodes a `BulkLoader` writes per transaction, unless configured otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 100_000;
+
+/// Builds a new tree from entries given in address order.
+pub struct BulkLoader {
+    db: Box<dyn Database>,
+    hash_provider: HashProvider,
+    compression: Option<ValueCompression>,
+    empty_root: Vec<u8>,
+    batch_size: usize,
+    // The nodes on the path to the last address added, from the root down, with their paths
+    open: Vec<(String, Node)>,
+    // The encoded nodes that have not yet been written, with their hashes
+    batch: Vec<(Vec<u8>, Vec<u8>)>,
+    // The hashes of the nodes that have been written
+    additions: Vec<Vec<u8>>,
+    last_address: Option<String>,
+}
+
+impl BulkLoader {
+    /// Constructs a loader over the given database, initializing it with an empty tree if it has
+    /// none.
+    pub fn new(db: Box<dyn Database>) -> Result<Self, StateDatabaseError> {
+        let empty_tree = MerkleRadixTree::new(db.clone(), None)?;
+        Ok(BulkLoader {
+            db,
+            hash_provider: empty_tree.hash_provider(),
+            compression: empty_tree.value_compression(),
+            // We expect this to be hex, since we generated it
+            empty_root: ::hex::decode(empty_tree.get_merkle_root()).expect("Improper hex"),
+            batch_size: DEFAULT_BATCH_SIZE,
+            open: vec![(String::new(), Node::default())],
+            batch: vec![],
+            additions: vec![],
+            last_address: None,
+        })
+    }
+
+    /// Sets the number of nodes written per transaction.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Adds the value at the given address.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidAddress` if the address is not a whole number of tokens, or does not
+    /// follow the previous address in order.
+    pub fn add(&mut self, address: &str, value: Vec<u8>) -> Result<(), StateDatabaseError> {
+        if address.is_empty() || !address.is_ascii() || address.len() % TOKEN_SIZE != 0 {
+            return Err(StateDatabaseError::InvalidAddress(format!(
+                "{} is not a whole number of tokens",
+                address
+            )));
+        }
+        if let Some(last_address) = &self.last_address {
+            if address <= last_address.as_str() {
+                return Err(StateDatabaseError::InvalidAddress(format!(
+                    "{} does not follow {}",
+                    address, last_address
+                )));
+            }
+        }
+
+        // No later address can fall under the nodes that are not on the path to this one
+        while !address.starts_with(self.open_path()) {
+            self.close_node()?;
+        }
+        let mut end = self.open_path().len();
+        while end < address.len() {
+            end += TOKEN_SIZE;
+            self.open
+                .push((address[..end].to_string(), Node::default()));
+        }
+        if let Some((_, node)) = self.open.last_mut() {
+            node.value = Some(value);
+        }
+
+        self.last_address = Some(address.to_string());
+        Ok(())
+    }
+
+    /// Adds the given entries, which must be in address order, and writes the tree, returning
+    /// its state root.
+    pub fn load<I>(mut self, entries: I) -> Result<String, StateDatabaseError>
+    where
+        I: IntoIterator<Item = (String, Vec<u8>)>,
+    {
+        for (address, value) in entries {
+            self.add(&address, value)?;
+        }
+        self.finish()
+    }
+
+    /// Writes the remaining nodes and the change log entry of the tree, and returns its state
+    /// root.
+    ///
+    /// If no entries were added, this is the root of the empty tree.
+    pub fn finish(mut self) -> Result<String, StateDatabaseError> {
+        if self.last_address.is_none() {
+            return Ok(::hex::encode(&self.empty_root));
+        }
+
+        while self.open.len() > 1 {
+            self.close_node()?;
+        }
+        let (_, root) = self.open.pop().expect("The root node is always open");
+        let (root_hash, packed) = encode_and_hash(root, self.hash_provider, self.compression)?;
+        self.batch.push((root_hash.clone(), packed));
+
+        let mut db_writer = self.db.get_writer()?;
+        put_nodes(&mut *db_writer, &self.batch)?;
+        self.additions
+            .extend(self.batch.drain(..).map(|(hash, _)| hash));
+        record_commit(
+            &mut *db_writer,
+            &self.empty_root,
+            &root_hash,
+            self.additions,
+            &[],
+        )?;
+        db_writer.commit()?;
+
+        Ok(::hex::encode(root_hash))
+    }
+
+    fn open_path(&self) -> &str {
+        self.open
+            .last()
+            .map(|(path, _)| path.as_str())
+            .expect("The root node is always open")
+    }
+
+    /// Encodes the deepest open node and adds it to its parent and to the batch, writing the
+    /// batch if it is full.
+    fn close_node(&mut self) -> Result<(), StateDatabaseError> {
+        let (path, node) = self.open.pop().expect("The root node is always open");
+        let (hash, packed) = encode_and_hash(node, self.hash_provider, self.compression)?;
+        let (parent_path, parent) = self.open.last_mut().expect("The root node is never closed");
+        parent
+            .children
+            .insert(path[parent_path.len()..].to_string(), ::hex::encode(&hash));
+        self.batch.push((hash, packed));
+
+        if self.batch.len() >= self.batch_size {
+            let mut db_writer = self.db.get_writer()?;
+            put_nodes(&mut *db_writer, &self.batch)?;
+            db_writer.commit()?;
+            self.additions
+                .extend(self.batch.drain(..).map(|(hash, _)| hash));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleState, INDEXES};
+    use crate::state::{Read, StateChange, Write};
+
+    /// Verifies that a bulk load produces the same tree as committing the same entries, and that
+    /// entries out of order are rejected.
+    #[test]
+    fn bulk_load_matches_commit() {
+        let entries: Vec<(String, Vec<u8>)> = vec![
+            ("ab".into(), b"interior".to_vec()),
+            ("ab0000".into(), b"same".to_vec()),
+            ("ab0001".into(), b"same".to_vec()),
+            ("ab01ff".into(), b"other".to_vec()),
+            ("cd0000".into(), b"last".to_vec()),
+        ];
+
+        let committed_db = BTreeDatabase::new(&INDEXES);
+        let empty_root = MerkleRadixTree::new(Box::new(committed_db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let committed_root = MerkleState::new(Box::new(committed_db))
+            .commit(
+                &empty_root,
+                &entries
+                    .iter()
+                    .map(|(key, value)| StateChange::Set {
+                        key: key.clone(),
+                        value: value.clone(),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap();
+
+        let state = MerkleState::new(Box::new(BTreeDatabase::new(&INDEXES)));
+        let loaded_root = state
+            .bulk_loader()
+            .unwrap()
+            .with_batch_size(2)
+            .load(entries.clone())
+            .unwrap();
+        assert_eq!(loaded_root, committed_root);
+
+        let keys: Vec<String> = entries.iter().map(|(key, _)| key.clone()).collect();
+        let values = state.get(&loaded_root, &keys).unwrap();
+        for (key, value) in &entries {
+            assert_eq!(values.get(key), Some(value));
+        }
+
+        let mut loader = state.bulk_loader().unwrap();
+        loader.add("ab0001", vec![]).unwrap();
+        match loader.add("ab0000", vec![]) {
+            Err(StateDatabaseError::InvalidAddress(_)) => (),
+            res => panic!("Expected InvalidAddress, got {:?}", res),
+        }
+        match loader.add("ab000", vec![]) {
+            Err(StateDatabaseError::InvalidAddress(_)) => (),
+            res => panic!("Expected InvalidAddress, got {:?}", res),
+        }
+    }
+}
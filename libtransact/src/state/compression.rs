@@ -0,0 +1,281 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Compression of merkle leaf values.
+//!
+//! Families that store large blobs in state, such as JSON or CBOR documents, may have their values
+//! compressed on disk.  Compression is chosen per database, and recorded in the database's
+//! `METADATA_INDEX`, so that every tree over the database compresses values the same way.  Each
+//! node with a compressed value records the algorithm in its encoding, so reads are transparent
+//! and the choice may be changed at any time.
+//!
+//! A node is stored under the hash of its uncompressed encoding, so state roots and proofs do
+//! not depend on whether or how values are compressed.
+//!
+//! The algorithms are available with the `state-compression` feature.
+
+use crate::database::{Database, DatabaseReader};
+
+use super::merkle::{StateDatabaseError, METADATA_INDEX};
+
+const VALUE_COMPRESSION_KEY: &[u8] = b"value_compression";
+
+/// The size of the smallest value compressed by a `ValueCompression`, unless configured
+/// otherwise.
+pub const DEFAULT_MIN_SIZE: usize = 256;
+
+/// The algorithms available for compressing values.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub enum CompressionAlgorithm {
+    /// LZ4, which is fast to compress and decompress.
+    #[cfg(feature = "state-compression")]
+    Lz4,
+    /// zstd, which compresses further than LZ4, more slowly.
+    #[cfg(feature = "state-compression")]
+    Zstd,
+}
+
+impl CompressionAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "state-compression")]
+            CompressionAlgorithm::Lz4 => "lz4",
+            #[cfg(feature = "state-compression")]
+            CompressionAlgorithm::Zstd => "zstd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CompressionAlgorithm> {
+        match name {
+            #[cfg(feature = "state-compression")]
+            "lz4" => Some(CompressionAlgorithm::Lz4),
+            #[cfg(feature = "state-compression")]
+            "zstd" => Some(CompressionAlgorithm::Zstd),
+            _ => None,
+        }
+    }
+
+    pub(super) fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, StateDatabaseError> {
+        match *self {
+            #[cfg(feature = "state-compression")]
+            CompressionAlgorithm::Lz4 => {
+                lz4::block::compress(bytes, None, true).map_err(compression_error)
+            }
+            #[cfg(feature = "state-compression")]
+            CompressionAlgorithm::Zstd => zstd::encode_all(bytes, 0).map_err(compression_error),
+        }
+    }
+
+    pub(super) fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, StateDatabaseError> {
+        match *self {
+            #[cfg(feature = "state-compression")]
+            CompressionAlgorithm::Lz4 => {
+                lz4::block::decompress(bytes, None).map_err(compression_error)
+            }
+            #[cfg(feature = "state-compression")]
+            CompressionAlgorithm::Zstd => zstd::decode_all(bytes).map_err(compression_error),
+        }
+    }
+}
+
+#[cfg(feature = "state-compression")]
+fn compression_error(err: std::io::Error) -> StateDatabaseError {
+    StateDatabaseError::CompressionError(format!("{}", err))
+}
+
+/// The compression of the values of a database.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub struct ValueCompression {
+    pub algorithm: CompressionAlgorithm,
+    /// The size of the smallest value compressed; smaller values are stored as they are.
+    pub min_size: usize,
+}
+
+impl ValueCompression {
+    /// Constructs a compression of values of at least `DEFAULT_MIN_SIZE` bytes with the given
+    /// algorithm.
+    pub fn new(algorithm: CompressionAlgorithm) -> Self {
+        ValueCompression {
+            algorithm,
+            min_size: DEFAULT_MIN_SIZE,
+        }
+    }
+
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Returns the compression as recorded in a database or snapshot: the algorithm's name and
+    /// the minimum size, separated by a colon.
+    pub(super) fn to_record(&self) -> String {
+        format!("{}:{}", self.algorithm.name(), self.min_size)
+    }
+
+    /// Parses a compression recorded by `to_record`, returning `None` if the record is malformed
+    /// or names an algorithm which is not available.
+    pub(super) fn from_record(record: &str) -> Option<ValueCompression> {
+        let mut parts = record.splitn(2, ':');
+        let algorithm = parts.next().and_then(CompressionAlgorithm::from_name)?;
+        let min_size = parts.next().and_then(|min_size| min_size.parse().ok())?;
+        Some(ValueCompression {
+            algorithm,
+            min_size,
+        })
+    }
+
+    /// Returns the given value compressed, or `None` if it is too small to be compressed or
+    /// compressing it would not make it smaller.
+    pub(super) fn compress(&self, value: &[u8]) -> Result<Option<Vec<u8>>, StateDatabaseError> {
+        if value.len() < self.min_size {
+            return Ok(None);
+        }
+        let compressed = self.algorithm.compress(value)?;
+        Ok(if compressed.len() < value.len() {
+            Some(compressed)
+        } else {
+            None
+        })
+    }
+}
+
+/// Returns the value compression recorded in the database, if any.
+pub(super) fn read_value_compression(
+    db_reader: &dyn DatabaseReader,
+) -> Result<Option<ValueCompression>, StateDatabaseError> {
+    let bytes = match db_reader.index_get(METADATA_INDEX, VALUE_COMPRESSION_KEY)? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    let record = String::from_utf8_lossy(&bytes);
+    ValueCompression::from_record(&record)
+        .map(Some)
+        .ok_or_else(|| {
+            StateDatabaseError::CompressionError(format!("Unknown value compression: {}", record))
+        })
+}
+
+/// Records the value compression of the database, or that values are not to be compressed.
+///
+/// Only values written afterwards are affected; values already written are read as they were
+/// stored.
+pub fn record_value_compression(
+    db: &dyn Database,
+    compression: Option<ValueCompression>,
+) -> Result<(), StateDatabaseError> {
+    let mut db_writer = db.get_writer()?;
+    match compression {
+        Some(compression) => db_writer.index_put(
+            METADATA_INDEX,
+            VALUE_COMPRESSION_KEY,
+            compression.to_record().as_bytes(),
+        )?,
+        None => {
+            if db_writer
+                .as_reader()
+                .index_get(METADATA_INDEX, VALUE_COMPRESSION_KEY)?
+                .is_some()
+            {
+                db_writer.index_delete(METADATA_INDEX, VALUE_COMPRESSION_KEY)?;
+            }
+        }
+    }
+    db_writer.commit()?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "state-compression"))]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::hash_provider::HashProvider;
+    use crate::state::merkle::{MerkleRadixTree, INDEXES};
+    use crate::state::StateChange;
+
+    #[test]
+    // test that compressed values are read back, stored smaller, and leave the state root and
+    // proofs unchanged
+    fn compressed_values() {
+        let changes = [
+            StateChange::Set {
+                key: "ab0000".into(),
+                value: b"{\"field\": \"value\"}".repeat(64),
+            },
+            StateChange::Set {
+                key: "ab0001".into(),
+                value: b"small".to_vec(),
+            },
+        ];
+        let plain_db = BTreeDatabase::new(&INDEXES);
+        let plain_root = MerkleRadixTree::new(Box::new(plain_db.clone()), None)
+            .unwrap()
+            .update(&changes, false)
+            .unwrap();
+
+        for &algorithm in &[CompressionAlgorithm::Lz4, CompressionAlgorithm::Zstd] {
+            let db = BTreeDatabase::new(&INDEXES);
+            let compression = ValueCompression::new(algorithm).with_min_size(64);
+            record_value_compression(&db, Some(compression)).unwrap();
+            assert_eq!(
+                read_value_compression(&*db.get_reader().unwrap()).unwrap(),
+                Some(compression)
+            );
+
+            let merkle_db = MerkleRadixTree::new(Box::new(db.clone()), None).unwrap();
+            let root = merkle_db.update(&changes, false).unwrap();
+            assert_eq!(root, plain_root);
+
+            let merkle_db = MerkleRadixTree::new(Box::new(db.clone()), Some(&root)).unwrap();
+            for change in &changes {
+                if let StateChange::Set { key, value } = change {
+                    assert_eq!(merkle_db.get_value(key).unwrap().as_ref(), Some(value));
+                }
+            }
+            assert_eq!(
+                merkle_db
+                    .prove("ab0000")
+                    .unwrap()
+                    .verify(&root, HashProvider::Sha512)
+                    .unwrap(),
+                Some(b"{\"field\": \"value\"}".repeat(64))
+            );
+
+            let size = |db: &BTreeDatabase| -> usize {
+                db.get_reader()
+                    .unwrap()
+                    .cursor()
+                    .unwrap()
+                    .map(|(_, value)| value.len())
+                    .sum()
+            };
+            assert!(size(&db) < size(&plain_db));
+
+            // Values already written are still read once compression is turned off
+            record_value_compression(&db, None).unwrap();
+            assert_eq!(
+                read_value_compression(&*db.get_reader().unwrap()).unwrap(),
+                None
+            );
+            let merkle_db = MerkleRadixTree::new(Box::new(db.clone()), Some(&root)).unwrap();
+            assert_eq!(
+                merkle_db.get_value("ab0000").unwrap(),
+                Some(b"{\"field\": \"value\"}".repeat(64))
+            );
+        }
+    }
+}
@@ -0,0 +1,202 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Differences between two merkle state roots.
+//!
+//! The difference is computed by walking both tries together from their roots.  Subtrees whose
+//! hashes match are identical and are skipped without being read, so the cost is proportional to
+//! the size of the change rather than the size of the state.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::database::Database;
+
+use super::merkle::{get_node_by_hash, Node, StateDatabaseError};
+
+/// A value that differs between two state roots.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangedValue {
+    pub old_value: Vec<u8>,
+    pub new_value: Vec<u8>,
+}
+
+/// The addresses that differ between two state roots, with their values, in address order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StateDiff {
+    /// Addresses set under the second root but not the first.
+    pub added: BTreeMap<String, Vec<u8>>,
+    /// Addresses set under the first root but not the second.
+    pub removed: BTreeMap<String, Vec<u8>>,
+    /// Addresses set under both roots, to different values.
+    pub changed: BTreeMap<String, ChangedValue>,
+}
+
+impl StateDiff {
+    /// Returns true if the two roots hold the same state.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Computes the difference from the state at `root_a` to the state at `root_b`.
+pub(super) fn diff_roots(
+    db: &dyn Database,
+    root_a: &str,
+    root_b: &str,
+) -> Result<StateDiff, StateDatabaseError> {
+    let mut diff = StateDiff::default();
+    let mut pending = vec![(
+        String::new(),
+        Some(root_a.to_string()),
+        Some(root_b.to_string()),
+    )];
+
+    while let Some((path, hash_a, hash_b)) = pending.pop() {
+        if hash_a == hash_b {
+            continue;
+        }
+        let node_a = load_node(db, hash_a.as_ref())?;
+        let node_b = load_node(db, hash_b.as_ref())?;
+
+        match (node_a.value, node_b.value) {
+            (Some(old_value), Some(new_value)) => {
+                if old_value != new_value {
+                    diff.changed.insert(
+                        path.clone(),
+                        ChangedValue {
+                            old_value,
+                            new_value,
+                        },
+                    );
+                }
+            }
+            (Some(old_value), None) => {
+                diff.removed.insert(path.clone(), old_value);
+            }
+            (None, Some(new_value)) => {
+                diff.added.insert(path.clone(), new_value);
+            }
+            (None, None) => (),
+        }
+
+        let tokens = node_a
+            .children
+            .keys()
+            .chain(node_b.children.keys())
+            .collect::<BTreeSet<_>>();
+        for token in tokens {
+            pending.push((
+                format!("{}{}", path, token),
+                node_a.children.get(token).cloned(),
+                node_b.children.get(token).cloned(),
+            ));
+        }
+    }
+
+    Ok(diff)
+}
+
+/// Loads the node with the given hash, treating a missing subtree as an empty node.
+fn load_node(db: &dyn Database, hash: Option<&String>) -> Result<Node, StateDatabaseError> {
+    match hash {
+        Some(hash) => get_node_by_hash(db, hash),
+        None => Ok(Node::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleRadixTree, INDEXES};
+    use crate::state::StateChange;
+
+    fn set(address: &str, value: &[u8]) -> StateChange {
+        StateChange::Set {
+            key: address.into(),
+            value: value.to_vec(),
+        }
+    }
+
+    /// Verifies that additions, removals and changes are reported, including whole subtrees
+    /// present under only one of the roots.
+    #[test]
+    fn diff_between_roots() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let mut merkle_db = MerkleRadixTree::new(Box::new(db.clone()), None).unwrap();
+
+        let root_a = merkle_db
+            .update(
+                &[
+                    set("ab0000", b"unchanged"),
+                    set("ab0001", b"old"),
+                    set("cd0000", b"removed"),
+                    set("cd0001", b"removed too"),
+                ],
+                false,
+            )
+            .unwrap();
+        merkle_db.set_merkle_root(root_a.clone()).unwrap();
+
+        let root_b = merkle_db
+            .update(
+                &[
+                    set("ab0001", b"new"),
+                    set("ef0000", b"added"),
+                    StateChange::Delete {
+                        key: "cd0000".into(),
+                    },
+                    StateChange::Delete {
+                        key: "cd0001".into(),
+                    },
+                ],
+                false,
+            )
+            .unwrap();
+
+        let diff = diff_roots(&db, &root_a, &root_b).unwrap();
+        assert_eq!(
+            diff.added.into_iter().collect::<Vec<_>>(),
+            vec![("ef0000".to_string(), b"added".to_vec())]
+        );
+        assert_eq!(
+            diff.removed.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("cd0000".to_string(), b"removed".to_vec()),
+                ("cd0001".to_string(), b"removed too".to_vec()),
+            ]
+        );
+        assert_eq!(
+            diff.changed.into_iter().collect::<Vec<_>>(),
+            vec![(
+                "ab0001".to_string(),
+                ChangedValue {
+                    old_value: b"old".to_vec(),
+                    new_value: b"new".to_vec(),
+                }
+            )]
+        );
+
+        let reversed = diff_roots(&db, &root_b, &root_a).unwrap();
+        assert_eq!(reversed.added.len(), 2);
+        assert_eq!(reversed.removed.len(), 1);
+        assert_eq!(reversed.changed.len(), 1);
+
+        assert!(diff_roots(&db, &root_a, &root_a).unwrap().is_empty());
+    }
+}
@@ -65,6 +65,8 @@ pub enum StateReadError {
     InvalidKey(String),
     /// An error occurred with the underlying storage mechanism
     StorageError(Box<dyn Error>),
+    /// The operation is not supported by the state implementation.
+    Unsupported(String),
 }
 
 impl fmt::Display for StateReadError {
@@ -73,6 +75,7 @@ impl fmt::Display for StateReadError {
             StateReadError::InvalidStateId(msg) => write!(f, "Invalid State Id: {}", msg),
             StateReadError::InvalidKey(key) => write!(f, "Invalid Key: {}", key),
             StateReadError::StorageError(err) => write!(f, "Storage Error: {}", err.description()),
+            StateReadError::Unsupported(msg) => write!(f, "Unsupported: {}", msg),
         }
     }
 }
@@ -85,12 +88,15 @@ impl Error for StateReadError {
             StateReadError::StorageError(_) => {
                 "An error occurred with the underlying storage layer."
             }
+            StateReadError::Unsupported(_) => "The operation is not supported.",
         }
     }
 
     fn cause(&self) -> Option<&Error> {
         match self {
-            StateReadError::InvalidStateId(_) | StateReadError::InvalidKey(_) => None,
+            StateReadError::InvalidStateId(_)
+            | StateReadError::InvalidKey(_)
+            | StateReadError::Unsupported(_) => None,
             StateReadError::StorageError(err) => Some(err.as_ref()),
         }
     }
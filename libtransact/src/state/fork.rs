@@ -0,0 +1,357 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Copy-on-write branches of merkle state.
+//!
+//! A `StateFork` is created from a root of a `MerkleState` with `MerkleState::fork`.  Commits to
+//! the fork compute their nodes as usual, reading the unmodified nodes from the database, but
+//! hold the new nodes in memory rather than writing them.  Any number of forks may be taken from
+//! the same root and written concurrently, for example to execute competing candidate blocks;
+//! the chosen fork is then merged, which applies its commits to the database in order, recording
+//! them in the change log as if they had been committed directly.  Other forks are dropped.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::database::error::DatabaseError;
+use crate::database::{Database, DatabaseCursor, DatabaseReader, DatabaseWriter};
+
+use super::error::{StateReadError, StateWriteError};
+use super::merkle::{MerkleRadixTree, StateDatabaseError};
+use super::wal::{apply_pending_commit, log_pending_commit, PendingCommit};
+use super::{Read, StateChange, ValueIter, Write};
+
+/// The nodes written by a fork's commits, by hex-encoded hash.
+type Nodes = Arc<RwLock<HashMap<Vec<u8>, Vec<u8>>>>;
+
+/// A writable branch of merkle state.
+///
+/// Cloning a `StateFork` produces a handle to the same branch.
+#[derive(Clone)]
+pub struct StateFork {
+    db: ForkDatabase,
+    base_state_id: String,
+    commits: Arc<Mutex<Vec<PendingCommit>>>,
+}
+
+impl StateFork {
+    pub(super) fn new(db: Box<dyn Database>, base_state_id: String) -> Self {
+        StateFork {
+            db: ForkDatabase {
+                base: db,
+                nodes: Arc::new(RwLock::new(HashMap::new())),
+            },
+            base_state_id,
+            commits: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Returns the root the fork was taken from.
+    pub fn base_state_id(&self) -> &str {
+        &self.base_state_id
+    }
+
+    /// Returns the roots committed to the fork and not yet merged, in commit order.
+    pub fn state_ids(&self) -> Vec<String> {
+        self.lock()
+            .iter()
+            .map(|pending| ::hex::encode(&pending.successor))
+            .collect()
+    }
+
+    /// Applies the fork's commits to the database, in commit order, and returns their roots.
+    ///
+    /// Once merged, the roots may be read and written through the `MerkleState` the fork was
+    /// taken from.  The fork remains usable, and later commits to it may be merged in turn.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StorageError` if a commit cannot be applied, in which case the commits before it
+    /// remain merged, and it and the commits after it remain in the fork.
+    pub fn merge(&self) -> Result<Vec<String>, StateWriteError> {
+        let mut commits = self.lock();
+        for i in 0..commits.len() {
+            let merged = log_pending_commit(&*self.db.base, &commits[i])
+                .and_then(|_| apply_pending_commit(&*self.db.base, &commits[i]));
+            if let Err(err) = merged {
+                commits.drain(..i);
+                return Err(StateWriteError::StorageError(Box::new(err)));
+            }
+        }
+
+        // The nodes are now read from the database.
+        self.db
+            .nodes
+            .write()
+            .expect("Couldn't lock fork nodes lock!")
+            .clear();
+        Ok(commits
+            .drain(..)
+            .map(|pending| ::hex::encode(&pending.successor))
+            .collect())
+    }
+
+    fn tree(&self, state_id: &str) -> Result<MerkleRadixTree, StateDatabaseError> {
+        MerkleRadixTree::new(Box::new(self.db.clone()), Some(state_id))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<Vec<PendingCommit>> {
+        self.commits
+            .lock()
+            .expect("Couldn't lock fork commits mutex!")
+    }
+}
+
+impl Write for StateFork {
+    type StateId = String;
+    type Key = String;
+    type Value = Vec<u8>;
+
+    fn commit(
+        &self,
+        state_id: &Self::StateId,
+        state_changes: &[StateChange],
+    ) -> Result<Self::StateId, StateWriteError> {
+        let pending = self
+            .tree(state_id)
+            .map_err(|err| match err {
+                StateDatabaseError::NotFound(msg) => StateWriteError::InvalidStateId(msg),
+                _ => StateWriteError::StorageError(Box::new(err)),
+            })?
+            .compute_commit(state_changes)
+            .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
+
+        // Hold the commits' lock while adding the nodes, so that a concurrent merge does not
+        // clear them before the commit is recorded.
+        let mut commits = self.lock();
+        self.db
+            .nodes
+            .write()
+            .expect("Couldn't lock fork nodes lock!")
+            .extend(
+                pending
+                    .nodes
+                    .iter()
+                    .map(|(hash, node)| (::hex::encode(hash).into_bytes(), node.clone())),
+            );
+        let state_id = ::hex::encode(&pending.successor);
+        commits.push(pending);
+        Ok(state_id)
+    }
+
+    fn compute_state_id(
+        &self,
+        state_id: &Self::StateId,
+        state_changes: &[StateChange],
+    ) -> Result<Self::StateId, StateWriteError> {
+        self.tree(state_id)
+            .map_err(|err| match err {
+                StateDatabaseError::NotFound(msg) => StateWriteError::InvalidStateId(msg),
+                _ => StateWriteError::StorageError(Box::new(err)),
+            })?
+            .update(state_changes, true)
+            .map_err(|err| StateWriteError::StorageError(Box::new(err)))
+    }
+}
+
+impl Read for StateFork {
+    type StateId = String;
+    type Key = String;
+    type Value = Vec<u8>;
+
+    fn get(
+        &self,
+        state_id: &Self::StateId,
+        keys: &[Self::Key],
+    ) -> Result<HashMap<Self::Key, Self::Value>, StateReadError> {
+        self.tree(state_id)
+            .map_err(to_read_error)?
+            .get_values(keys)
+            .map_err(|err| StateReadError::StorageError(Box::new(err)))
+    }
+
+    fn range(
+        &self,
+        state_id: &Self::StateId,
+        prefix: &Self::Key,
+    ) -> Result<ValueIter<Self::Key, Self::Value>, StateReadError> {
+        let leaves = self
+            .tree(state_id)
+            .map_err(to_read_error)?
+            .leaves(Some(prefix.as_str()))
+            .map_err(|err| StateReadError::StorageError(Box::new(err)))?;
+        Ok(Box::new(leaves.map(|entry| {
+            entry.map_err(|err| StateReadError::StorageError(Box::new(err)))
+        })))
+    }
+
+    fn clone_box(&self) -> Box<Read<StateId = String, Key = String, Value = Vec<u8>>> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+fn to_read_error(err: StateDatabaseError) -> StateReadError {
+    match err {
+        StateDatabaseError::NotFound(msg) => StateReadError::InvalidStateId(msg),
+        _ => StateReadError::StorageError(Box::new(err)),
+    }
+}
+
+/// A database that reads the nodes written by a fork before those of the underlying database.
+/// It cannot be written; a fork's nodes are only written to the underlying database by merging.
+#[derive(Clone)]
+struct ForkDatabase {
+    base: Box<dyn Database>,
+    nodes: Nodes,
+}
+
+impl Database for ForkDatabase {
+    fn get_reader<'a>(&'a self) -> Result<Box<dyn DatabaseReader + 'a>, DatabaseError> {
+        Ok(Box::new(ForkReader {
+            base: self.base.get_reader()?,
+            nodes: &self.nodes,
+        }))
+    }
+
+    fn get_writer<'a>(&'a self) -> Result<Box<dyn DatabaseWriter + 'a>, DatabaseError> {
+        Err(DatabaseError::WriterError(
+            "A fork is only written by merging it".into(),
+        ))
+    }
+
+    fn clone_box(&self) -> Box<Database> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+struct ForkReader<'a> {
+    base: Box<dyn DatabaseReader + 'a>,
+    nodes: &'a RwLock<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl<'a> DatabaseReader for ForkReader<'a> {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let node = self
+            .nodes
+            .read()
+            .expect("Couldn't lock fork nodes lock!")
+            .get(key)
+            .cloned();
+        node.or_else(|| self.base.get(key))
+    }
+
+    fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.base.index_get(index, key)
+    }
+
+    fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+        Err(DatabaseError::ReaderError(
+            "The nodes of a fork cannot be iterated".into(),
+        ))
+    }
+
+    fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+        self.base.index_cursor(index)
+    }
+
+    fn count(&self) -> Result<usize, DatabaseError> {
+        Err(DatabaseError::ReaderError(
+            "The nodes of a fork cannot be counted".into(),
+        ))
+    }
+
+    fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+        self.base.index_count(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleState, INDEXES};
+    use crate::state::Prune;
+
+    fn set(key: &str, value: &[u8]) -> StateChange {
+        StateChange::Set {
+            key: key.into(),
+            value: value.to_vec(),
+        }
+    }
+
+    /// Verifies that forks of the same root are independent, that they do not write the
+    /// database, and that a merged fork's roots become readable from the state.
+    #[test]
+    fn fork_and_merge() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let initial_root = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let state = MerkleState::new(Box::new(db.clone()));
+        let root = state
+            .commit(&initial_root, &[set("ab0000", b"base")])
+            .unwrap();
+        let node_count = db.get_reader().unwrap().count().unwrap();
+
+        let winner = state.fork(&root).unwrap();
+        let loser = state.fork(&root).unwrap();
+        let winner_1 = winner.commit(&root, &[set("ab0001", b"winner")]).unwrap();
+        let winner_2 = winner
+            .commit(&winner_1, &[set("ab0000", b"winner")])
+            .unwrap();
+        let loser_1 = loser.commit(&root, &[set("ab0001", b"loser")]).unwrap();
+
+        assert_eq!(db.get_reader().unwrap().count().unwrap(), node_count);
+        assert!(state.get(&winner_1, &["ab0001".to_string()]).is_err());
+        assert_eq!(
+            winner.get(&winner_2, &["ab0000".to_string()]).unwrap()["ab0000"],
+            b"winner".to_vec()
+        );
+        assert_eq!(
+            loser.get(&loser_1, &["ab0001".to_string()]).unwrap()["ab0001"],
+            b"loser".to_vec()
+        );
+        assert!(loser.get(&winner_1, &["ab0001".to_string()]).is_err());
+
+        // The fork computes the same roots as the state itself would
+        let expected = state
+            .compute_state_id(&root, &[set("ab0001", b"winner")])
+            .unwrap();
+        assert_eq!(winner_1, expected);
+
+        assert_eq!(winner.state_ids(), vec![winner_1.clone(), winner_2.clone()]);
+        assert_eq!(
+            winner.merge().unwrap(),
+            vec![winner_1.clone(), winner_2.clone()]
+        );
+        assert!(winner.state_ids().is_empty());
+        drop(loser);
+
+        let values = state
+            .get(&winner_2, &["ab0000".to_string(), "ab0001".to_string()])
+            .unwrap();
+        assert_eq!(values["ab0000"], b"winner".to_vec());
+        assert_eq!(values["ab0001"], b"winner".to_vec());
+        assert!(state.get(&loser_1, &["ab0001".to_string()]).is_err());
+
+        // The merged roots are in the change log, so they may be built upon and pruned
+        let root_3 = state.commit(&winner_2, &[set("cd0000", b"next")]).unwrap();
+        assert!(state.prune(vec![winner_1.clone()]).is_ok());
+        assert!(state.get(&root_3, &["cd0000".to_string()]).is_ok());
+    }
+}
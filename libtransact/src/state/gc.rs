@@ -0,0 +1,266 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Mark-and-sweep garbage collection of merkle nodes.
+//!
+//! Pruning deletes the nodes each commit added as its root is pruned, and never prunes a root
+//! with more than one successor, so databases with long, branching histories can accumulate nodes
+//! that no retained root reaches.  A `MerkleGarbageCollector` marks every node reachable from the
+//! given retained roots, then sweeps the rest, along with the change log entries of the roots
+//! whose nodes were swept.
+//!
+//! The collector may run while the state is in use.  Marking only reads the database, and the
+//! sweep deletes nodes in batches of bounded size, each committed on its own, so that commits may
+//! proceed between batches.  A node that a commit re-adds after the mark is detected by its
+//! reference count having changed, and kept.  Commits made while collecting must be made on top
+//! of retained roots, as the nodes of other roots may be swept.
+
+use std::collections::HashSet;
+
+use crate::database::Database;
+
+use super::change_log::ChangeLogEntry;
+use super::merkle::{
+    delete_ignore_missing, get_ref_count, write_change_log, Node, StateDatabaseError,
+    CHANGE_LOG_INDEX, DUPLICATE_LOG_INDEX,
+};
+
+/// The number of nodes deleted per database transaction, by default.
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// The outcome of a collection.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CollectionSummary {
+    /// The number of nodes reachable from the retained roots.
+    pub reachable_nodes: usize,
+    /// The number of unreachable nodes deleted.
+    pub removed_nodes: usize,
+    /// The number of unreachable nodes kept, as commits made during the collection re-added them.
+    pub kept_nodes: usize,
+    /// The state roots whose change log entries were removed along with their nodes.
+    pub removed_roots: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct MerkleGarbageCollector {
+    db: Box<dyn Database>,
+    batch_size: usize,
+}
+
+impl MerkleGarbageCollector {
+    pub fn new(db: Box<dyn Database>) -> Self {
+        MerkleGarbageCollector {
+            db,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Sets the number of nodes deleted per database transaction, which bounds how long the
+    /// sweep holds the database's writer at a time.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Deletes every node that is not reachable from the given roots.
+    ///
+    /// The hashes of the reachable nodes are held in memory while collecting.
+    ///
+    /// # Errors
+    ///
+    /// An `InvalidChangeLogIndex` error is returned if no roots are to be retained, as removing
+    /// every node would leave the state empty, and a `NotFound` error if a retained root, or a
+    /// node under it, is missing.
+    pub fn collect(&self, retain: &[String]) -> Result<CollectionSummary, StateDatabaseError> {
+        if retain.is_empty() {
+            return Err(StateDatabaseError::InvalidChangeLogIndex(
+                "At least one state root must be retained".into(),
+            ));
+        }
+
+        let (reachable, unreachable) = self.mark(retain)?;
+        let mut summary = CollectionSummary {
+            reachable_nodes: reachable.len(),
+            ..CollectionSummary::default()
+        };
+
+        let mut removed = HashSet::new();
+        for batch in unreachable.chunks(self.batch_size) {
+            let mut db_writer = self.db.get_writer()?;
+            for (hash, ref_count) in batch {
+                if get_ref_count(db_writer.as_reader(), hash)? != *ref_count {
+                    summary.kept_nodes += 1;
+                    continue;
+                }
+                delete_ignore_missing(&mut *db_writer, ::hex::encode(hash).as_bytes())?;
+                if *ref_count > 0 {
+                    db_writer.index_delete(DUPLICATE_LOG_INDEX, hash)?;
+                }
+                removed.insert(hash.clone());
+            }
+            db_writer.commit()?;
+        }
+        debug!(
+            "Swept {} of {} unreachable nodes",
+            removed.len(),
+            unreachable.len()
+        );
+
+        summary.removed_nodes = removed.len();
+        summary.removed_roots = self.remove_change_logs(&removed)?;
+        Ok(summary)
+    }
+
+    /// Returns the hashes of the nodes reachable from the given roots, and the hashes and
+    /// reference counts of the others, as of a single read of the database.
+    fn mark(
+        &self,
+        retain: &[String],
+    ) -> Result<(HashSet<Vec<u8>>, Vec<(Vec<u8>, u64)>), StateDatabaseError> {
+        let db_reader = self.db.get_reader()?;
+
+        let mut reachable = HashSet::new();
+        let mut to_visit = retain.to_vec();
+        while let Some(hash) = to_visit.pop() {
+            let hash_bytes = ::hex::decode(&hash).map_err(|_| {
+                StateDatabaseError::InvalidHash(format!("{} is not a valid hash", hash))
+            })?;
+            if !reachable.insert(hash_bytes) {
+                continue;
+            }
+            let node = match db_reader.get(hash.as_bytes()) {
+                Some(bytes) => Node::from_bytes(&bytes)?,
+                None => return Err(StateDatabaseError::NotFound(hash)),
+            };
+            to_visit.extend(node.children.into_iter().map(|(_, child)| child));
+        }
+
+        let mut unreachable = vec![];
+        for (key, _) in db_reader.cursor()? {
+            let hash = ::hex::decode(&key).map_err(|_| {
+                StateDatabaseError::InvalidHash(format!("{:?} is not a valid hash", key))
+            })?;
+            if !reachable.contains(&hash) {
+                let ref_count = get_ref_count(&*db_reader, &hash)?;
+                unreachable.push((hash, ref_count));
+            }
+        }
+
+        Ok((reachable, unreachable))
+    }
+
+    /// Removes the change log entries of the roots whose nodes were removed, and those roots from
+    /// the successors of the remaining entries.  Returns the removed roots.
+    fn remove_change_logs(
+        &self,
+        removed: &HashSet<Vec<u8>>,
+    ) -> Result<Vec<String>, StateDatabaseError> {
+        if removed.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut db_writer = self.db.get_writer()?;
+        let change_logs = db_writer
+            .as_reader()
+            .index_cursor(CHANGE_LOG_INDEX)?
+            .map(|(root, bytes)| Ok((root, ChangeLogEntry::from_bytes(&bytes)?)))
+            .collect::<Result<Vec<_>, StateDatabaseError>>()?;
+
+        let mut removed_roots = vec![];
+        for (root, mut change_log) in change_logs {
+            if removed.contains(&root) {
+                db_writer.index_delete(CHANGE_LOG_INDEX, &root)?;
+                removed_roots.push(::hex::encode(&root));
+                continue;
+            }
+            let successor_count = change_log.successors.len();
+            change_log
+                .successors
+                .retain(|successor| !removed.contains(&successor.successor));
+            if change_log.successors.len() != successor_count {
+                write_change_log(&mut *db_writer, &root, &change_log)?;
+            }
+        }
+        db_writer.commit()?;
+
+        removed_roots.sort();
+        Ok(removed_roots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleRadixTree, INDEXES};
+    use crate::state::StateChange;
+
+    /// Commits a single set on top of the given root, returning the new root.
+    fn commit(db: &BTreeDatabase, root: &str, key: &str, value: &[u8]) -> String {
+        let merkle_db = MerkleRadixTree::new(Box::new(db.clone()), Some(root)).unwrap();
+        merkle_db
+            .update(
+                &[StateChange::Set {
+                    key: key.to_string(),
+                    value: value.to_vec(),
+                }],
+                false,
+            )
+            .unwrap()
+    }
+
+    /// Verifies that the nodes of a branch and of older roots are swept, while the retained root
+    /// remains readable.
+    #[test]
+    fn collect_branching_history() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let root_0 = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let root_1 = commit(&db, &root_0, "ab0000", b"1");
+        let root_2 = commit(&db, &root_1, "ab0000", b"2");
+        let branch = commit(&db, &root_1, "cd0000", b"branch");
+
+        let collector = MerkleGarbageCollector::new(Box::new(db.clone())).with_batch_size(2);
+        let summary = collector.collect(&[root_2.clone()]).unwrap();
+
+        // root_2 reaches its root node, "ab", "ab00" and the leaf
+        assert_eq!(summary.reachable_nodes, 4);
+        assert!(summary.removed_nodes > 0);
+        assert_eq!(summary.kept_nodes, 0);
+        let mut expected_roots = vec![root_1.clone(), branch.clone()];
+        expected_roots.sort();
+        assert_eq!(summary.removed_roots, expected_roots);
+
+        assert_eq!(db.get_reader().unwrap().count().unwrap(), 4);
+        assert!(MerkleRadixTree::new(Box::new(db.clone()), Some(branch.as_str())).is_err());
+        assert!(MerkleRadixTree::new(Box::new(db.clone()), Some(root_1.as_str())).is_err());
+        assert_eq!(
+            MerkleRadixTree::new(Box::new(db.clone()), Some(root_2.as_str()))
+                .unwrap()
+                .get_value("ab0000")
+                .unwrap(),
+            Some(b"2".to_vec())
+        );
+
+        // Everything left is reachable
+        let summary = collector.collect(&[root_2.clone()]).unwrap();
+        assert_eq!(summary.removed_nodes, 0);
+        assert!(summary.removed_roots.is_empty());
+    }
+}
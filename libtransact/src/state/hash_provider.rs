@@ -0,0 +1,217 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! The hash functions of merkle tries.
+//!
+//! A merkle node is stored under the hash of its encoding, so the hash function determines every
+//! state root.  The function is chosen when a database is first used, and recorded in the
+//! database's `METADATA_INDEX`, so that the same function is used whenever it is opened.
+//! Databases without a record predate the choice, and use SHA-512.
+
+use crate::database::{Database, DatabaseReader};
+
+use super::merkle::{StateDatabaseError, METADATA_INDEX};
+
+const HASH_PROVIDER_KEY: &[u8] = b"hash_provider";
+
+/// The hash functions available for merkle tries.  Every function produces 32-byte hashes.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub enum HashProvider {
+    /// The first half of the SHA-512 hash, as used by Sawtooth.  This is the default.
+    Sha512,
+    /// The SHA-256 hash.
+    Sha256,
+    /// The BLAKE2b hash with a 32-byte output, BLAKE2b-256.
+    #[cfg(feature = "blake2")]
+    Blake2b,
+}
+
+impl HashProvider {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashProvider::Sha512 => "sha512",
+            HashProvider::Sha256 => "sha256",
+            #[cfg(feature = "blake2")]
+            HashProvider::Blake2b => "blake2b-256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HashProvider> {
+        match name {
+            "sha512" => Some(HashProvider::Sha512),
+            "sha256" => Some(HashProvider::Sha256),
+            #[cfg(feature = "blake2")]
+            "blake2b-256" => Some(HashProvider::Blake2b),
+            _ => None,
+        }
+    }
+
+    /// Returns the hash of the given bytes.
+    pub fn hash(&self, input: &[u8]) -> Vec<u8> {
+        match self {
+            HashProvider::Sha512 => openssl::sha::sha512(input)[..32].to_vec(),
+            HashProvider::Sha256 => openssl::sha::sha256(input).to_vec(),
+            #[cfg(feature = "blake2")]
+            HashProvider::Blake2b => {
+                use blake2::digest::{Input, VariableOutput};
+
+                let mut hasher =
+                    blake2::VarBlake2b::new(32).expect("32 bytes is a valid BLAKE2b output size");
+                hasher.input(input);
+                let mut hash = Vec::with_capacity(32);
+                hasher.variable_result(|result| hash.extend_from_slice(result));
+                hash
+            }
+        }
+    }
+}
+
+impl Default for HashProvider {
+    fn default() -> Self {
+        HashProvider::Sha512
+    }
+}
+
+/// Returns the hash provider recorded in the database, if any.
+pub(super) fn read_hash_provider(
+    db_reader: &dyn DatabaseReader,
+) -> Result<Option<HashProvider>, StateDatabaseError> {
+    match db_reader.index_get(METADATA_INDEX, HASH_PROVIDER_KEY)? {
+        Some(bytes) => {
+            let name = String::from_utf8_lossy(&bytes);
+            HashProvider::from_name(&name).map(Some).ok_or_else(|| {
+                StateDatabaseError::InvalidHash(format!("Unknown hash provider: {}", name))
+            })
+        }
+        None => Ok(None),
+    }
+}
+
+/// Records the hash provider of the database.
+///
+/// # Errors
+///
+/// Returns an error if the database already records another provider, or holds state from
+/// before providers were recorded and the given provider is not the default.
+pub(super) fn record_hash_provider(
+    db: &dyn Database,
+    hash_provider: HashProvider,
+) -> Result<(), StateDatabaseError> {
+    let mut db_writer = db.get_writer()?;
+    match read_hash_provider(db_writer.as_reader())? {
+        Some(recorded) if recorded == hash_provider => return Ok(()),
+        Some(recorded) => {
+            return Err(StateDatabaseError::InvalidHash(format!(
+                "Database uses the {} hash provider, not {}",
+                recorded.name(),
+                hash_provider.name()
+            )));
+        }
+        None if hash_provider != HashProvider::default() && db_writer.count()? > 0 => {
+            return Err(StateDatabaseError::InvalidHash(format!(
+                "Database holds state hashed with {}, not {}",
+                HashProvider::default().name(),
+                hash_provider.name()
+            )));
+        }
+        None => (),
+    }
+    db_writer.index_put(
+        METADATA_INDEX,
+        HASH_PROVIDER_KEY,
+        hash_provider.name().as_bytes(),
+    )?;
+    db_writer.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleRadixTree, INDEXES};
+    use crate::state::StateChange;
+
+    #[test]
+    // test the providers' hashes of the empty input
+    fn known_hashes() {
+        assert_eq!(
+            ::hex::encode(HashProvider::Sha512.hash(b"")),
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce"
+        );
+        assert_eq!(
+            ::hex::encode(HashProvider::Sha256.hash(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        #[cfg(feature = "blake2")]
+        assert_eq!(
+            ::hex::encode(HashProvider::Blake2b.hash(b"")),
+            "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+        );
+
+        assert_eq!(
+            HashProvider::from_name("sha256"),
+            Some(HashProvider::Sha256)
+        );
+        assert_eq!(HashProvider::from_name("md5"), None);
+    }
+
+    #[test]
+    // test that the provider recorded for a database is used when it is reopened, and that it
+    // may not be changed
+    fn recorded_provider() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let merkle_db =
+            MerkleRadixTree::new_with_hash_provider(Box::new(db.clone()), HashProvider::Sha256)
+                .unwrap();
+        let changes = [StateChange::Set {
+            key: "ab0000".into(),
+            value: b"value".to_vec(),
+        }];
+        let root = merkle_db.update(&changes, false).unwrap();
+
+        let default_db = BTreeDatabase::new(&INDEXES);
+        let default_root = MerkleRadixTree::new(Box::new(default_db.clone()), None)
+            .unwrap()
+            .update(&changes, false)
+            .unwrap();
+        assert_ne!(root, default_root);
+
+        let reopened = MerkleRadixTree::new(Box::new(db.clone()), Some(&root)).unwrap();
+        assert_eq!(reopened.update(&changes, true).unwrap(), root);
+        assert_eq!(
+            reopened.get_value("ab0000").unwrap(),
+            Some(b"value".to_vec())
+        );
+
+        assert!(
+            MerkleRadixTree::new_with_hash_provider(Box::new(db), HashProvider::Sha512).is_err()
+        );
+        // Databases holding state from before providers were recorded use the default
+        assert!(MerkleRadixTree::new_with_hash_provider(
+            Box::new(default_db.clone()),
+            HashProvider::Sha256
+        )
+        .is_err());
+        assert!(MerkleRadixTree::new_with_hash_provider(
+            Box::new(default_db),
+            HashProvider::Sha512
+        )
+        .is_ok());
+    }
+}
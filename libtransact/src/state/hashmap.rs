@@ -18,7 +18,7 @@
 //! Provides a simple, in-memory implementation of backed by `std::collections::HashMap`.
 
 use super::error::{StateReadError, StateWriteError};
-use super::{Read, StateChange, Write};
+use super::{Read, StateChange, ValueIter, Write};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
@@ -134,6 +134,25 @@ impl Read for HashMapState {
             .collect())
     }
 
+    fn range(
+        &self,
+        state_id: &Self::StateId,
+        prefix: &Self::Key,
+    ) -> Result<ValueIter<Self::Key, Self::Value>, StateReadError> {
+        let states = self.states.lock().expect("Couldn't lock states mutex!");
+        let state = states.get(state_id).ok_or_else(|| {
+            StateReadError::InvalidStateId(format!("Unknown state id {}", state_id))
+        })?;
+
+        let mut entries = state
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+        entries.sort();
+        Ok(Box::new(entries.into_iter().map(Ok)))
+    }
+
     fn clone_box(&self) -> Box<Read<StateId = String, Key = String, Value = Vec<u8>>> {
         Box::new(Clone::clone(self))
     }
@@ -0,0 +1,264 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! A history of committed state roots, under identifiers supplied by the caller.
+//!
+//! A `StateRootHistory` records which state root an identifier, such as a block or batch id,
+//! produced, and numbers the entries in the order they are recorded.  The entries are kept in the
+//! `root_history` index of the merkle database, alongside the change log, so that replay and
+//! audit tools can walk the history from the database alone.
+//!
+//! The history is not updated when state roots are pruned; an entry may name a root that is no
+//! longer present.
+
+use crate::database::error::DatabaseError;
+use crate::database::{Database, DatabaseReader, DatabaseReaderCursor};
+
+use super::merkle::{StateDatabaseError, ROOT_HISTORY_INDEX};
+
+/// The prefix of the keys of entries, which are followed by the entry's sequence number.
+const SEQUENCE_PREFIX: u8 = b's';
+/// The prefix of the keys of identifiers, which are followed by the identifier and map to the
+/// sequence number of its entry.  It sorts before `SEQUENCE_PREFIX`, so that the last key of the
+/// index is that of the latest entry.
+const ID_PREFIX: u8 = b'i';
+
+/// An entry of the history.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    /// The position of the entry in the history, counting from zero.
+    pub sequence: u64,
+    pub id: String,
+    pub state_root: String,
+}
+
+#[derive(Clone)]
+pub struct StateRootHistory {
+    db: Box<dyn Database>,
+}
+
+impl StateRootHistory {
+    pub fn new(db: Box<dyn Database>) -> Self {
+        StateRootHistory { db }
+    }
+
+    /// Records that the given identifier produced the given state root, and returns the
+    /// sequence number of the new entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if the state root is not in the database, and a `DuplicateEntry`
+    /// database error if the identifier has already been recorded.
+    pub fn record(&self, id: &str, state_root: &str) -> Result<u64, StateDatabaseError> {
+        let mut db_writer = self.db.get_writer()?;
+        if db_writer.as_reader().get(state_root.as_bytes()).is_none() {
+            return Err(StateDatabaseError::NotFound(format!(
+                "state root {}",
+                state_root
+            )));
+        }
+        if db_writer
+            .as_reader()
+            .index_get(ROOT_HISTORY_INDEX, &id_key(id))?
+            .is_some()
+        {
+            return Err(StateDatabaseError::DatabaseError(
+                DatabaseError::DuplicateEntry,
+            ));
+        }
+
+        let sequence = match latest(db_writer.as_reader())? {
+            Some(entry) => entry.sequence + 1,
+            None => 0,
+        };
+        db_writer.index_put(
+            ROOT_HISTORY_INDEX,
+            &sequence_key(sequence),
+            &encode_entry(id, state_root),
+        )?;
+        db_writer.index_put(ROOT_HISTORY_INDEX, &id_key(id), &sequence.to_be_bytes())?;
+        db_writer.commit()?;
+
+        Ok(sequence)
+    }
+
+    /// Returns the entry recorded for the given identifier, if any.
+    pub fn get(&self, id: &str) -> Result<Option<HistoryEntry>, StateDatabaseError> {
+        let db_reader = self.db.get_reader()?;
+        let sequence = match db_reader.index_get(ROOT_HISTORY_INDEX, &id_key(id))? {
+            Some(bytes) => decode_sequence(&bytes)?,
+            None => return Ok(None),
+        };
+        match db_reader.index_get(ROOT_HISTORY_INDEX, &sequence_key(sequence))? {
+            Some(bytes) => Ok(Some(decode_entry(sequence, &bytes)?)),
+            None => Err(malformed(&format!("no entry for sequence {}", sequence))),
+        }
+    }
+
+    /// Returns the most recently recorded entry, if any.
+    pub fn latest(&self) -> Result<Option<HistoryEntry>, StateDatabaseError> {
+        latest(&*self.db.get_reader()?)
+    }
+
+    /// Returns the entries with the given sequence number and later, in the order they were
+    /// recorded.
+    pub fn entries_from(&self, sequence: u64) -> Result<Vec<HistoryEntry>, StateDatabaseError> {
+        let db_reader = self.db.get_reader()?;
+        let mut entries = vec![];
+        for (key, value) in db_reader.index_cursor(ROOT_HISTORY_INDEX)? {
+            if key.first() != Some(&SEQUENCE_PREFIX) {
+                continue;
+            }
+            let entry_sequence = decode_sequence(&key[1..])?;
+            if entry_sequence >= sequence {
+                entries.push(decode_entry(entry_sequence, &value)?);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+fn latest(db_reader: &dyn DatabaseReader) -> Result<Option<HistoryEntry>, StateDatabaseError> {
+    let mut cursor = db_reader.index_cursor(ROOT_HISTORY_INDEX)?;
+    match DatabaseReaderCursor::last(&mut *cursor) {
+        Some((key, value)) if key.first() == Some(&SEQUENCE_PREFIX) => {
+            let sequence = decode_sequence(&key[1..])?;
+            Ok(Some(decode_entry(sequence, &value)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn sequence_key(sequence: u64) -> Vec<u8> {
+    let mut key = vec![SEQUENCE_PREFIX];
+    key.extend_from_slice(&sequence.to_be_bytes());
+    key
+}
+
+fn id_key(id: &str) -> Vec<u8> {
+    let mut key = vec![ID_PREFIX];
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+fn decode_sequence(bytes: &[u8]) -> Result<u64, StateDatabaseError> {
+    if bytes.len() != 8 {
+        return Err(malformed("invalid sequence number"));
+    }
+    let mut sequence = [0u8; 8];
+    sequence.copy_from_slice(bytes);
+    Ok(u64::from_be_bytes(sequence))
+}
+
+/// Encodes an entry as the length of the identifier, as four big-endian bytes, followed by the
+/// identifier and the state root.
+fn encode_entry(id: &str, state_root: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(4 + id.len() + state_root.len());
+    bytes.extend_from_slice(&(id.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(id.as_bytes());
+    bytes.extend_from_slice(state_root.as_bytes());
+    bytes
+}
+
+fn decode_entry(sequence: u64, bytes: &[u8]) -> Result<HistoryEntry, StateDatabaseError> {
+    if bytes.len() < 4 {
+        return Err(malformed("truncated entry"));
+    }
+    let mut id_len = [0u8; 4];
+    id_len.copy_from_slice(&bytes[..4]);
+    let id_len = u32::from_be_bytes(id_len) as usize;
+    if bytes.len() < 4 + id_len {
+        return Err(malformed("truncated entry"));
+    }
+
+    let to_string = |bytes: &[u8]| {
+        String::from_utf8(bytes.to_vec()).map_err(|_| malformed("entry is not valid UTF-8"))
+    };
+    Ok(HistoryEntry {
+        sequence,
+        id: to_string(&bytes[4..4 + id_len])?,
+        state_root: to_string(&bytes[4 + id_len..])?,
+    })
+}
+
+fn malformed(msg: &str) -> StateDatabaseError {
+    StateDatabaseError::InvalidChangeLogIndex(format!("Malformed root history: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleRadixTree, MerkleState, INDEXES};
+    use crate::state::{StateChange, Write};
+
+    /// Verifies that entries are found by identifier and listed in the order they were recorded.
+    #[test]
+    fn record_and_iterate() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let root_0 = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let state = MerkleState::new(Box::new(db));
+        let root_1 = state
+            .commit(
+                &root_0,
+                &[StateChange::Set {
+                    key: "ab0000".into(),
+                    value: b"value".to_vec(),
+                }],
+            )
+            .unwrap();
+
+        let history = state.history();
+        assert_eq!(history.latest().unwrap(), None);
+        assert_eq!(history.record("genesis", &root_0).unwrap(), 0);
+        assert_eq!(history.record("block-1", &root_1).unwrap(), 1);
+        // Identifiers may name the same root
+        assert_eq!(history.record("block-2", &root_1).unwrap(), 2);
+
+        assert_eq!(
+            history.get("block-1").unwrap(),
+            Some(HistoryEntry {
+                sequence: 1,
+                id: "block-1".into(),
+                state_root: root_1.clone(),
+            })
+        );
+        assert_eq!(history.get("block-3").unwrap(), None);
+        assert_eq!(history.latest().unwrap().unwrap().id, "block-2");
+        assert_eq!(
+            history
+                .entries_from(1)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.id)
+                .collect::<Vec<_>>(),
+            vec!["block-1".to_string(), "block-2".to_string()]
+        );
+
+        match history.record("block-1", &root_0) {
+            Err(StateDatabaseError::DatabaseError(DatabaseError::DuplicateEntry)) => (),
+            res => panic!("Expected DuplicateEntry, got {:?}", res),
+        }
+        match history.record("block-3", &"00".repeat(32)) {
+            Err(StateDatabaseError::NotFound(_)) => (),
+            res => panic!("Expected NotFound, got {:?}", res),
+        }
+    }
+}
@@ -16,31 +16,63 @@
  * ------------------------------------------------------------------------------
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Instant;
 
 use cbor;
 use cbor::decoder::GenericDecoder;
 use cbor::encoder::GenericEncoder;
 use cbor::value::{Bytes, Key, Text, Value};
 
-use openssl;
-
 use crate::database::error::DatabaseError;
 use crate::database::{Database, DatabaseReader, DatabaseWriter};
+use crate::metrics::MetricsRecorder;
 
+use super::bulk::BulkLoader;
 use super::change_log::{ChangeLogEntry, Successor};
+use super::compression::{read_value_compression, CompressionAlgorithm, ValueCompression};
+use super::diff::{diff_roots, StateDiff};
 use super::error::{StatePruneError, StateReadError, StateWriteError};
-use super::{Prune, Read, StateChange, Write};
+use super::fork::StateFork;
+use super::hash_provider::{read_hash_provider, record_hash_provider, HashProvider};
+use super::history::StateRootHistory;
+use super::node_cache::NodeCache;
+use super::proof::MerkleProof;
+use super::wal::{apply_pending_commit, log_pending_commit, recover, PendingCommit, RecoveryMode};
+use super::{Prune, Read, StateChange, ValueIter, Write};
 
 pub use super::merkle_error::StateDatabaseError;
 
-const TOKEN_SIZE: usize = 2;
+pub(super) const TOKEN_SIZE: usize = 2;
 
 pub const CHANGE_LOG_INDEX: &str = "change_log";
 pub const DUPLICATE_LOG_INDEX: &str = "duplicate_log";
-pub const INDEXES: [&str; 2] = [CHANGE_LOG_INDEX, DUPLICATE_LOG_INDEX];
+pub const WRITE_AHEAD_LOG_INDEX: &str = "write_ahead_log";
+pub const METADATA_INDEX: &str = "metadata";
+pub const ROOT_HISTORY_INDEX: &str = "root_history";
+pub const INDEXES: [&str; 5] = [
+    CHANGE_LOG_INDEX,
+    DUPLICATE_LOG_INDEX,
+    WRITE_AHEAD_LOG_INDEX,
+    METADATA_INDEX,
+    ROOT_HISTORY_INDEX,
+];
+
+/// The number of keys read from state.
+pub const STATE_READS: &str = "state.reads";
+/// The duration of state reads, each of any number of keys.
+pub const STATE_READ_DURATION: &str = "state.read_duration";
+/// The number of commits to state.
+pub const STATE_COMMITS: &str = "state.commits";
+/// The number of state changes committed.
+pub const STATE_CHANGES: &str = "state.changes";
+/// The number of merkle nodes written by commits.
+pub const STATE_NODES_WRITTEN: &str = "state.nodes_written";
+/// The duration of commits to state, including the computation of their nodes.
+pub const STATE_COMMIT_DURATION: &str = "state.commit_duration";
 
 type StateIter = Iterator<Item = Result<(String, Vec<u8>), StateDatabaseError>>;
 type StateHash = Vec<u8>;
@@ -48,11 +80,136 @@ type StateHash = Vec<u8>;
 #[derive(Clone)]
 pub struct MerkleState {
     db: Box<dyn Database>,
+    cache: Option<NodeCache>,
+    metrics: Option<Arc<dyn MetricsRecorder>>,
 }
 
 impl MerkleState {
     pub fn new(db: Box<dyn Database>) -> Self {
-        MerkleState { db }
+        MerkleState {
+            db,
+            cache: None,
+            metrics: None,
+        }
+    }
+
+    /// Opens merkle state over the given database, first recovering any commits that were
+    /// interrupted, such as by a crash, as determined by `mode`.
+    pub fn open(db: Box<dyn Database>, mode: RecoveryMode) -> Result<Self, StateDatabaseError> {
+        let summary = recover(&*db, mode)?;
+        if !summary.is_empty() {
+            warn!("Recovered interrupted state commits: {:?}", summary);
+        }
+        Ok(MerkleState::new(db))
+    }
+
+    /// Reads merkle nodes through the given cache, which may be shared with other instances over
+    /// the same database.
+    pub fn with_node_cache(mut self, cache: NodeCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Reports the `STATE_*` metrics of reads and commits to the given recorder.
+    pub fn with_metrics(mut self, metrics: Arc<dyn MetricsRecorder>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Returns an iterator over the address/value pairs under the given address prefix at the
+    /// given state root, in address order.
+    ///
+    /// Only the subtree under the prefix is read, as the iteration proceeds.
+    pub fn leaves(
+        &self,
+        state_id: &str,
+        prefix: Option<&str>,
+    ) -> Result<Box<StateIter>, StateReadError> {
+        self.tree_at(state_id)?
+            .leaves(prefix)
+            .map_err(|err| StateReadError::StorageError(Box::new(err)))
+    }
+
+    /// Returns a proof of the value at the given address, or of its absence, at the given state
+    /// root.
+    pub fn prove(&self, address: &str, state_id: &str) -> Result<MerkleProof, StateReadError> {
+        self.tree_at(state_id)?
+            .prove(address)
+            .map_err(|err| StateReadError::StorageError(Box::new(err)))
+    }
+
+    /// Returns the addresses added, removed and changed from the state at `root_a` to the state
+    /// at `root_b`, with their values.
+    ///
+    /// The tries are compared node by node, skipping subtrees that are identical under both
+    /// roots, so change logs are not needed and the roots need not be related.
+    pub fn diff(&self, root_a: &str, root_b: &str) -> Result<StateDiff, StateReadError> {
+        self.tree_at(root_a)?;
+        self.tree_at(root_b)?;
+        diff_roots(&*self.db, root_a, root_b)
+            .map_err(|err| StateReadError::StorageError(Box::new(err)))
+    }
+
+    /// Returns the state root that applying the given changes to the state at `state_id` would
+    /// produce, without writing anything to the database.
+    ///
+    /// A proposed state root may be verified against the result before its changes are
+    /// committed.
+    pub fn compute_state_root(
+        &self,
+        state_id: &str,
+        changes: &[StateChange],
+    ) -> Result<String, StateWriteError> {
+        let mut merkle_tree = self
+            .open_tree(state_id)
+            .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
+
+        merkle_tree
+            .set_merkle_root(state_id.to_string())
+            .map_err(|err| match err {
+                StateDatabaseError::NotFound(msg) => StateWriteError::InvalidStateId(msg),
+                _ => StateWriteError::StorageError(Box::new(err)),
+            })?;
+        merkle_tree
+            .update(changes, true)
+            .map_err(|err| StateWriteError::StorageError(Box::new(err)))
+    }
+
+    /// Forks the state at the given root into an independent, writable branch.
+    ///
+    /// The branch's commits are held in memory, sharing the unmodified nodes of the database, until
+    /// they are merged with `StateFork::merge`; a branch that is not merged is simply dropped.
+    pub fn fork(&self, state_id: &str) -> Result<StateFork, StateReadError> {
+        self.tree_at(state_id)?;
+        Ok(StateFork::new(self.db.clone(), state_id.to_string()))
+    }
+
+    /// Returns a loader that builds a new tree of this state from sorted entries.
+    ///
+    /// See `BulkLoader`.
+    pub fn bulk_loader(&self) -> Result<BulkLoader, StateDatabaseError> {
+        BulkLoader::new(self.db.clone())
+    }
+
+    /// Returns the history of state roots recorded in this state's database.
+    pub fn history(&self) -> StateRootHistory {
+        StateRootHistory::new(self.db.clone())
+    }
+
+    fn tree_at(&self, state_id: &str) -> Result<MerkleRadixTree, StateReadError> {
+        self.open_tree(state_id).map_err(|err| match err {
+            StateDatabaseError::NotFound(msg) => StateReadError::InvalidStateId(msg),
+            _ => StateReadError::StorageError(Box::new(err)),
+        })
+    }
+
+    fn open_tree(&self, state_id: &str) -> Result<MerkleRadixTree, StateDatabaseError> {
+        MerkleRadixTree::open(
+            self.db.clone(),
+            Some(state_id),
+            self.cache.clone(),
+            self.metrics.clone(),
+        )
     }
 }
 
@@ -66,7 +223,8 @@ impl Write for MerkleState {
         state_id: &Self::StateId,
         state_changes: &[StateChange],
     ) -> Result<Self::StateId, StateWriteError> {
-        let mut merkle_tree = MerkleRadixTree::new(self.db.clone(), Some(state_id))
+        let mut merkle_tree = self
+            .open_tree(state_id)
             .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
         merkle_tree
             .set_merkle_root(state_id.to_string())
@@ -84,18 +242,7 @@ impl Write for MerkleState {
         state_id: &Self::StateId,
         state_changes: &[StateChange],
     ) -> Result<Self::StateId, StateWriteError> {
-        let mut merkle_tree = MerkleRadixTree::new(self.db.clone(), Some(state_id))
-            .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
-
-        merkle_tree
-            .set_merkle_root(state_id.to_string())
-            .map_err(|err| match err {
-                StateDatabaseError::NotFound(msg) => StateWriteError::InvalidStateId(msg),
-                _ => StateWriteError::StorageError(Box::new(err)),
-            })?;
-        merkle_tree
-            .update(state_changes, true)
-            .map_err(|err| StateWriteError::StorageError(Box::new(err)))
+        self.compute_state_root(state_id, state_changes)
     }
 }
 
@@ -109,7 +256,9 @@ impl Read for MerkleState {
         state_id: &Self::StateId,
         keys: &[Self::Key],
     ) -> Result<HashMap<Self::Key, Self::Value>, StateReadError> {
-        let mut merkle_tree = MerkleRadixTree::new(self.db.clone(), Some(state_id))
+        let start = Instant::now();
+        let mut merkle_tree = self
+            .open_tree(state_id)
             .map_err(|err| StateReadError::StorageError(Box::new(err)))?;
 
         merkle_tree
@@ -118,19 +267,25 @@ impl Read for MerkleState {
                 StateDatabaseError::NotFound(msg) => StateReadError::InvalidStateId(msg),
                 _ => StateReadError::StorageError(Box::new(err)),
             })?;
-        keys.iter().try_fold(HashMap::new(), |mut result, key| {
-            let value = match merkle_tree.get_by_address(key) {
-                Ok(value) => Ok(value.value),
-                Err(err) => match err {
-                    StateDatabaseError::NotFound(_) => Ok(None),
-                    _ => Err(StateReadError::StorageError(Box::new(err))),
-                },
-            }?;
-            if value.is_some() {
-                result.insert(key.to_string(), value.unwrap());
-            }
-            Ok(result)
-        })
+        let values = merkle_tree
+            .get_values(keys)
+            .map_err(|err| StateReadError::StorageError(Box::new(err)))?;
+
+        if let Some(metrics) = &self.metrics {
+            metrics.increment_counter(STATE_READS, keys.len() as u64);
+            metrics.record_duration(STATE_READ_DURATION, start.elapsed());
+        }
+        Ok(values)
+    }
+
+    fn range(
+        &self,
+        state_id: &Self::StateId,
+        prefix: &Self::Key,
+    ) -> Result<ValueIter<Self::Key, Self::Value>, StateReadError> {
+        Ok(Box::new(self.leaves(state_id, Some(prefix.as_str()))?.map(
+            |entry| entry.map_err(|err| StateReadError::StorageError(Box::new(err))),
+        )))
     }
 
     fn clone_box(&self) -> Box<Read<StateId = String, Key = String, Value = Vec<u8>>> {
@@ -164,6 +319,10 @@ pub struct MerkleRadixTree {
     root_hash: String,
     db: Box<dyn Database>,
     root_node: Node,
+    cache: Option<NodeCache>,
+    hash_provider: HashProvider,
+    compression: Option<ValueCompression>,
+    metrics: Option<Arc<dyn MetricsRecorder>>,
 }
 
 impl MerkleRadixTree {
@@ -174,13 +333,59 @@ impl MerkleRadixTree {
         db: Box<dyn Database>,
         merkle_root: Option<&str>,
     ) -> Result<Self, StateDatabaseError> {
-        let root_hash = merkle_root.map_or_else(|| initialize_db(&*db), |s| Ok(s.into()))?;
-        let root_node = get_node_by_hash(&*db, &root_hash)?;
+        Self::open(db, merkle_root, None, None)
+    }
+
+    /// Constructs a new MerkleRadixTree, backed by a given Database, which reads nodes through
+    /// the given cache.
+    ///
+    /// An optional starting merkle root may be provided.
+    pub fn new_with_cache(
+        db: Box<dyn Database>,
+        merkle_root: Option<&str>,
+        cache: NodeCache,
+    ) -> Result<Self, StateDatabaseError> {
+        Self::open(db, merkle_root, Some(cache), None)
+    }
+
+    /// Constructs a new, empty MerkleRadixTree, backed by a given Database, whose nodes are
+    /// hashed by the given provider.
+    ///
+    /// The provider is recorded in the database, and is then used by any MerkleRadixTree over
+    /// it.  Returns an error if the database already uses another provider.
+    pub fn new_with_hash_provider(
+        db: Box<dyn Database>,
+        hash_provider: HashProvider,
+    ) -> Result<Self, StateDatabaseError> {
+        record_hash_provider(&*db, hash_provider)?;
+        Self::open(db, None, None, None)
+    }
+
+    fn open(
+        db: Box<dyn Database>,
+        merkle_root: Option<&str>,
+        cache: Option<NodeCache>,
+        metrics: Option<Arc<dyn MetricsRecorder>>,
+    ) -> Result<Self, StateDatabaseError> {
+        let (hash_provider, compression) = {
+            let db_reader = db.get_reader()?;
+            (
+                read_hash_provider(&*db_reader)?.unwrap_or_default(),
+                read_value_compression(&*db_reader)?,
+            )
+        };
+        let root_hash =
+            merkle_root.map_or_else(|| initialize_db(&*db, hash_provider), |s| Ok(s.into()))?;
+        let root_node = read_node(&*db, cache.as_ref(), &root_hash)?;
 
         Ok(MerkleRadixTree {
             root_hash,
             db,
             root_node,
+            cache,
+            hash_provider,
+            compression,
+            metrics,
         })
     }
 
@@ -278,6 +483,16 @@ impl MerkleRadixTree {
             }
         }))
     }
+    /// Returns the provider of the hashes of this MerkleRadixTree's nodes
+    pub fn hash_provider(&self) -> HashProvider {
+        self.hash_provider
+    }
+
+    /// Returns the compression of the values written by this MerkleRadixTree, if any
+    pub fn value_compression(&self) -> Option<ValueCompression> {
+        self.compression
+    }
+
     /// Returns the current merkle root for this MerkleRadixTree
     pub fn get_merkle_root(&self) -> String {
         self.root_hash.clone()
@@ -289,7 +504,7 @@ impl MerkleRadixTree {
         merkle_root: S,
     ) -> Result<(), StateDatabaseError> {
         let new_root = merkle_root.into();
-        self.root_node = get_node_by_hash(&*self.db, &new_root)?;
+        self.root_node = read_node(&*self.db, self.cache.as_ref(), &new_root)?;
         self.root_hash = new_root;
         Ok(())
     }
@@ -306,6 +521,29 @@ impl MerkleRadixTree {
         state_changes: &[StateChange],
         is_virtual: bool,
     ) -> Result<String, StateDatabaseError> {
+        let start = Instant::now();
+        let pending = self.compute_commit(state_changes)?;
+
+        if !is_virtual {
+            self.store_changes(&pending)?;
+
+            if let Some(metrics) = &self.metrics {
+                metrics.increment_counter(STATE_COMMITS, 1);
+                metrics.increment_counter(STATE_CHANGES, state_changes.len() as u64);
+                metrics.increment_counter(STATE_NODES_WRITTEN, pending.nodes.len() as u64);
+                metrics.record_duration(STATE_COMMIT_DURATION, start.elapsed());
+            }
+        }
+
+        Ok(::hex::encode(&pending.successor))
+    }
+
+    /// Computes the nodes that applying the given changes to this tree would write, without
+    /// writing them.
+    pub(super) fn compute_commit(
+        &self,
+        state_changes: &[StateChange],
+    ) -> Result<PendingCommit, StateDatabaseError> {
         let mut path_map = HashMap::new();
 
         let mut deletions = HashSet::new();
@@ -316,22 +554,20 @@ impl MerkleRadixTree {
             match state_change {
                 StateChange::Set { key, value } => {
                     let tokens = tokenize_address(key);
-                    let mut set_path_map = self.get_path_by_tokens(&tokens, false)?;
-                    {
-                        let node = set_path_map
-                            .get_mut(key)
-                            .expect("Path map not correctly generated");
-                        node.value = Some(value.to_vec());
-                    }
+                    let set_path_map = self.get_path_by_tokens(&tokens, false)?;
                     for pkey in set_path_map.keys() {
                         additions.insert(pkey.clone());
                     }
-                    path_map.extend(set_path_map);
+                    merge_path(&mut path_map, set_path_map);
+                    let node = path_map
+                        .get_mut(key)
+                        .expect("Path map not correctly generated");
+                    node.value = Some(value.to_vec());
                 }
                 StateChange::Delete { key } => {
                     let tokens = tokenize_address(key);
                     let del_path_map = self.get_path_by_tokens(&tokens, true)?;
-                    path_map.extend(del_path_map);
+                    merge_path(&mut path_map, del_path_map);
                     delete_items.push(key);
                 }
             }
@@ -390,7 +626,7 @@ impl MerkleRadixTree {
             let node = path_map
                 .remove(&path)
                 .expect("Path map keys are out of sink");
-            let (hash_key, packed) = encode_and_hash(node)?;
+            let (hash_key, packed) = encode_and_hash(node, self.hash_provider, self.compression)?;
             key_hash = hash_key.clone();
 
             if path != "" {
@@ -409,77 +645,120 @@ impl MerkleRadixTree {
             batch.push((hash_key, packed));
         }
 
-        if !is_virtual {
-            let deletions: Vec<Vec<u8>> = deletions
+        Ok(PendingCommit {
+            // We expect this to be hex, since we generated it
+            parent: ::hex::decode(&self.root_hash).expect("Improper hex"),
+            successor: key_hash,
+            nodes: batch,
+            deletions: deletions
                 .iter()
-                // We expect this to be hex, since we generated it
                 .map(|s| ::hex::decode(s).expect("Improper hex"))
-                .collect();
-            self.store_changes(&key_hash, &batch, &deletions)?;
-        }
-
-        Ok(::hex::encode(key_hash))
+                .collect(),
+        })
     }
 
     /// Puts all the items into the database.
-    fn store_changes(
+    ///
+    /// The commit is first recorded in the write-ahead log, so that it may be recovered if it is
+    /// interrupted; see `MerkleState::open`.
+    fn store_changes(&self, pending: &PendingCommit) -> Result<(), StateDatabaseError> {
+        log_pending_commit(&*self.db, pending)?;
+        apply_pending_commit(&*self.db, pending)
+    }
+
+    pub fn get_value(&self, address: &str) -> Result<Option<Vec<u8>>, StateDatabaseError> {
+        match self.get_by_address(address) {
+            Ok(value) => Ok(value.value),
+            Err(StateDatabaseError::NotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the values at the given addresses, keyed by address. Addresses without a value
+    /// are absent from the result.
+    ///
+    /// The addresses are looked up together, one level of the tree at a time, so that the nodes
+    /// of each level are read from the database in a single batch.
+    pub fn get_values<S: AsRef<str>>(
         &self,
-        successor_root_hash: &[u8],
-        batch: &[(Vec<u8>, Vec<u8>)],
-        deletions: &[Vec<u8>],
-    ) -> Result<(), StateDatabaseError> {
-        let mut db_writer = self.db.get_writer()?;
+        addresses: &[S],
+    ) -> Result<HashMap<String, Vec<u8>>, StateDatabaseError> {
+        let tokens: Vec<Box<[&str]>> = addresses
+            .iter()
+            .map(|address| tokenize_address(address.as_ref()))
+            .collect();
 
-        // We expect this to be hex, since we generated it
-        let root_hash_bytes = ::hex::decode(&self.root_hash).expect("Improper hex");
+        let mut values = HashMap::new();
+        let mut nodes = HashMap::new();
+        nodes.insert(self.root_hash.clone(), self.root_node.clone());
+        // The address being looked up, and the hash of its node at the current depth
+        let mut pending: Vec<(usize, String)> = (0..addresses.len())
+            .map(|i| (i, self.root_hash.clone()))
+            .collect();
 
-        for &(ref key, ref value) in batch {
-            match db_writer.put(::hex::encode(key).as_bytes(), &value) {
-                Ok(_) => continue,
-                Err(DatabaseError::DuplicateEntry) => {
-                    increment_ref_count(&mut *db_writer, key)?;
+        let mut depth = 0;
+        while !pending.is_empty() {
+            let mut next = Vec::with_capacity(pending.len());
+            for (i, hash) in pending {
+                // As with get_value, an address whose path is missing a node has no value
+                let node = match nodes.get(&hash) {
+                    Some(node) => node,
+                    None => continue,
+                };
+                match tokens[i].get(depth) {
+                    Some(token) => {
+                        if let Some(child_hash) = node.children.get(*token) {
+                            next.push((i, child_hash.clone()));
+                        }
+                    }
+                    None => {
+                        if let Some(value) = &node.value {
+                            values.insert(addresses[i].as_ref().to_string(), value.clone());
+                        }
+                    }
                 }
-                Err(err) => return Err(StateDatabaseError::from(err)),
             }
+            nodes = self.read_nodes(next.iter().map(|(_, hash)| hash.as_str()))?;
+            pending = next;
+            depth += 1;
         }
 
-        let mut current_change_log = get_change_log(db_writer.as_reader(), &root_hash_bytes)?;
-        if let Some(change_log) = current_change_log.as_mut() {
-            let successor = Successor {
-                successor: Vec::from(successor_root_hash),
-                deletions: deletions.to_vec(),
-            };
-            change_log.successors.push(successor);
-        }
-
-        let next_change_log = ChangeLogEntry {
-            parent: root_hash_bytes.clone(),
-            additions: batch
-                .iter()
-                .map(|&(ref hash, _)| hash.clone())
-                .collect::<Vec<Vec<u8>>>(),
-            successors: vec![],
-        };
+        Ok(values)
+    }
 
-        if current_change_log.is_some() {
-            write_change_log(
-                &mut *db_writer,
-                &root_hash_bytes,
-                &current_change_log.unwrap(),
-            )?;
+    /// Reads the nodes with the given hashes, through the cache if any, fetching those not
+    /// cached from the database in a single batch. Nodes missing from the database are absent
+    /// from the result.
+    fn read_nodes<'h, I: Iterator<Item = &'h str>>(
+        &self,
+        hashes: I,
+    ) -> Result<HashMap<String, Node>, StateDatabaseError> {
+        let mut nodes = HashMap::new();
+        let mut uncached = vec![];
+        for hash in hashes.collect::<BTreeSet<_>>() {
+            match self.cache.as_ref().and_then(|cache| cache.get(hash)) {
+                Some(node) => {
+                    nodes.insert(hash.to_string(), node);
+                }
+                None => uncached.push(hash),
+            }
+        }
+        if uncached.is_empty() {
+            return Ok(nodes);
         }
-        write_change_log(&mut *db_writer, successor_root_hash, &next_change_log)?;
-
-        db_writer.commit()?;
-        Ok(())
-    }
 
-    pub fn get_value(&self, address: &str) -> Result<Option<Vec<u8>>, StateDatabaseError> {
-        match self.get_by_address(address) {
-            Ok(value) => Ok(value.value),
-            Err(StateDatabaseError::NotFound(_)) => Ok(None),
-            Err(err) => Err(err),
+        let keys: Vec<&[u8]> = uncached.iter().map(|hash| hash.as_bytes()).collect();
+        let found = self.db.get_reader()?.get_multiple(&keys)?;
+        for (hash, bytes) in uncached.into_iter().zip(found) {
+            if let Some(bytes) = bytes {
+                let node = Node::from_bytes(&bytes)?;
+                if let Some(cache) = &self.cache {
+                    cache.insert(hash, node.clone());
+                }
+                nodes.insert(hash.to_string(), node);
+            }
         }
+        Ok(nodes)
     }
 
     fn get_by_address(&self, address: &str) -> Result<Node, StateDatabaseError> {
@@ -497,7 +776,7 @@ impl MerkleRadixTree {
                         self.root_hash.clone()
                     )));
                 }
-                Some(child_hash) => get_node_by_hash(&*self.db, child_hash)?,
+                Some(child_hash) => read_node(&*self.db, self.cache.as_ref(), child_hash)?,
             }
         }
         Ok(node)
@@ -511,10 +790,61 @@ impl MerkleRadixTree {
         }
     }
 
+    /// Returns an iterator over the address/value pairs under the given address prefix, in
+    /// address order.
+    ///
+    /// The prefix need not end on a whole token, and need not be present in the tree, in which
+    /// case the iterator is empty.
     pub fn leaves(&self, prefix: Option<&str>) -> Result<Box<StateIter>, StateDatabaseError> {
         Ok(Box::new(MerkleLeafIterator::new(self.clone(), prefix)?))
     }
 
+    /// Returns a proof of the value at the given address, or of its absence, under this tree's
+    /// current merkle root.
+    ///
+    /// The proof holds the encoded nodes on the path from the root towards the address, and can
+    /// be verified against the root hash alone; see `MerkleProof::verify`.
+    pub fn prove(&self, address: &str) -> Result<MerkleProof, StateDatabaseError> {
+        if address.len() % TOKEN_SIZE != 0 {
+            return Err(StateDatabaseError::NotFound(format!(
+                "invalid address {}",
+                address
+            )));
+        }
+        let tokens = tokenize_address(address);
+
+        let db_reader = self.db.get_reader()?;
+        let mut nodes = Vec::with_capacity(tokens.len() + 1);
+        let mut hash_key = self.root_hash.clone();
+        loop {
+            let bytes = db_reader
+                .get(hash_key.as_bytes())
+                .ok_or_else(|| StateDatabaseError::NotFound(hash_key.clone()))?;
+            let (node, compressed) = Node::decode(&bytes)?;
+            // A proof holds the encodings that were hashed, which are uncompressed
+            nodes.push(if compressed {
+                node.clone().into_bytes()?
+            } else {
+                bytes
+            });
+
+            // The path ends at the address itself, or at the node missing the next token.
+            match tokens
+                .get(nodes.len() - 1)
+                .and_then(|token| node.children.get(*token))
+            {
+                Some(child_hash) => hash_key = child_hash.clone(),
+                None => break,
+            }
+        }
+
+        Ok(MerkleProof::new(
+            address.to_string(),
+            nodes,
+            self.hash_provider,
+        ))
+    }
+
     fn get_path_by_tokens(
         &self,
         tokens: &[&str],
@@ -533,7 +863,7 @@ impl MerkleRadixTree {
                 let child_address = &nodes[&path].children.get(&token.to_string());
 
                 match (!new_branch && child_address.is_some(), strict) {
-                    (true, _) => get_node_by_hash(&*self.db, child_address.unwrap())?,
+                    (true, _) => read_node(&*self.db, self.cache.as_ref(), child_address.unwrap())?,
                     (false, true) => {
                         return Err(StateDatabaseError::NotFound(format!(
                             "invalid address {} from root {}",
@@ -555,22 +885,64 @@ impl MerkleRadixTree {
     }
 }
 
+/// Adds the nodes of a path read from the stored tree to the nodes of a commit.  Nodes already
+/// in the commit are kept, since an earlier change may have set the value of an interior node
+/// that the path passes through.
+fn merge_path(path_map: &mut HashMap<String, Node>, path: HashMap<String, Node>) {
+    for (address, node) in path {
+        path_map.entry(address).or_insert(node);
+    }
+}
+
 // A MerkleLeafIterator is fixed to iterate over the state address/value pairs
 // the merkle root hash at the time of its creation.
+//
+// Only the subtree under the iterator's prefix is visited, and nodes are read from the database
+// as the iteration reaches them.
 pub struct MerkleLeafIterator {
-    merkle_db: MerkleRadixTree,
-    visited: VecDeque<(String, Node)>,
+    db: Box<dyn Database>,
+    cache: Option<NodeCache>,
+    // The nodes yet to be visited, with their addresses; the last is the next in address order.
+    pending: Vec<(String, PendingNode)>,
+}
+
+enum PendingNode {
+    Loaded(Node),
+    Stored(String),
 }
 
 impl MerkleLeafIterator {
     fn new(merkle_db: MerkleRadixTree, prefix: Option<&str>) -> Result<Self, StateDatabaseError> {
-        let path = prefix.unwrap_or("");
+        let prefix = prefix.unwrap_or("");
+
+        // A prefix may end part way through a token, in which case the iteration starts at the
+        // node of the whole tokens, and is limited to the children matching the partial token.
+        let (path, partial_token) = prefix.split_at(prefix.len() - prefix.len() % TOKEN_SIZE);
 
-        let mut visited = VecDeque::new();
-        let initial_node = merkle_db.get_by_address(path)?;
-        visited.push_front((path.to_string(), initial_node));
+        let mut pending = vec![];
+        match merkle_db.get_by_address(path) {
+            Ok(node) => {
+                if partial_token.is_empty() {
+                    pending.push((path.to_string(), PendingNode::Loaded(node)));
+                } else {
+                    for (child_path, hash_key) in node.children.into_iter().rev() {
+                        if child_path.starts_with(partial_token) {
+                            let child_address = format!("{}{}", path, child_path);
+                            pending.push((child_address, PendingNode::Stored(hash_key)));
+                        }
+                    }
+                }
+            }
+            // Nothing is stored under the prefix
+            Err(StateDatabaseError::NotFound(_)) => (),
+            Err(err) => return Err(err),
+        }
 
-        Ok(MerkleLeafIterator { merkle_db, visited })
+        Ok(MerkleLeafIterator {
+            db: merkle_db.db,
+            cache: merkle_db.cache,
+            pending,
+        })
     }
 }
 
@@ -578,37 +950,40 @@ impl Iterator for MerkleLeafIterator {
     type Item = Result<(String, Vec<u8>), StateDatabaseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.visited.is_empty() {
-            return None;
-        }
-
-        loop {
-            if let Some((path, node)) = self.visited.pop_front() {
-                if node.value.is_some() {
-                    return Some(Ok((path, node.value.unwrap())));
-                }
-
-                // Reverse the list, such that we have an in-order traversal of the
-                // children, based on the natural path order.
-                for (child_path, hash_key) in node.children.iter().rev() {
-                    let child = match get_node_by_hash(&*self.merkle_db.db, hash_key) {
+        while let Some((path, pending_node)) = self.pending.pop() {
+            let node = match pending_node {
+                PendingNode::Loaded(node) => node,
+                PendingNode::Stored(hash_key) => {
+                    match read_node(&*self.db, self.cache.as_ref(), &hash_key) {
                         Ok(node) => node,
                         Err(err) => return Some(Err(err)),
-                    };
-                    let mut child_address = path.clone();
-                    child_address.push_str(child_path);
-                    self.visited.push_front((child_address, child));
+                    }
                 }
-            } else {
-                return None;
+            };
+
+            // Push the children in reverse, such that they are visited in the natural path
+            // order.
+            for (child_path, hash_key) in node.children.into_iter().rev() {
+                let child_address = format!("{}{}", path, child_path);
+                self.pending
+                    .push((child_address, PendingNode::Stored(hash_key)));
+            }
+
+            if let Some(value) = node.value {
+                return Some(Ok((path, value)));
             }
         }
+
+        None
     }
 }
 
 /// Initializes a database with an empty Trie
-fn initialize_db(db: &dyn Database) -> Result<String, StateDatabaseError> {
-    let (hash, packed) = encode_and_hash(Node::default())?;
+fn initialize_db(
+    db: &dyn Database,
+    hash_provider: HashProvider,
+) -> Result<String, StateDatabaseError> {
+    let (hash, packed) = encode_and_hash(Node::default(), hash_provider, None)?;
 
     let mut db_writer = db.get_writer()?;
     let hex_hash = ::hex::encode(hash);
@@ -619,8 +994,77 @@ fn initialize_db(db: &dyn Database) -> Result<String, StateDatabaseError> {
     Ok(hex_hash)
 }
 
+/// Writes the nodes and change log entries of a commit from `root_hash` to
+/// `successor_root_hash`.
+pub(super) fn apply_changes(
+    db_writer: &mut dyn DatabaseWriter,
+    root_hash: &[u8],
+    successor_root_hash: &[u8],
+    batch: &[(Vec<u8>, Vec<u8>)],
+    deletions: &[Vec<u8>],
+) -> Result<(), StateDatabaseError> {
+    put_nodes(db_writer, batch)?;
+    record_commit(
+        db_writer,
+        root_hash,
+        successor_root_hash,
+        batch.iter().map(|(hash, _)| hash.clone()).collect(),
+        deletions,
+    )
+}
+
+/// Writes the given nodes, keyed by hash, counting a reference to any node that is already
+/// present.
+pub(super) fn put_nodes(
+    db_writer: &mut dyn DatabaseWriter,
+    batch: &[(Vec<u8>, Vec<u8>)],
+) -> Result<(), StateDatabaseError> {
+    for &(ref key, ref value) in batch {
+        match db_writer.put(::hex::encode(key).as_bytes(), &value) {
+            Ok(_) => continue,
+            Err(DatabaseError::DuplicateEntry) => {
+                increment_ref_count(db_writer, key)?;
+            }
+            Err(err) => return Err(StateDatabaseError::from(err)),
+        }
+    }
+    Ok(())
+}
+
+/// Records in the change log that `successor_root_hash` was committed from `root_hash`, adding
+/// the nodes with the given hashes and removing those in `deletions`.
+pub(super) fn record_commit(
+    db_writer: &mut dyn DatabaseWriter,
+    root_hash: &[u8],
+    successor_root_hash: &[u8],
+    additions: Vec<Vec<u8>>,
+    deletions: &[Vec<u8>],
+) -> Result<(), StateDatabaseError> {
+    let mut current_change_log = get_change_log(db_writer.as_reader(), root_hash)?;
+    if let Some(change_log) = current_change_log.as_mut() {
+        let successor = Successor {
+            successor: Vec::from(successor_root_hash),
+            deletions: deletions.to_vec(),
+        };
+        change_log.successors.push(successor);
+    }
+
+    let next_change_log = ChangeLogEntry {
+        parent: root_hash.to_vec(),
+        additions,
+        successors: vec![],
+    };
+
+    if current_change_log.is_some() {
+        write_change_log(db_writer, root_hash, &current_change_log.unwrap())?;
+    }
+    write_change_log(db_writer, successor_root_hash, &next_change_log)?;
+
+    Ok(())
+}
+
 /// Returns the change log entry for a given root hash.
-fn get_change_log(
+pub(super) fn get_change_log(
     db_reader: &dyn DatabaseReader,
     root_hash: &[u8],
 ) -> Result<Option<ChangeLogEntry>, StateDatabaseError> {
@@ -635,7 +1079,7 @@ fn get_change_log(
 }
 
 /// Writes the given change log entry to the database
-fn write_change_log(
+pub(super) fn write_change_log(
     db_writer: &mut dyn DatabaseWriter,
     root_hash: &[u8],
     change_log: &ChangeLogEntry,
@@ -669,7 +1113,10 @@ fn decrement_ref_count(
     })
 }
 
-fn get_ref_count(db_reader: &dyn DatabaseReader, key: &[u8]) -> Result<u64, StateDatabaseError> {
+pub(super) fn get_ref_count(
+    db_reader: &dyn DatabaseReader,
+    key: &[u8],
+) -> Result<u64, StateDatabaseError> {
     Ok(
         if let Some(ref_count) = db_reader.index_get(DUPLICATE_LOG_INDEX, key)? {
             from_bytes(&ref_count)
@@ -689,8 +1136,8 @@ fn from_bytes(bytes: &[u8]) -> u64 {
     u64::from_le(unsafe { ::std::mem::transmute(num_bytes) })
 }
 
-/// This delete ignores any MDB_NOTFOUND errors
-fn delete_ignore_missing(
+/// This delete ignores any MDB_NOTFOUND or NotFoundError errors
+pub(super) fn delete_ignore_missing(
     db_writer: &mut dyn DatabaseWriter,
     key: &[u8],
 ) -> Result<(), StateDatabaseError> {
@@ -705,16 +1152,47 @@ fn delete_ignore_missing(
             );
             Ok(())
         }
+        Err(DatabaseError::NotFoundError(_)) => {
+            debug!(
+                "Attempting to delete a missing entry: {}",
+                ::hex::encode(key)
+            );
+            Ok(())
+        }
         Err(err) => Err(StateDatabaseError::DatabaseError(err)),
         Ok(_) => Ok(()),
     }
 }
 
-/// Encodes the given node, and returns the hash of the bytes.
-fn encode_and_hash(node: Node) -> Result<(Vec<u8>, Vec<u8>), StateDatabaseError> {
-    let packed = node.into_bytes()?;
-    let hash = hash(&packed);
-    Ok((hash, packed))
+/// Encodes the given node for storage, compressing its value if the given compression applies,
+/// and returns the hash of its uncompressed encoding with the stored bytes.
+pub(super) fn encode_and_hash(
+    node: Node,
+    hash_provider: HashProvider,
+    compression: Option<ValueCompression>,
+) -> Result<(Vec<u8>, Vec<u8>), StateDatabaseError> {
+    let compressed = match (compression, &node.value) {
+        (Some(compression), Some(value)) => compression
+            .compress(value)?
+            .map(|compressed| (compression.algorithm, compressed)),
+        _ => None,
+    };
+
+    match compressed {
+        Some((algorithm, compressed)) => {
+            let Node { value, children } = node;
+            let hash = hash_provider.hash(&encode_node(value, children.clone(), None)?);
+            Ok((
+                hash,
+                encode_node(Some(compressed), children, Some(algorithm))?,
+            ))
+        }
+        None => {
+            let packed = node.into_bytes()?;
+            let hash = hash_provider.hash(&packed);
+            Ok((hash, packed))
+        }
+    }
 }
 
 /// Given a path, split it into its parent's path and the specific branch for
@@ -736,7 +1214,7 @@ fn parent_and_branch(path: &str) -> (&str, &str) {
 }
 
 /// Splits an address into tokens
-fn tokenize_address(address: &str) -> Box<[&str]> {
+pub(super) fn tokenize_address(address: &str) -> Box<[&str]> {
     let mut tokens: Vec<&str> = Vec::with_capacity(address.len() / TOKEN_SIZE);
     let mut i = 0;
     while i < address.len() {
@@ -747,68 +1225,83 @@ fn tokenize_address(address: &str) -> Box<[&str]> {
 }
 
 /// Fetch a node by its hash
-fn get_node_by_hash(db: &dyn Database, hash: &str) -> Result<Node, StateDatabaseError> {
+pub(super) fn get_node_by_hash(db: &dyn Database, hash: &str) -> Result<Node, StateDatabaseError> {
     match db.get_reader()?.get(hash.as_bytes()) {
         Some(bytes) => Node::from_bytes(&bytes),
         None => Err(StateDatabaseError::NotFound(hash.to_string())),
     }
 }
 
+/// Fetch a node by its hash, through the given cache if any
+fn read_node(
+    db: &dyn Database,
+    cache: Option<&NodeCache>,
+    hash: &str,
+) -> Result<Node, StateDatabaseError> {
+    let cache = match cache {
+        Some(cache) => cache,
+        None => return get_node_by_hash(db, hash),
+    };
+    if let Some(node) = cache.get(hash) {
+        return Ok(node);
+    }
+    let node = get_node_by_hash(db, hash)?;
+    cache.insert(hash, node.clone());
+    Ok(node)
+}
+
 /// Internal Node structure of the Radix tree
 #[derive(Default, Debug, PartialEq, Clone)]
-struct Node {
-    value: Option<Vec<u8>>,
-    children: BTreeMap<String, String>,
+pub(super) struct Node {
+    pub(super) value: Option<Vec<u8>>,
+    pub(super) children: BTreeMap<String, String>,
 }
 
 impl Node {
     /// Consumes this node and serializes it to bytes
     fn into_bytes(self) -> Result<Vec<u8>, StateDatabaseError> {
-        let mut e = GenericEncoder::new(Cursor::new(Vec::new()));
-
-        let mut map = BTreeMap::new();
-        map.insert(
-            Key::Text(Text::Text("v".to_string())),
-            match self.value {
-                Some(bytes) => Value::Bytes(Bytes::Bytes(bytes)),
-                None => Value::Null,
-            },
-        );
-
-        let children = self
-            .children
-            .into_iter()
-            .map(|(k, v)| {
-                (
-                    Key::Text(Text::Text(k.to_string())),
-                    Value::Text(Text::Text(v.to_string())),
-                )
-            })
-            .collect();
-
-        map.insert(Key::Text(Text::Text("c".to_string())), Value::Map(children));
-
-        e.value(&Value::Map(map))?;
-
-        Ok(e.into_inner().into_writer().into_inner())
+        encode_node(self.value, self.children, None)
     }
 
     /// Deserializes the given bytes to a Node
-    fn from_bytes(bytes: &[u8]) -> Result<Node, StateDatabaseError> {
+    pub(super) fn from_bytes(bytes: &[u8]) -> Result<Node, StateDatabaseError> {
+        Self::decode(bytes).map(|(node, _)| node)
+    }
+
+    /// Deserializes the given bytes to a Node, decompressing its value if it was stored
+    /// compressed, and returns whether it was.
+    fn decode(bytes: &[u8]) -> Result<(Node, bool), StateDatabaseError> {
         let input = Cursor::new(bytes);
         let mut decoder = GenericDecoder::new(cbor::Config::default(), input);
         let decoder_value = decoder.value()?;
-        let (val, children_raw) = match decoder_value {
+        let (val, children_raw, compression_raw) = match decoder_value {
             Value::Map(mut root_map) => (
                 root_map.remove(&Key::Text(Text::Text("v".to_string()))),
                 root_map.remove(&Key::Text(Text::Text("c".to_string()))),
+                root_map.remove(&Key::Text(Text::Text("z".to_string()))),
             ),
             _ => return Err(StateDatabaseError::InvalidRecord),
         };
 
-        let value = match val {
-            Some(Value::Bytes(Bytes::Bytes(bytes))) => Some(bytes),
-            Some(Value::Null) => None,
+        let compression = match compression_raw {
+            Some(Value::Text(Text::Text(name))) => {
+                Some(CompressionAlgorithm::from_name(&name).ok_or_else(|| {
+                    StateDatabaseError::CompressionError(format!(
+                        "Unknown compression algorithm: {}",
+                        name
+                    ))
+                })?)
+            }
+            None => None,
+            _ => return Err(StateDatabaseError::InvalidRecord),
+        };
+
+        let value = match (val, compression) {
+            (Some(Value::Bytes(Bytes::Bytes(bytes))), Some(algorithm)) => {
+                Some(algorithm.decompress(&bytes)?)
+            }
+            (Some(Value::Bytes(Bytes::Bytes(bytes))), None) => Some(bytes),
+            (Some(Value::Null), None) => None,
             _ => return Err(StateDatabaseError::InvalidRecord),
         };
 
@@ -824,10 +1317,52 @@ impl Node {
             _ => return Err(StateDatabaseError::InvalidRecord),
         };
 
-        Ok(Node { value, children })
+        Ok((Node { value, children }, compression.is_some()))
     }
 }
 
+/// Serializes a node with the given value and children, recording the algorithm that compressed
+/// the value, if any.
+fn encode_node(
+    value: Option<Vec<u8>>,
+    children: BTreeMap<String, String>,
+    compression: Option<CompressionAlgorithm>,
+) -> Result<Vec<u8>, StateDatabaseError> {
+    let mut e = GenericEncoder::new(Cursor::new(Vec::new()));
+
+    let mut map = BTreeMap::new();
+    map.insert(
+        Key::Text(Text::Text("v".to_string())),
+        match value {
+            Some(bytes) => Value::Bytes(Bytes::Bytes(bytes)),
+            None => Value::Null,
+        },
+    );
+
+    let children = children
+        .into_iter()
+        .map(|(k, v)| {
+            (
+                Key::Text(Text::Text(k.to_string())),
+                Value::Text(Text::Text(v.to_string())),
+            )
+        })
+        .collect();
+
+    map.insert(Key::Text(Text::Text("c".to_string())), Value::Map(children));
+
+    if let Some(algorithm) = compression {
+        map.insert(
+            Key::Text(Text::Text("z".to_string())),
+            Value::Text(Text::Text(algorithm.name().to_string())),
+        );
+    }
+
+    e.value(&Value::Map(map))?;
+
+    Ok(e.into_inner().into_writer().into_inner())
+}
+
 /// Converts a CBOR Key to its String content
 fn key_to_string(key_val: Key) -> Result<String, StateDatabaseError> {
     match key_val {
@@ -844,20 +1379,13 @@ fn text_to_string(text_val: Value) -> Result<String, StateDatabaseError> {
     }
 }
 
-/// Creates a hash of the given bytes
-fn hash(input: &[u8]) -> Vec<u8> {
-    let mut bytes: Vec<u8> = Vec::new();
-    bytes.extend(openssl::sha::sha512(input).iter());
-    let (hash, _rest) = bytes.split_at(bytes.len() / 2);
-    hash.to_vec()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::database::btree::BTreeDatabase;
     use crate::database::error::DatabaseError;
     use crate::database::lmdb::{LmdbContext, LmdbDatabase};
+    use crate::metrics::InMemoryMetrics;
 
     use super::StateChange;
     use crate::state::change_log::ChangeLogEntry;
@@ -937,7 +1465,7 @@ mod tests {
     fn merkle_trie_root_advance() {
         run_test(|merkle_path| {
             let db = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
 
             let orig_root = merkle_db.get_merkle_root();
@@ -988,7 +1516,7 @@ mod tests {
     fn merkle_trie_delete() {
         run_test(|merkle_path| {
             let db = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
 
             let state_change_set = StateChange::Set {
@@ -1030,7 +1558,7 @@ mod tests {
     fn merkle_trie_update() {
         run_test(|merkle_path| {
             let db = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
 
             let init_root = merkle_db.get_merkle_root();
@@ -1121,7 +1649,7 @@ mod tests {
     fn merkle_trie_update_same_address_space() {
         run_test(|merkle_path| {
             let db = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
 
             let init_root = merkle_db.get_merkle_root();
@@ -1242,7 +1770,7 @@ mod tests {
     fn merkle_trie_update_same_address_space_with_no_children() {
         run_test(|merkle_path| {
             let db = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).unwrap();
 
             let init_root = merkle_db.get_merkle_root();
@@ -1368,7 +1896,7 @@ mod tests {
     fn merkle_trie_pruning_parent() {
         run_test(|merkle_path| {
             let db: Box<dyn Database> = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).expect("No db errors");
             let mut updates: Vec<StateChange> = Vec::with_capacity(3);
 
@@ -1471,7 +1999,7 @@ mod tests {
     fn merkle_trie_pruning_successors() {
         run_test(|merkle_path| {
             let db: Box<dyn Database> = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).expect("No db errors");
             let mut updates: Vec<StateChange> = Vec::with_capacity(3);
 
@@ -1554,7 +2082,7 @@ mod tests {
     fn merkle_trie_pruning_duplicate_leaves() {
         run_test(|merkle_path| {
             let db: Box<dyn Database> = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).expect("No db errors");
             let mut updates: Vec<StateChange> = Vec::with_capacity(3);
             updates.push(StateChange::Set {
@@ -1630,7 +2158,7 @@ mod tests {
     fn merkle_trie_pruning_successor_duplicate_leaves() {
         run_test(|merkle_path| {
             let db: Box<dyn Database> = make_lmdb(&merkle_path);
-            let merkle_state = MerkleState { db: db.clone() };
+            let merkle_state = MerkleState::new(db.clone());
             let mut merkle_db = MerkleRadixTree::new(db.clone(), None).expect("No db errors");
             let mut updates: Vec<StateChange> = Vec::with_capacity(3);
 
@@ -1742,6 +2270,18 @@ mod tests {
                 leaf_iter.next().unwrap().unwrap()
             );
             assert!(leaf_iter.next().is_none(), "Iterator should be Exhausted");
+
+            // test that a prefix may end part way through a token:
+            let leaves = merkle_db
+                .leaves(Some("aba"))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(vec![("aba001".into(), "000a".as_bytes().to_vec())], leaves);
+
+            // test that a missing prefix yields no leaves:
+            let mut leaf_iter = merkle_db.leaves(Some("cd")).unwrap();
+            assert!(leaf_iter.next().is_none(), "Iterator should be Exhausted");
         })
     }
 
@@ -1866,6 +2406,198 @@ mod tests {
         }
     }
 
+    #[test]
+    // test that a MerkleState given a recorder reports its reads and commits
+    fn merkle_state_metrics() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let initial_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+        let metrics = Arc::new(InMemoryMetrics::new());
+        let merkle_state = MerkleState::new(db).with_metrics(metrics.clone());
+
+        let root = merkle_state
+            .commit(
+                &initial_root,
+                &[
+                    StateChange::Set {
+                        key: "ab0000".into(),
+                        value: b"value".to_vec(),
+                    },
+                    StateChange::Set {
+                        key: "ab0001".into(),
+                        value: b"value".to_vec(),
+                    },
+                ],
+            )
+            .unwrap();
+        merkle_state
+            .get(&root, &["ab0000".to_string(), "cd0000".to_string()])
+            .unwrap();
+
+        assert_eq!(metrics.counter(STATE_COMMITS), 1);
+        assert_eq!(metrics.counter(STATE_CHANGES), 2);
+        // The two leaves, their parents "ab00" and "ab", and the root
+        assert_eq!(metrics.counter(STATE_NODES_WRITTEN), 5);
+        assert_eq!(metrics.histogram(STATE_COMMIT_DURATION).unwrap().count, 1);
+        assert_eq!(metrics.counter(STATE_READS), 2);
+        assert_eq!(metrics.histogram(STATE_READ_DURATION).unwrap().count, 1);
+    }
+
+    #[test]
+    fn merkle_trie_get_values() {
+        let mut merkle_db = MerkleRadixTree::new(Box::new(BTreeDatabase::new(&INDEXES)), None)
+            .expect("No db errors");
+        let root = merkle_db
+            .update(
+                &[
+                    StateChange::Set {
+                        key: "ab0000".into(),
+                        value: b"first".to_vec(),
+                    },
+                    StateChange::Set {
+                        key: "ab0001".into(),
+                        value: b"second".to_vec(),
+                    },
+                    StateChange::Set {
+                        key: "cd0000".into(),
+                        value: b"third".to_vec(),
+                    },
+                ],
+                false,
+            )
+            .unwrap();
+        merkle_db.set_merkle_root(root).unwrap();
+
+        // Includes an address without a leaf, an interior node, and a duplicate
+        let values = merkle_db
+            .get_values(&["ab0001", "ab0000", "ef0000", "ab00", "cd0000", "ab0000"])
+            .unwrap();
+
+        assert_eq!(values.len(), 3);
+        assert_eq!(values["ab0000"], b"first".to_vec());
+        assert_eq!(values["ab0001"], b"second".to_vec());
+        assert_eq!(values["cd0000"], b"third".to_vec());
+    }
+
+    #[test]
+    fn merkle_state_range() {
+        let db = Box::new(BTreeDatabase::new(&INDEXES));
+        let initial_root = MerkleRadixTree::new(db.clone(), None)
+            .unwrap()
+            .get_merkle_root();
+        let merkle_state = MerkleState::new(db);
+        let root = merkle_state
+            .commit(
+                &initial_root,
+                &[
+                    StateChange::Set {
+                        key: "ab0001".into(),
+                        value: b"second".to_vec(),
+                    },
+                    StateChange::Set {
+                        key: "ab0000".into(),
+                        value: b"first".to_vec(),
+                    },
+                    StateChange::Set {
+                        key: "cd0000".into(),
+                        value: b"third".to_vec(),
+                    },
+                ],
+            )
+            .unwrap();
+
+        let entries = merkle_state
+            .range(&root, &"ab0".to_string())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("ab0000".to_string(), b"first".to_vec()),
+                ("ab0001".to_string(), b"second".to_vec()),
+            ]
+        );
+
+        match merkle_state.range(&"00".repeat(32), &"ab".to_string()) {
+            Err(StateReadError::InvalidStateId(_)) => (),
+            Err(err) => panic!("Expected InvalidStateId, got {}", err),
+            Ok(_) => panic!("Expected InvalidStateId"),
+        }
+    }
+
+    #[test]
+    fn merkle_state_compute_state_root() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let initial_root = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let merkle_state = MerkleState::new(Box::new(db.clone()));
+        let changes = [
+            StateChange::Set {
+                key: "ab0000".into(),
+                value: b"value".to_vec(),
+            },
+            StateChange::Set {
+                key: "cd0000".into(),
+                value: b"value".to_vec(),
+            },
+        ];
+
+        let entry_count = |db: &BTreeDatabase| {
+            let reader = db.get_reader().unwrap();
+            reader.count().unwrap()
+                + INDEXES
+                    .iter()
+                    .map(|index| reader.index_count(index).unwrap())
+                    .sum::<usize>()
+        };
+        let count_before = entry_count(&db);
+
+        let computed = merkle_state
+            .compute_state_root(&initial_root, &changes)
+            .unwrap();
+        assert_eq!(entry_count(&db), count_before);
+        assert!(merkle_state
+            .get(&computed, &["ab0000".to_string()])
+            .is_err());
+
+        let committed = merkle_state.commit(&initial_root, &changes).unwrap();
+        assert_eq!(computed, committed);
+    }
+
+    #[test]
+    // test that a single commit may set the value of an interior node and of the nodes under it
+    fn merkle_state_commit_interior_value() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let initial_root = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let merkle_state = MerkleState::new(Box::new(db));
+        let interior = StateChange::Set {
+            key: "ab".into(),
+            value: b"interior".to_vec(),
+        };
+        let leaf = StateChange::Set {
+            key: "ab0000".into(),
+            value: b"leaf".to_vec(),
+        };
+
+        let together = merkle_state
+            .commit(&initial_root, &[interior.clone(), leaf.clone()])
+            .unwrap();
+        let first = merkle_state.commit(&initial_root, &[interior]).unwrap();
+        let separately = merkle_state.commit(&first, &[leaf]).unwrap();
+        assert_eq!(together, separately);
+
+        let values = merkle_state
+            .get(&together, &["ab".to_string(), "ab0000".to_string()])
+            .unwrap();
+        assert_eq!(values.get("ab"), Some(&b"interior".to_vec()));
+        assert_eq!(values.get("ab0000"), Some(&b"leaf".to_vec()));
+    }
+
     fn make_lmdb(merkle_path: &str) -> Box<LmdbDatabase> {
         let ctx = LmdbContext::new(
             Path::new(merkle_path),
@@ -1894,7 +2626,7 @@ mod tests {
     }
 
     fn hex_hash(b: &[u8]) -> String {
-        ::hex::encode(hash(b))
+        ::hex::encode(HashProvider::default().hash(b))
     }
 
 }
@@ -33,6 +33,8 @@ pub enum StateDatabaseError {
     InvalidRecord,
     InvalidHash(String),
     InvalidChangeLogIndex(String),
+    InvalidAddress(String),
+    CompressionError(String),
     DatabaseError(DatabaseError),
     ProtobufConversionError(ProtoConversionError),
     UnknownError,
@@ -58,6 +60,10 @@ impl fmt::Display for StateDatabaseError {
             StateDatabaseError::InvalidChangeLogIndex(ref msg) => {
                 write!(f, "A change log entry was missing or malformed: {}", msg)
             }
+            StateDatabaseError::InvalidAddress(ref msg) => write!(f, "Invalid address: {}", msg),
+            StateDatabaseError::CompressionError(ref msg) => {
+                write!(f, "Unable to compress or decompress a value: {}", msg)
+            }
             StateDatabaseError::DatabaseError(ref err) => {
                 write!(f, "A database error occurred: {}", err)
             }
@@ -79,6 +85,8 @@ impl Error for StateDatabaseError {
             StateDatabaseError::InvalidRecord => "Invalid record",
             StateDatabaseError::InvalidHash(ref msg) => &msg,
             StateDatabaseError::InvalidChangeLogIndex(ref msg) => &msg,
+            StateDatabaseError::InvalidAddress(ref msg) => &msg,
+            StateDatabaseError::CompressionError(ref msg) => &msg,
             StateDatabaseError::DatabaseError(ref err) => err.description(),
             StateDatabaseError::ProtobufConversionError(ref err) => err.description(),
             StateDatabaseError::UnknownError => "Unknown Error",
@@ -94,6 +102,8 @@ impl Error for StateDatabaseError {
             StateDatabaseError::InvalidRecord => None,
             StateDatabaseError::InvalidHash(_) => None,
             StateDatabaseError::InvalidChangeLogIndex(_) => None,
+            StateDatabaseError::InvalidAddress(_) => None,
+            StateDatabaseError::CompressionError(_) => None,
             StateDatabaseError::DatabaseError(ref err) => Some(err),
             StateDatabaseError::ProtobufConversionError(ref err) => Some(err),
             StateDatabaseError::UnknownError => None,
@@ -22,15 +22,34 @@
 //! `Write`, `Read`, and `Prune`.  These provide commit, read access,
 //! and a way to purge old state, respectively, to an underlying storage mechanism.
 
+#[cfg(feature = "async")]
+pub mod async_state;
+pub mod bulk;
 pub mod change_log;
+pub mod compression;
+pub mod diff;
 pub mod error;
+pub mod fork;
+pub mod gc;
+pub mod hash_provider;
 pub mod hashmap;
+pub mod history;
 pub mod merkle;
 mod merkle_error;
+pub mod node_cache;
+pub mod overlay;
+pub mod proof;
+pub mod pruner;
+pub mod snapshot;
+pub mod subscription;
+pub mod wal;
 
 pub use crate::state::error::{StatePruneError, StateReadError, StateWriteError};
 use std::collections::HashMap;
 
+/// An iterator over key/value pairs of state, as returned by `Read::range`.
+pub type ValueIter<K, V> = Box<dyn Iterator<Item = Result<(K, V), StateReadError>>>;
+
 /// A change to be applied to state, in terms of keys and values.
 ///
 /// A `StateChange` represents the basic level of changes that can be applied to
@@ -173,6 +192,27 @@ pub trait Read: Send + Send {
         keys: &[Self::Key],
     ) -> Result<HashMap<Self::Key, Self::Value>, StateReadError>;
 
+    /// At a given `StateId`, returns an iterator over the keys that start with the given prefix,
+    /// and their values, in key order.
+    ///
+    /// Implementations read the entries as the iteration proceeds where they can, rather than
+    /// collecting them up front.  The default implementation does not support ranges.
+    ///
+    /// # Errors
+    ///
+    /// `StateReadError` is returned if the state id is invalid, or, by the iterator, if any
+    /// issues occur while fetching the entries.  `StateReadError::Unsupported` is returned if
+    /// the implementation cannot enumerate keys.
+    fn range(
+        &self,
+        _state_id: &Self::StateId,
+        _prefix: &Self::Key,
+    ) -> Result<ValueIter<Self::Key, Self::Value>, StateReadError> {
+        Err(StateReadError::Unsupported(
+            "This state does not support reading ranges of keys".into(),
+        ))
+    }
+
     fn clone_box(&self)
         -> Box<Read<StateId = Self::StateId, Key = Self::Key, Value = Self::Value>>;
 }
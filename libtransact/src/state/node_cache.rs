@@ -0,0 +1,351 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! An in-memory cache of decoded merkle nodes.
+//!
+//! Every read of merkle state walks from the root, so the upper levels of a trie are decoded
+//! again on each read.  A `NodeCache` keeps the most recently used nodes decoded, and may be
+//! shared by any number of `MerkleState` and `MerkleRadixTree` instances over the same database.
+//!
+//! Nodes are keyed by their hash, so a cached node can never be stale: a node with the same hash
+//! always has the same contents.
+//!
+//! The cache is divided into shards, each with its own lock and its own share of the capacity,
+//! so that readers at different state roots, which mostly read different nodes, rarely contend.
+//! Eviction is least-recently-used within each shard.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::merkle::Node;
+
+/// The number of shards of a cache constructed with `NodeCache::new`.
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Hit and miss counts of a `NodeCache`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NodeCacheStats {
+    /// The number of reads served from the cache.
+    pub hits: u64,
+    /// The number of reads that required a node to be read from the database.
+    pub misses: u64,
+    /// The number of nodes removed to make room for others.
+    pub evictions: u64,
+    /// The number of nodes currently cached.
+    pub entries: usize,
+}
+
+impl NodeCacheStats {
+    /// Returns the fraction of reads served from the cache, or zero if there have been none.
+    pub fn hit_rate(&self) -> f64 {
+        let reads = self.hits + self.misses;
+        if reads == 0 {
+            0.0
+        } else {
+            self.hits as f64 / reads as f64
+        }
+    }
+}
+
+/// A least-recently-used cache of decoded merkle nodes.
+///
+/// Cloning a `NodeCache` produces a handle to the same cache.
+#[derive(Clone)]
+pub struct NodeCache {
+    shards: Arc<Vec<Mutex<LruNodes>>>,
+}
+
+struct LruNodes {
+    capacity: usize,
+    // The nodes by hash, with the tick at which each was last used
+    nodes: HashMap<String, (Node, u64)>,
+    // The hashes by the tick at which they were last used, oldest first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    stats: NodeCacheStats,
+}
+
+impl NodeCache {
+    /// Constructs a cache holding at most `capacity` nodes, divided into `DEFAULT_SHARDS` shards.
+    ///
+    /// A capacity of zero disables caching, while still counting misses.
+    pub fn new(capacity: usize) -> Self {
+        Self::with_shards(capacity, DEFAULT_SHARDS)
+    }
+
+    /// Constructs a cache holding at most `capacity` nodes, divided into the given number of
+    /// shards.
+    ///
+    /// More shards allow more concurrent readers without contention, while fewer keep eviction
+    /// closer to least-recently-used over the whole cache; a single shard is exact.  The number
+    /// of shards is limited to the capacity, so that every shard can hold a node.
+    pub fn with_shards(capacity: usize, shards: usize) -> Self {
+        let shards = shards.min(capacity).max(1);
+        NodeCache {
+            shards: Arc::new(
+                (0..shards)
+                    .map(|i| {
+                        // Divide the capacity as evenly as possible
+                        let shard_capacity =
+                            capacity / shards + if i < capacity % shards { 1 } else { 0 };
+                        Mutex::new(LruNodes::new(shard_capacity))
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Returns the maximum number of nodes held by this cache.
+    pub fn capacity(&self) -> usize {
+        (0..self.shards.len())
+            .map(|i| self.lock_shard(i).capacity)
+            .sum()
+    }
+
+    /// Returns the cache's statistics since it was constructed.
+    pub fn stats(&self) -> NodeCacheStats {
+        (0..self.shards.len()).fold(NodeCacheStats::default(), |total, i| {
+            let lru = self.lock_shard(i);
+            NodeCacheStats {
+                hits: total.hits + lru.stats.hits,
+                misses: total.misses + lru.stats.misses,
+                evictions: total.evictions + lru.stats.evictions,
+                entries: total.entries + lru.nodes.len(),
+            }
+        })
+    }
+
+    /// Removes all cached nodes, keeping the statistics.
+    pub fn clear(&self) {
+        for i in 0..self.shards.len() {
+            let mut lru = self.lock_shard(i);
+            lru.nodes.clear();
+            lru.recency.clear();
+        }
+    }
+
+    /// Returns the node with the given hash, if cached, marking it as most recently used.
+    pub(super) fn get(&self, hash: &str) -> Option<Node> {
+        self.lock_shard(self.shard_of(hash)).get(hash)
+    }
+
+    /// Adds a node read from the database, evicting the least recently used node of its shard if
+    /// the shard is full.
+    pub(super) fn insert(&self, hash: &str, node: Node) {
+        self.lock_shard(self.shard_of(hash)).insert(hash, node)
+    }
+
+    fn shard_of(&self, hash: &str) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+        let mut hasher = DefaultHasher::new();
+        hash.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    fn lock_shard(&self, shard: usize) -> MutexGuard<LruNodes> {
+        // The cache holds no invariants that a panicking thread could break part way, so a
+        // poisoned lock is still usable.
+        self.shards[shard]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl LruNodes {
+    fn new(capacity: usize) -> Self {
+        LruNodes {
+            capacity,
+            nodes: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            stats: NodeCacheStats::default(),
+        }
+    }
+
+    fn get(&mut self, hash: &str) -> Option<Node> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        let previous_tick = match self.nodes.get_mut(hash) {
+            Some(entry) => std::mem::replace(&mut entry.1, tick),
+            None => {
+                self.stats.misses += 1;
+                return None;
+            }
+        };
+        self.stats.hits += 1;
+        self.recency.remove(&previous_tick);
+        self.recency.insert(tick, hash.to_string());
+        self.nodes.get(hash).map(|(node, _)| node.clone())
+    }
+
+    fn insert(&mut self, hash: &str, node: Node) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        let tick = self.tick;
+
+        if let Some((_, previous_tick)) = self.nodes.insert(hash.to_string(), (node, tick)) {
+            self.recency.remove(&previous_tick);
+        } else if self.nodes.len() > self.capacity {
+            let oldest = self.recency.keys().next().cloned();
+            if let Some(oldest) = oldest {
+                if let Some(evicted) = self.recency.remove(&oldest) {
+                    self.nodes.remove(&evicted);
+                    self.stats.evictions += 1;
+                }
+            }
+        }
+        self.recency.insert(tick, hash.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleRadixTree, MerkleState, INDEXES};
+    use crate::state::{Read, StateChange, Write};
+
+    fn node(value: &[u8]) -> Node {
+        Node {
+            value: Some(value.to_vec()),
+            children: BTreeMap::new(),
+        }
+    }
+
+    /// Verifies that the least recently used node is evicted, and that hits and misses are
+    /// counted.
+    #[test]
+    fn lru_eviction() {
+        // A single shard, so that eviction is exactly least-recently-used
+        let cache = NodeCache::with_shards(2, 1);
+        cache.insert("a", node(b"a"));
+        cache.insert("b", node(b"b"));
+
+        // Use "a", so "b" is the least recently used
+        assert_eq!(cache.get("a"), Some(node(b"a")));
+        cache.insert("c", node(b"c"));
+
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(node(b"a")));
+        assert_eq!(cache.get("c"), Some(node(b"c")));
+
+        assert_eq!(
+            cache.stats(),
+            NodeCacheStats {
+                hits: 3,
+                misses: 1,
+                evictions: 1,
+                entries: 2,
+            }
+        );
+        assert!((cache.stats().hit_rate() - 0.75).abs() < std::f64::EPSILON);
+
+        cache.clear();
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    /// Verifies that reads through a cached MerkleState are served from the cache when repeated.
+    #[test]
+    fn cached_state_reads() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let initial_root = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+
+        let cache = NodeCache::new(64);
+        let state = MerkleState::new(Box::new(db)).with_node_cache(cache.clone());
+        let root = state
+            .commit(
+                &initial_root,
+                &[StateChange::Set {
+                    key: "ab0000".into(),
+                    value: b"value".to_vec(),
+                }],
+            )
+            .unwrap();
+
+        let keys = vec!["ab0000".to_string()];
+        let first = state.get(&root, &keys).unwrap();
+        assert_eq!(first.get("ab0000"), Some(&b"value".to_vec()));
+        let NodeCacheStats { hits, misses, .. } = cache.stats();
+
+        // A clone of the state shares its cache
+        let second = state.clone().get(&root, &keys).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(cache.stats().misses, misses);
+        assert!(cache.stats().hits > hits);
+    }
+
+    /// Verifies that the capacity is divided among the shards, and that readers at different
+    /// state roots may share a cache from several threads.
+    #[test]
+    fn concurrent_sharded_reads() {
+        assert_eq!(NodeCache::with_shards(10, 4).capacity(), 10);
+        assert_eq!(NodeCache::with_shards(3, 16).capacity(), 3);
+        assert_eq!(NodeCache::new(0).capacity(), 0);
+
+        let db = BTreeDatabase::new(&INDEXES);
+        let mut root = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+
+        let cache = NodeCache::new(256);
+        let state = MerkleState::new(Box::new(db)).with_node_cache(cache.clone());
+        let mut roots = vec![];
+        for i in 0..8u8 {
+            root = state
+                .commit(
+                    &root,
+                    &[StateChange::Set {
+                        key: format!("ab{:02x}00", i),
+                        value: vec![i],
+                    }],
+                )
+                .unwrap();
+            roots.push(root.clone());
+        }
+
+        let handles: Vec<_> = roots
+            .into_iter()
+            .enumerate()
+            .map(|(i, root)| {
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    let keys = vec![format!("ab{:02x}00", i)];
+                    for _ in 0..16 {
+                        let values = state.get(&root, &keys).unwrap();
+                        assert_eq!(values.get(&keys[0]), Some(&vec![i as u8]));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert!(cache.stats().hits > 0);
+    }
+}
@@ -0,0 +1,562 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Staged, speculative writes over a base state.
+//!
+//! An `OverlayState` collects the changes committed to it in memory, as a stack of layers over a
+//! root of a base state.  Reads see the changes of the layers, falling through to the base for
+//! anything they have not changed.  Once the speculation is settled, the layers are either
+//! materialized into a single commit to the base, or discarded without the base ever having
+//! been written.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::iter::Peekable;
+use std::sync::{Arc, Mutex};
+use std::vec;
+
+use super::error::{StateReadError, StateWriteError};
+use super::{Read, StateChange, ValueIter, Write};
+
+/// The changes made by the layers of an overlay, where `None` marks a deleted key.
+type Changes = BTreeMap<String, Option<Vec<u8>>>;
+
+/// A layer of changes, and the state id it produces.
+struct Layer {
+    state_id: String,
+    changes: Changes,
+}
+
+struct Overlay {
+    base_state_id: String,
+    layers: Vec<Layer>,
+}
+
+impl Overlay {
+    fn top_state_id(&self) -> &str {
+        self.layers
+            .last()
+            .map(|layer| layer.state_id.as_str())
+            .unwrap_or(&self.base_state_id)
+    }
+
+    /// Returns the number of layers up to and including the one producing the given state id,
+    /// with zero for the base state id.
+    fn depth_of(&self, state_id: &str) -> Option<usize> {
+        if state_id == self.base_state_id {
+            return Some(0);
+        }
+        self.layers
+            .iter()
+            .position(|layer| layer.state_id == state_id)
+            .map(|position| position + 1)
+    }
+
+    /// Returns the combined changes of the given number of layers.
+    fn changes(&self, depth: usize) -> Changes {
+        self.layers[..depth]
+            .iter()
+            .fold(Changes::new(), |mut changes, layer| {
+                changes.extend(layer.changes.clone());
+                changes
+            })
+    }
+}
+
+/// A state that holds written changes in memory, over a root of a base state.
+///
+/// Each commit to the overlay adds a layer; its state id is the id the base would produce for the
+/// combined changes of the layers, so ids may be compared with those of real commits.  Commits
+/// must be made on top of the most recent layer, or on the base state id if there are none.
+///
+/// Cloning an `OverlayState` produces a handle to the same layers.
+#[derive(Clone)]
+pub struct OverlayState<S> {
+    base: S,
+    overlay: Arc<Mutex<Overlay>>,
+}
+
+impl<S> OverlayState<S>
+where
+    S: Read<StateId = String, Key = String, Value = Vec<u8>>
+        + Write<StateId = String, Key = String, Value = Vec<u8>>,
+{
+    /// Constructs an overlay, without any layers, over the given root of the base state.
+    pub fn new(base: S, base_state_id: String) -> Self {
+        OverlayState {
+            base,
+            overlay: Arc::new(Mutex::new(Overlay {
+                base_state_id,
+                layers: vec![],
+            })),
+        }
+    }
+
+    /// Returns the root of the base state the overlay's layers apply to.
+    pub fn base_state_id(&self) -> String {
+        self.lock().base_state_id.clone()
+    }
+
+    /// Returns the state id of the most recent layer, or the base state id if there are none.
+    pub fn state_id(&self) -> String {
+        self.lock().top_state_id().to_string()
+    }
+
+    /// Returns the number of layers.
+    pub fn depth(&self) -> usize {
+        self.lock().layers.len()
+    }
+
+    /// Returns the combined changes of all layers, relative to the base state.
+    pub fn changes(&self) -> Result<Vec<StateChange>, StateReadError> {
+        let overlay = self.lock();
+        let changes = overlay.changes(overlay.layers.len());
+        self.effective_changes(&overlay.base_state_id, changes)
+    }
+
+    /// Removes the layers above the one producing the given state id; given the base state id,
+    /// all layers are removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `InvalidStateId` if no layer produces the given state id.
+    pub fn discard_to(&self, state_id: &str) -> Result<(), StateWriteError> {
+        let mut overlay = self.lock();
+        let depth = overlay.depth_of(state_id).ok_or_else(|| {
+            StateWriteError::InvalidStateId(format!("Unknown state id {}", state_id))
+        })?;
+        overlay.layers.truncate(depth);
+        Ok(())
+    }
+
+    /// Removes all layers, leaving only the base state.
+    pub fn discard(&self) {
+        self.lock().layers.clear();
+    }
+
+    /// Commits the combined changes of all layers to the base state, and returns the resulting
+    /// state id.  The overlay is left without layers, over the new state id.
+    pub fn materialize(&self) -> Result<String, StateWriteError> {
+        let mut overlay = self.lock();
+        let changes = overlay.changes(overlay.layers.len());
+        let state_changes = self
+            .effective_changes(&overlay.base_state_id, changes)
+            .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
+
+        let state_id = self.base.commit(&overlay.base_state_id, &state_changes)?;
+        overlay.base_state_id = state_id.clone();
+        overlay.layers.clear();
+        Ok(state_id)
+    }
+
+    /// Converts combined changes into state changes for the base.
+    ///
+    /// A key may be set and then deleted within the overlay; deletions of keys that are not in
+    /// the base state are dropped, since the base would reject them.
+    fn effective_changes(
+        &self,
+        base_state_id: &str,
+        changes: Changes,
+    ) -> Result<Vec<StateChange>, StateReadError> {
+        let deleted = changes
+            .iter()
+            .filter(|(_, value)| value.is_none())
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        let present = if deleted.is_empty() {
+            HashMap::new()
+        } else {
+            self.base.get(&base_state_id.to_string(), &deleted)?
+        };
+
+        Ok(changes
+            .into_iter()
+            .filter_map(|(key, value)| match value {
+                Some(value) => Some(StateChange::Set { key, value }),
+                None if present.contains_key(&key) => Some(StateChange::Delete { key }),
+                None => None,
+            })
+            .collect())
+    }
+
+    /// Returns the state id the base would produce for the changes of the given number of
+    /// layers, followed by the given changes.
+    fn next_state_id(
+        &self,
+        overlay: &Overlay,
+        depth: usize,
+        state_changes: &[StateChange],
+    ) -> Result<(String, Changes), StateWriteError> {
+        let mut layer_changes = Changes::new();
+        for state_change in state_changes {
+            match state_change {
+                StateChange::Set { key, value } => {
+                    layer_changes.insert(key.clone(), Some(value.clone()))
+                }
+                StateChange::Delete { key } => layer_changes.insert(key.clone(), None),
+            };
+        }
+
+        let mut changes = overlay.changes(depth);
+        changes.extend(layer_changes.clone());
+        let state_changes = self
+            .effective_changes(&overlay.base_state_id, changes)
+            .map_err(|err| StateWriteError::StorageError(Box::new(err)))?;
+        let state_id = self
+            .base
+            .compute_state_id(&overlay.base_state_id, &state_changes)?;
+
+        Ok((state_id, layer_changes))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<Overlay> {
+        self.overlay.lock().expect("Couldn't lock overlay mutex!")
+    }
+}
+
+impl<S> Write for OverlayState<S>
+where
+    S: Read<StateId = String, Key = String, Value = Vec<u8>>
+        + Write<StateId = String, Key = String, Value = Vec<u8>>,
+{
+    type StateId = String;
+    type Key = String;
+    type Value = Vec<u8>;
+
+    fn commit(
+        &self,
+        state_id: &Self::StateId,
+        state_changes: &[StateChange],
+    ) -> Result<Self::StateId, StateWriteError> {
+        let mut overlay = self.lock();
+        if state_id != overlay.top_state_id() {
+            return Err(StateWriteError::InvalidStateId(format!(
+                "State id {} is not the most recent state of the overlay",
+                state_id
+            )));
+        }
+
+        let depth = overlay.layers.len();
+        let (next_state_id, changes) = self.next_state_id(&overlay, depth, state_changes)?;
+        overlay.layers.push(Layer {
+            state_id: next_state_id.clone(),
+            changes,
+        });
+
+        Ok(next_state_id)
+    }
+
+    fn compute_state_id(
+        &self,
+        state_id: &Self::StateId,
+        state_changes: &[StateChange],
+    ) -> Result<Self::StateId, StateWriteError> {
+        let overlay = self.lock();
+        let depth = overlay.depth_of(state_id).ok_or_else(|| {
+            StateWriteError::InvalidStateId(format!("Unknown state id {}", state_id))
+        })?;
+
+        Ok(self.next_state_id(&overlay, depth, state_changes)?.0)
+    }
+}
+
+impl<S> Read for OverlayState<S>
+where
+    S: Read<StateId = String, Key = String, Value = Vec<u8>>
+        + Write<StateId = String, Key = String, Value = Vec<u8>>
+        + 'static,
+{
+    type StateId = String;
+    type Key = String;
+    type Value = Vec<u8>;
+
+    fn get(
+        &self,
+        state_id: &Self::StateId,
+        keys: &[Self::Key],
+    ) -> Result<HashMap<Self::Key, Self::Value>, StateReadError> {
+        let overlay = self.lock();
+        let depth = overlay.depth_of(state_id).ok_or_else(|| {
+            StateReadError::InvalidStateId(format!("Unknown state id {}", state_id))
+        })?;
+
+        let mut values = HashMap::new();
+        let mut unchanged = vec![];
+        for key in keys {
+            let change = overlay.layers[..depth]
+                .iter()
+                .rev()
+                .find_map(|layer| layer.changes.get(key));
+            match change {
+                Some(Some(value)) => {
+                    values.insert(key.clone(), value.clone());
+                }
+                Some(None) => (),
+                None => unchanged.push(key.clone()),
+            }
+        }
+
+        if !unchanged.is_empty() {
+            values.extend(self.base.get(&overlay.base_state_id, &unchanged)?);
+        }
+        Ok(values)
+    }
+
+    fn range(
+        &self,
+        state_id: &Self::StateId,
+        prefix: &Self::Key,
+    ) -> Result<ValueIter<Self::Key, Self::Value>, StateReadError> {
+        let overlay = self.lock();
+        let depth = overlay.depth_of(state_id).ok_or_else(|| {
+            StateReadError::InvalidStateId(format!("Unknown state id {}", state_id))
+        })?;
+
+        let changes = overlay
+            .changes(depth)
+            .into_iter()
+            .filter(|(key, _)| key.starts_with(prefix.as_str()))
+            .collect::<Vec<_>>();
+        let base = self.base.range(&overlay.base_state_id, prefix)?;
+
+        Ok(Box::new(OverlayRange {
+            base: base.peekable(),
+            changes: changes.into_iter().peekable(),
+        }))
+    }
+
+    fn clone_box(&self) -> Box<Read<StateId = String, Key = String, Value = Vec<u8>>> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+/// The entries of a range of the base, with the changes of the layers merged in.
+struct OverlayRange {
+    base: Peekable<ValueIter<String, Vec<u8>>>,
+    changes: Peekable<vec::IntoIter<(String, Option<Vec<u8>>)>>,
+}
+
+impl Iterator for OverlayRange {
+    type Item = Result<(String, Vec<u8>), StateReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // Compares the next change with the next entry of the base, if it is not an error
+            let order = match (self.base.peek(), self.changes.peek()) {
+                (Some(Ok((base_key, _))), Some((key, _))) => Some(key.cmp(base_key)),
+                (None, Some(_)) => Some(Ordering::Less),
+                _ => None,
+            };
+            match order {
+                None | Some(Ordering::Greater) => return self.base.next(),
+                // The change replaces the base's entry
+                Some(Ordering::Equal) => {
+                    self.base.next();
+                }
+                Some(Ordering::Less) => (),
+            }
+            if let Some((key, Some(value))) = self.changes.next() {
+                return Some(Ok((key, value)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleRadixTree, MerkleState, INDEXES};
+
+    fn set(key: &str, value: &[u8]) -> StateChange {
+        StateChange::Set {
+            key: key.into(),
+            value: value.to_vec(),
+        }
+    }
+
+    fn delete(key: &str) -> StateChange {
+        StateChange::Delete { key: key.into() }
+    }
+
+    fn make_base() -> (MerkleState, String) {
+        let db = BTreeDatabase::new(&INDEXES);
+        let initial_root = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let base = MerkleState::new(Box::new(db));
+        let root = base
+            .commit(
+                &initial_root,
+                &[set("ab0000", b"base"), set("ab0001", b"base")],
+            )
+            .unwrap();
+        (base, root)
+    }
+
+    fn get(
+        state: &dyn Read<StateId = String, Key = String, Value = Vec<u8>>,
+        id: &str,
+    ) -> Vec<(String, Vec<u8>)> {
+        let keys = ["ab0000", "ab0001", "cd0000"]
+            .iter()
+            .map(|key| key.to_string())
+            .collect::<Vec<_>>();
+        let mut values = state
+            .get(&id.to_string(), &keys)
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+        values.sort();
+        values
+    }
+
+    /// Verifies that layers are read in order over the base, that their state ids match those of
+    /// real commits, and that the base is not written.
+    #[test]
+    fn layered_reads_and_writes() {
+        let (base, root) = make_base();
+        let overlay = OverlayState::new(base.clone(), root.clone());
+
+        let first = overlay
+            .commit(&root, &[set("ab0000", b"first"), set("cd0000", b"first")])
+            .unwrap();
+        let second = overlay
+            .commit(&first, &[delete("ab0001"), set("cd0000", b"second")])
+            .unwrap();
+        assert_eq!(overlay.depth(), 2);
+        assert_eq!(overlay.state_id(), second);
+
+        assert_eq!(
+            get(&overlay, &first),
+            vec![
+                ("ab0000".to_string(), b"first".to_vec()),
+                ("ab0001".to_string(), b"base".to_vec()),
+                ("cd0000".to_string(), b"first".to_vec()),
+            ]
+        );
+        assert_eq!(
+            get(&overlay, &second),
+            vec![
+                ("ab0000".to_string(), b"first".to_vec()),
+                ("cd0000".to_string(), b"second".to_vec()),
+            ]
+        );
+        assert_eq!(get(&overlay, &root), get(&base, &root));
+
+        let expected = base
+            .compute_state_id(
+                &root,
+                &[
+                    set("ab0000", b"first"),
+                    delete("ab0001"),
+                    set("cd0000", b"second"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(second, expected);
+        assert!(base.get(&second, &["ab0000".to_string()]).is_err());
+
+        // Layers may only be added on top of the most recent one
+        match overlay.commit(&first, &[set("ab0000", b"branch")]) {
+            Err(StateWriteError::InvalidStateId(_)) => (),
+            res => panic!("Expected InvalidStateId, got {:?}", res),
+        }
+    }
+
+    /// Verifies that discarded layers are no longer readable, and that materializing commits the
+    /// remaining layers to the base.
+    /// Verifies that ranges merge the changes of the layers into the entries of the base.
+    #[test]
+    fn layered_range() {
+        let (base, root) = make_base();
+        let overlay = OverlayState::new(base, root.clone());
+
+        let first = overlay
+            .commit(
+                &root,
+                &[
+                    set("ab0000", b"first"),
+                    set("ab0002", b"first"),
+                    set("cd0000", b"first"),
+                ],
+            )
+            .unwrap();
+        let second = overlay.commit(&first, &[delete("ab0001")]).unwrap();
+
+        let range = |id: &str| {
+            overlay
+                .range(&id.to_string(), &"ab".to_string())
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        assert_eq!(
+            range(&first),
+            vec![
+                ("ab0000".to_string(), b"first".to_vec()),
+                ("ab0001".to_string(), b"base".to_vec()),
+                ("ab0002".to_string(), b"first".to_vec()),
+            ]
+        );
+        assert_eq!(
+            range(&second),
+            vec![
+                ("ab0000".to_string(), b"first".to_vec()),
+                ("ab0002".to_string(), b"first".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn discard_and_materialize() {
+        let (base, root) = make_base();
+        let overlay = OverlayState::new(base.clone(), root.clone());
+
+        // A key set and deleted within the overlay is not deleted from the base
+        let first = overlay
+            .commit(&root, &[set("cd0000", b"first"), set("ab0000", b"first")])
+            .unwrap();
+        let second = overlay.commit(&first, &[delete("cd0000")]).unwrap();
+        let third = overlay.commit(&second, &[set("ab0001", b"third")]).unwrap();
+
+        overlay.discard_to(&second).unwrap();
+        assert_eq!(overlay.depth(), 2);
+        assert!(overlay.get(&third, &["ab0001".to_string()]).is_err());
+
+        let materialized = overlay.materialize().unwrap();
+        assert_eq!(materialized, second);
+        assert_eq!(overlay.depth(), 0);
+        assert_eq!(overlay.base_state_id(), second);
+        assert_eq!(get(&base, &second), get(&overlay, &second));
+        assert_eq!(
+            get(&base, &second),
+            vec![
+                ("ab0000".to_string(), b"first".to_vec()),
+                ("ab0001".to_string(), b"base".to_vec()),
+            ]
+        );
+
+        overlay
+            .commit(&second, &[set("ab0000", b"discarded")])
+            .unwrap();
+        overlay.discard();
+        assert_eq!(overlay.state_id(), second);
+        assert!(overlay.changes().unwrap().is_empty());
+    }
+}
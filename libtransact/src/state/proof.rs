@@ -0,0 +1,376 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Merkle proofs of inclusion and exclusion.
+//!
+//! A `MerkleProof` holds the encoded merkle nodes on the path from a state root towards an
+//! address.  It proves the value at the address when the path reaches it, and proves that the
+//! address is absent when the path ends at a node with no child for the address's next token.
+//! A proof is verified against the state root hash and the hash provider of the trie alone, so
+//! a light client need not hold any state to trust an individual entry.  The hash provider is
+//! supplied by the verifier rather than taken from the proof, so that a forged proof cannot
+//! choose a weaker hash function.
+
+use std::error::Error;
+use std::fmt;
+
+use super::hash_provider::HashProvider;
+use super::merkle::{tokenize_address, Node, TOKEN_SIZE};
+
+/// An error that may occur when verifying or decoding a proof.
+#[derive(Debug, PartialEq)]
+pub enum ProofError {
+    /// The proof could not be decoded, or does not describe a complete path.
+    MalformedProof(String),
+    /// A node of the proof does not have the hash referenced by its parent, or by the state root.
+    HashMismatch { depth: usize },
+    /// The proof was produced with a hash provider other than the one expected.
+    HashProviderMismatch {
+        expected: HashProvider,
+        found: HashProvider,
+    },
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProofError::MalformedProof(msg) => write!(f, "MalformedProof: {}", msg),
+            ProofError::HashMismatch { depth } => {
+                write!(
+                    f,
+                    "HashMismatch: node at depth {} has the wrong hash",
+                    depth
+                )
+            }
+            ProofError::HashProviderMismatch { expected, found } => write!(
+                f,
+                "HashProviderMismatch: expected {}, found {}",
+                expected.name(),
+                found.name()
+            ),
+        }
+    }
+}
+
+impl Error for ProofError {
+    fn description(&self) -> &str {
+        match self {
+            ProofError::MalformedProof(msg) => msg,
+            ProofError::HashMismatch { .. } => "A proof node has the wrong hash",
+            ProofError::HashProviderMismatch { .. } => "The proof has the wrong hash provider",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleProof {
+    address: String,
+    nodes: Vec<Vec<u8>>,
+    hash_provider: HashProvider,
+}
+
+impl MerkleProof {
+    pub(super) fn new(address: String, nodes: Vec<Vec<u8>>, hash_provider: HashProvider) -> Self {
+        MerkleProof {
+            address,
+            nodes,
+            hash_provider,
+        }
+    }
+
+    /// Returns the address this proof is about.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns the encoded nodes on the path, starting with the root.
+    pub fn nodes(&self) -> &[Vec<u8>] {
+        &self.nodes
+    }
+
+    /// Returns the provider of the hashes of the proof's nodes.
+    pub fn hash_provider(&self) -> HashProvider {
+        self.hash_provider
+    }
+
+    /// Verifies this proof against the given state root of a trie using the given hash provider,
+    /// returning the value at the proof's address, or `None` if the proof shows the address is
+    /// absent.
+    ///
+    /// # Errors
+    ///
+    /// A `ProofError` is returned if the proof was produced with another hash provider, or does
+    /// not hold for the state root.
+    pub fn verify(
+        &self,
+        state_root: &str,
+        hash_provider: HashProvider,
+    ) -> Result<Option<Vec<u8>>, ProofError> {
+        if self.hash_provider != hash_provider {
+            return Err(ProofError::HashProviderMismatch {
+                expected: hash_provider,
+                found: self.hash_provider,
+            });
+        }
+
+        let is_hex = self.address.chars().all(|c| c.is_ascii_hexdigit());
+        if !is_hex || self.address.len() % TOKEN_SIZE != 0 {
+            return Err(ProofError::MalformedProof(format!(
+                "Invalid address: {}",
+                self.address
+            )));
+        }
+        let tokens = tokenize_address(&self.address);
+
+        let mut expected_hash = state_root.to_string();
+        for (depth, bytes) in self.nodes.iter().enumerate() {
+            if ::hex::encode(hash_provider.hash(bytes)) != expected_hash {
+                return Err(ProofError::HashMismatch { depth });
+            }
+            let node = Node::from_bytes(bytes).map_err(|err| {
+                ProofError::MalformedProof(format!("Invalid node at depth {}: {}", depth, err))
+            })?;
+
+            let is_last = depth + 1 == self.nodes.len();
+            let child_hash = match tokens.get(depth) {
+                Some(token) => node.children.get(*token),
+                // The path has reached the address
+                None if is_last => return Ok(node.value),
+                None => break,
+            };
+            match child_hash {
+                Some(child_hash) => expected_hash = child_hash.clone(),
+                // The node has no child for the address, so the address is absent
+                None if is_last => return Ok(None),
+                None => break,
+            }
+        }
+
+        Err(ProofError::MalformedProof(
+            "Proof does not end at the address or at a missing child".into(),
+        ))
+    }
+
+    /// Encodes this proof: the name of its hash provider, as a one-byte length and its bytes,
+    /// then the address, as a two-byte length and its bytes, followed by the number of nodes as
+    /// two bytes, and each node as a four-byte length and its bytes, all big-endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let hash_provider = self.hash_provider.name();
+        bytes.push(hash_provider.len() as u8);
+        bytes.extend_from_slice(hash_provider.as_bytes());
+        bytes.extend_from_slice(&(self.address.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(self.address.as_bytes());
+        bytes.extend_from_slice(&(self.nodes.len() as u16).to_be_bytes());
+        for node in &self.nodes {
+            bytes.extend_from_slice(&(node.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(node);
+        }
+        bytes
+    }
+
+    /// Decodes a proof encoded by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProofError> {
+        let mut remaining = bytes;
+
+        let name_len = take_length(&mut remaining, 1)?;
+        let name = String::from_utf8_lossy(take(&mut remaining, name_len)?);
+        let hash_provider = HashProvider::from_name(&name).ok_or_else(|| {
+            ProofError::MalformedProof(format!("Unknown hash provider: {}", name))
+        })?;
+
+        let address_len = take_length(&mut remaining, 2)?;
+        let address = String::from_utf8(take(&mut remaining, address_len)?.to_vec())
+            .map_err(|_| ProofError::MalformedProof("Address is not valid UTF-8".into()))?;
+
+        let node_count = take_length(&mut remaining, 2)?;
+        let mut nodes = Vec::with_capacity(node_count);
+        for _ in 0..node_count {
+            let node_len = take_length(&mut remaining, 4)?;
+            nodes.push(take(&mut remaining, node_len)?.to_vec());
+        }
+
+        if !remaining.is_empty() {
+            return Err(ProofError::MalformedProof(
+                "Unexpected bytes after the proof".into(),
+            ));
+        }
+
+        Ok(MerkleProof {
+            address,
+            nodes,
+            hash_provider,
+        })
+    }
+}
+
+fn take<'a>(remaining: &mut &'a [u8], len: usize) -> Result<&'a [u8], ProofError> {
+    if remaining.len() < len {
+        return Err(ProofError::MalformedProof("Proof is truncated".into()));
+    }
+    let (taken, rest) = remaining.split_at(len);
+    *remaining = rest;
+    Ok(taken)
+}
+
+/// Reads a big-endian length of the given number of bytes.
+fn take_length(remaining: &mut &[u8], size: usize) -> Result<usize, ProofError> {
+    Ok(take(remaining, size)?
+        .iter()
+        .fold(0usize, |len, byte| (len << 8) | usize::from(*byte)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::{MerkleRadixTree, INDEXES};
+    use crate::state::StateChange;
+
+    fn make_tree() -> MerkleRadixTree {
+        let db = BTreeDatabase::new(&INDEXES);
+        let mut merkle_db = MerkleRadixTree::new(Box::new(db), None).unwrap();
+        let changes = ["ab0000", "ab0001", "abff00"]
+            .iter()
+            .map(|address| StateChange::Set {
+                key: address.to_string(),
+                value: address.as_bytes().to_vec(),
+            })
+            .collect::<Vec<_>>();
+        let root = merkle_db.update(&changes, false).unwrap();
+        merkle_db.set_merkle_root(root).unwrap();
+        merkle_db
+    }
+
+    /// Verifies that proofs of present and absent addresses verify against the state root.
+    #[test]
+    fn inclusion_and_exclusion() {
+        let merkle_db = make_tree();
+        let root = merkle_db.get_merkle_root();
+
+        let proof = merkle_db.prove("ab0001").unwrap();
+        assert_eq!(proof.nodes().len(), 4);
+        assert_eq!(
+            proof.verify(&root, HashProvider::Sha512),
+            Ok(Some(b"ab0001".to_vec()))
+        );
+
+        // The path ends at "ab00", which has no "02" child
+        let proof = merkle_db.prove("ab0002").unwrap();
+        assert_eq!(proof.nodes().len(), 3);
+        assert_eq!(proof.verify(&root, HashProvider::Sha512), Ok(None));
+
+        // The path ends at the root, which has no "cd" child
+        let proof = merkle_db.prove("cd0000").unwrap();
+        assert_eq!(proof.nodes().len(), 1);
+        assert_eq!(proof.verify(&root, HashProvider::Sha512), Ok(None));
+
+        let decoded = MerkleProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(decoded, proof);
+    }
+
+    /// Verifies that proofs fail against another root, or when tampered with.
+    #[test]
+    fn invalid_proofs() {
+        let merkle_db = make_tree();
+        let root = merkle_db.get_merkle_root();
+        let proof = merkle_db.prove("ab0001").unwrap();
+
+        let other_root = make_tree()
+            .update(
+                &[StateChange::Delete {
+                    key: "abff00".into(),
+                }],
+                true,
+            )
+            .unwrap();
+        assert_eq!(
+            proof.verify(&other_root, HashProvider::Sha512),
+            Err(ProofError::HashMismatch { depth: 0 })
+        );
+
+        // Claim a different value for the leaf
+        let mut nodes = proof.nodes().to_vec();
+        let leaf = nodes.last_mut().unwrap();
+        let position = leaf.len() - 1;
+        leaf[position] ^= 0x01;
+        let tampered = MerkleProof::new(proof.address().into(), nodes, proof.hash_provider());
+        assert_eq!(
+            tampered.verify(&root, HashProvider::Sha512),
+            Err(ProofError::HashMismatch { depth: 3 })
+        );
+
+        // Drop the leaf, so the proof ends part way along the path
+        let truncated = MerkleProof::new(
+            proof.address().into(),
+            proof.nodes()[..3].to_vec(),
+            proof.hash_provider(),
+        );
+        match truncated.verify(&root, HashProvider::Sha512) {
+            Err(ProofError::MalformedProof(_)) => (),
+            res => panic!("Expected MalformedProof, got {:?}", res),
+        }
+
+        // Claim the proof is about another address under the same path
+        let moved = MerkleProof::new(
+            "ab0000".into(),
+            proof.nodes().to_vec(),
+            proof.hash_provider(),
+        );
+        match moved.verify(&root, HashProvider::Sha512) {
+            Err(ProofError::HashMismatch { .. }) => (),
+            res => panic!("Expected HashMismatch, got {:?}", res),
+        }
+
+        assert!(MerkleProof::from_bytes(&proof.to_bytes()[1..]).is_err());
+    }
+
+    /// Verifies that a proof claiming another hash provider is rejected, even when its nodes
+    /// hash to the root under the provider it claims.
+    #[test]
+    fn hash_provider_mismatch() {
+        let mut merkle_db = MerkleRadixTree::new_with_hash_provider(
+            Box::new(BTreeDatabase::new(&INDEXES)),
+            HashProvider::Sha256,
+        )
+        .unwrap();
+        let root = merkle_db
+            .update(
+                &[StateChange::Set {
+                    key: "ab0000".into(),
+                    value: b"ab0000".to_vec(),
+                }],
+                false,
+            )
+            .unwrap();
+        merkle_db.set_merkle_root(root.clone()).unwrap();
+        let proof = merkle_db.prove("ab0000").unwrap();
+
+        assert_eq!(
+            proof.verify(&root, HashProvider::Sha256),
+            Ok(Some(b"ab0000".to_vec()))
+        );
+        assert_eq!(
+            proof.verify(&root, HashProvider::Sha512),
+            Err(ProofError::HashProviderMismatch {
+                expected: HashProvider::Sha512,
+                found: HashProvider::Sha256,
+            })
+        );
+    }
+}
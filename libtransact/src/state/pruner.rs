@@ -0,0 +1,388 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Retention-based pruning of merkle state.
+//!
+//! A `MerklePruner` is given the state roots to retain, extends them according to its
+//! `RetentionPolicy`, and prunes every other root recorded in the change log, oldest lineages
+//! first, deleting the merkle nodes that are no longer reachable.  Pruning is performed through
+//! `MerkleRadixTree::prune`, so a root with more than one successor is only pruned once all but
+//! one of its successors have been.
+//!
+//! Pruning may be run to completion with `prune`, or in bounded steps with `prune_step`; a
+//! `BackgroundPruner` runs such steps periodically on its own thread.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::database::Database;
+
+use super::change_log::ChangeLogEntry;
+use super::merkle::{MerkleRadixTree, StateDatabaseError, CHANGE_LOG_INDEX};
+
+/// Determines which state roots are retained, in addition to those given to the pruner.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RetentionPolicy {
+    /// Retain only the given roots.
+    Exact,
+    /// Retain the given number of most recently committed roots along each given root's lineage,
+    /// counting the given root itself.
+    LastCommitted(usize),
+}
+
+/// The outcome of a pruning run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PruneSummary {
+    /// The pruned state roots, in the order they were pruned.
+    pub pruned_roots: Vec<String>,
+    /// The number of merkle nodes deleted.
+    pub removed_nodes: usize,
+    /// Whether every root outside the retention policy has been pruned.
+    pub complete: bool,
+}
+
+#[derive(Clone)]
+pub struct MerklePruner {
+    db: Box<dyn Database>,
+    policy: RetentionPolicy,
+}
+
+impl MerklePruner {
+    pub fn new(db: Box<dyn Database>, policy: RetentionPolicy) -> Self {
+        MerklePruner { db, policy }
+    }
+
+    /// Returns the roots retained for the given roots under this pruner's policy.
+    pub fn retained_roots(&self, retain: &[String]) -> Result<HashSet<String>, StateDatabaseError> {
+        Ok(self.expand_retained(retain, &self.change_logs()?))
+    }
+
+    /// Prunes every root that is not retained.
+    pub fn prune(&self, retain: &[String]) -> Result<PruneSummary, StateDatabaseError> {
+        self.prune_step(retain, usize::max_value())
+    }
+
+    /// Prunes at most `max_roots` roots that are not retained.
+    ///
+    /// The summary reports whether any prunable roots remain.
+    ///
+    /// # Errors
+    ///
+    /// An `InvalidChangeLogIndex` error is returned if no roots are to be retained, as removing
+    /// every root would leave the state empty.
+    pub fn prune_step(
+        &self,
+        retain: &[String],
+        max_roots: usize,
+    ) -> Result<PruneSummary, StateDatabaseError> {
+        if retain.is_empty() {
+            return Err(StateDatabaseError::InvalidChangeLogIndex(
+                "At least one state root must be retained".into(),
+            ));
+        }
+
+        let mut summary = PruneSummary::default();
+        loop {
+            let change_logs = self.change_logs()?;
+            let retained = self.expand_retained(retain, &change_logs);
+
+            // Roots with several successors are left until all but one of those are pruned.
+            let mut candidates = change_logs
+                .iter()
+                .filter(|(root, change_log)| {
+                    !retained.contains(*root) && change_log.successors.len() <= 1
+                })
+                .map(|(root, _)| root.clone())
+                .collect::<Vec<_>>();
+            if candidates.is_empty() {
+                summary.complete = true;
+                return Ok(summary);
+            }
+            candidates.sort();
+
+            for root in candidates {
+                if summary.pruned_roots.len() >= max_roots {
+                    return Ok(summary);
+                }
+                let removed = MerkleRadixTree::prune(&*self.db, &root)?;
+                debug!(
+                    "Pruned state root {}, removing {} nodes",
+                    root,
+                    removed.len()
+                );
+                summary.removed_nodes += removed.len();
+                summary.pruned_roots.push(root);
+            }
+        }
+    }
+
+    /// Reads every entry of the change log, keyed by hex-encoded state root.
+    fn change_logs(&self) -> Result<HashMap<String, ChangeLogEntry>, StateDatabaseError> {
+        let reader = self.db.get_reader()?;
+        let cursor = reader.index_cursor(CHANGE_LOG_INDEX)?;
+        let mut change_logs = HashMap::new();
+        for (root, bytes) in cursor {
+            change_logs.insert(::hex::encode(root), ChangeLogEntry::from_bytes(&bytes)?);
+        }
+        Ok(change_logs)
+    }
+
+    fn expand_retained(
+        &self,
+        retain: &[String],
+        change_logs: &HashMap<String, ChangeLogEntry>,
+    ) -> HashSet<String> {
+        let depth = match self.policy {
+            RetentionPolicy::Exact => 1,
+            RetentionPolicy::LastCommitted(depth) => depth,
+        };
+
+        let mut retained = HashSet::new();
+        for root in retain {
+            let mut current = root.clone();
+            retained.insert(current.clone());
+            for _ in 1..depth {
+                match change_logs.get(&current) {
+                    Some(change_log) => current = ::hex::encode(&change_log.parent),
+                    None => break,
+                }
+                retained.insert(current.clone());
+            }
+        }
+        retained
+    }
+}
+
+enum PrunerMessage {
+    Retain(Vec<String>),
+    Shutdown,
+}
+
+/// Runs a `MerklePruner` incrementally on a background thread.
+///
+/// Every interval, the pruner prunes a bounded number of roots outside the most recently given
+/// retained roots.  Nothing is pruned until roots to retain have been given.
+pub struct BackgroundPruner {
+    sender: Sender<PrunerMessage>,
+    join_handle: Option<JoinHandle<()>>,
+}
+
+impl BackgroundPruner {
+    /// Starts the background thread, which prunes at most `roots_per_pass` roots every
+    /// `interval`.
+    pub fn start(
+        pruner: MerklePruner,
+        interval: Duration,
+        roots_per_pass: usize,
+    ) -> Result<Self, io::Error> {
+        let (sender, receiver) = channel();
+
+        let join_handle = thread::Builder::new()
+            .name(String::from("Thread-BackgroundPruner"))
+            .spawn(move || {
+                let mut retain = vec![];
+                let mut next_pass = Instant::now() + interval;
+                loop {
+                    let now = Instant::now();
+                    let wait = if next_pass > now {
+                        next_pass - now
+                    } else {
+                        Duration::from_secs(0)
+                    };
+
+                    match receiver.recv_timeout(wait) {
+                        Ok(PrunerMessage::Retain(roots)) => retain = roots,
+                        Ok(PrunerMessage::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                        Err(RecvTimeoutError::Timeout) => {
+                            next_pass = Instant::now() + interval;
+                            if retain.is_empty() {
+                                continue;
+                            }
+                            if let Err(err) = pruner.prune_step(&retain, roots_per_pass) {
+                                error!("Unable to prune state: {}", err);
+                            }
+                        }
+                    }
+                }
+            })?;
+
+        Ok(BackgroundPruner {
+            sender,
+            join_handle: Some(join_handle),
+        })
+    }
+
+    /// Replaces the roots to retain, typically with the latest committed root.
+    pub fn retain(&self, roots: Vec<String>) {
+        if self.sender.send(PrunerMessage::Retain(roots)).is_err() {
+            error!("Background pruner has stopped");
+        }
+    }
+
+    /// Stops the background thread, waiting for any pass in progress to finish.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(join_handle) = self.join_handle.take() {
+            // The thread may already have stopped, in which case there is nothing to signal.
+            let _ = self.sender.send(PrunerMessage::Shutdown);
+            if join_handle.join().is_err() {
+                error!("Background pruner thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for BackgroundPruner {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::INDEXES;
+    use crate::state::StateChange;
+
+    /// Commits a single set on top of the given root, returning the new root.
+    fn commit(db: &BTreeDatabase, root: &str, key: &str, value: &[u8]) -> String {
+        let merkle_db = MerkleRadixTree::new(Box::new(db.clone()), Some(root)).unwrap();
+        merkle_db
+            .update(
+                &[StateChange::Set {
+                    key: key.to_string(),
+                    value: value.to_vec(),
+                }],
+                false,
+            )
+            .unwrap()
+    }
+
+    fn exists(db: &BTreeDatabase, root: &str) -> bool {
+        MerkleRadixTree::new(Box::new(db.clone()), Some(root)).is_ok()
+    }
+
+    fn value(db: &BTreeDatabase, root: &str, key: &str) -> Option<Vec<u8>> {
+        MerkleRadixTree::new(Box::new(db.clone()), Some(root))
+            .unwrap()
+            .get_value(key)
+            .unwrap()
+    }
+
+    /// Verifies that roots older than the retained depth are pruned, while the retained roots
+    /// remain readable.
+    #[test]
+    fn prune_last_committed() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let root_0 = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let root_1 = commit(&db, &root_0, "ab0000", b"1");
+        let root_2 = commit(&db, &root_1, "ab0000", b"2");
+        let root_3 = commit(&db, &root_2, "ab0001", b"3");
+
+        let pruner = MerklePruner::new(Box::new(db.clone()), RetentionPolicy::LastCommitted(2));
+        assert_eq!(
+            pruner.retained_roots(&[root_3.clone()]).unwrap(),
+            vec![root_3.clone(), root_2.clone()].into_iter().collect()
+        );
+
+        let summary = pruner.prune(&[root_3.clone()]).unwrap();
+        assert_eq!(summary.pruned_roots, vec![root_1.clone()]);
+        assert!(summary.removed_nodes > 0);
+        assert!(summary.complete);
+
+        assert!(!exists(&db, &root_1));
+        assert_eq!(value(&db, &root_2, "ab0000"), Some(b"2".to_vec()));
+        assert_eq!(value(&db, &root_3, "ab0000"), Some(b"2".to_vec()));
+        assert_eq!(value(&db, &root_3, "ab0001"), Some(b"3".to_vec()));
+
+        // Nothing further is prunable
+        assert_eq!(
+            pruner.prune(&[root_3.clone()]).unwrap(),
+            PruneSummary {
+                complete: true,
+                ..PruneSummary::default()
+            }
+        );
+    }
+
+    /// Verifies that abandoned forks are pruned, which in turn allows their common ancestor to
+    /// be pruned, and that `prune_step` bounds the number of roots pruned.
+    #[test]
+    fn prune_forks_in_steps() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let root_0 = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let root_1 = commit(&db, &root_0, "ab0000", b"1");
+        let root_2a = commit(&db, &root_1, "ab0001", b"2a");
+        let root_2b = commit(&db, &root_1, "ab0001", b"2b");
+
+        let pruner = MerklePruner::new(Box::new(db.clone()), RetentionPolicy::Exact);
+        assert!(pruner.prune(&[]).is_err());
+
+        // The fork is pruned first, as root 1 has two successors
+        let summary = pruner.prune_step(&[root_2a.clone()], 1).unwrap();
+        assert_eq!(summary.pruned_roots, vec![root_2b.clone()]);
+        assert!(!summary.complete);
+        assert!(!exists(&db, &root_2b));
+
+        let summary = pruner.prune_step(&[root_2a.clone()], 1).unwrap();
+        assert_eq!(summary.pruned_roots, vec![root_1.clone()]);
+
+        let summary = pruner.prune_step(&[root_2a.clone()], 1).unwrap();
+        assert!(summary.pruned_roots.is_empty());
+        assert!(summary.complete);
+
+        assert!(!exists(&db, &root_1));
+        assert_eq!(value(&db, &root_2a, "ab0000"), Some(b"1".to_vec()));
+        assert_eq!(value(&db, &root_2a, "ab0001"), Some(b"2a".to_vec()));
+    }
+
+    /// Verifies that the background pruner prunes once given roots to retain.
+    #[test]
+    fn background_pruner() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let root_0 = MerkleRadixTree::new(Box::new(db.clone()), None)
+            .unwrap()
+            .get_merkle_root();
+        let root_1 = commit(&db, &root_0, "ab0000", b"1");
+        let root_2 = commit(&db, &root_1, "ab0000", b"2");
+
+        let pruner = MerklePruner::new(Box::new(db.clone()), RetentionPolicy::Exact);
+        let background = BackgroundPruner::start(pruner, Duration::from_millis(10), 10).unwrap();
+        background.retain(vec![root_2.clone()]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while exists(&db, &root_1) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        background.shutdown();
+
+        assert!(!exists(&db, &root_1));
+        assert_eq!(value(&db, &root_2, "ab0000"), Some(b"2".to_vec()));
+    }
+}
@@ -0,0 +1,545 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Portable snapshots of merkle state.
+//!
+//! A snapshot holds every address/value pair of the state at a single state root, and may be
+//! imported into a fresh database to reproduce that state root.  Snapshots are written and read
+//! as streams, so they need never be held in memory.
+//!
+//! # Format
+//!
+//! All integers are big-endian.
+//!
+//! * The magic bytes `TRNSNAP`, followed by the format version, currently 2, as a byte.
+//! * The state root, as a two-byte length followed by its hex-encoded bytes.
+//! * The name of the database's hash provider, as a two-byte length followed by its bytes.
+//! * The database's value compression, as recorded in its metadata, as a two-byte length
+//!   followed by its bytes; the record is empty if values are not compressed.
+//! * One record per entry, in address order: the byte 1, the address as a two-byte length and
+//!   its bytes, and the value as a four-byte length and its bytes.
+//! * The byte 0, followed by the number of entries as eight bytes.
+//! * The SHA-512 digest of all of the preceding bytes.
+//!
+//! Version 1 snapshots have neither the hash provider nor the value compression; they were
+//! exported from databases using SHA-512, without compression.
+//!
+//! The hash provider and value compression are recorded in the database a snapshot is imported
+//! into, so that the imported state reproduces the snapshot's state root, and is stored as it
+//! was in the exported database.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use sha2::{Digest, Sha512};
+
+use crate::database::error::DatabaseError;
+use crate::database::Database;
+
+use super::compression::{record_value_compression, ValueCompression};
+use super::hash_provider::HashProvider;
+use super::merkle::{MerkleRadixTree, StateDatabaseError, CHANGE_LOG_INDEX};
+use super::StateChange;
+
+const MAGIC: &[u8] = b"TRNSNAP";
+const VERSION: u8 = 2;
+
+/// The version of snapshots without a hash provider or value compression.
+const VERSION_1: u8 = 1;
+
+const ENTRY_TAG: u8 = 1;
+const END_TAG: u8 = 0;
+
+const CHECKSUM_SIZE: usize = 64;
+
+/// The number of entries applied to the tree in each update during an import.
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
+/// An error that may occur while exporting or importing a snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Reading or writing the snapshot failed.
+    IoError(io::Error),
+    /// The snapshot is malformed or of an unsupported version.
+    InvalidFormat(String),
+    /// The snapshot's contents do not match its checksum.
+    ChecksumMismatch,
+    /// The imported state does not produce the snapshot's state root.
+    StateRootMismatch { expected: String, actual: String },
+    /// A snapshot may only be imported into a database without existing state.
+    DatabaseNotEmpty,
+    /// An error occurred reading or writing state.
+    StateError(StateDatabaseError),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::IoError(err) => write!(f, "IoError: {}", err),
+            SnapshotError::InvalidFormat(msg) => write!(f, "InvalidFormat: {}", msg),
+            SnapshotError::ChecksumMismatch => {
+                write!(f, "ChecksumMismatch: snapshot does not match its checksum")
+            }
+            SnapshotError::StateRootMismatch { expected, actual } => write!(
+                f,
+                "StateRootMismatch: expected {}, imported {}",
+                expected, actual
+            ),
+            SnapshotError::DatabaseNotEmpty => {
+                write!(f, "DatabaseNotEmpty: database already contains state")
+            }
+            SnapshotError::StateError(err) => write!(f, "StateError: {}", err),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn description(&self) -> &str {
+        match self {
+            SnapshotError::IoError(err) => err.description(),
+            SnapshotError::InvalidFormat(msg) => msg,
+            SnapshotError::ChecksumMismatch => "Snapshot does not match its checksum",
+            SnapshotError::StateRootMismatch { .. } => "Imported state root does not match",
+            SnapshotError::DatabaseNotEmpty => "Database already contains state",
+            SnapshotError::StateError(err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match self {
+            SnapshotError::IoError(err) => Some(err),
+            SnapshotError::StateError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::IoError(err)
+    }
+}
+
+impl From<DatabaseError> for SnapshotError {
+    fn from(err: DatabaseError) -> Self {
+        SnapshotError::StateError(StateDatabaseError::DatabaseError(err))
+    }
+}
+
+impl From<StateDatabaseError> for SnapshotError {
+    fn from(err: StateDatabaseError) -> Self {
+        SnapshotError::StateError(err)
+    }
+}
+
+/// Describes an exported or imported snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotSummary {
+    pub state_root: String,
+    pub entry_count: u64,
+}
+
+/// Writes a snapshot of the state at the given state root.
+pub fn export_snapshot<W: Write>(
+    db: Box<dyn Database>,
+    state_root: &str,
+    writer: W,
+) -> Result<SnapshotSummary, SnapshotError> {
+    let merkle_db = MerkleRadixTree::new(db, Some(state_root))?;
+    let mut writer = HashingWriter::new(writer);
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])?;
+    write_u16_bytes(&mut writer, state_root.as_bytes())?;
+    write_u16_bytes(&mut writer, merkle_db.hash_provider().name().as_bytes())?;
+    let compression = merkle_db
+        .value_compression()
+        .map(|compression| compression.to_record())
+        .unwrap_or_default();
+    write_u16_bytes(&mut writer, compression.as_bytes())?;
+
+    let mut entry_count = 0u64;
+    for leaf in merkle_db.leaves(None)? {
+        let (address, value) = leaf?;
+        if value.len() > u32::max_value() as usize {
+            return Err(SnapshotError::InvalidFormat(format!(
+                "Value at {} is too large for a snapshot",
+                address
+            )));
+        }
+        writer.write_all(&[ENTRY_TAG])?;
+        write_u16_bytes(&mut writer, address.as_bytes())?;
+        writer.write_all(&(value.len() as u32).to_be_bytes())?;
+        writer.write_all(&value)?;
+        entry_count += 1;
+    }
+
+    writer.write_all(&[END_TAG])?;
+    writer.write_all(&entry_count.to_be_bytes())?;
+
+    let (mut writer, checksum) = writer.finish();
+    writer.write_all(&checksum)?;
+    writer.flush()?;
+
+    Ok(SnapshotSummary {
+        state_root: state_root.to_string(),
+        entry_count,
+    })
+}
+
+/// Reads a snapshot into a database without existing state, verifying that it reproduces the
+/// snapshot's state root.
+///
+/// As the snapshot is streamed, entries are written to the database before the checksum is
+/// verified; if an error is returned, the database should be discarded.
+pub fn import_snapshot<R: Read>(
+    db: Box<dyn Database>,
+    reader: R,
+) -> Result<SnapshotSummary, SnapshotError> {
+    if db.get_reader()?.index_count(CHANGE_LOG_INDEX)? > 0 {
+        return Err(SnapshotError::DatabaseNotEmpty);
+    }
+
+    let mut reader = HashingReader::new(reader);
+
+    let mut magic = [0u8; 7];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(SnapshotError::InvalidFormat("Not a snapshot".into()));
+    }
+    let version = read_u8(&mut reader)?;
+    if version != VERSION && version != VERSION_1 {
+        return Err(SnapshotError::InvalidFormat(format!(
+            "Unsupported snapshot version: {}",
+            version
+        )));
+    }
+    let expected_root = read_string(read_u16_bytes(&mut reader)?)?;
+
+    let (hash_provider, compression) = if version == VERSION_1 {
+        (HashProvider::Sha512, None)
+    } else {
+        let name = read_string(read_u16_bytes(&mut reader)?)?;
+        let hash_provider = HashProvider::from_name(&name).ok_or_else(|| {
+            SnapshotError::InvalidFormat(format!("Unknown hash provider: {}", name))
+        })?;
+        let record = read_string(read_u16_bytes(&mut reader)?)?;
+        let compression = if record.is_empty() {
+            None
+        } else {
+            Some(ValueCompression::from_record(&record).ok_or_else(|| {
+                SnapshotError::InvalidFormat(format!("Unknown value compression: {}", record))
+            })?)
+        };
+        (hash_provider, compression)
+    };
+
+    // The compression is recorded first, as the tree reads it when it is opened; creating the
+    // tree fails if the database already records a different hash provider.
+    record_value_compression(&*db, compression)?;
+    let mut merkle_db = MerkleRadixTree::new_with_hash_provider(db.clone(), hash_provider)?;
+    let mut intermediate_roots = vec![];
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut entry_count = 0u64;
+    loop {
+        match read_u8(&mut reader)? {
+            ENTRY_TAG => {
+                let key = read_string(read_u16_bytes(&mut reader)?)?;
+                let mut length = [0u8; 4];
+                reader.read_exact(&mut length)?;
+                let value = read_exact_vec(&mut reader, u32::from_be_bytes(length) as usize)?;
+                batch.push(StateChange::Set { key, value });
+                entry_count += 1;
+            }
+            END_TAG => break,
+            tag => {
+                return Err(SnapshotError::InvalidFormat(format!(
+                    "Unknown record tag: {}",
+                    tag
+                )))
+            }
+        }
+
+        if batch.len() == IMPORT_BATCH_SIZE {
+            intermediate_roots.push(merkle_db.get_merkle_root());
+            let root = merkle_db.update(&batch, false)?;
+            merkle_db.set_merkle_root(root)?;
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        intermediate_roots.push(merkle_db.get_merkle_root());
+        let root = merkle_db.update(&batch, false)?;
+        merkle_db.set_merkle_root(root)?;
+    }
+
+    let mut count = [0u8; 8];
+    reader.read_exact(&mut count)?;
+    if u64::from_be_bytes(count) != entry_count {
+        return Err(SnapshotError::InvalidFormat(format!(
+            "Snapshot declares {} entries, but contains {}",
+            u64::from_be_bytes(count),
+            entry_count
+        )));
+    }
+
+    let (mut reader, expected_checksum) = reader.finish();
+    let mut checksum = [0u8; CHECKSUM_SIZE];
+    reader.read_exact(&mut checksum)?;
+    if checksum[..] != expected_checksum[..] {
+        return Err(SnapshotError::ChecksumMismatch);
+    }
+
+    let actual_root = merkle_db.get_merkle_root();
+    if actual_root != expected_root {
+        return Err(SnapshotError::StateRootMismatch {
+            expected: expected_root,
+            actual: actual_root,
+        });
+    }
+
+    // Remove the nodes of the roots produced along the way, which each have the next as their
+    // only successor.
+    for root in intermediate_roots {
+        MerkleRadixTree::prune(&*db, &root)?;
+    }
+
+    Ok(SnapshotSummary {
+        state_root: actual_root,
+        entry_count,
+    })
+}
+
+fn write_u16_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), SnapshotError> {
+    if bytes.len() > u16::max_value() as usize {
+        return Err(SnapshotError::InvalidFormat(
+            "Field is too long for a snapshot".into(),
+        ));
+    }
+    writer.write_all(&(bytes.len() as u16).to_be_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, SnapshotError> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u16_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>, SnapshotError> {
+    let mut length = [0u8; 2];
+    reader.read_exact(&mut length)?;
+    read_exact_vec(reader, u16::from_be_bytes(length) as usize)
+}
+
+/// Reads exactly `length` bytes, without trusting the length for the initial allocation.
+fn read_exact_vec<R: Read>(reader: &mut R, length: usize) -> Result<Vec<u8>, SnapshotError> {
+    let mut bytes = Vec::new();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != length {
+        return Err(SnapshotError::InvalidFormat("Snapshot is truncated".into()));
+    }
+    Ok(bytes)
+}
+
+fn read_string(bytes: Vec<u8>) -> Result<String, SnapshotError> {
+    String::from_utf8(bytes)
+        .map_err(|_| SnapshotError::InvalidFormat("Invalid UTF-8 in snapshot".into()))
+}
+
+/// A writer that computes the digest of everything written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha512,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hasher: Sha512::new(),
+        }
+    }
+
+    fn finish(self) -> (W, Vec<u8>) {
+        (self.inner, self.hasher.result().to_vec())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.input(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader that computes the digest of everything read through it.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha512,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            hasher: Sha512::new(),
+        }
+    }
+
+    fn finish(self) -> (R, Vec<u8>) {
+        (self.inner, self.hasher.result().to_vec())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.input(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::state::merkle::INDEXES;
+
+    fn populated_db() -> (BTreeDatabase, String) {
+        let db = BTreeDatabase::new(&INDEXES);
+        let merkle_db = MerkleRadixTree::new(Box::new(db.clone()), None).unwrap();
+        let changes = (0..25u8)
+            .map(|i| StateChange::Set {
+                key: format!("ab{:02x}{:02x}", i % 5, i),
+                value: vec![i; i as usize],
+            })
+            .collect::<Vec<_>>();
+        let root = merkle_db.update(&changes, false).unwrap();
+        (db, root)
+    }
+
+    /// Verifies that a snapshot imported into a fresh database reproduces the exported state.
+    #[test]
+    fn export_and_import() {
+        let (db, root) = populated_db();
+
+        let mut snapshot = vec![];
+        let summary = export_snapshot(Box::new(db.clone()), &root, &mut snapshot).unwrap();
+        assert_eq!(summary.entry_count, 25);
+        assert_eq!(&snapshot[..7], MAGIC);
+
+        let imported = BTreeDatabase::new(&INDEXES);
+        let summary = import_snapshot(Box::new(imported.clone()), &snapshot[..]).unwrap();
+        assert_eq!(summary.state_root, root);
+        assert_eq!(summary.entry_count, 25);
+
+        let original = MerkleRadixTree::new(Box::new(db), Some(&root)).unwrap();
+        let copy = MerkleRadixTree::new(Box::new(imported.clone()), Some(&root)).unwrap();
+        assert_eq!(
+            original
+                .leaves(None)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            copy.leaves(None)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        );
+
+        // The database already has state, so a second import is refused
+        match import_snapshot(Box::new(imported), &snapshot[..]) {
+            Err(SnapshotError::DatabaseNotEmpty) => (),
+            res => panic!("Expected DatabaseNotEmpty, got {:?}", res),
+        }
+    }
+
+    /// Verifies that a snapshot of a database with a non-default hash provider records the
+    /// provider, so that the import reproduces its state root, and that it may not be imported
+    /// into a database using another provider.
+    #[test]
+    fn export_and_import_hash_provider() {
+        let db = BTreeDatabase::new(&INDEXES);
+        let merkle_db =
+            MerkleRadixTree::new_with_hash_provider(Box::new(db.clone()), HashProvider::Sha256)
+                .unwrap();
+        let changes = (0..25u8)
+            .map(|i| StateChange::Set {
+                key: format!("ab{:02x}{:02x}", i % 5, i),
+                value: vec![i; i as usize],
+            })
+            .collect::<Vec<_>>();
+        let root = merkle_db.update(&changes, false).unwrap();
+
+        let mut snapshot = vec![];
+        export_snapshot(Box::new(db), &root, &mut snapshot).unwrap();
+
+        let imported = BTreeDatabase::new(&INDEXES);
+        let summary = import_snapshot(Box::new(imported.clone()), &snapshot[..]).unwrap();
+        assert_eq!(summary.state_root, root);
+        assert_eq!(
+            MerkleRadixTree::new(Box::new(imported), Some(&root))
+                .unwrap()
+                .hash_provider(),
+            HashProvider::Sha256
+        );
+
+        let sha512_db = BTreeDatabase::new(&INDEXES);
+        MerkleRadixTree::new_with_hash_provider(Box::new(sha512_db.clone()), HashProvider::Sha512)
+            .unwrap();
+        match import_snapshot(Box::new(sha512_db), &snapshot[..]) {
+            Err(SnapshotError::StateError(StateDatabaseError::InvalidHash(_))) => (),
+            res => panic!("Expected InvalidHash, got {:?}", res),
+        }
+    }
+
+    /// Verifies that corrupted and truncated snapshots are rejected.
+    #[test]
+    fn import_rejects_damaged_snapshots() {
+        let (db, root) = populated_db();
+        let mut snapshot = vec![];
+        export_snapshot(Box::new(db), &root, &mut snapshot).unwrap();
+
+        let mut corrupted = snapshot.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        match import_snapshot(Box::new(BTreeDatabase::new(&INDEXES)), &corrupted[..]) {
+            Err(SnapshotError::ChecksumMismatch) => (),
+            res => panic!("Expected ChecksumMismatch, got {:?}", res),
+        }
+
+        let truncated = &snapshot[..snapshot.len() / 2];
+        assert!(import_snapshot(Box::new(BTreeDatabase::new(&INDEXES)), truncated).is_err());
+
+        match import_snapshot(
+            Box::new(BTreeDatabase::new(&INDEXES)),
+            &b"not a snapshot"[..],
+        ) {
+            Err(SnapshotError::InvalidFormat(_)) => (),
+            res => panic!("Expected InvalidFormat, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,299 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! Notifications of committed state changes.
+//!
+//! A `SubscribedState` wraps a state backend, and after each successful commit sends the
+//! committed changes to the subscribers registered with its `StateSubscriptions`.  Each
+//! subscriber is given the address prefixes it is interested in, and receives only the changes
+//! under them, over a channel.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use super::error::{StatePruneError, StateReadError, StateWriteError};
+use super::{Prune, Read, StateChange, ValueIter, Write};
+
+/// The changes under a subscriber's prefixes made by a commit.
+#[derive(Clone, Debug)]
+pub struct StateCommitEvent {
+    /// The state id the changes were committed on.
+    pub state_id: String,
+    /// The state id produced by the commit.
+    pub new_state_id: String,
+    /// The committed changes under the subscriber's prefixes, in commit order.
+    pub changes: Vec<StateChange>,
+}
+
+struct Subscriber {
+    prefixes: Vec<String>,
+    sender: Sender<StateCommitEvent>,
+}
+
+impl Subscriber {
+    fn matches(&self, state_change: &StateChange) -> bool {
+        let key = match state_change {
+            StateChange::Set { key, .. } | StateChange::Delete { key } => key,
+        };
+        self.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+}
+
+/// A registry of subscribers to committed state changes.
+///
+/// Cloning a `StateSubscriptions` produces a handle to the same registry.
+#[derive(Clone, Default)]
+pub struct StateSubscriptions {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl StateSubscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a subscriber to the changes under any of the given address prefixes; an empty
+    /// prefix matches every address.
+    ///
+    /// Events are sent for the commits that change at least one matching address.  The
+    /// subscription ends when the returned receiver is dropped.
+    pub fn subscribe(&self, prefixes: Vec<String>) -> Receiver<StateCommitEvent> {
+        let (sender, receiver) = channel();
+        self.lock().push(Subscriber { prefixes, sender });
+        receiver
+    }
+
+    /// Returns the number of subscribers registered, including any whose receivers have been
+    /// dropped since the last commit.
+    pub fn subscriber_count(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Sends the changes of a commit to the matching subscribers, removing those whose receivers
+    /// have been dropped.
+    pub fn notify(&self, state_id: &str, new_state_id: &str, state_changes: &[StateChange]) {
+        self.lock().retain(|subscriber| {
+            let changes = state_changes
+                .iter()
+                .filter(|state_change| subscriber.matches(state_change))
+                .cloned()
+                .collect::<Vec<_>>();
+            if changes.is_empty() {
+                return true;
+            }
+            subscriber
+                .sender
+                .send(StateCommitEvent {
+                    state_id: state_id.to_string(),
+                    new_state_id: new_state_id.to_string(),
+                    changes,
+                })
+                .is_ok()
+        });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<Vec<Subscriber>> {
+        self.subscribers
+            .lock()
+            .expect("Couldn't lock subscribers mutex!")
+    }
+}
+
+/// Wraps a state backend, notifying subscribers of its commits.
+///
+/// Only `commit` notifies; `compute_state_id` writes nothing, so it has no changes to report.
+#[derive(Clone)]
+pub struct SubscribedState<S> {
+    state: S,
+    subscriptions: StateSubscriptions,
+}
+
+impl<S> SubscribedState<S> {
+    pub fn new(state: S, subscriptions: StateSubscriptions) -> Self {
+        SubscribedState {
+            state,
+            subscriptions,
+        }
+    }
+
+    pub fn subscriptions(&self) -> &StateSubscriptions {
+        &self.subscriptions
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.state
+    }
+}
+
+impl<S> Write for SubscribedState<S>
+where
+    S: Write<StateId = String, Key = String, Value = Vec<u8>>,
+{
+    type StateId = String;
+    type Key = String;
+    type Value = Vec<u8>;
+
+    fn commit(
+        &self,
+        state_id: &Self::StateId,
+        state_changes: &[StateChange],
+    ) -> Result<Self::StateId, StateWriteError> {
+        let new_state_id = self.state.commit(state_id, state_changes)?;
+        self.subscriptions
+            .notify(state_id, &new_state_id, state_changes);
+        Ok(new_state_id)
+    }
+
+    fn compute_state_id(
+        &self,
+        state_id: &Self::StateId,
+        state_changes: &[StateChange],
+    ) -> Result<Self::StateId, StateWriteError> {
+        self.state.compute_state_id(state_id, state_changes)
+    }
+}
+
+impl<S> Read for SubscribedState<S>
+where
+    S: Read<StateId = String, Key = String, Value = Vec<u8>> + Clone + 'static,
+{
+    type StateId = String;
+    type Key = String;
+    type Value = Vec<u8>;
+
+    fn get(
+        &self,
+        state_id: &Self::StateId,
+        keys: &[Self::Key],
+    ) -> Result<HashMap<Self::Key, Self::Value>, StateReadError> {
+        self.state.get(state_id, keys)
+    }
+
+    fn range(
+        &self,
+        state_id: &Self::StateId,
+        prefix: &Self::Key,
+    ) -> Result<ValueIter<Self::Key, Self::Value>, StateReadError> {
+        self.state.range(state_id, prefix)
+    }
+
+    fn clone_box(&self) -> Box<Read<StateId = String, Key = String, Value = Vec<u8>>> {
+        Box::new(Clone::clone(self))
+    }
+}
+
+impl<S> Prune for SubscribedState<S>
+where
+    S: Prune<StateId = String, Key = String, Value = Vec<u8>>,
+{
+    type StateId = String;
+    type Key = String;
+    type Value = Vec<u8>;
+
+    fn prune(&self, state_ids: Vec<Self::StateId>) -> Result<Vec<Self::Key>, StatePruneError> {
+        self.state.prune(state_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::TryRecvError;
+
+    use crate::state::hashmap::HashMapState;
+
+    fn set(key: &str, value: &[u8]) -> StateChange {
+        StateChange::Set {
+            key: key.into(),
+            value: value.to_vec(),
+        }
+    }
+
+    fn keys(event: &StateCommitEvent) -> Vec<&str> {
+        event
+            .changes
+            .iter()
+            .map(|state_change| match state_change {
+                StateChange::Set { key, .. } | StateChange::Delete { key } => key.as_str(),
+            })
+            .collect()
+    }
+
+    /// Verifies that subscribers receive only the committed changes under their prefixes.
+    #[test]
+    fn prefix_subscriptions() {
+        let state = SubscribedState::new(HashMapState::new(), StateSubscriptions::new());
+        let ab_receiver = state.subscriptions().subscribe(vec!["ab".into()]);
+        let all_receiver = state.subscriptions().subscribe(vec!["".into()]);
+        let ef_receiver = state
+            .subscriptions()
+            .subscribe(vec!["ef".into(), "12".into()]);
+
+        let initial_state_id = HashMapState::state_id(&HashMap::new());
+        let state_id = state
+            .commit(
+                &initial_state_id,
+                &[set("ab0000", b"value"), set("cd0000", b"value")],
+            )
+            .unwrap();
+        state
+            .compute_state_id(&state_id, &[set("ab0001", b"uncommitted")])
+            .unwrap();
+        let next_state_id = state
+            .commit(
+                &state_id,
+                &[StateChange::Delete {
+                    key: "ab0000".into(),
+                }],
+            )
+            .unwrap();
+
+        let event = ab_receiver.try_recv().unwrap();
+        assert_eq!(event.state_id, initial_state_id);
+        assert_eq!(event.new_state_id, state_id);
+        assert_eq!(keys(&event), vec!["ab0000"]);
+        let event = ab_receiver.try_recv().unwrap();
+        assert_eq!(event.state_id, state_id);
+        assert_eq!(event.new_state_id, next_state_id);
+        assert_eq!(keys(&event), vec!["ab0000"]);
+        assert_eq!(ab_receiver.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        assert_eq!(
+            keys(&all_receiver.try_recv().unwrap()),
+            vec!["ab0000", "cd0000"]
+        );
+        assert_eq!(keys(&all_receiver.try_recv().unwrap()), vec!["ab0000"]);
+
+        // Commits without matching changes send nothing
+        assert_eq!(ef_receiver.try_recv().unwrap_err(), TryRecvError::Empty);
+    }
+
+    /// Verifies that subscribers are removed once their receivers are dropped.
+    #[test]
+    fn dropped_subscribers() {
+        let subscriptions = StateSubscriptions::new();
+        let receiver = subscriptions.subscribe(vec!["ab".into()]);
+        let _other = subscriptions.subscribe(vec!["ab".into()]);
+        assert_eq!(subscriptions.subscriber_count(), 2);
+
+        drop(receiver);
+        subscriptions.notify("a", "b", &[set("ab0000", b"value")]);
+        assert_eq!(subscriptions.subscriber_count(), 1);
+    }
+}
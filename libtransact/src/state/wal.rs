@@ -0,0 +1,455 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * ------------------------------------------------------------------------------
+ */
+
+//! A write-ahead log of merkle state commits.
+//!
+//! Each commit is first recorded in the `WRITE_AHEAD_LOG_INDEX`, with every node and change log
+//! update it makes, and the record is removed in the same database transaction that applies the
+//! commit.  A record that remains in the log therefore marks a commit that was interrupted, such
+//! as by a crash, and holds all that is needed to roll it forward or to discard it.
+//!
+//! `recover` resolves any such records, and is run by `MerkleState::open`.
+
+use crate::database::{Database, DatabaseReader};
+
+use super::change_log::ChangeLogEntry;
+use super::merkle::{apply_changes, StateDatabaseError, CHANGE_LOG_INDEX, WRITE_AHEAD_LOG_INDEX};
+
+/// Determines what is done with commits found interrupted on recovery.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecoveryMode {
+    /// Complete the interrupted commits, such that their state roots are available.
+    RollForward,
+    /// Remove the interrupted commits from the log, leaving state as it was before them.
+    Discard,
+}
+
+/// The interrupted commits resolved by `recover`, by their resulting state roots.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecoverySummary {
+    /// Commits that were completed on recovery.
+    pub rolled_forward: Vec<String>,
+    /// Commits that were discarded on recovery.
+    pub discarded: Vec<String>,
+    /// Commits that had been applied, but whose log records had not been removed.
+    pub already_applied: Vec<String>,
+}
+
+impl RecoverySummary {
+    /// Returns true if no interrupted commits were found.
+    pub fn is_empty(&self) -> bool {
+        self.rolled_forward.is_empty()
+            && self.discarded.is_empty()
+            && self.already_applied.is_empty()
+    }
+}
+
+/// A commit from the `parent` state root to the `successor` state root, as recorded in the log.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct PendingCommit {
+    pub parent: Vec<u8>,
+    pub successor: Vec<u8>,
+    /// The nodes written by the commit, by hash.
+    pub nodes: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The hashes of the nodes the commit replaces.
+    pub deletions: Vec<Vec<u8>>,
+}
+
+impl PendingCommit {
+    /// The log record's key; a successor root may be reached from more than one parent.
+    fn key(&self) -> Vec<u8> {
+        let mut key = self.parent.clone();
+        key.extend_from_slice(&self.successor);
+        key
+    }
+
+    /// Encodes the commit as a sequence of fields, each a four-byte big-endian length followed
+    /// by its bytes: the parent, the successor, the number of nodes and each node's hash and
+    /// contents, then the number of deletions and each deletion.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        put_field(&mut bytes, &self.parent);
+        put_field(&mut bytes, &self.successor);
+        bytes.extend_from_slice(&(self.nodes.len() as u32).to_be_bytes());
+        for (hash, node) in &self.nodes {
+            put_field(&mut bytes, hash);
+            put_field(&mut bytes, node);
+        }
+        bytes.extend_from_slice(&(self.deletions.len() as u32).to_be_bytes());
+        for deletion in &self.deletions {
+            put_field(&mut bytes, deletion);
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, StateDatabaseError> {
+        let mut remaining = bytes;
+        let parent = take_field(&mut remaining)?;
+        let successor = take_field(&mut remaining)?;
+
+        let node_count = take_u32(&mut remaining)?;
+        let mut nodes = Vec::new();
+        for _ in 0..node_count {
+            let hash = take_field(&mut remaining)?;
+            nodes.push((hash, take_field(&mut remaining)?));
+        }
+
+        let deletion_count = take_u32(&mut remaining)?;
+        let mut deletions = Vec::new();
+        for _ in 0..deletion_count {
+            deletions.push(take_field(&mut remaining)?);
+        }
+
+        if !remaining.is_empty() {
+            return Err(StateDatabaseError::InvalidRecord);
+        }
+
+        Ok(PendingCommit {
+            parent,
+            successor,
+            nodes,
+            deletions,
+        })
+    }
+}
+
+/// Records a commit in the log, before it is applied.
+pub(super) fn log_pending_commit(
+    db: &dyn Database,
+    pending: &PendingCommit,
+) -> Result<(), StateDatabaseError> {
+    let mut db_writer = db.get_writer()?;
+    db_writer.index_put(WRITE_AHEAD_LOG_INDEX, &pending.key(), &pending.to_bytes())?;
+    db_writer.commit()?;
+    Ok(())
+}
+
+/// Applies a logged commit, removing its record in the same transaction.
+pub(super) fn apply_pending_commit(
+    db: &dyn Database,
+    pending: &PendingCommit,
+) -> Result<(), StateDatabaseError> {
+    let mut db_writer = db.get_writer()?;
+    apply_changes(
+        &mut *db_writer,
+        &pending.parent,
+        &pending.successor,
+        &pending.nodes,
+        &pending.deletions,
+    )?;
+    db_writer.index_delete(WRITE_AHEAD_LOG_INDEX, &pending.key())?;
+    db_writer.commit()?;
+    Ok(())
+}
+
+/// Resolves the commits that remain in the log, rolling them forward or discarding them as
+/// determined by `mode`.
+///
+/// Commits found to have been applied already have their records removed, whatever the mode.
+pub fn recover(
+    db: &dyn Database,
+    mode: RecoveryMode,
+) -> Result<RecoverySummary, StateDatabaseError> {
+    let pending_commits = {
+        let db_reader = db.get_reader()?;
+        let cursor = db_reader.index_cursor(WRITE_AHEAD_LOG_INDEX)?;
+        let mut pending_commits = vec![];
+        for (_, bytes) in cursor {
+            pending_commits.push(PendingCommit::from_bytes(&bytes)?);
+        }
+        pending_commits
+    };
+
+    let mut summary = RecoverySummary::default();
+    for pending in pending_commits {
+        let successor = ::hex::encode(&pending.successor);
+        let applied = {
+            let db_reader = db.get_reader()?;
+            is_applied(&*db_reader, &pending)?
+        };
+
+        if !applied && mode == RecoveryMode::RollForward {
+            apply_pending_commit(db, &pending)?;
+            summary.rolled_forward.push(successor);
+            continue;
+        }
+
+        let mut db_writer = db.get_writer()?;
+        db_writer.index_delete(WRITE_AHEAD_LOG_INDEX, &pending.key())?;
+        db_writer.commit()?;
+
+        if applied {
+            summary.already_applied.push(successor);
+        } else {
+            summary.discarded.push(successor);
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Returns true if the commit's change log entries have been written.
+fn is_applied(
+    db_reader: &dyn DatabaseReader,
+    pending: &PendingCommit,
+) -> Result<bool, StateDatabaseError> {
+    Ok(
+        match db_reader.index_get(CHANGE_LOG_INDEX, &pending.successor)? {
+            Some(bytes) => ChangeLogEntry::from_bytes(&bytes)?.parent == pending.parent,
+            None => false,
+        },
+    )
+}
+
+fn put_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(field);
+}
+
+fn take_u32(remaining: &mut &[u8]) -> Result<u32, StateDatabaseError> {
+    if remaining.len() < 4 {
+        return Err(StateDatabaseError::InvalidRecord);
+    }
+    let (value, rest) = remaining.split_at(4);
+    *remaining = rest;
+    let mut buf = [0; 4];
+    buf.copy_from_slice(value);
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn take_field(remaining: &mut &[u8]) -> Result<Vec<u8>, StateDatabaseError> {
+    let len = take_u32(remaining)? as usize;
+    if remaining.len() < len {
+        return Err(StateDatabaseError::InvalidRecord);
+    }
+    let (field, rest) = remaining.split_at(len);
+    *remaining = rest;
+    Ok(field.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::database::btree::BTreeDatabase;
+    use crate::database::error::DatabaseError;
+    use crate::database::{DatabaseCursor, DatabaseWriter};
+    use crate::state::merkle::{MerkleRadixTree, MerkleState, INDEXES};
+    use crate::state::{Read, StateChange};
+
+    /// A database whose writers fail to commit, as if the process had crashed, once a given
+    /// number of commits have succeeded.
+    #[derive(Clone)]
+    struct CrashingDatabase {
+        inner: BTreeDatabase,
+        commits_left: Arc<AtomicUsize>,
+    }
+
+    impl CrashingDatabase {
+        fn new() -> Self {
+            CrashingDatabase {
+                inner: BTreeDatabase::new(&INDEXES),
+                commits_left: Arc::new(AtomicUsize::new(usize::max_value())),
+            }
+        }
+
+        fn crash_after(&self, commits: usize) {
+            self.commits_left.store(commits, Ordering::SeqCst);
+        }
+    }
+
+    impl Database for CrashingDatabase {
+        fn get_reader<'a>(&'a self) -> Result<Box<dyn DatabaseReader + 'a>, DatabaseError> {
+            self.inner.get_reader()
+        }
+
+        fn get_writer<'a>(&'a self) -> Result<Box<dyn DatabaseWriter + 'a>, DatabaseError> {
+            Ok(Box::new(CrashingWriter {
+                inner: self.inner.get_writer()?,
+                commits_left: Arc::clone(&self.commits_left),
+            }))
+        }
+
+        fn clone_box(&self) -> Box<dyn Database> {
+            Box::new(self.clone())
+        }
+    }
+
+    struct CrashingWriter<'a> {
+        inner: Box<dyn DatabaseWriter + 'a>,
+        commits_left: Arc<AtomicUsize>,
+    }
+
+    impl<'a> DatabaseReader for CrashingWriter<'a> {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.inner.get(key)
+        }
+
+        fn index_get(&self, index: &str, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+            self.inner.index_get(index, key)
+        }
+
+        fn cursor(&self) -> Result<DatabaseCursor, DatabaseError> {
+            self.inner.cursor()
+        }
+
+        fn index_cursor(&self, index: &str) -> Result<DatabaseCursor, DatabaseError> {
+            self.inner.index_cursor(index)
+        }
+
+        fn count(&self) -> Result<usize, DatabaseError> {
+            self.inner.count()
+        }
+
+        fn index_count(&self, index: &str) -> Result<usize, DatabaseError> {
+            self.inner.index_count(index)
+        }
+    }
+
+    impl<'a> DatabaseWriter for CrashingWriter<'a> {
+        fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+            self.inner.put(key, value)
+        }
+
+        fn overwrite(&mut self, key: &[u8], value: &[u8]) -> Result<(), DatabaseError> {
+            self.inner.overwrite(key, value)
+        }
+
+        fn delete(&mut self, key: &[u8]) -> Result<(), DatabaseError> {
+            self.inner.delete(key)
+        }
+
+        fn index_put(
+            &mut self,
+            index: &str,
+            key: &[u8],
+            value: &[u8],
+        ) -> Result<(), DatabaseError> {
+            self.inner.index_put(index, key, value)
+        }
+
+        fn index_delete(&mut self, index: &str, key: &[u8]) -> Result<(), DatabaseError> {
+            self.inner.index_delete(index, key)
+        }
+
+        fn commit(self: Box<Self>) -> Result<(), DatabaseError> {
+            let commits_left = self.commits_left.load(Ordering::SeqCst);
+            if commits_left == 0 {
+                // The uncommitted writes are dropped with the inner writer
+                return Err(DatabaseError::WriterError("simulated crash".into()));
+            }
+            self.commits_left.store(commits_left - 1, Ordering::SeqCst);
+            self.inner.commit()
+        }
+
+        fn as_reader(&self) -> &dyn DatabaseReader {
+            self
+        }
+    }
+
+    /// Starts a tree, then interrupts a commit after it has been logged, returning the database,
+    /// the initial root, and the root the commit would have produced.
+    fn interrupted_commit() -> (CrashingDatabase, String, String) {
+        let db = CrashingDatabase::new();
+        let merkle_db = MerkleRadixTree::new(Box::new(db.clone()), None).unwrap();
+        let initial_root = merkle_db.get_merkle_root();
+
+        let changes = vec![StateChange::Set {
+            key: "ab0000".into(),
+            value: b"value".to_vec(),
+        }];
+        let expected_root = merkle_db.update(&changes, true).unwrap();
+
+        db.crash_after(1);
+        assert!(merkle_db.update(&changes, false).is_err());
+        db.crash_after(usize::max_value());
+
+        (db, initial_root, expected_root)
+    }
+
+    fn log_len(db: &dyn Database) -> usize {
+        db.get_reader()
+            .unwrap()
+            .index_count(WRITE_AHEAD_LOG_INDEX)
+            .unwrap()
+    }
+
+    #[test]
+    // test that a completed commit leaves nothing in the log
+    fn completed_commit() {
+        let db = CrashingDatabase::new();
+        let merkle_db = MerkleRadixTree::new(Box::new(db.clone()), None).unwrap();
+        merkle_db
+            .update(
+                &[StateChange::Set {
+                    key: "ab0000".into(),
+                    value: b"value".to_vec(),
+                }],
+                false,
+            )
+            .unwrap();
+
+        assert_eq!(log_len(&db), 0);
+        assert!(recover(&db, RecoveryMode::RollForward).unwrap().is_empty());
+    }
+
+    #[test]
+    // test that an interrupted commit is completed on opening with roll forward
+    fn roll_forward() {
+        let (db, _, expected_root) = interrupted_commit();
+        assert_eq!(log_len(&db), 1);
+        assert!(MerkleRadixTree::new(Box::new(db.clone()), Some(&expected_root)).is_err());
+
+        let state = MerkleState::open(Box::new(db.clone()), RecoveryMode::RollForward).unwrap();
+        assert_eq!(log_len(&db), 0);
+        let values = state.get(&expected_root, &["ab0000".to_string()]).unwrap();
+        assert_eq!(values.get("ab0000"), Some(&b"value".to_vec()));
+
+        // Recovery is idempotent
+        assert!(recover(&db, RecoveryMode::RollForward).unwrap().is_empty());
+    }
+
+    #[test]
+    // test that an interrupted commit is removed on recovery with discard, leaving the prior
+    // state intact
+    fn discard() {
+        let (db, initial_root, expected_root) = interrupted_commit();
+
+        let summary = recover(&db, RecoveryMode::Discard).unwrap();
+        assert_eq!(summary.discarded, vec![expected_root.clone()]);
+        assert_eq!(log_len(&db), 0);
+        assert!(MerkleRadixTree::new(Box::new(db.clone()), Some(&expected_root)).is_err());
+        assert!(MerkleRadixTree::new(Box::new(db), Some(&initial_root)).is_ok());
+    }
+
+    #[test]
+    // test that logged commits survive encoding
+    fn pending_commit_roundtrip() {
+        let pending = PendingCommit {
+            parent: vec![1; 32],
+            successor: vec![2; 32],
+            nodes: vec![(vec![3; 32], b"node".to_vec()), (vec![4; 32], vec![])],
+            deletions: vec![vec![5; 32]],
+        };
+        let bytes = pending.to_bytes();
+        assert_eq!(PendingCommit::from_bytes(&bytes).unwrap(), pending);
+        assert!(PendingCommit::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+/*
+ * Copyright 2019 Cargill Incorporated
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ * -----------------------------------------------------------------------------
+ */
+
+//! Policy rules for incoming batches.
+//!
+//! Deployments often restrict the batches they accept beyond what the protocol requires: a
+//! validator may cap the number of transactions in a batch, serve only some transaction
+//! families, require that batches be signed by particular keys, or keep clients out of reserved
+//! namespaces.  `Rules` declares such a policy, and `Rules::check` reports every way a batch
+//! breaks it.
+//!
+//! Rules inspect headers only.  They do not verify signatures (see `protocol::verify`) or check
+//! the batch's structure (see `Batch::validate_structure`), and a required signer is satisfied
+//! by the presence of its public key as the batch signer or a co-signer.
+
+use std::collections::HashSet;
+
+use crate::execution::TransactionFamily;
+use crate::protocol::batch::Batch;
+use crate::protocol::namespace;
+
+/// A way in which a batch breaks a rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleViolation {
+    /// The batch header could not be deserialized; no further rules are checked.
+    InvalidBatchHeader(String),
+    /// The batch contains more transactions than allowed.
+    BatchTooLarge { count: usize, max: usize },
+    /// A required signer neither signed nor co-signed the batch; the key is hex-encoded.
+    MissingSigner { public_key: String },
+    /// A transaction header could not be deserialized; no rules are checked for it.
+    InvalidTransactionHeader {
+        transaction_id: String,
+        reason: String,
+    },
+    /// The transaction belongs to a family that is not allowed.
+    FamilyNotAllowed {
+        transaction_id: String,
+        family_name: String,
+        family_version: String,
+    },
+    /// An input or output of the transaction lies in a banned namespace; both are hex-encoded.
+    BannedNamespace {
+        transaction_id: String,
+        address: String,
+        namespace: String,
+    },
+}
+
+impl std::fmt::Display for RuleViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            RuleViolation::InvalidBatchHeader(ref s) => write!(f, "InvalidBatchHeader: {}", s),
+            RuleViolation::BatchTooLarge { count, max } => write!(
+                f,
+                "BatchTooLarge: batch contains {} transactions, maximum is {}",
+                count, max
+            ),
+            RuleViolation::MissingSigner { ref public_key } => {
+                write!(f, "MissingSigner: {}", public_key)
+            }
+            RuleViolation::InvalidTransactionHeader {
+                ref transaction_id,
+                ref reason,
+            } => write!(
+                f,
+                "InvalidTransactionHeader: {}: {}",
+                transaction_id, reason
+            ),
+            RuleViolation::FamilyNotAllowed {
+                ref transaction_id,
+                ref family_name,
+                ref family_version,
+            } => write!(
+                f,
+                "FamilyNotAllowed: {} belongs to {} {}",
+                transaction_id, family_name, family_version
+            ),
+            RuleViolation::BannedNamespace {
+                ref transaction_id,
+                ref address,
+                ref namespace,
+            } => write!(
+                f,
+                "BannedNamespace: {} declares {}, within {}",
+                transaction_id, address, namespace
+            ),
+        }
+    }
+}
+
+/// A batch acceptance policy.
+///
+/// A new `Rules` accepts every batch; each `with_` method adds a restriction.
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    max_batch_size: Option<usize>,
+    allowed_families: Option<Vec<TransactionFamily>>,
+    required_signers: Vec<Vec<u8>>,
+    banned_namespaces: Vec<Vec<u8>>,
+}
+
+impl Rules {
+    pub fn new() -> Self {
+        Rules::default()
+    }
+
+    /// Limits the number of transactions in a batch.
+    pub fn with_max_batch_size(mut self, max: usize) -> Rules {
+        self.max_batch_size = Some(max);
+        self
+    }
+
+    /// Allows transactions of the given family version.  Once any family is allowed,
+    /// transactions of every other family are rejected.
+    pub fn with_allowed_family(mut self, family: TransactionFamily) -> Rules {
+        self.allowed_families
+            .get_or_insert_with(Vec::new)
+            .push(family);
+        self
+    }
+
+    /// Requires that the key sign or co-sign every batch.
+    pub fn with_required_signer(mut self, public_key: Vec<u8>) -> Rules {
+        self.required_signers.push(public_key);
+        self
+    }
+
+    /// Rejects transactions whose inputs or outputs intersect the namespace.
+    pub fn with_banned_namespace(mut self, namespace: Vec<u8>) -> Rules {
+        self.banned_namespaces.push(namespace);
+        self
+    }
+
+    /// Returns every violation of these rules by the batch, in the order the rules are checked.
+    pub fn check(&self, batch: &Batch) -> Vec<RuleViolation> {
+        let mut violations = Vec::new();
+
+        let batch_header = match batch.parse_header() {
+            Ok(header) => header,
+            Err(err) => {
+                violations.push(RuleViolation::InvalidBatchHeader(format!("{}", err)));
+                return violations;
+            }
+        };
+
+        let count = batch.transactions().len();
+        if let Some(max) = self.max_batch_size {
+            if count > max {
+                violations.push(RuleViolation::BatchTooLarge { count, max });
+            }
+        }
+
+        let signers = std::iter::once(batch_header.signer_public_key())
+            .chain(batch.cosignatures().iter().map(|c| c.signer_public_key()))
+            .collect::<HashSet<_>>();
+        for required in &self.required_signers {
+            if !signers.contains(required.as_slice()) {
+                violations.push(RuleViolation::MissingSigner {
+                    public_key: hex::encode(required),
+                });
+            }
+        }
+
+        for transaction in batch.transactions() {
+            let transaction_id = transaction.header_signature();
+            let header = match transaction.parse_header() {
+                Ok(header) => header,
+                Err(err) => {
+                    violations.push(RuleViolation::InvalidTransactionHeader {
+                        transaction_id: transaction_id.to_string(),
+                        reason: format!("{}", err),
+                    });
+                    continue;
+                }
+            };
+
+            if let Some(ref allowed) = self.allowed_families {
+                if !allowed.iter().any(|family| {
+                    family.family_name() == header.family_name()
+                        && family.family_version() == header.family_version()
+                }) {
+                    violations.push(RuleViolation::FamilyNotAllowed {
+                        transaction_id: transaction_id.to_string(),
+                        family_name: header.family_name().to_string(),
+                        family_version: header.family_version().to_string(),
+                    });
+                }
+            }
+
+            let mut seen = HashSet::new();
+            for address in header.inputs().iter().chain(header.outputs()) {
+                if !seen.insert(address) {
+                    continue;
+                }
+                if let Some(banned) = self
+                    .banned_namespaces
+                    .iter()
+                    .find(|banned| namespace::intersects(banned, address))
+                {
+                    violations.push(RuleViolation::BannedNamespace {
+                        transaction_id: transaction_id.to_string(),
+                        address: hex::encode(address),
+                        namespace: hex::encode(banned),
+                    });
+                }
+            }
+        }
+
+        violations
+    }
+
+    /// Returns true if the batch satisfies every rule.
+    pub fn permits(&self, batch: &Batch) -> bool {
+        self.check(batch).is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::protocol::batch::BatchBuilder;
+    use crate::protocol::transaction::{HashMethod, Transaction, TransactionBuilder};
+    use crate::signing::hash::HashSigner;
+    use crate::signing::Signer;
+
+    fn make_transaction(family_name: &str, addresses: Vec<Vec<u8>>) -> Transaction {
+        TransactionBuilder::new()
+            .with_family_name(family_name.to_string())
+            .with_family_version("1.0".to_string())
+            .with_inputs(addresses.clone())
+            .with_outputs(addresses)
+            .with_payload_hash_method(HashMethod::SHA512)
+            .with_payload(b"payload".to_vec())
+            .build(&HashSigner::new())
+            .unwrap()
+    }
+
+    fn make_batch(transactions: Vec<Transaction>) -> Batch {
+        BatchBuilder::new()
+            .with_transactions(transactions)
+            .build(&HashSigner::new())
+            .unwrap()
+    }
+
+    #[test]
+    // test that a batch satisfying every rule is permitted, and that no rules permit any batch
+    fn rules_permit() {
+        let batch = make_batch(vec![
+            make_transaction("intkey", vec![vec![0x1c, 0xf1]]),
+            make_transaction("xo", vec![vec![0x5b, 0x73]]),
+        ]);
+        assert!(Rules::new().permits(&batch));
+
+        let rules = Rules::new()
+            .with_max_batch_size(2)
+            .with_allowed_family(TransactionFamily::new("intkey".into(), "1.0".into()))
+            .with_allowed_family(TransactionFamily::new("xo".into(), "1.0".into()))
+            .with_required_signer(HashSigner::new().public_key().to_vec())
+            .with_banned_namespace(vec![0x00]);
+        assert_eq!(Vec::<RuleViolation>::new(), rules.check(&batch));
+    }
+
+    #[test]
+    // test that every broken rule is reported
+    fn rules_violations() {
+        let intkey = make_transaction("intkey", vec![vec![0x1c, 0xf1]]);
+        let xo = make_transaction("xo", vec![vec![0x00, 0x01], vec![0x5b, 0x73]]);
+        let xo_id = xo.header_signature().to_string();
+        let batch = make_batch(vec![intkey, xo]);
+
+        let rules = Rules::new()
+            .with_max_batch_size(1)
+            .with_allowed_family(TransactionFamily::new("intkey".into(), "1.0".into()))
+            .with_required_signer(vec![0x02, 0x03])
+            .with_banned_namespace(vec![0x00]);
+        assert!(!rules.permits(&batch));
+        assert_eq!(
+            vec![
+                RuleViolation::BatchTooLarge { count: 2, max: 1 },
+                RuleViolation::MissingSigner {
+                    public_key: "0203".to_string(),
+                },
+                RuleViolation::FamilyNotAllowed {
+                    transaction_id: xo_id.clone(),
+                    family_name: "xo".to_string(),
+                    family_version: "1.0".to_string(),
+                },
+                RuleViolation::BannedNamespace {
+                    transaction_id: xo_id,
+                    address: "0001".to_string(),
+                    namespace: "00".to_string(),
+                },
+            ],
+            rules.check(&batch)
+        );
+    }
+}
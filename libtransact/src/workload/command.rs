@@ -65,6 +65,32 @@ impl TransactionHandler for CommandTransactionHandler {
                 Command::Get { address } => {
                     context.get_state_entry(&address)?;
                 }
+                Command::DeleteRange { prefix } => {
+                    context.delete_state_entries_by_prefix(&prefix)?;
+                }
+                Command::ReadModifyWrite { address, increment } => {
+                    let current = match context.get_state_entry(&address)? {
+                        Some(value) => decode_counter(&value).ok_or_else(|| {
+                            ApplyError::InvalidTransaction(format!(
+                                "Value at {} is not a counter",
+                                address
+                            ))
+                        })?,
+                        None => 0,
+                    };
+                    let updated = current.wrapping_add(increment);
+                    context.set_state_entry(address, updated.to_be_bytes().to_vec())?;
+                }
+                Command::AddEvent {
+                    event_type,
+                    attributes,
+                    data,
+                } => {
+                    context.add_event(event_type, attributes, data)?;
+                }
+                Command::AddReceiptData { data } => {
+                    context.add_receipt_data(data)?;
+                }
                 Command::Fail { error_msg } => {
                     return Err(ApplyError::InvalidTransaction(error_msg));
                 }
@@ -75,12 +101,55 @@ impl TransactionHandler for CommandTransactionHandler {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Command {
-    Set { address: String, value: Vec<u8> },
-    Delete { address: String },
-    Get { address: String },
-    Fail { error_msg: String },
+    Set {
+        address: String,
+        value: Vec<u8>,
+    },
+    Delete {
+        address: String,
+    },
+    Get {
+        address: String,
+    },
+    /// Deletes every address that starts with `prefix`, including addresses already committed.
+    DeleteRange {
+        prefix: String,
+    },
+    /// Reads the counter at `address`, adds `increment` and writes it back.
+    ///
+    /// Counters are stored as 8-byte big-endian integers; an unset address reads as zero.
+    ReadModifyWrite {
+        address: String,
+        increment: u64,
+    },
+    /// Adds an event to the receipt.
+    ///
+    /// Attribute keys and values may not contain `,`, `|`, `;` or `=`, which delimit them in the
+    /// payload.
+    AddEvent {
+        event_type: String,
+        attributes: Vec<(String, String)>,
+        data: Vec<u8>,
+    },
+    /// Adds data to the receipt.
+    AddReceiptData {
+        data: Vec<u8>,
+    },
+    Fail {
+        error_msg: String,
+    },
+}
+
+/// Decodes a counter value written by a `ReadModifyWrite` command.
+pub fn decode_counter(value: &[u8]) -> Option<u64> {
+    if value.len() != 8 {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(value);
+    Some(u64::from_be_bytes(bytes))
 }
 
 impl std::fmt::Display for Command {
@@ -92,6 +161,27 @@ impl std::fmt::Display for Command {
             } => write!(f, "set,{},{}", address, hex::encode(value)),
             Command::Get { ref address } => write!(f, "get,{}", address),
             Command::Delete { ref address } => write!(f, "del,{}", address),
+            Command::DeleteRange { ref prefix } => write!(f, "delrange,{}", prefix),
+            Command::ReadModifyWrite {
+                ref address,
+                increment,
+            } => write!(f, "rmw,{},{}", address, increment),
+            Command::AddEvent {
+                ref event_type,
+                ref attributes,
+                ref data,
+            } => write!(
+                f,
+                "event,{},{},{}",
+                event_type,
+                attributes
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect::<Vec<_>>()
+                    .join(";"),
+                hex::encode(data)
+            ),
+            Command::AddReceiptData { ref data } => write!(f, "data,{}", hex::encode(data)),
             Command::Fail { ref error_msg } => write!(f, "fail,{}", error_msg),
         }
     }
@@ -103,32 +193,8 @@ pub fn make_command_transaction(commands: &[Command]) -> TransactionPair {
         .with_batcher_public_key(vec![0u8, 0u8, 0u8, 0u8])
         .with_family_name(COMMAND_FAMILY_NAME.to_owned())
         .with_family_version(COMMAND_VERSION.to_owned())
-        .with_inputs(
-            commands
-                .iter()
-                .map(|cmd| match cmd {
-                    Command::Set { address, .. } | Command::Delete { address } => {
-                        Some(address.as_bytes().to_vec())
-                    }
-                    _ => None,
-                })
-                .filter(Option::is_some)
-                .map(Option::unwrap)
-                .collect(),
-        )
-        .with_outputs(
-            commands
-                .iter()
-                .map(|cmd| match cmd {
-                    Command::Set { address, .. } | Command::Delete { address } => {
-                        Some(address.as_bytes().to_vec())
-                    }
-                    _ => None,
-                })
-                .filter(Option::is_some)
-                .map(Option::unwrap)
-                .collect(),
-        )
+        .with_inputs(commands.iter().filter_map(written_address).collect())
+        .with_outputs(commands.iter().filter_map(written_address).collect())
         .with_payload_hash_method(HashMethod::SHA512)
         .with_payload(
             commands
@@ -142,6 +208,17 @@ pub fn make_command_transaction(commands: &[Command]) -> TransactionPair {
         .unwrap()
 }
 
+// The address, or address prefix, written by the given command
+fn written_address(command: &Command) -> Option<Vec<u8>> {
+    match command {
+        Command::Set { address, .. }
+        | Command::Delete { address }
+        | Command::ReadModifyWrite { address, .. } => Some(address.as_bytes().to_vec()),
+        Command::DeleteRange { prefix, .. } => Some(prefix.as_bytes().to_vec()),
+        _ => None,
+    }
+}
+
 fn parse_commands(payload: &[u8]) -> Result<Vec<Command>, ParseCommandError> {
     std::str::from_utf8(payload)
         .map_err(|err| ParseCommandError(format!("Payload not valid utf8 bytes: {}", err)))?
@@ -196,6 +273,54 @@ impl std::str::FromStr for Command {
 
                 Ok(Command::Get { address })
             }
+            Some(ref delrange) if delrange == "delrange" => {
+                let prefix = command_parts.next().map(|s| s.to_owned()).ok_or_else(|| {
+                    ParseCommandError("Cannot delete range without prefix".into())
+                })?;
+
+                Ok(Command::DeleteRange { prefix })
+            }
+            Some(ref rmw) if rmw == "rmw" => {
+                let address = command_parts
+                    .next()
+                    .map(|s| s.to_owned())
+                    .ok_or_else(|| ParseCommandError("Cannot modify without address".into()))?;
+                let increment = parse_number(command_parts.next(), "increment")?;
+
+                Ok(Command::ReadModifyWrite { address, increment })
+            }
+            Some(ref event) if event == "event" => {
+                let event_type = command_parts
+                    .next()
+                    .map(|s| s.to_owned())
+                    .ok_or_else(|| ParseCommandError("Cannot add event without type".into()))?;
+                let attributes = command_parts
+                    .next()
+                    .unwrap_or("")
+                    .split(';')
+                    .filter(|attribute| !attribute.is_empty())
+                    .map(|attribute| {
+                        let mut parts = attribute.splitn(2, '=');
+                        match (parts.next(), parts.next()) {
+                            (Some(key), Some(value)) => Ok((key.to_owned(), value.to_owned())),
+                            _ => Err(ParseCommandError(format!(
+                                "Invalid event attribute: {}",
+                                attribute
+                            ))),
+                        }
+                    })
+                    .collect::<Result<_, _>>()?;
+                let data = parse_hex(command_parts.next())?;
+
+                Ok(Command::AddEvent {
+                    event_type,
+                    attributes,
+                    data,
+                })
+            }
+            Some(ref data) if data == "data" => Ok(Command::AddReceiptData {
+                data: parse_hex(command_parts.next())?,
+            }),
             Some(ref fail) if fail == "fail" => {
                 let error_msg = command_parts
                     .next()
@@ -210,6 +335,21 @@ impl std::str::FromStr for Command {
     }
 }
 
+fn parse_number<T: std::str::FromStr>(
+    part: Option<&str>,
+    name: &str,
+) -> Result<T, ParseCommandError> {
+    part.ok_or_else(|| ParseCommandError(format!("Missing {}", name)))?
+        .parse()
+        .map_err(|_| ParseCommandError(format!("Invalid {}", name)))
+}
+
+// Parses an optional hex field, where a missing or empty field is empty data
+fn parse_hex(part: Option<&str>) -> Result<Vec<u8>, ParseCommandError> {
+    hex::decode(part.unwrap_or(""))
+        .map_err(|err| ParseCommandError(format!("Invalid hex: {}", err)))
+}
+
 #[derive(Debug)]
 pub struct ParseCommandError(String);
 
@@ -220,3 +360,178 @@ impl std::fmt::Display for ParseCommandError {
         write!(f, "Unable to parse command: {}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use crate::handler::ContextError;
+
+    #[derive(Default)]
+    struct MemoryContext {
+        state: RefCell<HashMap<String, Vec<u8>>>,
+        events: RefCell<Vec<(String, Vec<(String, String)>, Vec<u8>)>>,
+        data: RefCell<Vec<Vec<u8>>>,
+    }
+
+    impl TransactionContext for MemoryContext {
+        fn get_state_entries(
+            &self,
+            addresses: &[String],
+        ) -> Result<Vec<(String, Vec<u8>)>, ContextError> {
+            let state = self.state.borrow();
+            Ok(addresses
+                .iter()
+                .filter_map(|address| {
+                    state
+                        .get(address)
+                        .map(|value| (address.clone(), value.clone()))
+                })
+                .collect())
+        }
+
+        fn set_state_entries(&self, entries: Vec<(String, Vec<u8>)>) -> Result<(), ContextError> {
+            self.state.borrow_mut().extend(entries);
+            Ok(())
+        }
+
+        fn delete_state_entries(&self, addresses: &[String]) -> Result<Vec<String>, ContextError> {
+            let mut state = self.state.borrow_mut();
+            Ok(addresses
+                .iter()
+                .filter(|address| state.remove(*address).is_some())
+                .cloned()
+                .collect())
+        }
+
+        fn delete_state_entries_by_prefix(
+            &self,
+            prefix: &str,
+        ) -> Result<Vec<String>, ContextError> {
+            let mut state = self.state.borrow_mut();
+            let mut addresses = state
+                .keys()
+                .filter(|address| address.starts_with(prefix))
+                .cloned()
+                .collect::<Vec<_>>();
+            addresses.sort();
+            for address in &addresses {
+                state.remove(address);
+            }
+            Ok(addresses)
+        }
+
+        fn add_receipt_data(&self, data: Vec<u8>) -> Result<(), ContextError> {
+            self.data.borrow_mut().push(data);
+            Ok(())
+        }
+
+        fn add_event(
+            &self,
+            event_type: String,
+            attributes: Vec<(String, String)>,
+            data: Vec<u8>,
+        ) -> Result<(), ContextError> {
+            self.events
+                .borrow_mut()
+                .push((event_type, attributes, data));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn command_parse_round_trip() {
+        let commands = vec![
+            Command::DeleteRange {
+                prefix: "abc".into(),
+            },
+            Command::ReadModifyWrite {
+                address: "abc".into(),
+                increment: 7,
+            },
+            Command::AddEvent {
+                event_type: "test/event".into(),
+                attributes: vec![("a".into(), "1".into()), ("b".into(), "".into())],
+                data: vec![0x01, 0x02],
+            },
+            Command::AddEvent {
+                event_type: "test/empty".into(),
+                attributes: vec![],
+                data: vec![],
+            },
+            Command::AddReceiptData { data: vec![0xff] },
+        ];
+
+        for command in commands {
+            assert_eq!(command, command.to_string().parse::<Command>().unwrap());
+        }
+    }
+
+    #[test]
+    // test that delete-range removes the addresses under the prefix, and read-modify-write
+    // updates a counter in place
+    fn command_delete_range_and_read_modify_write() {
+        let handler = CommandTransactionHandler::new();
+        let mut context = MemoryContext::default();
+        for address in &["ns0", "ns1", "ns12", "nt0"] {
+            context
+                .set_state_entry(address.to_string(), vec![1])
+                .unwrap();
+        }
+
+        let pair = make_command_transaction(&[
+            Command::DeleteRange {
+                prefix: "ns1".into(),
+            },
+            Command::ReadModifyWrite {
+                address: "counter".into(),
+                increment: 2,
+            },
+            Command::ReadModifyWrite {
+                address: "counter".into(),
+                increment: 3,
+            },
+        ]);
+        handler.apply(&pair, &mut context).unwrap();
+
+        let state = context.state.borrow();
+        let mut remaining = state
+            .keys()
+            .filter(|address| address.starts_with('n'))
+            .cloned()
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(vec!["ns0".to_string(), "nt0".to_string()], remaining);
+        assert_eq!(Some(5), decode_counter(&state["counter"]));
+    }
+
+    #[test]
+    // test that event and data commands are added to the transaction's results
+    fn command_add_event_and_receipt_data() {
+        let handler = CommandTransactionHandler::new();
+        let mut context = MemoryContext::default();
+
+        let pair = make_command_transaction(&[
+            Command::AddEvent {
+                event_type: "test/event".into(),
+                attributes: vec![("a".into(), "1".into())],
+                data: vec![0x01],
+            },
+            Command::AddReceiptData { data: vec![0x02] },
+        ]);
+        handler.apply(&pair, &mut context).unwrap();
+
+        assert_eq!(
+            vec![(
+                "test/event".to_string(),
+                vec![("a".to_string(), "1".to_string())],
+                vec![0x01]
+            )],
+            *context.events.borrow()
+        );
+        assert_eq!(vec![vec![0x02]], *context.data.borrow());
+    }
+}